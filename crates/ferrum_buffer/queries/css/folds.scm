@@ -0,0 +1,10 @@
+; CSS folds.scm
+
+[
+  (block)
+  (keyframe_block_list)
+] @fold
+
+(import_statement) @fold.imports
+
+(comment) @fold.comment
@@ -32,7 +32,6 @@
   "@namespace"
   "@keyframes"
   "@supports"
-  "@font-face"
 ] @keyword
 
 ; Important
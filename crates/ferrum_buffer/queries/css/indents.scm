@@ -0,0 +1,12 @@
+; CSS indents.scm
+
+[
+  (block)
+  (keyframe_block_list)
+  (arguments)
+] @indent
+
+[
+  "}"
+  ")"
+] @outdent
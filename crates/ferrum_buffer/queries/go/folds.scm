@@ -0,0 +1,21 @@
+; Go folds.scm
+
+[
+  (function_declaration)
+  (method_declaration)
+  (func_literal)
+  (block)
+  (literal_value)
+  (field_declaration_list)
+  (interface_type)
+  (expression_switch_statement)
+  (type_switch_statement)
+  (select_statement)
+  (const_declaration)
+  (var_declaration)
+  (argument_list)
+] @fold
+
+(import_declaration) @fold.imports
+
+(comment) @fold.comment
@@ -0,0 +1,4 @@
+; Go imports.scm
+
+(import_spec
+  path: (interpreted_string_literal) @import.source) @import
@@ -0,0 +1,32 @@
+; Go indents.scm
+
+[
+  (block)
+  (literal_value)
+  (field_declaration_list)
+  (interface_type)
+  (expression_switch_statement)
+  (type_switch_statement)
+  (select_statement)
+  (expression_case)
+  (type_case)
+  (default_case)
+  (communication_case)
+  (const_declaration)
+  (var_spec_list)
+  (import_spec_list)
+  (argument_list)
+  (parameter_list)
+] @indent
+
+[
+  "}"
+  ")"
+  "]"
+] @outdent
+
+; Cases line up with their switch
+(expression_case "case" @outdent)
+(type_case "case" @outdent)
+(communication_case "case" @outdent)
+(default_case "default" @outdent)
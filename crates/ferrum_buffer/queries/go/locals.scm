@@ -0,0 +1,24 @@
+; Go locals.scm
+
+; Scopes
+[
+  (function_declaration)
+  (method_declaration)
+  (func_literal)
+  (block)
+  (for_statement)
+  (if_statement)
+] @local.scope
+
+; Definitions
+(parameter_declaration name: (identifier) @local.definition.parameter)
+(variadic_parameter_declaration name: (identifier) @local.definition.parameter)
+
+(short_var_declaration left: (expression_list (identifier) @local.definition.var))
+(var_spec name: (identifier) @local.definition.var)
+(range_clause left: (expression_list (identifier) @local.definition.var))
+
+(const_spec name: (identifier) @local.definition.constant)
+
+; References
+(identifier) @local.reference
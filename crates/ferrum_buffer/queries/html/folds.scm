@@ -0,0 +1,9 @@
+; HTML folds.scm
+
+[
+  (element)
+  (script_element)
+  (style_element)
+] @fold
+
+(comment) @fold.comment
@@ -0,0 +1,11 @@
+; HTML indents.scm
+
+[
+  (element)
+  (script_element)
+  (style_element)
+  (start_tag)
+  (self_closing_tag)
+] @indent
+
+(end_tag) @outdent
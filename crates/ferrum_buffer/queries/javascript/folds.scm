@@ -0,0 +1,17 @@
+; JavaScript folds.scm
+
+[
+  (statement_block)
+  (class_body)
+  (switch_body)
+  (object)
+  (array)
+  (arguments)
+  (template_string)
+  (jsx_element)
+  (jsx_self_closing_element)
+] @fold
+
+(import_statement) @fold.imports
+
+(comment) @fold.comment
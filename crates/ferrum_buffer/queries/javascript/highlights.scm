@@ -110,7 +110,6 @@
   ">>"
   ">>>"
   "=>"
-  "?."
   "..."
 ] @operator
 
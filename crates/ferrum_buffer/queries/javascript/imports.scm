@@ -0,0 +1,17 @@
+; JavaScript imports.scm
+
+(import_statement
+  source: (string) @import.source) @import
+
+(export_statement
+  source: (string) @import.source) @import
+
+((call_expression
+  function: (identifier) @_require
+  arguments: (arguments . (string) @import.source))
+  (#eq? @_require "require")) @import
+
+; Dynamic import()
+(call_expression
+  function: (import)
+  arguments: (arguments . [(string) (template_string)] @import.source)) @import
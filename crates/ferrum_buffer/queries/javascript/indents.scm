@@ -0,0 +1,30 @@
+; JavaScript indents.scm
+
+[
+  (statement_block)
+  (class_body)
+  (object)
+  (array)
+  (object_pattern)
+  (array_pattern)
+  (named_imports)
+  (export_clause)
+  (switch_body)
+  (switch_case)
+  (switch_default)
+  (arguments)
+  (formal_parameters)
+  (parenthesized_expression)
+  (template_substitution)
+  (jsx_element)
+  (jsx_opening_element)
+  (jsx_self_closing_element)
+] @indent
+
+[
+  "}"
+  "]"
+  ")"
+] @outdent
+
+(jsx_closing_element) @outdent
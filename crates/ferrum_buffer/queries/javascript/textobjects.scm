@@ -0,0 +1,29 @@
+; JavaScript textobjects.scm
+
+[
+  (function_declaration
+    body: (_) @function.inside)
+  (function_expression
+    body: (_) @function.inside)
+  (generator_function_declaration
+    body: (_) @function.inside)
+  (arrow_function
+    body: (_) @function.inside)
+  (method_definition
+    body: (_) @function.inside)
+] @function.around
+
+[
+  (class_declaration
+    body: (_) @class.inside)
+  (class
+    body: (_) @class.inside)
+] @class.around
+
+(formal_parameters
+  ((_) @parameter.inside . ","? @parameter.around) @parameter.around)
+
+(arguments
+  ((_) @parameter.inside . ","? @parameter.around) @parameter.around)
+
+(comment) @comment.inside @comment.around
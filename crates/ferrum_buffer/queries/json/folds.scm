@@ -0,0 +1,6 @@
+; JSON folds.scm
+
+[
+  (object)
+  (array)
+] @fold
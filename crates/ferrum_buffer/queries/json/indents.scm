@@ -0,0 +1,11 @@
+; JSON indents.scm
+
+[
+  (object)
+  (array)
+] @indent
+
+[
+  "}"
+  "]"
+] @outdent
@@ -0,0 +1,8 @@
+; Markdown folds.scm
+
+[
+  (section)
+  (fenced_code_block)
+  (html_block)
+  (list)
+] @fold
@@ -9,22 +9,15 @@
 (atx_heading (atx_h6_marker) @keyword)
 (setext_heading) @keyword
 
-; Inline elements
-(emphasis) @string
-(strong_emphasis) @string
-(strikethrough) @string
-
 ; Code
-(code_span) @string
 (fenced_code_block) @embedded
 (indented_code_block) @embedded
 (info_string) @property
 
-; Links
-(link_text) @string
+; Link reference definitions
+(link_label) @string
 (link_destination) @string
 (link_title) @string
-(image) @string
 
 ; Lists
 (list_marker_minus) @punctuation
@@ -0,0 +1,4 @@
+; Markdown indents.scm
+
+; List item continuations line up with the item's text
+(list_item) @align
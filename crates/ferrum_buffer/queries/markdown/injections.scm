@@ -0,0 +1,11 @@
+; Markdown injections.scm
+
+; Fenced code blocks, in the language named by the info string
+(fenced_code_block
+  (info_string
+    (language) @injection.language)
+  (code_fence_content) @injection.content)
+
+; Raw HTML
+((html_block) @injection.content
+  (#set! injection.language "html"))
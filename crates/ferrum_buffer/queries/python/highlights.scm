@@ -115,4 +115,5 @@
 ["," ":" "."] @punctuation.delimiter
 
 ; Self
-(self) @variable.builtin
+((identifier) @variable.builtin
+  (#eq? @variable.builtin "self"))
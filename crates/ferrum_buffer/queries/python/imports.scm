@@ -0,0 +1,19 @@
+; Python imports.scm
+
+(import_statement
+  name: (dotted_name) @import.source) @import
+
+(import_statement
+  name: (aliased_import
+    name: (dotted_name) @import.source)) @import
+
+(import_from_statement
+  module_name: (_) @import.source) @import
+
+; Imported names may be submodules
+(import_from_statement
+  name: (dotted_name) @import.name) @import
+
+(import_from_statement
+  name: (aliased_import
+    name: (dotted_name) @import.name)) @import
@@ -0,0 +1,44 @@
+; Python indents.scm
+
+; Compound statements also hold the line typed right after them, so a new
+; line at the end of a body stays in it
+[
+  (function_definition)
+  (class_definition)
+  (if_statement)
+  (elif_clause)
+  (else_clause)
+  (for_statement)
+  (while_statement)
+  (with_statement)
+  (try_statement)
+  (except_clause)
+  (finally_clause)
+  (match_statement)
+  (case_clause)
+] @indent @extend
+
+[
+  (argument_list)
+  (parameters)
+  (list)
+  (dictionary)
+  (set)
+  (tuple)
+  (parenthesized_expression)
+  (list_comprehension)
+  (dictionary_comprehension)
+  (set_comprehension)
+  (generator_expression)
+] @align
+
+[
+  ")"
+  "]"
+  "}"
+] @outdent
+
+(elif_clause "elif" @outdent)
+(else_clause "else" @outdent)
+(except_clause "except" @outdent)
+(finally_clause "finally" @outdent)
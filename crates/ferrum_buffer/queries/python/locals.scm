@@ -0,0 +1,29 @@
+; Python locals.scm
+
+; Scopes
+[
+  (function_definition)
+  (lambda)
+] @local.scope
+
+; Definitions
+(parameters (identifier) @local.definition.parameter)
+(default_parameter name: (identifier) @local.definition.parameter)
+(typed_parameter (identifier) @local.definition.parameter)
+(typed_default_parameter name: (identifier) @local.definition.parameter)
+(list_splat_pattern (identifier) @local.definition.parameter)
+(dictionary_splat_pattern (identifier) @local.definition.parameter)
+(lambda_parameters (identifier) @local.definition.parameter)
+
+(assignment left: (identifier) @local.definition.var)
+(assignment left: (pattern_list (identifier) @local.definition.var))
+(for_statement left: (identifier) @local.definition.var)
+(for_statement left: (pattern_list (identifier) @local.definition.var))
+(as_pattern alias: (as_pattern_target (identifier) @local.definition.var))
+
+(import_statement name: (dotted_name . (identifier) @local.definition.import))
+(import_from_statement name: (dotted_name . (identifier) @local.definition.import))
+(aliased_import alias: (identifier) @local.definition.import)
+
+; References
+(identifier) @local.reference
@@ -0,0 +1,29 @@
+; Rust folds.scm
+
+[
+  (function_item)
+  (impl_item)
+  (trait_item)
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (mod_item)
+  (macro_definition)
+  (block)
+  (match_block)
+  (match_arm)
+  (token_tree)
+  (field_initializer_list)
+  (array_expression)
+  (arguments)
+] @fold
+
+[
+  (use_declaration)
+  (extern_crate_declaration)
+] @fold.imports
+
+[
+  (line_comment)
+  (block_comment)
+] @fold.comment
@@ -0,0 +1,9 @@
+; Rust imports.scm
+
+(use_declaration
+  argument: (_) @import.source) @import
+
+; A module in another file
+(mod_item
+  name: (identifier) @import.module
+  !body) @import
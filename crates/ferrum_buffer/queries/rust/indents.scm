@@ -0,0 +1,25 @@
+; Rust indents.scm
+
+[
+  (block)
+  (declaration_list)
+  (field_declaration_list)
+  (ordered_field_declaration_list)
+  (enum_variant_list)
+  (field_initializer_list)
+  (match_block)
+  (use_list)
+  (token_tree)
+  (arguments)
+  (parameters)
+  (array_expression)
+  (tuple_expression)
+  (where_clause)
+  (let_declaration)
+] @indent
+
+[
+  "}"
+  "]"
+  ")"
+] @outdent
@@ -0,0 +1,37 @@
+; Rust locals.scm
+
+; Scopes
+[
+  (function_item)
+  (closure_expression)
+  (block)
+  (match_arm)
+  (for_expression)
+] @local.scope
+
+; Definitions
+(parameter pattern: (identifier) @local.definition.parameter)
+(parameter pattern: (mut_pattern (identifier) @local.definition.parameter))
+(self_parameter (self) @local.definition.parameter)
+(closure_parameters (identifier) @local.definition.parameter)
+(closure_parameters (parameter pattern: (identifier) @local.definition.parameter))
+
+(let_declaration pattern: (identifier) @local.definition.var)
+(let_declaration pattern: (mut_pattern (identifier) @local.definition.var))
+(let_declaration pattern: (tuple_pattern (identifier) @local.definition.var))
+(let_condition pattern: (tuple_struct_pattern "(" (identifier) @local.definition.var))
+(for_expression pattern: (identifier) @local.definition.var)
+(for_expression pattern: (tuple_pattern (identifier) @local.definition.var))
+
+(const_item name: (identifier) @local.definition.constant)
+(static_item name: (identifier) @local.definition.constant)
+
+(use_declaration argument: (identifier) @local.definition.import)
+(use_declaration argument: (scoped_identifier name: (identifier) @local.definition.import))
+(use_as_clause alias: (identifier) @local.definition.import)
+(use_list (identifier) @local.definition.import)
+(use_list (scoped_identifier name: (identifier) @local.definition.import))
+
+; References
+(identifier) @local.reference
+(self) @local.reference
@@ -0,0 +1,10 @@
+; TOML folds.scm
+
+[
+  (table)
+  (table_array_element)
+  (array)
+  (inline_table)
+] @fold
+
+(comment) @fold.comment
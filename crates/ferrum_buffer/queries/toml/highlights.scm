@@ -3,8 +3,8 @@
 ; Tables
 (table (bare_key) @type)
 (table (dotted_key) @type)
-(array_table (bare_key) @type)
-(array_table (dotted_key) @type)
+(table_array_element (bare_key) @type)
+(table_array_element (dotted_key) @type)
 
 ; Keys
 (pair (bare_key) @property)
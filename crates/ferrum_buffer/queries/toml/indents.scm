@@ -0,0 +1,11 @@
+; TOML indents.scm
+
+[
+  (array)
+  (inline_table)
+] @indent
+
+[
+  "]"
+  "}"
+] @outdent
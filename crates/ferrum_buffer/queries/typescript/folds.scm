@@ -0,0 +1,18 @@
+; TypeScript folds.scm
+
+[
+  (statement_block)
+  (class_body)
+  (interface_body)
+  (object_type)
+  (enum_body)
+  (switch_body)
+  (object)
+  (array)
+  (arguments)
+  (template_string)
+] @fold
+
+(import_statement) @fold.imports
+
+(comment) @fold.comment
@@ -141,10 +141,3 @@
 ["(" ")" "[" "]" "{" "}"] @punctuation.bracket
 ["," ";" ":" "."] @punctuation.delimiter
 
-; JSX/TSX
-(jsx_element) @tag
-(jsx_opening_element name: (identifier) @tag)
-(jsx_closing_element name: (identifier) @tag)
-(jsx_self_closing_element name: (identifier) @tag)
-(jsx_attribute (property_identifier) @property)
-["<" ">" "</" "/>"] @tag.delimiter
@@ -0,0 +1,31 @@
+; TypeScript indents.scm
+
+[
+  (statement_block)
+  (class_body)
+  (interface_body)
+  (object_type)
+  (enum_body)
+  (object)
+  (array)
+  (object_pattern)
+  (array_pattern)
+  (named_imports)
+  (export_clause)
+  (switch_body)
+  (switch_case)
+  (switch_default)
+  (arguments)
+  (formal_parameters)
+  (type_arguments)
+  (type_parameters)
+  (parenthesized_expression)
+  (template_substitution)
+] @indent
+
+[
+  "}"
+  "]"
+  ")"
+  ">"
+] @outdent
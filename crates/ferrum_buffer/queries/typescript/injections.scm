@@ -0,0 +1,13 @@
+; TypeScript injections.scm
+
+; Tagged template literals, in the language named by the tag, e.g.
+; html`<p>${text}</p>` or styled.css`color: red;`
+(call_expression
+  function: [
+    (identifier) @injection.language
+    (member_expression
+      property: (property_identifier) @injection.language)
+  ]
+  arguments: (template_string (string_fragment) @injection.content)
+  (#set! injection.combined)
+  (#set! injection.include-children))
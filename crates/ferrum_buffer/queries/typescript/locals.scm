@@ -0,0 +1,32 @@
+; TypeScript locals.scm
+
+; Scopes
+[
+  (statement_block)
+  (function_declaration)
+  (function_expression)
+  (arrow_function)
+  (method_definition)
+  (for_statement)
+  (for_in_statement)
+  (catch_clause)
+] @local.scope
+
+; Definitions
+(required_parameter pattern: (identifier) @local.definition.parameter)
+(optional_parameter pattern: (identifier) @local.definition.parameter)
+(required_parameter pattern: (rest_pattern (identifier) @local.definition.parameter))
+(arrow_function parameter: (identifier) @local.definition.parameter)
+(catch_clause parameter: (identifier) @local.definition.parameter)
+
+(variable_declarator name: (identifier) @local.definition.var)
+(for_in_statement left: (identifier) @local.definition.var)
+
+(import_clause (identifier) @local.definition.import)
+(namespace_import (identifier) @local.definition.import)
+(import_specifier alias: (identifier) @local.definition.import)
+(import_specifier !alias name: (identifier) @local.definition.import)
+
+; References
+(identifier) @local.reference
+(shorthand_property_identifier) @local.reference
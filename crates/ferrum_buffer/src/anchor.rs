@@ -13,6 +13,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 
+use crate::edit::Change;
+
 /// Unique identifier for an anchor
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct AnchorId(u64);
@@ -204,6 +206,16 @@ impl AnchorSet {
     }
   }
 
+  /// Update all anchors after an ordered list of changes (char offsets)
+  pub fn apply_changes(&self, changes: &[Change]) {
+    let mut anchors = self.anchors.write();
+    for change in changes {
+      for anchor in anchors.values_mut() {
+        anchor.apply_edit(change.start, change.old_len, change.new_len);
+      }
+    }
+  }
+
   /// Get the number of anchors
   pub fn len(&self) -> usize {
     self.anchors.read().len()
@@ -234,6 +246,7 @@ impl Default for AnchorSet {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::position::Point;
 
   #[test]
   fn test_anchor_before_edit() {
@@ -296,4 +309,37 @@ mod tests {
     assert_eq!(set.offset(id1), Some(10)); // Before edit
     assert_eq!(set.offset(id2), Some(25)); // After edit
   }
+
+  #[test]
+  fn test_anchor_set_apply_changes() {
+    let set = AnchorSet::new();
+    let id = set.create_left(20);
+
+    set.apply_changes(&[
+      Change {
+        start: 0,
+        old_len: 0,
+        new_len: 3,
+        start_byte: 0,
+        old_end_byte: 0,
+        new_end_byte: 3,
+        start_point: Point::new(0, 0),
+        old_end_point: Point::new(0, 0),
+        new_end_point: Point::new(0, 3),
+      },
+      Change {
+        start: 13,
+        old_len: 5,
+        new_len: 0,
+        start_byte: 13,
+        old_end_byte: 18,
+        new_end_byte: 13,
+        start_point: Point::new(0, 13),
+        old_end_point: Point::new(0, 18),
+        new_end_point: Point::new(0, 13),
+      },
+    ]);
+
+    assert_eq!(set.offset(id), Some(18));
+  }
 }
@@ -0,0 +1,309 @@
+//! Bracket pairs
+//!
+//! Brackets are matched with the syntax tree: an open and a close bracket
+//! pair up when they are tokens of the same node, so bracket characters in
+//! strings and comments, which are not tokens of their own, never count.
+//! Angle brackets pair up where a grammar makes them tokens of one node,
+//! e.g. in type arguments; anywhere else they are operators and are never
+//! reported as unmatched.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use tree_sitter::Node;
+
+/// Open and close tokens of each kind of bracket
+const PAIRS: &[(&str, &str)] = &[("(", ")"), ("[", "]"), ("{", "}"), ("${", "}"), ("<", ">")];
+
+/// Index of the angle brackets in [`PAIRS`]
+const ANGLE: usize = 4;
+
+/// A matched pair of brackets
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct BracketPair {
+  pub open: Range<usize>,
+  pub close: Range<usize>,
+  /// Number of pairs enclosing this one, for colorization
+  pub depth: usize,
+}
+
+/// The brackets of part of a tree
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Brackets {
+  /// Sorted by open bracket
+  pub pairs: Vec<BracketPair>,
+  /// Brackets without a partner, sorted
+  pub unmatched: Vec<Range<usize>>,
+}
+
+impl Brackets {
+  pub(crate) fn sort(&mut self) {
+    self.pairs.sort_by_key(|p| p.open.start);
+    self.pairs.dedup();
+    self.unmatched.sort_by_key(|r| r.start);
+    self.unmatched.dedup();
+  }
+}
+
+/// A bracket token: an open bracket by index in [`PAIRS`], or a close
+/// bracket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+  Open(usize),
+  Close(&'static str),
+}
+
+impl Token {
+  fn of(node: &Node) -> Option<Self> {
+    // A token the parser inserted to recover from an error is not there
+    if node.is_named() || node.is_missing() {
+      return None;
+    }
+    let kind = node.kind();
+    PAIRS
+      .iter()
+      .position(|(open, _)| *open == kind)
+      .map(Self::Open)
+      .or_else(|| {
+        PAIRS
+          .iter()
+          .find(|(_, close)| *close == kind)
+          .map(|(_, close)| Self::Close(close))
+      })
+  }
+}
+
+/// The brackets among the children of a node
+struct Children<'tree> {
+  children: Vec<Node<'tree>>,
+  /// Child indices of the open and close bracket of each pair
+  pairs: Vec<(usize, usize)>,
+  unmatched: Vec<usize>,
+}
+
+impl<'tree> Children<'tree> {
+  fn new(node: Node<'tree>) -> Self {
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.children(&mut cursor).collect();
+    let mut pairs = Vec::new();
+    let mut unmatched = Vec::new();
+    let mut open: Vec<(usize, usize)> = Vec::new();
+
+    for (i, child) in children.iter().enumerate() {
+      match Token::of(child) {
+        Some(Token::Open(pair)) => open.push((i, pair)),
+        Some(Token::Close(close)) => {
+          // Brackets opened after the matching one are left unclosed
+          match open.iter().rposition(|(_, pair)| PAIRS[*pair].1 == close) {
+            Some(at) => {
+              let (start, _) = open[at];
+              for (j, pair) in open.drain(at..).skip(1) {
+                if pair != ANGLE {
+                  unmatched.push(j);
+                }
+              }
+              pairs.push((start, i));
+            },
+            None if close != PAIRS[ANGLE].1 => unmatched.push(i),
+            None => {},
+          }
+        },
+        None => {},
+      }
+    }
+    unmatched.extend(
+      open
+        .into_iter()
+        .filter(|(_, pair)| *pair != ANGLE)
+        .map(|(i, _)| i),
+    );
+
+    Self {
+      children,
+      pairs,
+      unmatched,
+    }
+  }
+
+  /// Number of this node's pairs around each child
+  fn depths(&self) -> Vec<usize> {
+    let mut deltas = vec![0isize; self.children.len() + 1];
+    for (open, close) in &self.pairs {
+      deltas[open + 1] += 1;
+      deltas[*close] -= 1;
+    }
+    let mut depth = 0;
+    deltas[..self.children.len()]
+      .iter()
+      .map(|delta| {
+        depth += delta;
+        depth as usize
+      })
+      .collect()
+  }
+
+  fn range(&self, i: usize) -> Range<usize> {
+    self.children[i].byte_range()
+  }
+}
+
+fn intersects(a: &Range<usize>, b: &Range<usize>) -> bool {
+  a.start < b.end && b.start < a.end
+}
+
+/// Collect the brackets of a tree intersecting a byte range
+///
+/// Only nodes intersecting the range are visited, so the cost depends on
+/// the size of the range and not of the tree.
+pub(crate) fn collect(root: Node, range: &Range<usize>, brackets: &mut Brackets) {
+  // Walk with a stack instead of recursing, as trees can be deep
+  let mut stack = vec![(root, 0)];
+  while let Some((node, depth)) = stack.pop() {
+    if node.child_count() == 0 {
+      continue;
+    }
+
+    let children = Children::new(node);
+    let depths = children.depths();
+    for (open, close) in &children.pairs {
+      let pair = BracketPair {
+        open: children.range(*open),
+        close: children.range(*close),
+        depth: depth + depths[*open],
+      };
+      if intersects(&pair.open, range) || intersects(&pair.close, range) {
+        brackets.pairs.push(pair);
+      }
+    }
+    brackets.unmatched.extend(
+      children
+        .unmatched
+        .iter()
+        .map(|i| children.range(*i))
+        .filter(|r| intersects(r, range)),
+    );
+
+    for (child, child_depth) in children.children.iter().zip(depths) {
+      if child.child_count() > 0 && intersects(&child.byte_range(), range) {
+        stack.push((*child, depth + child_depth));
+      }
+    }
+  }
+}
+
+/// Find the pair of brackets of the bracket at or just before a byte
+/// offset, or else the innermost pair enclosing it
+///
+/// Returns the open and close bracket.
+pub(crate) fn pair_at(root: Node, byte: usize) -> Option<(Range<usize>, Range<usize>)> {
+  for at in [Some(byte), byte.checked_sub(1)].into_iter().flatten() {
+    let Some(node) = root.descendant_for_byte_range(at, at + 1) else {
+      continue;
+    };
+    if Token::of(&node).is_none() || !node.byte_range().contains(&at) {
+      continue;
+    }
+    let Some(parent) = node.parent() else {
+      continue;
+    };
+    let children = Children::new(parent);
+    let pair = children
+      .pairs
+      .iter()
+      .find(|(open, close)| children.children[*open] == node || children.children[*close] == node);
+    if let Some((open, close)) = pair {
+      return Some((children.range(*open), children.range(*close)));
+    }
+  }
+
+  let mut node = root.descendant_for_byte_range(byte, byte);
+  while let Some(current) = node {
+    let children = Children::new(current);
+    let enclosing = children
+      .pairs
+      .iter()
+      .map(|(open, close)| (children.range(*open), children.range(*close)))
+      .filter(|(open, close)| open.end <= byte && byte <= close.start)
+      .min_by_key(|(open, close)| close.end - open.start);
+    if enclosing.is_some() {
+      return enclosing;
+    }
+    node = current.parent();
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::syntax::LanguageId;
+  use tree_sitter::{Parser, Tree};
+
+  fn parse(language: LanguageId, source: &str) -> Tree {
+    let mut parser = Parser::new();
+    parser
+      .set_language(&language.tree_sitter_language().unwrap())
+      .unwrap();
+    parser.parse(source, None).unwrap()
+  }
+
+  fn brackets(language: LanguageId, source: &str, range: Range<usize>) -> Brackets {
+    let tree = parse(language, source);
+    let mut brackets = Brackets::default();
+    collect(tree.root_node(), &range, &mut brackets);
+    brackets.sort();
+    brackets
+  }
+
+  #[test]
+  fn test_pairs_and_depth() {
+    let source = "fn f(v: Vec<u8>) { g(\"(\", [1]); }";
+    let all = brackets(LanguageId::Rust, source, 0..source.len());
+    let pairs: Vec<_> = all
+      .pairs
+      .iter()
+      .map(|p| (&source[p.open.start..p.close.end], p.depth))
+      .collect();
+    assert_eq!(
+      pairs,
+      [
+        ("(v: Vec<u8>)", 0),
+        ("<u8>", 1),
+        ("{ g(\"(\", [1]); }", 0),
+        ("(\"(\", [1])", 1),
+        ("[1]", 2),
+      ]
+    );
+    assert!(all.unmatched.is_empty());
+
+    // Only the brackets in the range, still at their depth
+    let start = source.find('[').unwrap();
+    let in_range = brackets(LanguageId::Rust, source, start..start + 1);
+    assert_eq!(in_range.pairs.len(), 1);
+    assert_eq!(in_range.pairs[0].depth, 2);
+  }
+
+  #[test]
+  fn test_unmatched() {
+    let source = "if (a < b) { f(]; }";
+    let brackets = brackets(LanguageId::JavaScript, source, 0..source.len());
+    let unmatched: Vec<_> = brackets.unmatched.iter().map(|r| r.start).collect();
+    assert!(unmatched.contains(&source.find(']').unwrap()));
+    assert!(!unmatched.contains(&source.find('<').unwrap()));
+    assert!(brackets.pairs.iter().any(|p| p.open.start == 3));
+  }
+
+  #[test]
+  fn test_pair_at() {
+    let source = "x = [1, (2, 3)]\n";
+    let tree = parse(LanguageId::Python, source);
+    let root = tree.root_node();
+
+    // On and just after a bracket
+    assert_eq!(pair_at(root, 4), Some((4..5, 14..15)));
+    assert_eq!(pair_at(root, 15), Some((4..5, 14..15)));
+    assert_eq!(pair_at(root, 13), Some((8..9, 13..14)));
+    // Inside a pair
+    assert_eq!(pair_at(root, 10), Some((8..9, 13..14)));
+    assert_eq!(pair_at(root, 0), None);
+  }
+}
@@ -1,14 +1,25 @@
 //! Core buffer implementation
 
 use ferrum_core::id::BufferId;
+use ferrum_core::constants;
+use ferrum_core::hash::HashingReader;
 use ferrum_core::prelude::*;
+use ferrum_core::task::TaskProgress;
 use parking_lot::RwLock;
 use ropey::Rope;
+use std::borrow::Cow;
 use std::path::PathBuf;
 
-use crate::edit::{Edit, EditKind};
-use crate::history::History;
+use crate::coords::{self, TextUnit};
+use crate::diff;
+use crate::edit::{Change, Edit, EditKind};
+use crate::format::{self, FileFormat, LineEnding};
+use crate::grammar::global_grammar_registry;
+use crate::history::{History, HistoryStep, UndoTree};
+use crate::large_file::{self, Viewport};
 use crate::position::Point;
+use crate::snapshot::BufferSnapshot;
+use crate::transaction::{LineEndingChange, Transaction, TransactionBuilder};
 
 /// A text buffer backed by a rope data structure
 pub struct Buffer {
@@ -19,6 +30,12 @@ pub struct Buffer {
   is_dirty: RwLock<bool>,
   language_id: RwLock<Option<String>>,
   version: RwLock<u64>,
+  format: RwLock<FileFormat>,
+  /// Content as last loaded from or saved to disk
+  saved_content: RwLock<Rope>,
+  /// Undo state the saved content is at, if known
+  saved_seq: RwLock<Option<usize>>,
+  large_file: bool,
 }
 
 impl Buffer {
@@ -32,35 +49,97 @@ impl Buffer {
       is_dirty: RwLock::new(false),
       language_id: RwLock::new(None),
       version: RwLock::new(0),
+      format: RwLock::new(FileFormat::default()),
+      saved_content: RwLock::new(Rope::new()),
+      saved_seq: RwLock::new(Some(0)),
+      large_file: false,
     }
   }
 
   /// Create a buffer with initial content
   pub fn with_content(content: &str) -> Self {
+    let rope = Rope::from_str(content);
     Self {
       id: BufferId::new(),
-      content: RwLock::new(Rope::from_str(content)),
+      content: RwLock::new(rope.clone()),
       history: RwLock::new(History::new()),
       file_path: RwLock::new(None),
       is_dirty: RwLock::new(false),
       language_id: RwLock::new(None),
       version: RwLock::new(0),
+      format: RwLock::new(FileFormat::from_text(content)),
+      saved_content: RwLock::new(rope),
+      saved_seq: RwLock::new(Some(0)),
+      large_file: false,
     }
   }
 
-  /// Create a buffer from a file
+  /// Create a buffer from a file's decoded UTF-8 content
   pub fn from_file(path: impl Into<PathBuf>, content: &str) -> Self {
+    Self::from_file_with_format(path, content, FileFormat::from_text(content))
+  }
+
+  /// Create a buffer from a file's raw bytes
+  ///
+  /// The encoding, BOM and line endings are detected and kept so that
+  /// `encode` writes the file back in the same format.
+  pub fn from_file_bytes(path: impl Into<PathBuf>, bytes: &[u8]) -> Self {
+    let (content, format) = format::decode(bytes);
+    Self::from_file_with_format(path, &content, format)
+  }
+
+  /// Load a file in large-file mode
+  ///
+  /// The file is streamed into the rope in chunks, with `progress` counting
+  /// bytes read. The buffer has no language, so it is never parsed, and its
+  /// undo history is capped at `LARGE_FILE_MAX_HISTORY_BYTES`. Returns the
+  /// buffer along with the `content_hash` of the bytes read.
+  pub fn load_large_file(
+    path: impl Into<PathBuf>,
+    progress: &TaskProgress,
+  ) -> Result<(Self, String)> {
+    let path = path.into();
+    let file = std::fs::File::open(&path)?;
+    progress.set_total(file.metadata()?.len());
+
+    let mut reader = HashingReader::new(file);
+    let (content, format) = large_file::load(&mut reader, progress)?;
+    let mut history = History::new();
+    history.set_max_bytes(constants::LARGE_FILE_MAX_HISTORY_BYTES);
+
+    let buffer = Self {
+      id: BufferId::new(),
+      content: RwLock::new(content.clone()),
+      history: RwLock::new(history),
+      file_path: RwLock::new(Some(path)),
+      is_dirty: RwLock::new(false),
+      language_id: RwLock::new(None),
+      version: RwLock::new(0),
+      format: RwLock::new(format),
+      saved_content: RwLock::new(content),
+      saved_seq: RwLock::new(Some(0)),
+      large_file: true,
+    };
+    Ok((buffer, reader.hash()))
+  }
+
+  fn from_file_with_format(path: impl Into<PathBuf>, content: &str, format: FileFormat) -> Self {
     let path = path.into();
     let language_id = Self::detect_language(&path);
+    let content = Rope::from_str(content);
 
     Self {
       id: BufferId::new(),
-      content: RwLock::new(Rope::from_str(content)),
+      content: RwLock::new(content.clone()),
       history: RwLock::new(History::new()),
       file_path: RwLock::new(Some(path)),
       is_dirty: RwLock::new(false),
       language_id: RwLock::new(language_id),
       version: RwLock::new(0),
+      format: RwLock::new(format),
+      saved_content: RwLock::new(content),
+      saved_seq: RwLock::new(Some(0)),
+      large_file: false,
     }
   }
 
@@ -77,11 +156,20 @@ impl Buffer {
   /// Set the file path
   pub fn set_file_path(&self, path: impl Into<PathBuf>) {
     let path = path.into();
-    let language = Self::detect_language(&path);
+    let language = if self.large_file {
+      None
+    } else {
+      Self::detect_language(&path)
+    };
     *self.file_path.write() = Some(path);
     *self.language_id.write() = language;
   }
 
+  /// Check if the buffer was loaded in large-file mode
+  pub fn is_large_file(&self) -> bool {
+    self.large_file
+  }
+
   /// Check if buffer has unsaved changes
   pub fn is_dirty(&self) -> bool {
     *self.is_dirty.read()
@@ -89,19 +177,160 @@ impl Buffer {
 
   /// Mark buffer as saved
   pub fn mark_saved(&self) {
+    *self.saved_content.write() = self.content.read().clone();
+    *self.saved_seq.write() = Some(self.history.read().current_seq());
     *self.is_dirty.write() = false;
   }
 
+  /// Mark buffer as having unsaved changes, e.g. after recovering it
+  pub fn mark_dirty(&self) {
+    *self.is_dirty.write() = true;
+  }
+
+  /// Get the content as last loaded from or saved to disk
+  pub fn saved_content(&self) -> String {
+    self.saved_content.read().to_string()
+  }
+
+  /// Reload the content from a new version of the file on disk
+  ///
+  /// Only the regions that differ are replaced, as one undoable transaction,
+  /// so anchors and selections outside them keep their place. The buffer is
+  /// clean afterwards, with `text` as its saved content. Returns the applied
+  /// changes.
+  pub fn reload(&self, text: &str, format: FileFormat) -> Result<Vec<Change>> {
+    let mut content = self.content.write();
+    let changes = self.apply_diff(&mut content, text)?;
+    *self.saved_content.write() = content.clone();
+    *self.saved_seq.write() = Some(self.history.read().current_seq());
+    *self.format.write() = format;
+    *self.is_dirty.write() = false;
+
+    Ok(changes)
+  }
+
+  /// Replace the whole content with `text`, touching only what differs
+  ///
+  /// The replacement is one undoable transaction. Returns the applied
+  /// changes.
+  pub fn set_text(&self, text: &str) -> Result<Vec<Change>> {
+    let mut content = self.content.write();
+    self.apply_diff(&mut content, text)
+  }
+
+  /// Record `text` as the content last seen on disk, without touching the
+  /// buffer content
+  pub fn set_saved_content(&self, text: &str) {
+    *self.saved_content.write() = Rope::from_str(text);
+    *self.saved_seq.write() = None;
+  }
+
+  fn apply_diff(&self, content: &mut Rope, text: &str) -> Result<Vec<Change>> {
+    let current: Cow<'_, str> = content.slice(..).into();
+    let mut builder = TransactionBuilder::new();
+    for edit in diff::diff(&current, text) {
+      builder.replace(edit.start, edit.end, edit.text);
+    }
+    drop(current);
+
+    self.apply_builder(content, builder)
+  }
+
   /// Get the language ID
   pub fn language_id(&self) -> Option<String> {
     self.language_id.read().clone()
   }
 
+  /// Get the on-disk format of the buffer
+  pub fn format(&self) -> FileFormat {
+    *self.format.read()
+  }
+
+  /// Set the on-disk format, e.g. one recorded in a previous session
+  pub fn set_format(&self, format: FileFormat) {
+    *self.format.write() = format;
+  }
+
+  /// Convert all line breaks to the given line ending
+  ///
+  /// The conversion is recorded as one undoable edit, and the line ending is
+  /// used for the rest of the buffer's lifetime, including lines typed later.
+  pub fn set_line_ending(&self, line_ending: LineEnding) -> Result<()> {
+    let text = self.to_string();
+    if let Cow::Owned(converted) = format::normalize_line_endings(&text, line_ending) {
+      let format = self.format();
+      self.transact(|tx| {
+        tx.replace(0, text.chars().count(), converted);
+        // So undo brings back the line ending along with the text
+        tx.set_line_endings(LineEndingChange {
+          old: format.line_ending,
+          old_mixed: format.mixed_line_endings,
+          new: line_ending,
+        });
+      })?;
+    }
+
+    let mut format = self.format.write();
+    format.line_ending = line_ending;
+    format.mixed_line_endings = false;
+    Ok(())
+  }
+
+  /// Set the encoding used when saving, by WHATWG label
+  pub fn set_encoding(&self, label: &str) -> Result<()> {
+    let encoding = format::encoding_for_label(label)?;
+    if encoding != self.format.read().encoding {
+      self.format.write().encoding = encoding;
+      *self.is_dirty.write() = true;
+    }
+    Ok(())
+  }
+
+  /// Set whether a byte order mark is written when saving
+  pub fn set_bom(&self, bom: bool) {
+    let mut format = self.format.write();
+    if format.bom != bom {
+      format.bom = bom;
+      *self.is_dirty.write() = true;
+    }
+  }
+
+  /// Encode the content for writing to disk in the buffer's format
+  pub fn encode(&self) -> Result<Vec<u8>> {
+    let content = self.content.read();
+    let text: Cow<'_, str> = content.slice(..).into();
+    format::encode(&text, &self.format.read())
+  }
+
   /// Get the current version
   pub fn version(&self) -> u64 {
     *self.version.read()
   }
 
+  /// Take an immutable snapshot of the current content
+  ///
+  /// The snapshot shares the rope instead of copying it. Content and version
+  /// are read under the same lock, so the snapshot is always consistent.
+  pub fn snapshot(&self) -> BufferSnapshot {
+    let content = self.content.read();
+    BufferSnapshot::new(
+      self.id,
+      content.clone(),
+      *self.version.read(),
+      self.language_id(),
+      self.file_path(),
+    )
+  }
+
+  /// Read a window of lines for rendering
+  ///
+  /// Only the requested lines are copied out, so this stays cheap on
+  /// buffers of any size.
+  pub fn viewport(&self, first_line: usize, line_count: usize) -> Viewport {
+    let content = self.content.read();
+    Viewport::read(&content, *self.version.read(), first_line, line_count)
+  }
+
   /// Get the total number of characters
   pub fn len_chars(&self) -> usize {
     self.content.read().len_chars()
@@ -142,164 +371,290 @@ impl Buffer {
 
   /// Insert text at a position
   pub fn insert(&self, char_idx: usize, text: &str) -> Result<()> {
-    let mut content = self.content.write();
-    if char_idx > content.len_chars() {
+    if char_idx > self.len_chars() {
       return Err(Error::InvalidPosition {
         line: 0,
         column: char_idx,
       });
     }
 
-    // Record edit for undo
-    let edit = Edit::new(
-      EditKind::Insert,
-      char_idx,
-      text.len(),
-      String::new(),
-      text.to_string(),
-    );
-
-    self.history.write().push(edit);
-    content.insert(char_idx, text);
-
-    *self.is_dirty.write() = true;
-    *self.version.write() += 1;
-
+    self.edit(char_idx, char_idx, text)?;
     Ok(())
   }
 
   /// Delete text in a range
   pub fn delete(&self, start: usize, end: usize) -> Result<String> {
+    self
+      .apply_single(start, end, "")
+      .map(|(deleted, _)| deleted)
+  }
+
+  /// Replace text in a range
+  pub fn replace(&self, start: usize, end: usize, text: &str) -> Result<String> {
+    self
+      .apply_single(start, end, text)
+      .map(|(old_text, _)| old_text)
+  }
+
+  /// Replace the chars in `start..end` as a single edit and return the
+  /// applied change
+  ///
+  /// Pure inserts and deletes are recorded as such, so consecutive typing
+  /// and backspacing merge into one undo step.
+  pub fn edit(&self, start: usize, end: usize, text: &str) -> Result<Change> {
+    self
+      .apply_single(start, end, text)
+      .map(|(_, change)| change)
+  }
+
+  fn apply_single(&self, start: usize, end: usize, text: &str) -> Result<(String, Change)> {
     let mut content = self.content.write();
     if end > content.len_chars() || start > end {
       return Err(Error::InvalidRange { start, end });
     }
 
-    let deleted = content.slice(start..end).to_string();
+    let old_text = content.slice(start..end).to_string();
+    let kind = match (old_text.is_empty(), text.is_empty()) {
+      (true, _) => EditKind::Insert,
+      (false, true) => EditKind::Delete,
+      (false, false) => EditKind::Replace,
+    };
+    let len = match kind {
+      EditKind::Insert => text.chars().count(),
+      _ => end - start,
+    };
 
     // Record edit for undo
-    let edit = Edit::new(
-      EditKind::Delete,
-      start,
-      end - start,
-      deleted.clone(),
-      String::new(),
-    );
-
+    let edit = Edit::new(kind, start, len, old_text.clone(), text.to_string());
+    let change = apply_edit(&mut content, &edit);
     self.history.write().push(edit);
-    content.remove(start..end);
 
     *self.is_dirty.write() = true;
     *self.version.write() += 1;
 
-    Ok(deleted)
+    Ok((old_text, change))
   }
 
-  /// Replace text in a range
-  pub fn replace(&self, start: usize, end: usize, text: &str) -> Result<String> {
+  /// Apply a group of range edits as one atomic, undoable step
+  ///
+  /// Every range passed to the builder refers to the buffer as it was before
+  /// the transaction, so callers can hand over multi-cursor edits or LSP text
+  /// edits without adjusting offsets themselves. The edits are recorded as a
+  /// single `Transaction` and the version is bumped once. Returns the applied
+  /// changes in order, ready for `SyntaxManager::apply_changes` and
+  /// `AnchorSet::apply_changes`.
+  pub fn transact<F>(&self, f: F) -> Result<Vec<Change>>
+  where
+    F: FnOnce(&mut TransactionBuilder),
+  {
+    let mut builder = TransactionBuilder::new();
+    f(&mut builder);
+
     let mut content = self.content.write();
-    if end > content.len_chars() || start > end {
-      return Err(Error::InvalidRange { start, end });
-    }
+    self.apply_builder(&mut content, builder)
+  }
 
-    let old_text = content.slice(start..end).to_string();
+  fn apply_builder(&self, content: &mut Rope, builder: TransactionBuilder) -> Result<Vec<Change>> {
+    let transaction = builder.build(content.len_chars(), |start, end| {
+      content.slice(start..end).to_string()
+    })?;
 
-    // Record edit for undo
-    let edit = Edit::new(
-      EditKind::Replace,
-      start,
-      end - start,
-      old_text.clone(),
-      text.to_string(),
-    );
+    if transaction.is_empty() {
+      return Ok(Vec::new());
+    }
 
-    self.history.write().push(edit);
-    content.remove(start..end);
-    content.insert(start, text);
+    let changes = replay_transaction(content, &transaction);
+    self.switch_line_endings(&transaction, false);
+    self.history.write().push_transaction(transaction);
 
     *self.is_dirty.write() = true;
     *self.version.write() += 1;
 
-    Ok(old_text)
+    Ok(changes)
   }
 
-  /// Undo the last edit
-  pub fn undo(&self) -> Option<Edit> {
-    let edit = self.history.write().undo()?;
+  /// Undo the last transaction
+  ///
+  /// Returns the changes that reverted it, or `None` if there was nothing to
+  /// undo.
+  pub fn undo(&self) -> Option<Vec<Change>> {
+    let transaction = self.history.write().undo()?;
     let mut content = self.content.write();
-
-    match edit.kind() {
-      EditKind::Insert => {
-        content.remove(edit.position()..edit.position() + edit.len());
-      },
-      EditKind::Delete => {
-        content.insert(edit.position(), edit.old_text());
-      },
-      EditKind::Replace => {
-        content.remove(edit.position()..edit.position() + edit.new_text().len());
-        content.insert(edit.position(), edit.old_text());
-      },
-    }
+    let changes = revert_transaction(&mut content, &transaction);
+    self.switch_line_endings(&transaction, true);
 
     *self.version.write() += 1;
-    Some(edit)
+    Some(changes)
   }
 
-  /// Redo the last undone edit
-  pub fn redo(&self) -> Option<Edit> {
-    let edit = self.history.write().redo()?;
+  /// Redo the last undone transaction
+  ///
+  /// Returns the changes that re-applied it, or `None` if there was nothing
+  /// to redo.
+  pub fn redo(&self) -> Option<Vec<Change>> {
+    let transaction = self.history.write().redo()?;
     let mut content = self.content.write();
+    let changes = replay_transaction(&mut content, &transaction);
+    self.switch_line_endings(&transaction, false);
+
+    *self.version.write() += 1;
+    Some(changes)
+  }
+
+  /// Get the shape of the undo tree
+  pub fn undo_tree(&self) -> UndoTree {
+    self.history.read().tree()
+  }
+
+  /// Move to the undo state with the given sequence number, across branches
+  ///
+  /// Returns the applied changes, or `None` if no such state is retained.
+  pub fn undo_goto(&self, seq: usize) -> Option<Vec<Change>> {
+    let steps = self.history.write().goto(seq)?;
+    Some(self.apply_history_steps(&steps))
+  }
 
-    match edit.kind() {
-      EditKind::Insert => {
-        content.insert(edit.position(), edit.new_text());
-      },
-      EditKind::Delete => {
-        content.remove(edit.position()..edit.position() + edit.len());
-      },
-      EditKind::Replace => {
-        content.remove(edit.position()..edit.position() + edit.old_text().len());
-        content.insert(edit.position(), edit.new_text());
-      },
+  /// Move to the state the buffer was in at the given time, in milliseconds
+  /// since the Unix epoch
+  ///
+  /// Returns the applied changes.
+  pub fn undo_goto_time(&self, timestamp: u64) -> Vec<Change> {
+    let steps = self.history.write().goto_time(timestamp);
+    self.apply_history_steps(&steps)
+  }
+
+  /// Get a copy of the undo history, e.g. to persist it
+  pub fn history(&self) -> History {
+    self.history.read().clone()
+  }
+
+  /// Replace the undo history with one restored from a previous session
+  ///
+  /// The history's current state must match the buffer content.
+  pub fn restore_history(&self, history: History) {
+    if !self.is_dirty() {
+      *self.saved_seq.write() = Some(history.current_seq());
+    }
+    *self.history.write() = history;
+  }
+
+  /// Get a copy of the undo history moved to the state of the saved content,
+  /// e.g. to persist it along with that content
+  ///
+  /// Unsaved edits are left on the redo path. Returns `None` if the saved
+  /// state isn't known or no longer retained.
+  pub fn saved_history(&self) -> Option<History> {
+    let mut history = self.history();
+    if self.is_dirty() {
+      let seq = (*self.saved_seq.read())?;
+      history.goto(seq)?;
+    }
+    Some(history)
+  }
+
+  fn apply_history_steps(&self, steps: &[HistoryStep]) -> Vec<Change> {
+    if steps.is_empty() {
+      return Vec::new();
+    }
+
+    let mut content = self.content.write();
+    let mut changes = Vec::new();
+    for step in steps {
+      changes.extend(match step {
+        HistoryStep::Undo(transaction) => {
+          self.switch_line_endings(transaction, true);
+          revert_transaction(&mut content, transaction)
+        },
+        HistoryStep::Redo(transaction) => {
+          self.switch_line_endings(transaction, false);
+          replay_transaction(&mut content, transaction)
+        },
+      });
     }
 
     *self.version.write() += 1;
-    Some(edit)
+    changes
+  }
+
+  /// Apply, or with `undo` revert, the line ending switch a transaction made
+  fn switch_line_endings(&self, transaction: &Transaction, undo: bool) {
+    let Some(change) = transaction.line_endings() else {
+      return;
+    };
+    let mut format = self.format.write();
+    if undo {
+      format.line_ending = change.old;
+      format.mixed_line_endings = change.old_mixed;
+    } else {
+      format.line_ending = change.new;
+      format.mixed_line_endings = false;
+    }
   }
 
   /// Convert a character index to a line/column position
   pub fn char_to_point(&self, char_idx: usize) -> Option<Point> {
-    let content = self.content.read();
-    if char_idx > content.len_chars() {
-      return None;
-    }
+    coords::char_to_point(&self.content.read(), char_idx)
+  }
 
-    let line = content.char_to_line(char_idx);
-    let line_start = content.line_to_char(line);
-    let column = char_idx - line_start;
+  /// Convert a line/column position to a character index, clamped to the
+  /// text
+  pub fn point_to_char(&self, point: Point) -> usize {
+    coords::point_to_char(&self.content.read(), point)
+  }
 
-    Some(Point::new(line, column))
+  /// Convert a char index to a byte offset
+  pub fn char_to_byte(&self, char_idx: usize) -> Option<usize> {
+    coords::from_char(&self.content.read(), char_idx, TextUnit::Byte)
   }
 
-  /// Convert a line/column position to a character index
-  pub fn point_to_char(&self, point: Point) -> Option<usize> {
-    let content = self.content.read();
-    if point.line >= content.len_lines() {
-      return None;
-    }
+  /// Convert a byte offset to a char index
+  pub fn byte_to_char(&self, byte_idx: usize) -> Option<usize> {
+    coords::to_char(&self.content.read(), byte_idx, TextUnit::Byte)
+  }
 
-    let line_start = content.line_to_char(point.line);
-    let line_len = content.line(point.line).len_chars();
+  /// Convert a char index to a UTF-16 code unit offset
+  pub fn char_to_utf16(&self, char_idx: usize) -> Option<usize> {
+    coords::from_char(&self.content.read(), char_idx, TextUnit::Utf16)
+  }
 
-    if point.column > line_len {
-      return None;
-    }
+  /// Convert a UTF-16 code unit offset to a char index
+  pub fn utf16_to_char(&self, utf16_idx: usize) -> Option<usize> {
+    coords::to_char(&self.content.read(), utf16_idx, TextUnit::Utf16)
+  }
 
-    Some(line_start + point.column)
+  /// Convert an offset in `unit` to a line/column position in the same unit
+  pub fn offset_to_point(&self, offset: usize, unit: TextUnit) -> Option<Point> {
+    coords::offset_to_point(&self.content.read(), offset, unit)
   }
 
-  /// Detect language from file extension
+  /// Convert a line/column position in `unit` to an offset in the same
+  /// unit, clamped to the text
+  pub fn point_to_offset(&self, point: Point, unit: TextUnit) -> usize {
+    coords::point_to_offset(&self.content.read(), point, unit)
+  }
+
+  /// Convert a line/column position from one column unit to another
+  pub fn convert_point(&self, point: Point, from: TextUnit, to: TextUnit) -> Option<Point> {
+    coords::convert_point(&self.content.read(), point, from, to)
+  }
+
+  /// Detect language from file name or extension
   fn detect_language(path: &PathBuf) -> Option<String> {
+    let registry = global_grammar_registry();
+    let runtime = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .and_then(|name| registry.by_file_name(name))
+      .or_else(|| {
+        path
+          .extension()
+          .and_then(|ext| ext.to_str())
+          .and_then(|ext| registry.by_extension(ext))
+      });
+    if let Some(language) = runtime {
+      return Some(language.name().into_owned());
+    }
+
     path
       .extension()
       .and_then(|ext| ext.to_str())
@@ -330,6 +685,63 @@ impl Buffer {
   }
 }
 
+/// Apply a recorded edit to the rope, returning the resulting change
+///
+/// Lengths are taken from the edit's text rather than `Edit::len`, so the
+/// same path serves forward edits and their inverses.
+fn apply_edit(content: &mut Rope, edit: &Edit) -> Change {
+  let start = edit.position();
+  let old_len = edit.old_text().chars().count();
+  let new_len = edit.new_text().chars().count();
+  let start_byte = content.char_to_byte(start);
+  let old_end_byte = start_byte + edit.old_text().len();
+  let new_end_byte = start_byte + edit.new_text().len();
+
+  let point_at = |content: &Rope, byte_idx| {
+    coords::byte_to_point(content, byte_idx).unwrap_or_default()
+  };
+  let start_point = point_at(content, start_byte);
+  let old_end_point = point_at(content, old_end_byte);
+
+  if old_len > 0 {
+    content.remove(start..start + old_len);
+  }
+  if new_len > 0 {
+    content.insert(start, edit.new_text());
+  }
+
+  Change {
+    start,
+    old_len,
+    new_len,
+    start_byte,
+    old_end_byte,
+    new_end_byte,
+    start_point,
+    old_end_point,
+    new_end_point: point_at(content, new_end_byte),
+  }
+}
+
+/// Revert a transaction's edits, last first
+fn revert_transaction(content: &mut Rope, transaction: &Transaction) -> Vec<Change> {
+  transaction
+    .edits()
+    .iter()
+    .rev()
+    .map(|edit| apply_edit(content, &edit.inverse()))
+    .collect()
+}
+
+/// Re-apply a transaction's edits in order
+fn replay_transaction(content: &mut Rope, transaction: &Transaction) -> Vec<Change> {
+  transaction
+    .edits()
+    .iter()
+    .map(|edit| apply_edit(content, edit))
+    .collect()
+}
+
 impl Default for Buffer {
   fn default() -> Self {
     Self::new()
@@ -347,6 +759,7 @@ impl ferrum_core::id::HasId for Buffer {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::anchor::AnchorSet;
 
   #[test]
   fn test_buffer_creation() {
@@ -406,6 +819,92 @@ mod tests {
     assert_eq!(buffer.to_string(), "Hello World");
   }
 
+  #[test]
+  fn test_undo_goto_other_branch() {
+    let buffer = Buffer::with_content("fn main() {}");
+    buffer.replace(3, 7, "start").unwrap();
+    let branch = buffer.undo_tree().current;
+
+    buffer.undo();
+    buffer.replace(3, 7, "run").unwrap();
+    assert_eq!(buffer.to_string(), "fn run() {}");
+
+    // Back out "run", then replay "start"
+    let changes = buffer.undo_goto(branch).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(buffer.to_string(), "fn start() {}");
+
+    assert!(buffer.undo_goto(buffer.undo_tree().root).is_some());
+    assert_eq!(buffer.to_string(), "fn main() {}");
+    assert!(buffer.undo_goto(99).is_none());
+  }
+
+  #[test]
+  fn test_transact_is_one_undo_step() {
+    let buffer = Buffer::with_content("let a = 1;\nlet b = 2;\n");
+    let version = buffer.version();
+
+    let changes = buffer
+      .transact(|tx| {
+        tx.replace(15, 16, "bb");
+        tx.replace(4, 5, "aa");
+      })
+      .unwrap();
+
+    assert_eq!(buffer.to_string(), "let aa = 1;\nlet bb = 2;\n");
+    assert_eq!(buffer.version(), version + 1);
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].start, 4);
+    // The second change is relative to the text after the first one
+    assert_eq!(changes[1].start, 16);
+
+    // Undo reverts the edits last to first
+    let undone = buffer.undo().unwrap();
+    assert_eq!(buffer.to_string(), "let a = 1;\nlet b = 2;\n");
+    assert_eq!(
+      (undone[0].start, undone[0].old_len, undone[0].new_len),
+      (16, 2, 1)
+    );
+    assert_eq!(
+      (undone[1].start, undone[1].old_len, undone[1].new_len),
+      (4, 2, 1)
+    );
+
+    buffer.redo();
+    assert_eq!(buffer.to_string(), "let aa = 1;\nlet bb = 2;\n");
+  }
+
+  #[test]
+  fn test_transact_rejects_overlapping_ranges() {
+    let buffer = Buffer::with_content("Hello, World!");
+
+    let result = buffer.transact(|tx| {
+      tx.delete(0, 5);
+      tx.replace(3, 7, "x");
+    });
+
+    assert!(result.is_err());
+    assert_eq!(buffer.to_string(), "Hello, World!");
+    assert!(!buffer.is_dirty());
+  }
+
+  #[test]
+  fn test_transact_multibyte_changes() {
+    let buffer = Buffer::with_content("héllo wörld");
+
+    let changes = buffer.transact(|tx| {
+      tx.insert(11, "!");
+      tx.delete(0, 2);
+    });
+
+    let changes = changes.unwrap();
+    assert_eq!(buffer.to_string(), "llo wörld!");
+    assert_eq!(changes[0].start_byte, 0);
+    assert_eq!(changes[0].old_end_byte, 3);
+    assert_eq!(changes[1].start, 9);
+    assert_eq!(changes[1].start_byte, 10);
+  }
+
   #[test]
   fn test_char_to_point() {
     let buffer = Buffer::with_content("Hello\nWorld\n!");
@@ -424,4 +923,59 @@ mod tests {
     let buffer = Buffer::from_file("/test/file.tsx", "");
     assert_eq!(buffer.language_id(), Some("typescript".to_string()));
   }
+
+  #[test]
+  fn test_crlf_file_round_trip() {
+    let buffer = Buffer::from_file_bytes("/test/file.rs", b"fn a() {}\r\nfn b() {}\r\n");
+    assert_eq!(buffer.format().line_ending, LineEnding::Crlf);
+
+    // A line typed with a bare LF is saved with the file's line ending
+    buffer.insert(11, "\n").unwrap();
+    assert_eq!(buffer.encode().unwrap(), b"fn a() {}\r\n\r\nfn b() {}\r\n");
+  }
+
+  #[test]
+  fn test_set_line_ending_is_undoable() {
+    let buffer = Buffer::with_content("a\r\nb\n");
+    assert!(buffer.format().mixed_line_endings);
+
+    buffer.set_line_ending(LineEnding::Lf).unwrap();
+    assert_eq!(buffer.to_string(), "a\nb\n");
+    assert!(!buffer.format().mixed_line_endings);
+
+    buffer.undo();
+    assert_eq!(buffer.to_string(), "a\r\nb\n");
+    assert!(buffer.format().mixed_line_endings);
+
+    buffer.redo();
+    assert_eq!(buffer.to_string(), "a\nb\n");
+    assert_eq!(buffer.format().line_ending, LineEnding::Lf);
+    assert!(!buffer.format().mixed_line_endings);
+
+    // A line ending switch on a file that didn't mix them
+    let buffer = Buffer::with_content("a\nb\n");
+    buffer.set_line_ending(LineEnding::Crlf).unwrap();
+    buffer.undo();
+    assert_eq!(buffer.format().line_ending, LineEnding::Lf);
+    assert!(!buffer.format().mixed_line_endings);
+  }
+
+  #[test]
+  fn test_reload_keeps_anchors_outside_changes() {
+    let buffer = Buffer::from_file("/tmp/test.rs", "fn a() {}\nfn b() {}\n");
+    let anchors = AnchorSet::new();
+    let anchor = anchors.create_left(13);
+
+    let changes = buffer
+      .reload("// header\nfn a() {}\nfn b() {}\n", FileFormat::default())
+      .unwrap();
+    anchors.apply_changes(&changes);
+
+    assert_eq!(anchors.offset(anchor), Some(23));
+    assert!(!buffer.is_dirty());
+    assert_eq!(buffer.saved_content(), buffer.to_string());
+
+    buffer.undo();
+    assert_eq!(buffer.to_string(), "fn a() {}\nfn b() {}\n");
+  }
 }
@@ -0,0 +1,253 @@
+//! Coordinate conversions between text units
+//!
+//! The buffer addresses text in chars, tree-sitter in bytes and LSP in UTF-16
+//! code units. These functions convert offsets and `(line, column)` points
+//! between the three over a `Rope`, with the column always counted in the
+//! same unit as the offset. Offsets that fall inside a character round down
+//! to its start; offsets past the end of the text return `None`. Points are
+//! clamped to the text instead, as LSP asks of positions.
+
+use ropey::{Rope, RopeSlice};
+use serde::{Deserialize, Serialize};
+
+use crate::position::Point;
+
+/// A unit for measuring text offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextUnit {
+  /// Unicode scalar values, as used by the buffer
+  Char,
+  /// UTF-8 bytes, as used by tree-sitter
+  Byte,
+  /// UTF-16 code units, as used by LSP
+  Utf16,
+}
+
+/// Get the length of the text in a unit
+pub fn len(rope: &Rope, unit: TextUnit) -> usize {
+  match unit {
+    TextUnit::Char => rope.len_chars(),
+    TextUnit::Byte => rope.len_bytes(),
+    TextUnit::Utf16 => rope.len_utf16_cu(),
+  }
+}
+
+/// Convert an offset in `unit` to a char offset
+pub fn to_char(rope: &Rope, offset: usize, unit: TextUnit) -> Option<usize> {
+  if offset > len(rope, unit) {
+    return None;
+  }
+
+  Some(match unit {
+    TextUnit::Char => offset,
+    TextUnit::Byte => rope.byte_to_char(offset),
+    TextUnit::Utf16 => rope.utf16_cu_to_char(offset),
+  })
+}
+
+/// Convert a char offset to an offset in `unit`
+pub fn from_char(rope: &Rope, char_idx: usize, unit: TextUnit) -> Option<usize> {
+  if char_idx > rope.len_chars() {
+    return None;
+  }
+
+  Some(match unit {
+    TextUnit::Char => char_idx,
+    TextUnit::Byte => rope.char_to_byte(char_idx),
+    TextUnit::Utf16 => rope.char_to_utf16_cu(char_idx),
+  })
+}
+
+/// Convert an offset from one unit to another
+pub fn convert(rope: &Rope, offset: usize, from: TextUnit, to: TextUnit) -> Option<usize> {
+  from_char(rope, to_char(rope, offset, from)?, to)
+}
+
+/// Convert an offset to a point whose column is in the same unit
+pub fn offset_to_point(rope: &Rope, offset: usize, unit: TextUnit) -> Option<Point> {
+  let char_idx = to_char(rope, offset, unit)?;
+  let line = rope.char_to_line(char_idx);
+  let line_start = from_char(rope, rope.line_to_char(line), unit)?;
+  let column = from_char(rope, char_idx, unit)? - line_start;
+
+  Some(Point::new(line, column))
+}
+
+/// Convert a point whose column is in `unit` to an offset in the same unit
+///
+/// A column past the end of the line is taken as its end, before the line
+/// break, and a line past the end of the text as the end of the text.
+pub fn point_to_offset(rope: &Rope, point: Point, unit: TextUnit) -> usize {
+  if point.line >= rope.len_lines() {
+    return len(rope, unit);
+  }
+
+  let line_start = rope.line_to_char(point.line);
+  let line = rope.line(point.line);
+  let line_end = line_start + line.len_chars() - line_break_len(line);
+  let start = from_char(rope, line_start, unit).unwrap_or_default();
+  let end = from_char(rope, line_end, unit).unwrap_or_default();
+  (start + point.column).min(end)
+}
+
+/// Number of chars the line break ending a line takes
+fn line_break_len(line: RopeSlice) -> usize {
+  let mut chars = line.chars_at(line.len_chars());
+  match (chars.prev(), chars.prev()) {
+    (Some('\n'), Some('\r')) => 2,
+    (Some('\n' | '\r'), _) => 1,
+    _ => 0,
+  }
+}
+
+/// Convert a point from one column unit to another
+pub fn convert_point(rope: &Rope, point: Point, from: TextUnit, to: TextUnit) -> Option<Point> {
+  let offset = point_to_offset(rope, point, from);
+  offset_to_point(rope, convert(rope, offset, from, to)?, to)
+}
+
+/// Convert a char offset to a point with a char column
+pub fn char_to_point(rope: &Rope, char_idx: usize) -> Option<Point> {
+  offset_to_point(rope, char_idx, TextUnit::Char)
+}
+
+/// Convert a point with a char column to a char offset
+pub fn point_to_char(rope: &Rope, point: Point) -> usize {
+  point_to_offset(rope, point, TextUnit::Char)
+}
+
+/// Convert a byte offset to a point with a byte column, as tree-sitter uses
+pub fn byte_to_point(rope: &Rope, byte_idx: usize) -> Option<Point> {
+  offset_to_point(rope, byte_idx, TextUnit::Byte)
+}
+
+/// Convert a char offset to a point with a UTF-16 column, as LSP uses
+pub fn char_to_utf16_point(rope: &Rope, char_idx: usize) -> Option<Point> {
+  offset_to_point(rope, from_char(rope, char_idx, TextUnit::Utf16)?, TextUnit::Utf16)
+}
+
+/// Convert a point with a UTF-16 column, as LSP uses, to a char offset
+pub fn utf16_point_to_char(rope: &Rope, point: Point) -> usize {
+  let offset = point_to_offset(rope, point, TextUnit::Utf16);
+  rope.utf16_cu_to_char(offset)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  const UNITS: [TextUnit; 3] = [TextUnit::Char, TextUnit::Byte, TextUnit::Utf16];
+
+  #[test]
+  fn test_emoji_offsets() {
+    // U+1F600 is 1 char, 4 bytes and 2 UTF-16 code units
+    let rope = Rope::from_str("a😀b\nc");
+
+    assert_eq!(from_char(&rope, 2, TextUnit::Byte), Some(5));
+    assert_eq!(from_char(&rope, 2, TextUnit::Utf16), Some(3));
+    assert_eq!(to_char(&rope, 3, TextUnit::Utf16), Some(2));
+    assert_eq!(char_to_utf16_point(&rope, 4), Some(Point::new(1, 0)));
+    assert_eq!(utf16_point_to_char(&rope, Point::new(0, 3)), 2);
+    assert_eq!(byte_to_point(&rope, 6), Some(Point::new(0, 6)));
+  }
+
+  #[test]
+  fn test_out_of_range() {
+    let rope = Rope::from_str("ab\r\ncd");
+
+    assert_eq!(to_char(&rope, 7, TextUnit::Char), None);
+    assert_eq!(point_to_char(&rope, Point::new(0, 2)), 2);
+    assert_eq!(point_to_char(&rope, Point::new(1, 2)), 6);
+    // Points past the end of a line, or inside its line break, are its end
+    assert_eq!(point_to_char(&rope, Point::new(0, 3)), 2);
+    assert_eq!(point_to_char(&rope, Point::new(0, 9)), 2);
+    assert_eq!(point_to_offset(&rope, Point::new(1, 9), TextUnit::Byte), 6);
+    // Points past the last line are the end of the text
+    assert_eq!(point_to_char(&rope, Point::new(2, 0)), 6);
+    assert_eq!(utf16_point_to_char(&rope, Point::new(5, 1)), 6);
+  }
+
+  /// The char offset a point at `char_idx` is taken as, which is the end of
+  /// the line for one between a `\r` and `\n`
+  fn clamped(rope: &Rope, char_idx: usize) -> usize {
+    let inside_crlf = char_idx > 0
+      && rope.get_char(char_idx - 1) == Some('\r')
+      && rope.get_char(char_idx) == Some('\n');
+    char_idx - inside_crlf as usize
+  }
+
+  fn text_strategy() -> impl Strategy<Value = String> {
+    // ASCII, CJK, emoji (surrogate pairs in UTF-16), combining marks, line
+    // breaks, and separators LSP doesn't break lines at
+    let pieces = prop::sample::select(vec![
+      "a", "Z", " ", "\n", "\r\n", "\r", "日", "本", "語", "😀", "👍🏽", "e\u{301}", "\u{308}", "é",
+      "\u{2028}", "\u{2029}", "\u{85}", "\u{b}", "\u{c}",
+    ]);
+    prop::collection::vec(pieces, 0..64).prop_map(|v| v.concat())
+  }
+
+  proptest! {
+    #[test]
+    fn prop_offsets_round_trip(text in text_strategy()) {
+      let rope = Rope::from_str(&text);
+      for char_idx in 0..=rope.len_chars() {
+        for unit in UNITS {
+          let offset = from_char(&rope, char_idx, unit).unwrap();
+          prop_assert_eq!(to_char(&rope, offset, unit), Some(char_idx));
+        }
+      }
+    }
+
+    #[test]
+    fn prop_points_round_trip(text in text_strategy()) {
+      let rope = Rope::from_str(&text);
+      for char_idx in 0..=rope.len_chars() {
+        let expected = clamped(&rope, char_idx);
+        for unit in UNITS {
+          let offset = from_char(&rope, char_idx, unit).unwrap();
+          let point = offset_to_point(&rope, offset, unit).unwrap();
+          prop_assert_eq!(
+            point_to_offset(&rope, point, unit),
+            from_char(&rope, expected, unit).unwrap()
+          );
+        }
+
+        let point = char_to_utf16_point(&rope, char_idx).unwrap();
+        prop_assert_eq!(utf16_point_to_char(&rope, point), expected);
+      }
+    }
+
+    #[test]
+    fn prop_lines_break_like_lsp(text in text_strategy()) {
+      let rope = Rope::from_str(&text);
+      let breaks = text.replace("\r\n", "\n").matches(['\n', '\r']).count();
+      prop_assert_eq!(rope.len_lines(), breaks + 1);
+    }
+
+    #[test]
+    fn prop_matches_std_counts(text in text_strategy()) {
+      let rope = Rope::from_str(&text);
+      let char_idx = rope.len_chars();
+      let std_utf16: usize = text.chars().map(char::len_utf16).sum();
+
+      prop_assert_eq!(from_char(&rope, char_idx, TextUnit::Byte), Some(text.len()));
+      prop_assert_eq!(from_char(&rope, char_idx, TextUnit::Utf16), Some(std_utf16));
+    }
+
+    #[test]
+    fn prop_convert_point_preserves_line(text in text_strategy()) {
+      let rope = Rope::from_str(&text);
+      for char_idx in 0..=rope.len_chars() {
+        let point = char_to_point(&rope, char_idx).unwrap();
+        let expected = char_to_point(&rope, clamped(&rope, char_idx));
+        for unit in UNITS {
+          let converted = convert_point(&rope, point, TextUnit::Char, unit).unwrap();
+          prop_assert_eq!(converted.line, point.line);
+          prop_assert_eq!(convert_point(&rope, converted, unit, TextUnit::Char), expected);
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,303 @@
+//! Text diffing and three-way merging
+//!
+//! `diff` turns two versions of a text into the smallest practical set of
+//! replacements, so reloading a file only touches what changed and anchors
+//! elsewhere keep their place. `merge3` combines two edited versions of a
+//! common base line by line, marking the places where both sides changed
+//! the same lines.
+
+use similar::{DiffTag, TextDiff};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Replacements up to this many chars on either side are refined per char
+const CHAR_REFINE_LIMIT: usize = 2000;
+
+/// Give up looking for a minimal line diff after this long
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A replacement of a char range of the old text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEdit {
+  /// Start of the replaced range, in chars of the old text
+  pub start: usize,
+  /// End of the replaced range, in chars of the old text
+  pub end: usize,
+  /// Replacement text
+  pub text: String,
+}
+
+/// Compute the edits that turn `old` into `new`
+///
+/// Lines are diffed first; small changed regions are then refined per char.
+/// Edits are sorted and do not overlap.
+pub fn diff(old: &str, new: &str) -> Vec<DiffEdit> {
+  let lines = TextDiff::configure()
+    .timeout(DIFF_TIMEOUT)
+    .diff_lines(old, new);
+  let old_lines = lines.old_slices();
+  let new_lines = lines.new_slices();
+
+  // Char offset of each old line start
+  let mut line_starts = Vec::with_capacity(old_lines.len() + 1);
+  let mut offset = 0;
+  line_starts.push(0);
+  for line in old_lines {
+    offset += line.chars().count();
+    line_starts.push(offset);
+  }
+
+  let mut edits = Vec::new();
+  for op in lines.ops() {
+    let (tag, old_range, new_range) = op.as_tag_tuple();
+    if tag == DiffTag::Equal {
+      continue;
+    }
+
+    let start = line_starts[old_range.start];
+    let old_text = old_lines[old_range].concat();
+    let new_text = new_lines[new_range].concat();
+
+    if tag == DiffTag::Replace
+      && old_text.len() <= CHAR_REFINE_LIMIT
+      && new_text.len() <= CHAR_REFINE_LIMIT
+    {
+      edits.extend(diff_chars(&old_text, &new_text, start));
+    } else {
+      edits.push(DiffEdit {
+        start,
+        end: start + old_text.chars().count(),
+        text: new_text,
+      });
+    }
+  }
+
+  edits
+}
+
+/// Diff two short texts per char, offsetting the edits by `base`
+fn diff_chars(old: &str, new: &str, base: usize) -> Vec<DiffEdit> {
+  let chars = TextDiff::from_chars(old, new);
+  let new_chars = chars.new_slices();
+
+  chars
+    .ops()
+    .iter()
+    .map(|op| op.as_tag_tuple())
+    .filter(|(tag, _, _)| *tag != DiffTag::Equal)
+    .map(|(_, old_range, new_range)| DiffEdit {
+      start: base + old_range.start,
+      end: base + old_range.end,
+      text: new_chars[new_range].concat(),
+    })
+    .collect()
+}
+
+/// The result of a three-way merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+  /// Merged text, with conflict markers around conflicting regions
+  pub text: String,
+  /// Number of conflicting regions
+  pub conflicts: usize,
+}
+
+impl Merge {
+  /// Check if both sides merged without conflicts
+  pub fn is_clean(&self) -> bool {
+    self.conflicts == 0
+  }
+}
+
+/// A changed run of base lines and the lines replacing it
+struct Hunk<'a> {
+  base: Range<usize>,
+  lines: &'a [&'a str],
+}
+
+/// Merge two edited versions of `base` line by line
+///
+/// Changes made on only one side are taken as is. Where both sides changed
+/// the same or adjacent lines differently, both versions are kept between
+/// git-style conflict markers labelled `ours_label` and `theirs_label`.
+pub fn merge3(base: &str, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str) -> Merge {
+  let ours_diff = TextDiff::configure()
+    .timeout(DIFF_TIMEOUT)
+    .diff_lines(base, ours);
+  let theirs_diff = TextDiff::configure()
+    .timeout(DIFF_TIMEOUT)
+    .diff_lines(base, theirs);
+  let base_lines = ours_diff.old_slices();
+  let ours_hunks = hunks(ours_diff.ops(), ours_diff.new_slices());
+  let theirs_hunks = hunks(theirs_diff.ops(), theirs_diff.new_slices());
+
+  let mut text = String::with_capacity(base.len().max(ours.len()).max(theirs.len()));
+  let mut conflicts = 0;
+  let (mut i, mut j, mut pos) = (0, 0, 0);
+
+  loop {
+    let start = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+      (Some(a), Some(b)) => a.base.start.min(b.base.start),
+      (Some(a), None) => a.base.start,
+      (None, Some(b)) => b.base.start,
+      (None, None) => break,
+    };
+    base_lines[pos..start].iter().for_each(|l| text.push_str(l));
+
+    // Grow the region until no hunk on either side touches its edges
+    let (first_ours, first_theirs) = (i, j);
+    let mut end = start;
+    loop {
+      let before = (i, j);
+      while let Some(h) = ours_hunks.get(i).filter(|h| h.base.start <= end) {
+        end = end.max(h.base.end);
+        i += 1;
+      }
+      while let Some(h) = theirs_hunks.get(j).filter(|h| h.base.start <= end) {
+        end = end.max(h.base.end);
+        j += 1;
+      }
+      if (i, j) == before {
+        break;
+      }
+    }
+
+    let ours_group = &ours_hunks[first_ours..i];
+    let theirs_group = &theirs_hunks[first_theirs..j];
+    let region = start..end;
+    if theirs_group.is_empty() {
+      text.push_str(&apply(base_lines, region, ours_group));
+    } else if ours_group.is_empty() {
+      text.push_str(&apply(base_lines, region, theirs_group));
+    } else {
+      let ours_text = apply(base_lines, region.clone(), ours_group);
+      let theirs_text = apply(base_lines, region, theirs_group);
+      if ours_text == theirs_text {
+        text.push_str(&ours_text);
+      } else {
+        conflicts += 1;
+        push_conflict(&mut text, &ours_text, &theirs_text, ours_label, theirs_label);
+      }
+    }
+
+    pos = end;
+  }
+  base_lines[pos..].iter().for_each(|l| text.push_str(l));
+
+  Merge { text, conflicts }
+}
+
+fn hunks<'a>(ops: &[similar::DiffOp], new_lines: &'a [&'a str]) -> Vec<Hunk<'a>> {
+  ops
+    .iter()
+    .map(|op| op.as_tag_tuple())
+    .filter(|(tag, _, _)| *tag != DiffTag::Equal)
+    .map(|(_, base, new)| Hunk {
+      base,
+      lines: &new_lines[new],
+    })
+    .collect()
+}
+
+/// Apply one side's hunks to a region of the base lines
+fn apply(base_lines: &[&str], region: Range<usize>, hunks: &[Hunk<'_>]) -> String {
+  let mut out = String::new();
+  let mut pos = region.start;
+  for hunk in hunks {
+    base_lines[pos..hunk.base.start].iter().for_each(|l| out.push_str(l));
+    hunk.lines.iter().for_each(|l| out.push_str(l));
+    pos = hunk.base.end;
+  }
+  base_lines[pos..region.end].iter().for_each(|l| out.push_str(l));
+  out
+}
+
+fn push_conflict(out: &mut String, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str) {
+  let push_section = |out: &mut String, text: &str| {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+      out.push('\n');
+    }
+  };
+
+  out.push_str(&format!("<<<<<<< {}\n", ours_label));
+  push_section(out, ours);
+  out.push_str("=======\n");
+  push_section(out, theirs);
+  out.push_str(&format!(">>>>>>> {}\n", theirs_label));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn apply_edits(old: &str, edits: &[DiffEdit]) -> String {
+    let chars: Vec<char> = old.chars().collect();
+    let mut out = String::new();
+    let mut pos = 0;
+    for edit in edits {
+      out.extend(&chars[pos..edit.start]);
+      out.push_str(&edit.text);
+      pos = edit.end;
+    }
+    out.extend(&chars[pos..]);
+    out
+  }
+
+  #[test]
+  fn test_diff_is_minimal_within_line() {
+    let old = "fn main() {\n  println!(\"héllo\");\n}\n";
+    let new = "fn main() {\n  println!(\"héllo world\");\n}\n";
+
+    let edits = diff(old, new);
+    assert_eq!(
+      edits,
+      vec![DiffEdit {
+        start: 29,
+        end: 29,
+        text: " world".to_string(),
+      }]
+    );
+    assert_eq!(apply_edits(old, &edits), new);
+  }
+
+  #[test]
+  fn test_diff_round_trips() {
+    let old = "a\nb\nc\nd\n";
+    let new = "a\nB\nc\nd\ne";
+    assert_eq!(apply_edits(old, &diff(old, new)), new);
+    assert!(diff(old, old).is_empty());
+  }
+
+  #[test]
+  fn test_merge3_disjoint_changes() {
+    let base = "one\ntwo\nthree\nfour\nfive\n";
+    let ours = "ONE\ntwo\nthree\nfour\nfive\n";
+    let theirs = "one\ntwo\nthree\nfour\nFIVE\n";
+
+    let merge = merge3(base, ours, theirs, "buffer", "disk");
+    assert!(merge.is_clean());
+    assert_eq!(merge.text, "ONE\ntwo\nthree\nfour\nFIVE\n");
+  }
+
+  #[test]
+  fn test_merge3_conflict() {
+    let base = "a\nb\nc\n";
+    let ours = "a\nours\nc\n";
+    let theirs = "a\ntheirs\nc\n";
+
+    let merge = merge3(base, ours, theirs, "buffer", "disk");
+    assert_eq!(merge.conflicts, 1);
+    assert_eq!(
+      merge.text,
+      "a\n<<<<<<< buffer\nours\n=======\ntheirs\n>>>>>>> disk\nc\n"
+    );
+  }
+
+  #[test]
+  fn test_merge3_same_change_on_both_sides() {
+    let merge = merge3("a\nb\n", "a\nx\n", "a\nx\n", "buffer", "disk");
+    assert!(merge.is_clean());
+    assert_eq!(merge.text, "a\nx\n");
+  }
+}
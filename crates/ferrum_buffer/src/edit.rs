@@ -2,6 +2,8 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::position::Point;
+
 /// Kind of edit operation
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EditKind {
@@ -11,6 +13,8 @@ pub enum EditKind {
 }
 
 /// A single edit operation
+///
+/// Positions and lengths are in chars.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Edit {
   kind: EditKind,
@@ -74,7 +78,7 @@ impl Edit {
       EditKind::Insert => Self {
         kind: EditKind::Delete,
         position: self.position,
-        len: self.new_text.len(),
+        len: self.new_text.chars().count(),
         old_text: self.new_text.clone(),
         new_text: String::new(),
       },
@@ -88,7 +92,7 @@ impl Edit {
       EditKind::Replace => Self {
         kind: EditKind::Replace,
         position: self.position,
-        len: self.new_text.len(),
+        len: self.new_text.chars().count(),
         old_text: self.new_text.clone(),
         new_text: self.old_text.clone(),
       },
@@ -105,14 +109,14 @@ impl Edit {
     match self.kind {
       EditKind::Insert => {
         // Merge consecutive inserts
-        other.position == self.position + self.new_text.len()
-          && self.new_text.len() < 50
+        other.position == self.position + self.new_text.chars().count()
+          && self.new_text.chars().count() < 50
           && !self.new_text.ends_with('\n')
       },
       EditKind::Delete => {
         // Merge consecutive deletes (backspace)
         other.position + other.len == self.position
-          && self.old_text.len() < 50
+          && self.old_text.chars().count() < 50
           && !other.old_text.ends_with('\n')
       },
       EditKind::Replace => false,
@@ -124,12 +128,12 @@ impl Edit {
     match self.kind {
       EditKind::Insert => {
         self.new_text.push_str(&other.new_text);
-        self.len = self.new_text.len();
+        self.len = self.new_text.chars().count();
       },
       EditKind::Delete => {
         self.position = other.position;
         self.old_text = other.old_text + &self.old_text;
-        self.len = self.old_text.len();
+        self.len = self.old_text.chars().count();
       },
       EditKind::Replace => {
         // Should not happen due to can_merge_with
@@ -138,6 +142,46 @@ impl Edit {
   }
 }
 
+/// A change that has been applied to a buffer
+///
+/// Carries both char and byte coordinates so it can be fed directly to
+/// `AnchorSet::apply_changes` and `SyntaxManager::apply_changes`. In a list of
+/// changes, each one is relative to the text produced by the ones before it.
+/// Points have byte columns, as tree-sitter expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+  /// Start char offset
+  pub start: usize,
+  /// Number of chars removed
+  pub old_len: usize,
+  /// Number of chars inserted
+  pub new_len: usize,
+  /// Start byte offset
+  pub start_byte: usize,
+  /// End byte offset of the removed text
+  pub old_end_byte: usize,
+  /// End byte offset of the inserted text
+  pub new_end_byte: usize,
+  /// Start point
+  pub start_point: Point,
+  /// End point of the removed text, in the text before the change
+  pub old_end_point: Point,
+  /// End point of the inserted text
+  pub new_end_point: Point,
+}
+
+impl Change {
+  /// Get the old end char offset
+  pub fn old_end(&self) -> usize {
+    self.start + self.old_len
+  }
+
+  /// Get the new end char offset
+  pub fn new_end(&self) -> usize {
+    self.start + self.new_len
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -162,4 +206,16 @@ mod tests {
 
     assert_eq!(edit1.new_text(), "Hi");
   }
+
+  #[test]
+  fn test_edit_merge_multibyte() {
+    let mut edit1 = Edit::new(EditKind::Insert, 0, 1, String::new(), "日".to_string());
+    let edit2 = Edit::new(EditKind::Insert, 1, 1, String::new(), "本".to_string());
+
+    assert!(edit1.can_merge_with(&edit2));
+    edit1.merge(edit2);
+
+    assert_eq!(edit1.len(), 2);
+    assert_eq!(edit1.inverse().len(), 2);
+  }
 }
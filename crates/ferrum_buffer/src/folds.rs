@@ -0,0 +1,263 @@
+//! Syntax-aware fold ranges
+//!
+//! A language's folds query marks the nodes that can be folded with
+//! `@fold`. Comments marked `@fold.comment` on consecutive lines fold as one
+//! block, as do imports marked `@fold.imports`. Comments reading
+//! `#region`/`#endregion`, or `region`/`endregion`, after their comment
+//! leader fold the lines between them.
+
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use tree_sitter::{Node, Query, QueryCursor};
+
+const FOLD_CAPTURE: &str = "fold";
+const COMMENT_CAPTURE: &str = "fold.comment";
+const IMPORTS_CAPTURE: &str = "fold.imports";
+
+/// What a fold range covers, as LSP folding range kinds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FoldKind {
+  Comment,
+  Imports,
+  Region,
+}
+
+/// A foldable range of lines
+///
+/// Folding hides the lines after `start_line`, up to and including
+/// `end_line`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FoldRange {
+  pub start_line: usize,
+  pub end_line: usize,
+  /// `None` for code
+  pub kind: Option<FoldKind>,
+}
+
+impl FoldRange {
+  pub fn new(start_line: usize, end_line: usize, kind: Option<FoldKind>) -> Self {
+    Self {
+      start_line,
+      end_line,
+      kind,
+    }
+  }
+}
+
+/// Sort fold ranges and keep one per start line, the longest
+pub fn dedup_fold_ranges(ranges: &mut Vec<FoldRange>) {
+  ranges.retain(|r| r.start_line < r.end_line);
+  ranges.sort_by_key(|r| (r.start_line, std::cmp::Reverse(r.end_line)));
+  ranges.dedup_by_key(|r| r.start_line);
+}
+
+/// A region marker comment
+enum Marker {
+  Start,
+  End,
+}
+
+impl Marker {
+  /// Parse a comment's text as a region marker
+  fn parse(comment: &str) -> Option<Self> {
+    let text = comment.trim_start_matches(|c: char| "/*#-;!<%".contains(c) || c.is_whitespace());
+    let text = text.strip_prefix('#').unwrap_or(text);
+    let (marker, rest) = if let Some(rest) = text.strip_prefix("endregion") {
+      (Self::End, rest)
+    } else {
+      (Self::Start, text.strip_prefix("region")?)
+    };
+    match rest.chars().next() {
+      Some(c) if c.is_alphanumeric() || c == '_' => None,
+      _ => Some(marker),
+    }
+  }
+}
+
+/// A compiled folds query
+pub(crate) struct FoldsQuery {
+  query: Query,
+  fold_capture: Option<u32>,
+  comment_capture: Option<u32>,
+  imports_capture: Option<u32>,
+}
+
+impl FoldsQuery {
+  pub(crate) fn new(language: &tree_sitter::Language, source: &str) -> Result<Self> {
+    let query = Query::new(language, source)
+      .map_err(|e| Error::Internal(format!("Failed to compile folds query: {}", e)))?;
+
+    Ok(Self {
+      fold_capture: query.capture_index_for_name(FOLD_CAPTURE),
+      comment_capture: query.capture_index_for_name(COMMENT_CAPTURE),
+      imports_capture: query.capture_index_for_name(IMPORTS_CAPTURE),
+      query,
+    })
+  }
+
+  /// Find the fold ranges of a tree, sorted by start line
+  pub(crate) fn folds(&self, root: Node, source: &[u8]) -> Vec<FoldRange> {
+    let mut ranges = Vec::new();
+    let mut comments = Vec::new();
+    let mut imports = Vec::new();
+    let mut cursor = QueryCursor::new();
+
+    for m in cursor.matches(&self.query, root, source) {
+      for capture in m.captures {
+        let index = Some(capture.index);
+        if index == self.fold_capture {
+          let (start, end) = lines(&capture.node);
+          ranges.push(FoldRange::new(start, end, None));
+        } else if index == self.comment_capture {
+          comments.push(capture.node);
+        } else if index == self.imports_capture {
+          imports.push(lines(&capture.node));
+        }
+      }
+    }
+    comments.sort_by_key(|node| node.start_byte());
+    comments.dedup_by_key(|node| node.id());
+    imports.sort();
+
+    let mut blocks = Vec::new();
+    let mut regions = Vec::new();
+    for node in comments {
+      let (start, end) = lines(&node);
+      match Marker::parse(node.utf8_text(source).unwrap_or_default()) {
+        Some(Marker::Start) => regions.push(start),
+        Some(Marker::End) => {
+          if let Some(region_start) = regions.pop() {
+            ranges.push(FoldRange::new(region_start, end, Some(FoldKind::Region)));
+          }
+        },
+        // A comment after code on its line is not part of a block
+        None if starts_line(&node, source) => merge_line_span(&mut blocks, start, end),
+        None => {},
+      }
+    }
+
+    let mut import_blocks = Vec::new();
+    for (start, end) in imports {
+      merge_line_span(&mut import_blocks, start, end);
+    }
+
+    for (spans, kind) in [
+      (blocks, FoldKind::Comment),
+      (import_blocks, FoldKind::Imports),
+    ] {
+      ranges.extend(
+        spans
+          .into_iter()
+          .map(|(start, end)| FoldRange::new(start, end, Some(kind))),
+      );
+    }
+    dedup_fold_ranges(&mut ranges);
+    ranges
+  }
+}
+
+/// Get the first and last line of a node
+///
+/// A node ending at the start of a line, after its line break, ends on the
+/// line before.
+fn lines(node: &Node) -> (usize, usize) {
+  let start = node.start_position().row;
+  let end = node.end_position();
+  match end.column == 0 && end.row > start {
+    true => (start, end.row - 1),
+    false => (start, end.row),
+  }
+}
+
+/// Add a line span, joining it to the last one if it continues it
+fn merge_line_span(spans: &mut Vec<(usize, usize)>, start: usize, end: usize) {
+  match spans.last_mut() {
+    Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+    _ => spans.push((start, end)),
+  }
+}
+
+/// Check if only whitespace comes before a node on its line
+fn starts_line(node: &Node, source: &[u8]) -> bool {
+  let start = node.start_byte();
+  let column = node.start_position().column;
+  source[start - column..start]
+    .iter()
+    .all(|b| b.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::syntax::LanguageId;
+  use tree_sitter::Parser;
+
+  fn folds(language: LanguageId, source: &str) -> Vec<FoldRange> {
+    let ts_lang = language.tree_sitter_language().unwrap();
+    let mut parser = Parser::new();
+    parser.set_language(&ts_lang).unwrap();
+    let tree = parser.parse(source, None).unwrap();
+
+    let query = FoldsQuery::new(&ts_lang, language.fold_query().unwrap()).unwrap();
+    query.folds(tree.root_node(), source.as_bytes())
+  }
+
+  #[test]
+  fn test_rust_folds() {
+    let source = "\
+use std::fmt;
+use std::io;
+
+// First line
+// second line
+fn main() {
+    let x = 1; // trailing
+    // inner
+}
+";
+    assert_eq!(
+      folds(LanguageId::Rust, source),
+      vec![
+        FoldRange::new(0, 1, Some(FoldKind::Imports)),
+        FoldRange::new(3, 4, Some(FoldKind::Comment)),
+        FoldRange::new(5, 8, None),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_region_markers() {
+    let source = "\
+# region: helpers
+def a():
+    pass
+
+# regional settings
+# endregion
+x = 1
+";
+    assert_eq!(
+      folds(LanguageId::Python, source),
+      vec![
+        FoldRange::new(0, 5, Some(FoldKind::Region)),
+        FoldRange::new(1, 2, None),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_marker_parsing() {
+    assert!(matches!(
+      Marker::parse("// #region Helpers"),
+      Some(Marker::Start)
+    ));
+    assert!(matches!(Marker::parse("//region"), Some(Marker::Start)));
+    assert!(matches!(
+      Marker::parse("<!-- #endregion -->"),
+      Some(Marker::End)
+    ));
+    assert!(Marker::parse("// regional").is_none());
+    assert!(Marker::parse("// the region").is_none());
+  }
+}
@@ -0,0 +1,328 @@
+//! File format handling - encoding, BOM and line endings
+//!
+//! The buffer always holds decoded text verbatim, including `\r\n`. The
+//! detected format is kept next to it so that saving writes the file back
+//! the way it was read: same encoding, same BOM, same line endings.
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// A line ending style
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+  /// `\n`
+  #[default]
+  Lf,
+  /// `\r\n`
+  Crlf,
+  /// `\r`
+  Cr,
+}
+
+impl LineEnding {
+  /// Get the line ending characters
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LineEnding::Lf => "\n",
+      LineEnding::Crlf => "\r\n",
+      LineEnding::Cr => "\r",
+    }
+  }
+
+  /// Detect the dominant line ending of a text
+  ///
+  /// Returns the most frequent style and whether more than one style occurs.
+  /// Text without line breaks is reported as `Lf`.
+  pub fn detect(text: &str) -> (LineEnding, bool) {
+    let mut counts = LineEndingCounts::default();
+    counts.add(text);
+    counts.finish()
+  }
+}
+
+/// Line breaks of each style in text read in pieces, with a `\r\n` split
+/// between two pieces counted once
+#[derive(Debug, Default)]
+pub(crate) struct LineEndingCounts {
+  lf: usize,
+  crlf: usize,
+  cr: usize,
+  /// Whether the last piece ended in a `\r`
+  pending_cr: bool,
+}
+
+impl LineEndingCounts {
+  /// Count the line breaks in the next piece of text
+  pub(crate) fn add(&mut self, text: &str) {
+    let mut bytes = text.bytes().peekable();
+    if self.pending_cr && bytes.peek().is_some() {
+      self.pending_cr = false;
+      match bytes.peek() {
+        Some(b'\n') => {
+          bytes.next();
+          self.crlf += 1;
+        },
+        _ => self.cr += 1,
+      }
+    }
+    while let Some(b) = bytes.next() {
+      match b {
+        b'\r' => match bytes.peek() {
+          Some(b'\n') => {
+            bytes.next();
+            self.crlf += 1;
+          },
+          Some(_) => self.cr += 1,
+          None => self.pending_cr = true,
+        },
+        b'\n' => self.lf += 1,
+        _ => {},
+      }
+    }
+  }
+
+  /// Get the most frequent style and whether more than one style occurs
+  pub(crate) fn finish(mut self) -> (LineEnding, bool) {
+    if self.pending_cr {
+      self.cr += 1;
+    }
+    let (lf, crlf, cr) = (self.lf, self.crlf, self.cr);
+
+    let mixed = [lf, crlf, cr].iter().filter(|n| **n > 0).count() > 1;
+    let dominant = if crlf > lf && crlf >= cr {
+      LineEnding::Crlf
+    } else if cr > lf && cr > crlf {
+      LineEnding::Cr
+    } else {
+      LineEnding::Lf
+    };
+
+    (dominant, mixed)
+  }
+}
+
+/// How a file is stored on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFormat {
+  /// Text encoding
+  pub encoding: &'static Encoding,
+  /// Whether the file starts with a byte order mark
+  pub bom: bool,
+  /// Line ending used when saving
+  pub line_ending: LineEnding,
+  /// Whether the file mixes line endings; such files are saved verbatim
+  /// until a line ending is chosen explicitly
+  pub mixed_line_endings: bool,
+}
+
+impl Default for FileFormat {
+  fn default() -> Self {
+    Self {
+      encoding: UTF_8,
+      bom: false,
+      line_ending: LineEnding::Lf,
+      mixed_line_endings: false,
+    }
+  }
+}
+
+impl FileFormat {
+  /// Detect the format of already decoded UTF-8 text
+  pub fn from_text(text: &str) -> Self {
+    let (line_ending, mixed_line_endings) = LineEnding::detect(text);
+    Self {
+      line_ending,
+      mixed_line_endings,
+      ..Self::default()
+    }
+  }
+
+  /// Get the encoding name, e.g. `UTF-8` or `Shift_JIS`
+  pub fn encoding_name(&self) -> &'static str {
+    self.encoding.name()
+  }
+}
+
+/// Look up an encoding by a WHATWG label such as `utf-8`, `sjis` or `latin1`
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding> {
+  Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| Error::UnknownEncoding {
+    label: label.to_string(),
+  })
+}
+
+/// Decode file bytes, detecting the encoding, BOM and line endings
+///
+/// A BOM wins; otherwise valid UTF-8 is taken as UTF-8 and anything else is
+/// guessed from the content. Malformed sequences decode to U+FFFD.
+pub fn decode(bytes: &[u8]) -> (String, FileFormat) {
+  let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+    Some(found) => found,
+    None if std::str::from_utf8(bytes).is_ok() => (UTF_8, 0),
+    None => {
+      let mut detector = chardetng::EncodingDetector::new();
+      detector.feed(bytes, true);
+      (detector.guess(None, true), 0)
+    },
+  };
+
+  let (text, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+  if had_errors {
+    warn!("Malformed {} sequences replaced while decoding", encoding.name());
+  }
+
+  let text = text.into_owned();
+  let (line_ending, mixed_line_endings) = LineEnding::detect(&text);
+  let format = FileFormat {
+    encoding,
+    bom: bom_len > 0,
+    line_ending,
+    mixed_line_endings,
+  };
+
+  (text, format)
+}
+
+/// Encode text for writing to disk in the given format
+///
+/// Line endings are normalized unless the format has mixed line endings.
+/// Fails if the text contains characters the encoding cannot represent.
+pub fn encode(text: &str, format: &FileFormat) -> Result<Vec<u8>> {
+  let text = if format.mixed_line_endings {
+    Cow::Borrowed(text)
+  } else {
+    normalize_line_endings(text, format.line_ending)
+  };
+
+  let encoding = format.encoding;
+  let mut bytes = Vec::with_capacity(text.len() + 3);
+
+  // encoding_rs only decodes UTF-16, so encode it by hand
+  if encoding == UTF_16LE || encoding == UTF_16BE {
+    let little_endian = encoding == UTF_16LE;
+    if format.bom {
+      bytes.extend_from_slice(if little_endian { &[0xFF, 0xFE] } else { &[0xFE, 0xFF] });
+    }
+    for unit in text.encode_utf16() {
+      let unit = if little_endian { unit.to_le_bytes() } else { unit.to_be_bytes() };
+      bytes.extend_from_slice(&unit);
+    }
+    return Ok(bytes);
+  }
+
+  if format.bom && encoding == UTF_8 {
+    bytes.extend_from_slice(&[0xEF, 0xBB, 0xBF]);
+  }
+
+  let (encoded, _, had_errors) = encoding.encode(&text);
+  if had_errors {
+    return Err(Error::UnencodableText {
+      encoding: encoding.name().to_string(),
+    });
+  }
+  bytes.extend_from_slice(&encoded);
+
+  Ok(bytes)
+}
+
+/// Convert every line break in the text to the given line ending
+pub fn normalize_line_endings(text: &str, line_ending: LineEnding) -> Cow<'_, str> {
+  let (dominant, mixed) = LineEnding::detect(text);
+  if !text.contains(['\n', '\r']) || (!mixed && dominant == line_ending) {
+    return Cow::Borrowed(text);
+  }
+
+  let target = line_ending.as_str();
+  let mut out = String::with_capacity(text.len() + text.len() / 32);
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '\r' => {
+        if chars.peek() == Some(&'\n') {
+          chars.next();
+        }
+        out.push_str(target);
+      },
+      '\n' => out.push_str(target),
+      c => out.push(c),
+    }
+  }
+
+  Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_detect_line_endings() {
+    assert_eq!(LineEnding::detect("a\nb\n"), (LineEnding::Lf, false));
+    assert_eq!(LineEnding::detect("a\r\nb\r\n"), (LineEnding::Crlf, false));
+    assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), (LineEnding::Crlf, true));
+    assert_eq!(LineEnding::detect("no breaks"), (LineEnding::Lf, false));
+  }
+
+  #[test]
+  fn test_normalize_line_endings() {
+    assert_eq!(normalize_line_endings("a\r\nb\nc", LineEnding::Lf), "a\nb\nc");
+    assert_eq!(normalize_line_endings("a\r\nb\nc", LineEnding::Crlf), "a\r\nb\r\nc");
+    assert!(matches!(
+      normalize_line_endings("a\r\nb", LineEnding::Crlf),
+      Cow::Borrowed(_)
+    ));
+  }
+
+  #[test]
+  fn test_round_trip_utf8_bom_crlf() {
+    let bytes = b"\xEF\xBB\xBFfn main() {\r\n}\r\n";
+    let (text, format) = decode(bytes);
+
+    assert_eq!(text, "fn main() {\r\n}\r\n");
+    assert!(format.bom);
+    assert_eq!(format.line_ending, LineEnding::Crlf);
+    assert_eq!(encode(&text, &format).unwrap(), bytes);
+  }
+
+  #[test]
+  fn test_round_trip_utf16le() {
+    let mut bytes = vec![0xFF, 0xFE];
+    bytes.extend("héllo\r\n".encode_utf16().flat_map(|u| u.to_le_bytes()));
+
+    let (text, format) = decode(&bytes);
+    assert_eq!(text, "héllo\r\n");
+    assert_eq!(format.encoding, UTF_16LE);
+    assert_eq!(encode(&text, &format).unwrap(), bytes);
+  }
+
+  #[test]
+  fn test_detect_shift_jis() {
+    let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode("こんにちは、世界。日本語のテキストです。");
+    let (text, format) = decode(&bytes);
+
+    assert_eq!(format.encoding_name(), "Shift_JIS");
+    assert_eq!(text, "こんにちは、世界。日本語のテキストです。");
+    assert_eq!(encode(&text, &format).unwrap(), bytes.as_ref());
+  }
+
+  #[test]
+  fn test_new_lines_follow_file_line_ending() {
+    let format = FileFormat {
+      line_ending: LineEnding::Crlf,
+      ..FileFormat::default()
+    };
+    assert_eq!(encode("a\r\nb\nc", &format).unwrap(), b"a\r\nb\r\nc");
+  }
+
+  #[test]
+  fn test_unencodable_text() {
+    let format = FileFormat {
+      encoding: encoding_rs::WINDOWS_1252,
+      ..FileFormat::default()
+    };
+    assert!(encode("日本", &format).is_err());
+    assert!(encoding_for_label("no-such-encoding").is_err());
+  }
+}
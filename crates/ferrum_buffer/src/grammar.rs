@@ -0,0 +1,477 @@
+//! Runtime-loadable grammars and query files
+//!
+//! The grammars of the built-in languages are compiled in. Others, such as
+//! Lua, C, YAML or Zig, are loaded at runtime from a shared library
+//! exporting `tree_sitter_<name>`, or from a WASM module with the `wasm`
+//! feature, and get a `LanguageId::Runtime` id. Query files can be loaded
+//! from disk for any language and take precedence over the bundled ones.
+//!
+//! A grammars directory holds one subdirectory per language:
+//!
+//! ```text
+//! grammars/
+//!   lua/
+//!     lua.so            # or .dylib, .dll, .wasm, or libtree-sitter-lua.so
+//!     queries/
+//!       highlights.scm
+//!       locals.scm
+//!   rust/
+//!     queries/
+//!       highlights.scm  # replaces the bundled Rust query
+//! ```
+
+use crate::syntax::LanguageId;
+use ferrum_core::prelude::*;
+use libloading::{Library, Symbol};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tree_sitter::Language;
+use tree_sitter_language::LanguageFn;
+
+/// File extensions of grammar shared libraries
+const LIBRARY_EXTENSIONS: [&str; 3] = ["so", "dylib", "dll"];
+
+/// Kinds of tree-sitter query files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryKind {
+  Highlights,
+  Injections,
+  Locals,
+  Folds,
+  Indents,
+  TextObjects,
+  Imports,
+}
+
+impl QueryKind {
+  pub const ALL: [QueryKind; 7] = [
+    Self::Highlights,
+    Self::Injections,
+    Self::Locals,
+    Self::Folds,
+    Self::Indents,
+    Self::TextObjects,
+    Self::Imports,
+  ];
+
+  /// Get the name of the query file
+  pub fn file_name(&self) -> &'static str {
+    match self {
+      Self::Highlights => "highlights.scm",
+      Self::Injections => "injections.scm",
+      Self::Locals => "locals.scm",
+      Self::Folds => "folds.scm",
+      Self::Indents => "indents.scm",
+      Self::TextObjects => "textobjects.scm",
+      Self::Imports => "imports.scm",
+    }
+  }
+}
+
+/// A grammar to load, e.g. from a plugin's language contribution
+#[derive(Debug, Clone, Default)]
+pub struct GrammarDefinition {
+  /// Language name, as used in Markdown code fences and injection queries
+  pub name: String,
+  /// Shared library or WASM module with the compiled grammar
+  pub grammar: PathBuf,
+  /// Directory with query files
+  pub queries: Option<PathBuf>,
+  /// File extensions, without the dot
+  pub extensions: Vec<String>,
+  /// Exact file names, e.g. `Makefile`
+  pub file_names: Vec<String>,
+}
+
+struct RuntimeGrammar {
+  name: String,
+  language: Language,
+  /// Keeps the shared library the grammar lives in loaded
+  library: Option<Library>,
+}
+
+#[derive(Default)]
+struct Lookup {
+  names: HashMap<String, LanguageId>,
+  extensions: HashMap<String, LanguageId>,
+  file_names: HashMap<String, LanguageId>,
+}
+
+/// Registry of grammars and query files loaded at runtime
+pub struct GrammarRegistry {
+  /// Runtime grammars, indexed by `LanguageId::Runtime`
+  grammars: RwLock<Vec<RuntimeGrammar>>,
+  lookup: RwLock<Lookup>,
+  /// Query files loaded from disk, for runtime and built-in languages
+  queries: RwLock<HashMap<(LanguageId, QueryKind), Arc<str>>>,
+  /// Libraries of grammars that were replaced, which trees, parsers and
+  /// queries made before may still point into
+  retired: Mutex<Vec<Library>>,
+  #[cfg(feature = "wasm")]
+  engine: tree_sitter::wasmtime::Engine,
+}
+
+/// Get the global grammar registry
+pub fn global_grammar_registry() -> &'static GrammarRegistry {
+  static REGISTRY: once_cell::sync::Lazy<GrammarRegistry> =
+    once_cell::sync::Lazy::new(GrammarRegistry::new);
+  &REGISTRY
+}
+
+impl GrammarRegistry {
+  fn new() -> Self {
+    Self {
+      grammars: RwLock::new(Vec::new()),
+      lookup: RwLock::new(Lookup::default()),
+      queries: RwLock::new(HashMap::new()),
+      retired: Mutex::new(Vec::new()),
+      #[cfg(feature = "wasm")]
+      engine: tree_sitter::wasmtime::Engine::default(),
+    }
+  }
+
+  /// Load a grammar with its queries, and map its file extensions and names
+  /// to it
+  ///
+  /// Registering a name again replaces the grammar under the same ID. The
+  /// library of the old one stays loaded for the rest of the process.
+  pub fn register(&self, definition: &GrammarDefinition) -> Result<LanguageId> {
+    let (language, library) = self.load_grammar(&definition.name, &definition.grammar)?;
+    let id = self.insert(&definition.name, language, library)?;
+
+    {
+      let mut lookup = self.lookup.write();
+      for ext in &definition.extensions {
+        let ext = ext.trim_start_matches('.').to_lowercase();
+        lookup.extensions.insert(ext, id);
+      }
+      for file_name in &definition.file_names {
+        lookup.file_names.insert(file_name.clone(), id);
+      }
+    }
+
+    if let Some(dir) = &definition.queries {
+      self.load_queries(id, dir)?;
+    }
+
+    info!("Registered grammar {} from {:?}", definition.name, definition.grammar);
+    Ok(id)
+  }
+
+  /// Register a grammar that is already loaded, e.g. one linked into an
+  /// embedding application
+  pub fn register_language(&self, name: &str, language: Language) -> Result<LanguageId> {
+    self.insert(name, language, None)
+  }
+
+  /// Map a file extension to a language
+  pub fn map_extension(&self, ext: &str, language: LanguageId) {
+    self
+      .lookup
+      .write()
+      .extensions
+      .insert(ext.trim_start_matches('.').to_lowercase(), language);
+  }
+
+  /// Load the query files found in `dir` for a language, replacing any
+  /// loaded before
+  ///
+  /// Returns the number of files loaded.
+  pub fn load_queries(&self, language: LanguageId, dir: &Path) -> Result<usize> {
+    let mut loaded = 0;
+    for kind in QueryKind::ALL {
+      let path = dir.join(kind.file_name());
+      match std::fs::read_to_string(&path) {
+        Ok(source) => {
+          self.queries.write().insert((language, kind), source.into());
+          loaded += 1;
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+        Err(e) => return Err(e.into()),
+      }
+    }
+
+    debug!("Loaded {} query files for {:?} from {:?}", loaded, language, dir);
+    Ok(loaded)
+  }
+
+  /// Load every grammar and query file in a grammars directory
+  ///
+  /// A subdirectory named after a built-in language only provides queries.
+  /// Languages that fail to load are logged and skipped. Returns the
+  /// languages loaded.
+  pub fn load_dir(&self, dir: &Path) -> Result<Vec<LanguageId>> {
+    let entries = match std::fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(e) => return Err(e.into()),
+    };
+
+    let mut loaded = Vec::new();
+    for entry in entries {
+      let path = entry?.path();
+      let Some(name) = path.file_name().and_then(|n| n.to_str()).map(String::from) else {
+        continue;
+      };
+      if !path.is_dir() {
+        continue;
+      }
+
+      let queries = path.join("queries");
+      let queries = queries.is_dir().then_some(queries);
+      let result = match builtin(&name) {
+        Some(language) => match &queries {
+          Some(queries) => self.load_queries(language, queries).map(|_| language),
+          None => continue,
+        },
+        None => match grammar_file(&path, &name) {
+          Some(grammar) => self.register(&GrammarDefinition {
+            name: name.clone(),
+            grammar,
+            queries,
+            extensions: vec![name.clone()],
+            file_names: Vec::new(),
+          }),
+          None => {
+            warn!("No grammar found in {:?}", path);
+            continue;
+          },
+        },
+      };
+
+      match result {
+        Ok(language) => loaded.push(language),
+        Err(e) => warn!("Skipping grammar {:?}: {}", path, e),
+      }
+    }
+
+    Ok(loaded)
+  }
+
+  /// Get a query file loaded at runtime for a language
+  pub fn query(&self, language: LanguageId, kind: QueryKind) -> Option<Arc<str>> {
+    self.queries.read().get(&(language, kind)).cloned()
+  }
+
+  /// Get the grammar of a runtime language
+  pub fn language(&self, id: LanguageId) -> Option<Language> {
+    let LanguageId::Runtime(index) = id else {
+      return None;
+    };
+    let grammars = self.grammars.read();
+    grammars.get(index as usize).map(|g| g.language.clone())
+  }
+
+  /// Get the name of a runtime language
+  pub fn name(&self, id: LanguageId) -> Option<String> {
+    let LanguageId::Runtime(index) = id else {
+      return None;
+    };
+    let grammars = self.grammars.read();
+    grammars.get(index as usize).map(|g| g.name.clone())
+  }
+
+  /// Find a runtime language by name
+  pub fn by_name(&self, name: &str) -> Option<LanguageId> {
+    self.lookup.read().names.get(&name.to_lowercase()).copied()
+  }
+
+  /// Find the language mapped to a file extension
+  pub fn by_extension(&self, ext: &str) -> Option<LanguageId> {
+    self.lookup.read().extensions.get(&ext.to_lowercase()).copied()
+  }
+
+  /// Find the language mapped to an exact file name
+  pub fn by_file_name(&self, file_name: &str) -> Option<LanguageId> {
+    self.lookup.read().file_names.get(file_name).copied()
+  }
+
+  /// List the runtime languages with their names
+  pub fn languages(&self) -> Vec<(String, LanguageId)> {
+    self
+      .grammars
+      .read()
+      .iter()
+      .enumerate()
+      .map(|(i, g)| (g.name.clone(), LanguageId::Runtime(i as u32)))
+      .collect()
+  }
+
+  /// Create a WASM store for a parser of a WASM grammar
+  #[cfg(feature = "wasm")]
+  pub(crate) fn wasm_store(&self) -> Result<tree_sitter::WasmStore> {
+    tree_sitter::WasmStore::new(&self.engine).map_err(|e| Error::Internal(e.to_string()))
+  }
+
+  fn insert(&self, name: &str, language: Language, library: Option<Library>) -> Result<LanguageId> {
+    let failed = |reason: String| Error::GrammarLoadFailed {
+      name: name.to_string(),
+      reason,
+    };
+    if builtin(name).is_some() {
+      return Err(failed("a built-in language has this name".to_string()));
+    }
+
+    let version = language.version();
+    if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION)
+      .contains(&version)
+    {
+      return Err(failed(format!(
+        "ABI version {} is not supported, expected {} to {}",
+        version,
+        tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION,
+        tree_sitter::LANGUAGE_VERSION
+      )));
+    }
+
+    let grammar = RuntimeGrammar {
+      name: name.to_string(),
+      language,
+      library,
+    };
+
+    let key = name.to_lowercase();
+    let mut lookup = self.lookup.write();
+    let mut grammars = self.grammars.write();
+    let id = match lookup.names.get(&key) {
+      Some(&id @ LanguageId::Runtime(index)) => {
+        let old = std::mem::replace(&mut grammars[index as usize], grammar);
+        self.retired.lock().extend(old.library);
+        id
+      },
+      _ => {
+        let id = LanguageId::Runtime(grammars.len() as u32);
+        grammars.push(grammar);
+        lookup.names.insert(key, id);
+        id
+      },
+    };
+
+    Ok(id)
+  }
+
+  fn load_grammar(&self, name: &str, path: &Path) -> Result<(Language, Option<Library>)> {
+    let failed = |reason: String| Error::GrammarLoadFailed {
+      name: name.to_string(),
+      reason,
+    };
+    if !path.exists() {
+      return Err(Error::FileNotFound {
+        path: path.to_path_buf(),
+      });
+    }
+
+    if path.extension().and_then(|e| e.to_str()) == Some("wasm") {
+      return self
+        .load_wasm(name, path)
+        .map(|language| (language, None));
+    }
+
+    let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+    // SAFETY: loading a library runs its initialisers, and the symbol is
+    // trusted to be a tree-sitter language function. Grammars are installed
+    // by the user or a plugin and are as trusted as the plugin itself.
+    unsafe {
+      let library = Library::new(path).map_err(|e| failed(e.to_string()))?;
+      let language_fn: Symbol<'_, unsafe extern "C" fn() -> *const ()> = library
+        .get(symbol.as_bytes())
+        .map_err(|e| failed(e.to_string()))?;
+      let language = Language::new(LanguageFn::from_raw(*language_fn));
+      Ok((language, Some(library)))
+    }
+  }
+
+  #[cfg(feature = "wasm")]
+  fn load_wasm(&self, name: &str, path: &Path) -> Result<Language> {
+    let bytes = std::fs::read(path)?;
+    self
+      .wasm_store()?
+      .load_language(&name.replace('-', "_"), &bytes)
+      .map_err(|e| Error::GrammarLoadFailed {
+        name: name.to_string(),
+        reason: e.to_string(),
+      })
+  }
+
+  #[cfg(not(feature = "wasm"))]
+  fn load_wasm(&self, name: &str, _path: &Path) -> Result<Language> {
+    Err(Error::GrammarLoadFailed {
+      name: name.to_string(),
+      reason: "WASM grammars need the `wasm` feature".to_string(),
+    })
+  }
+}
+
+/// Get the built-in language with this name, if any
+fn builtin(name: &str) -> Option<LanguageId> {
+  match LanguageId::from_name(name) {
+    LanguageId::Unknown | LanguageId::Runtime(_) => None,
+    language => Some(language),
+  }
+}
+
+/// Find the grammar file in a language directory
+fn grammar_file(dir: &Path, name: &str) -> Option<PathBuf> {
+  let stems = [name.to_string(), format!("libtree-sitter-{}", name)];
+  stems
+    .iter()
+    .flat_map(|stem| {
+      LIBRARY_EXTENSIONS
+        .iter()
+        .chain(&["wasm"])
+        .map(move |ext| dir.join(format!("{}.{}", stem, ext)))
+    })
+    .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::syntax::SyntaxManager;
+  use ropey::Rope;
+
+  #[test]
+  fn test_register_language_with_queries() {
+    let registry = global_grammar_registry();
+    let id = registry
+      .register_language("test-jsonc", tree_sitter_json::LANGUAGE.into())
+      .unwrap();
+    registry.map_extension("testjsonc", id);
+
+    assert_eq!(LanguageId::from_extension("testjsonc"), id);
+    assert_eq!(LanguageId::from_name("test-jsonc"), id);
+    assert_eq!(registry.name(id).as_deref(), Some("test-jsonc"));
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("highlights.scm"), "(string) @string\n").unwrap();
+    assert_eq!(registry.load_queries(id, dir.path()).unwrap(), 1);
+
+    let manager = SyntaxManager::new(id).unwrap();
+    let rope = Rope::from_str("{\"a\": 1}");
+    manager.parse(&rope).unwrap();
+    assert!(!manager.highlights(&rope, 0..rope.len_bytes()).is_empty());
+  }
+
+  #[test]
+  fn test_builtin_names_are_reserved() {
+    let result = global_grammar_registry().register_language("rust", tree_sitter_rust::LANGUAGE.into());
+    assert!(matches!(result, Err(Error::GrammarLoadFailed { .. })));
+  }
+
+  #[test]
+  fn test_load_dir_skips_broken_grammars() {
+    let dir = tempfile::tempdir().unwrap();
+    let lua = dir.path().join("lua");
+    std::fs::create_dir_all(&lua).unwrap();
+    std::fs::write(lua.join("lua.so"), "not a library").unwrap();
+    std::fs::create_dir_all(dir.path().join("empty")).unwrap();
+
+    let loaded = global_grammar_registry().load_dir(dir.path()).unwrap();
+    assert!(loaded.is_empty());
+    assert_eq!(global_grammar_registry().by_name("lua"), None);
+  }
+}
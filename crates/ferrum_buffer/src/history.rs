@@ -1,16 +1,98 @@
 //! Undo/redo history management
+//!
+//! History is kept as a tree rather than a stack: undoing and then making a
+//! new edit starts a new branch instead of discarding the redo branch. Every
+//! node records when its state was reached, so the buffer can travel to any
+//! earlier state by sequence number or by wall-clock time.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::edit::Edit;
+use crate::transaction::Transaction;
 use ferrum_core::constants::MAX_UNDO_HISTORY;
+use serde::{Deserialize, Serialize};
+
+/// A node in the undo tree
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UndoNode {
+  /// Parent state, `None` for the root
+  parent: Option<usize>,
+  /// Child states in creation order
+  children: Vec<usize>,
+  /// Child that redo moves to
+  redo_child: Option<usize>,
+  /// Transaction leading from the parent to this state, `None` for the root
+  transaction: Option<Transaction>,
+  /// When this state was reached, in milliseconds since the Unix epoch
+  timestamp: u64,
+}
+
+impl UndoNode {
+  fn new(parent: Option<usize>, transaction: Option<Transaction>) -> Self {
+    Self {
+      parent,
+      children: Vec::new(),
+      redo_child: None,
+      transaction,
+      timestamp: now_millis(),
+    }
+  }
+}
+
+/// A single step the buffer must apply to move through the undo tree
+#[derive(Debug, Clone)]
+pub enum HistoryStep {
+  /// Revert a transaction
+  Undo(Transaction),
+  /// Re-apply a transaction
+  Redo(Transaction),
+}
+
+/// Serializable shape of an undo tree, for rendering
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoTree {
+  /// Sequence number of the root state
+  pub root: usize,
+  /// Sequence number of the current state
+  pub current: usize,
+  /// All states, in sequence order
+  pub nodes: Vec<UndoTreeNode>,
+}
+
+/// A state in an `UndoTree`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoTreeNode {
+  pub seq: usize,
+  pub parent: Option<usize>,
+  pub children: Vec<usize>,
+  /// When this state was reached, in milliseconds since the Unix epoch
+  pub timestamp: u64,
+  /// Number of edits in the transaction leading to this state
+  pub edit_count: usize,
+}
 
 /// Manages undo/redo history for a buffer
+///
+/// History is serializable so it can be persisted across sessions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct History {
-  /// Stack of undoable edits
-  undo_stack: Vec<Edit>,
-  /// Stack of redoable edits
-  redo_stack: Vec<Edit>,
+  /// All states, keyed by sequence number
+  nodes: BTreeMap<usize, UndoNode>,
+  /// Sequence number of the oldest retained state
+  root: usize,
+  /// Sequence number of the current state
+  current: usize,
+  /// Sequence number for the next state
+  next_seq: usize,
   /// Maximum number of entries to keep
   max_entries: usize,
+  /// Bytes of edit text held by all transactions
+  #[serde(default)]
+  bytes: usize,
+  /// Maximum bytes of edit text to keep
+  #[serde(default = "unlimited")]
+  max_bytes: usize,
   /// Whether to merge consecutive edits
   merge_enabled: bool,
 }
@@ -18,10 +100,17 @@ pub struct History {
 impl History {
   /// Create a new history
   pub fn new() -> Self {
+    let mut nodes = BTreeMap::new();
+    nodes.insert(0, UndoNode::new(None, None));
+
     Self {
-      undo_stack: Vec::new(),
-      redo_stack: Vec::new(),
+      nodes,
+      root: 0,
+      current: 0,
+      next_seq: 1,
       max_entries: MAX_UNDO_HISTORY,
+      bytes: 0,
+      max_bytes: usize::MAX,
       merge_enabled: true,
     }
   }
@@ -34,67 +123,279 @@ impl History {
     }
   }
 
-  /// Push an edit onto the history
+  /// Get the maximum number of entries kept
+  pub fn max_entries(&self) -> usize {
+    self.max_entries
+  }
+
+  /// Limit the number of entries kept, dropping the oldest states first
+  pub fn set_max_entries(&mut self, max_entries: usize) {
+    self.max_entries = max_entries;
+    self.prune();
+  }
+
+  /// Limit the bytes of edit text kept, dropping the oldest states first
+  pub fn set_max_bytes(&mut self, max_bytes: usize) {
+    self.max_bytes = max_bytes;
+    self.prune();
+  }
+
+  /// Get the bytes of edit text held by the history
+  pub fn byte_size(&self) -> usize {
+    self.bytes
+  }
+
+  /// Push a single edit onto the history
   pub fn push(&mut self, edit: Edit) {
-    // Clear redo stack when new edit is made
-    self.redo_stack.clear();
-
-    // Try to merge with the last edit
-    if self.merge_enabled {
-      if let Some(last) = self.undo_stack.last_mut() {
-        if last.can_merge_with(&edit) {
-          last.merge(edit);
-          return;
+    // Only merge into the newest state; it has no children, so merged
+    // typing never rewrites a state another branch depends on
+    if self.merge_enabled && self.current + 1 == self.next_seq {
+      if let Some(node) = self.nodes.get_mut(&self.current) {
+        if let Some(last) = node.transaction.as_mut() {
+          if last.can_merge_with(&edit) {
+            self.bytes -= last.byte_size();
+            last.merge(edit);
+            self.bytes += last.byte_size();
+            node.timestamp = now_millis();
+            self.prune();
+            return;
+          }
+        }
+      }
+    }
+
+    self.push_node(Transaction::single(edit));
+  }
+
+  /// Push a transaction onto the history as one undo step
+  ///
+  /// Transactions are never merged with their neighbours.
+  pub fn push_transaction(&mut self, transaction: Transaction) {
+    if transaction.is_empty() {
+      return;
+    }
+
+    self.push_node(transaction);
+  }
+
+  /// Add a new state as a child of the current one and move to it
+  fn push_node(&mut self, transaction: Transaction) {
+    let seq = self.next_seq;
+    self.next_seq += 1;
+    self.bytes += transaction.byte_size();
+
+    self
+      .nodes
+      .insert(seq, UndoNode::new(Some(self.current), Some(transaction)));
+    if let Some(parent) = self.nodes.get_mut(&self.current) {
+      parent.children.push(seq);
+      parent.redo_child = Some(seq);
+    }
+    self.current = seq;
+
+    self.prune();
+  }
+
+  /// Drop states until the entry and byte limits are met
+  ///
+  /// The oldest abandoned branch tip goes first; once only the current line
+  /// of history is left, the root is collapsed into its child instead.
+  fn prune(&mut self) {
+    while self.nodes.len() > self.max_entries + 1 || self.bytes > self.max_bytes {
+      let leaf = self
+        .nodes
+        .iter()
+        .find(|(seq, node)| {
+          node.children.is_empty() && **seq != self.current && **seq != self.root
+        })
+        .map(|(seq, _)| *seq);
+
+      if let Some(seq) = leaf {
+        let node = self.nodes.remove(&seq).expect("leaf exists");
+        self.bytes -= node.transaction.as_ref().map_or(0, Transaction::byte_size);
+        if let Some(parent) = node.parent.and_then(|p| self.nodes.get_mut(&p)) {
+          parent.children.retain(|c| *c != seq);
+          if parent.redo_child == Some(seq) {
+            parent.redo_child = parent.children.last().copied();
+          }
         }
+        continue;
+      }
+
+      let root = &self.nodes[&self.root];
+      if self.current == self.root || root.children.len() != 1 {
+        break;
       }
+
+      let child = root.children[0];
+      self.nodes.remove(&self.root);
+      if let Some(node) = self.nodes.get_mut(&child) {
+        node.parent = None;
+        if let Some(transaction) = node.transaction.take() {
+          self.bytes -= transaction.byte_size();
+        }
+      }
+      self.root = child;
     }
+  }
 
-    self.undo_stack.push(edit);
+  /// Undo the last transaction, returning it
+  pub fn undo(&mut self) -> Option<Transaction> {
+    let node = &self.nodes[&self.current];
+    let parent = node.parent?;
+    let transaction = node.transaction.clone()?;
+
+    let seq = self.current;
+    if let Some(parent_node) = self.nodes.get_mut(&parent) {
+      parent_node.redo_child = Some(seq);
+    }
+    self.current = parent;
+
+    Some(transaction)
+  }
+
+  /// Redo the last undone transaction, returning it
+  pub fn redo(&mut self) -> Option<Transaction> {
+    let child = self.redo_target(self.current)?;
+    let transaction = self.nodes[&child].transaction.clone()?;
+    self.current = child;
+    Some(transaction)
+  }
+
+  /// Child state that redo moves to from `seq`
+  fn redo_target(&self, seq: usize) -> Option<usize> {
+    self.nodes.get(&seq)?.redo_child
+  }
 
-    // Enforce max entries
-    if self.undo_stack.len() > self.max_entries {
-      self.undo_stack.remove(0);
+  /// Move to the state with the given sequence number
+  ///
+  /// Returns the steps to apply to the buffer, in order, or `None` if no
+  /// such state is retained.
+  pub fn goto(&mut self, seq: usize) -> Option<Vec<HistoryStep>> {
+    if !self.nodes.contains_key(&seq) {
+      return None;
     }
+
+    let current_path = self.path_to_root(self.current);
+    let mut down = Vec::new();
+    let mut ancestor = seq;
+    while !current_path.contains(&ancestor) {
+      down.push(ancestor);
+      ancestor = self.nodes[&ancestor].parent?;
+    }
+
+    let mut steps = Vec::new();
+    for node_seq in current_path.iter().take_while(|s| **s != ancestor) {
+      if let Some(transaction) = self.nodes[node_seq].transaction.clone() {
+        steps.push(HistoryStep::Undo(transaction));
+      }
+    }
+
+    let mut parent = ancestor;
+    for node_seq in down.into_iter().rev() {
+      if let Some(parent_node) = self.nodes.get_mut(&parent) {
+        parent_node.redo_child = Some(node_seq);
+      }
+      if let Some(transaction) = self.nodes[&node_seq].transaction.clone() {
+        steps.push(HistoryStep::Redo(transaction));
+      }
+      parent = node_seq;
+    }
+
+    self.current = seq;
+    Some(steps)
+  }
+
+  /// Move to the state the buffer was in at the given time, in milliseconds
+  /// since the Unix epoch
+  ///
+  /// Times before the oldest retained state go to the root.
+  pub fn goto_time(&mut self, timestamp: u64) -> Vec<HistoryStep> {
+    let target = self
+      .nodes
+      .iter()
+      .filter(|(_, node)| node.timestamp <= timestamp)
+      .max_by_key(|(seq, node)| (node.timestamp, **seq))
+      .map(|(seq, _)| *seq)
+      .unwrap_or(self.root);
+
+    self.goto(target).unwrap_or_default()
+  }
+
+  /// Sequence numbers from `seq` up to and including the root
+  fn path_to_root(&self, seq: usize) -> Vec<usize> {
+    let mut path = vec![seq];
+    let mut node = seq;
+    while let Some(parent) = self.nodes.get(&node).and_then(|n| n.parent) {
+      path.push(parent);
+      node = parent;
+    }
+    path
+  }
+
+  /// Check if no edits are recorded on any branch
+  pub fn is_empty(&self) -> bool {
+    self.nodes.len() <= 1
   }
 
-  /// Undo the last edit, returning it
-  pub fn undo(&mut self) -> Option<Edit> {
-    let edit = self.undo_stack.pop()?;
-    self.redo_stack.push(edit.clone());
-    Some(edit)
+  /// Get the sequence number of the current state
+  pub fn current_seq(&self) -> usize {
+    self.current
   }
 
-  /// Redo the last undone edit, returning it
-  pub fn redo(&mut self) -> Option<Edit> {
-    let edit = self.redo_stack.pop()?;
-    self.undo_stack.push(edit.clone());
-    Some(edit)
+  /// Get the shape of the undo tree
+  pub fn tree(&self) -> UndoTree {
+    UndoTree {
+      root: self.root,
+      current: self.current,
+      nodes: self
+        .nodes
+        .iter()
+        .map(|(seq, node)| UndoTreeNode {
+          seq: *seq,
+          parent: node.parent,
+          children: node.children.clone(),
+          timestamp: node.timestamp,
+          edit_count: node.transaction.as_ref().map_or(0, Transaction::len),
+        })
+        .collect(),
+    }
   }
 
   /// Check if undo is available
   pub fn can_undo(&self) -> bool {
-    !self.undo_stack.is_empty()
+    self.current != self.root
   }
 
   /// Check if redo is available
   pub fn can_redo(&self) -> bool {
-    !self.redo_stack.is_empty()
+    self.redo_target(self.current).is_some()
   }
 
   /// Get the number of undoable operations
   pub fn undo_count(&self) -> usize {
-    self.undo_stack.len()
+    self.path_to_root(self.current).len() - 1
   }
 
-  /// Get the number of redoable operations
+  /// Get the number of redoable operations along the redo branch
   pub fn redo_count(&self) -> usize {
-    self.redo_stack.len()
+    let mut count = 0;
+    let mut node = self.current;
+    while let Some(child) = self.redo_target(node) {
+      count += 1;
+      node = child;
+    }
+    count
   }
 
   /// Clear all history
   pub fn clear(&mut self) {
-    self.undo_stack.clear();
-    self.redo_stack.clear();
+    *self = Self {
+      max_entries: self.max_entries,
+      max_bytes: self.max_bytes,
+      merge_enabled: self.merge_enabled,
+      ..Self::new()
+    };
   }
 
   /// Enable or disable edit merging
@@ -119,6 +420,18 @@ impl Default for History {
   }
 }
 
+fn unlimited() -> usize {
+  usize::MAX
+}
+
+/// Current wall-clock time in milliseconds since the Unix epoch
+fn now_millis() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or(0)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -211,4 +524,126 @@ mod tests {
     ));
     assert_eq!(history.redo_count(), 0);
   }
+
+  #[test]
+  fn test_transaction_is_one_step() {
+    let mut history = History::new();
+
+    history.push_transaction(Transaction::new(vec![
+      Edit::new(EditKind::Insert, 0, 1, String::new(), "a".to_string()),
+      Edit::new(EditKind::Insert, 5, 1, String::new(), "b".to_string()),
+    ]));
+    // A following single edit must not merge into the transaction
+    history.push(Edit::new(
+      EditKind::Insert,
+      6,
+      1,
+      String::new(),
+      "c".to_string(),
+    ));
+
+    assert_eq!(history.undo_count(), 2);
+    assert_eq!(history.undo().unwrap().len(), 1);
+    assert_eq!(history.undo().unwrap().len(), 2);
+  }
+
+  fn insert(position: usize, text: &str) -> Edit {
+    Edit::new(
+      EditKind::Insert,
+      position,
+      text.len(),
+      String::new(),
+      text.to_string(),
+    )
+  }
+
+  #[test]
+  fn test_edit_after_undo_keeps_branch() {
+    let mut history = History::new();
+    history.set_merge_enabled(false);
+
+    history.push(insert(0, "a"));
+    history.push(insert(1, "b"));
+    history.undo();
+    history.push(insert(1, "c"));
+
+    // Both "ab" and "ac" are retained as children of "a"
+    let tree = history.tree();
+    assert_eq!(tree.nodes.len(), 4);
+    assert_eq!(tree.nodes[1].children, vec![2, 3]);
+    assert_eq!(tree.current, 3);
+
+    let steps = history.goto(2).unwrap();
+    assert_eq!(steps.len(), 2);
+    assert!(matches!(&steps[0], HistoryStep::Undo(tx) if tx.edits()[0].new_text() == "c"));
+    assert!(matches!(&steps[1], HistoryStep::Redo(tx) if tx.edits()[0].new_text() == "b"));
+    assert_eq!(history.current_seq(), 2);
+
+    // Redo from "a" now follows the most recently visited branch
+    history.undo();
+    assert_eq!(history.redo().unwrap().edits()[0].new_text(), "b");
+  }
+
+  #[test]
+  fn test_goto_unknown_state() {
+    let mut history = History::new();
+    history.push(insert(0, "a"));
+
+    assert!(history.goto(42).is_none());
+    assert_eq!(history.current_seq(), 1);
+  }
+
+  #[test]
+  fn test_goto_time() {
+    let mut history = History::new();
+    history.set_merge_enabled(false);
+
+    history.push(insert(0, "a"));
+    history.push(insert(1, "b"));
+    history.push(insert(2, "c"));
+    for (seq, timestamp) in [(0, 1_000), (1, 2_000), (2, 3_000), (3, 4_000)] {
+      history.nodes.get_mut(&seq).unwrap().timestamp = timestamp;
+    }
+
+    let steps = history.goto_time(2_500);
+    assert_eq!(steps.len(), 2);
+    assert_eq!(history.current_seq(), 1);
+
+    history.goto_time(500);
+    assert_eq!(history.current_seq(), 0);
+
+    history.goto_time(10_000);
+    assert_eq!(history.current_seq(), 3);
+  }
+
+  #[test]
+  fn test_prune_drops_abandoned_branch_first() {
+    let mut history = History::with_max_entries(3);
+    history.set_merge_enabled(false);
+
+    history.push(insert(0, "a"));
+    history.push(insert(1, "b"));
+    history.undo();
+    history.push(insert(1, "c"));
+    history.push(insert(2, "d"));
+
+    // The abandoned "b" tip goes before any state on the current line
+    assert_eq!(history.tree().nodes.len(), 4);
+    assert_eq!(history.undo_count(), 3);
+    assert!(history.goto(2).is_none());
+  }
+
+  #[test]
+  fn test_max_bytes_drops_oldest() {
+    let mut history = History::new();
+    history.set_merge_enabled(false);
+    history.set_max_bytes(10);
+
+    history.push(insert(0, "aaaa"));
+    history.push(insert(4, "bbbb"));
+    history.push(insert(8, "cccc"));
+
+    assert_eq!(history.byte_size(), 8);
+    assert_eq!(history.undo_count(), 2);
+  }
 }
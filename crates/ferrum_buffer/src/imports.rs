@@ -0,0 +1,247 @@
+//! Import statements
+//!
+//! A language's imports query marks each import statement with `@import`
+//! and the module it imports from with `@import.source`, e.g. the string
+//! of a JavaScript `import` or the path of a Rust `use`. Names imported
+//! from the module are marked `@import.name`, as some may be modules of
+//! their own, e.g. in a Python `from package import module`. The name of
+//! a Rust `mod` declaration of a module in another file is marked
+//! `@import.module`. Captures starting with `_` are only for predicates.
+
+use crate::grammar::QueryKind;
+use crate::syntax::LanguageId;
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use tree_sitter::{Node, Parser, Query, QueryCursor};
+
+const IMPORT_CAPTURE: &str = "import";
+const SOURCE_CAPTURE: &str = "import.source";
+const NAME_CAPTURE: &str = "import.name";
+const MODULE_CAPTURE: &str = "import.module";
+
+/// What an import statement does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportKind {
+  /// Imports from a module
+  Import,
+  /// Declares a module in another file, e.g. a Rust `mod`
+  Module,
+}
+
+/// An import found by an imports query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Import {
+  pub kind: ImportKind,
+  /// Module imported from as written, without quotes
+  pub source: String,
+  /// Names imported from the module
+  pub names: Vec<String>,
+  /// Line of the source
+  pub line: usize,
+  /// Byte range of the source
+  pub range: Range<usize>,
+}
+
+/// A compiled imports query
+pub(crate) struct ImportsQuery {
+  query: Query,
+  import_capture: Option<u32>,
+  source_capture: Option<u32>,
+  name_capture: Option<u32>,
+  module_capture: Option<u32>,
+}
+
+impl ImportsQuery {
+  pub(crate) fn new(language: &tree_sitter::Language, source: &str) -> Result<Self> {
+    let query = Query::new(language, source)
+      .map_err(|e| Error::Internal(format!("Failed to compile imports query: {}", e)))?;
+
+    Ok(Self {
+      import_capture: query.capture_index_for_name(IMPORT_CAPTURE),
+      source_capture: query.capture_index_for_name(SOURCE_CAPTURE),
+      name_capture: query.capture_index_for_name(NAME_CAPTURE),
+      module_capture: query.capture_index_for_name(MODULE_CAPTURE),
+      query,
+    })
+  }
+
+  /// Find the imports of a tree, in document order
+  pub(crate) fn imports(&self, root: Node, source: &[u8]) -> Vec<Import> {
+    // Sources and names of each statement, by the statement's start
+    let mut statements: Vec<(usize, Vec<Import>, Vec<String>)> = Vec::new();
+    let mut by_start: HashMap<usize, usize> = HashMap::new();
+    let mut cursor = QueryCursor::new();
+
+    for m in cursor.matches(&self.query, root, source) {
+      let statement = m
+        .captures
+        .iter()
+        .find(|c| Some(c.index) == self.import_capture)
+        .map(|c| c.node);
+      let mut sources = Vec::new();
+      let mut names = Vec::new();
+      for capture in m.captures {
+        let index = Some(capture.index);
+        let kind = if index == self.source_capture {
+          ImportKind::Import
+        } else if index == self.module_capture {
+          ImportKind::Module
+        } else {
+          if index == self.name_capture {
+            names.push(text(&capture.node, source));
+          }
+          continue;
+        };
+        sources.push(Import {
+          kind,
+          source: text(&capture.node, source),
+          names: Vec::new(),
+          line: capture.node.start_position().row,
+          range: capture.node.byte_range(),
+        });
+      }
+
+      // Statements span their captures, so matches of one share a start
+      let start = statement
+        .or_else(|| m.captures.first().map(|c| c.node))
+        .map_or(0, |node| node.start_byte());
+      match by_start.get(&start) {
+        Some(&i) => {
+          statements[i].1.extend(sources);
+          statements[i].2.extend(names);
+        },
+        None => {
+          by_start.insert(start, statements.len());
+          statements.push((start, sources, names));
+        },
+      }
+    }
+
+    statements.sort_by_key(|(start, _, _)| *start);
+    let mut imports = Vec::new();
+    for (_, sources, names) in statements {
+      for mut import in sources {
+        if import.kind == ImportKind::Import {
+          import.names = names.clone();
+        }
+        imports.push(import);
+      }
+    }
+    imports.dedup();
+    imports
+  }
+}
+
+/// Get the text of a node without the quotes of a string
+fn text(node: &Node, source: &[u8]) -> String {
+  node
+    .utf8_text(source)
+    .unwrap_or_default()
+    .trim_matches(|c| c == '"' || c == '\'' || c == '`')
+    .to_string()
+}
+
+/// Finds the imports of files that are not open in the editor
+///
+/// Parsers and compiled queries are kept for each language, so scanning
+/// many files only compiles each query once.
+#[derive(Default)]
+pub struct ImportScanner {
+  languages: HashMap<LanguageId, Option<(Parser, ImportsQuery)>>,
+}
+
+impl ImportScanner {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Get the imports of a file's content
+  ///
+  /// Empty for languages without an imports query.
+  pub fn scan(&mut self, language: LanguageId, source: &str) -> Vec<Import> {
+    let entry = self.languages.entry(language).or_insert_with(|| {
+      let ts_lang = language.tree_sitter_language()?;
+      let query = language.query(QueryKind::Imports)?;
+      let mut parser = Parser::new();
+      parser.set_language(&ts_lang).ok()?;
+      match ImportsQuery::new(&ts_lang, &query) {
+        Ok(query) => Some((parser, query)),
+        Err(e) => {
+          warn!("No imports for {:?}: {}", language, e);
+          None
+        },
+      }
+    });
+    let Some((parser, query)) = entry else {
+      return Vec::new();
+    };
+
+    match parser.parse(source, None) {
+      Some(tree) => query.imports(tree.root_node(), source.as_bytes()),
+      None => Vec::new(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sources(language: LanguageId, source: &str) -> Vec<(String, Vec<String>)> {
+    ImportScanner::new()
+      .scan(language, source)
+      .into_iter()
+      .map(|import| (import.source, import.names))
+      .collect()
+  }
+
+  #[test]
+  fn test_javascript_imports() {
+    let source = r#"
+import React from "react";
+export { a } from './a';
+const b = require('./b');
+const c = await import(`./c`);
+const d = other('./d');
+"#;
+    let imports: Vec<_> = sources(LanguageId::TypeScript, source)
+      .into_iter()
+      .map(|(source, _)| source)
+      .collect();
+    assert_eq!(imports, ["react", "./a", "./b", "./c"]);
+  }
+
+  #[test]
+  fn test_python_imports() {
+    let source = "import os, a.b as c\nfrom . import x, y as z\nfrom ..pkg import mod\n";
+    assert_eq!(
+      sources(LanguageId::Python, source),
+      [
+        ("os".to_string(), vec![]),
+        ("a.b".to_string(), vec![]),
+        (".".to_string(), vec!["x".to_string(), "y".to_string()]),
+        ("..pkg".to_string(), vec!["mod".to_string()]),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_rust_imports() {
+    let source = "mod a;\nmod inline {}\nuse crate::b::{c, d};\n";
+    let imports = ImportScanner::new().scan(LanguageId::Rust, source);
+    let kinds: Vec<_> = imports
+      .iter()
+      .map(|i| (i.kind, i.source.as_str(), i.line))
+      .collect();
+    assert_eq!(
+      kinds,
+      [
+        (ImportKind::Module, "a", 0),
+        (ImportKind::Import, "crate::b::{c, d}", 2),
+      ]
+    );
+  }
+}
@@ -0,0 +1,698 @@
+//! Auto-indentation
+//!
+//! A language's indents query marks the nodes that shape indentation. Lines
+//! inside an `@indent` node are indented one level deeper than the line the
+//! node starts on, except a line starting with an `@outdent` node, such as
+//! a closing bracket, which lines up with that line. Lines inside an
+//! `@align` node line up with its first child after the opening delimiter,
+//! as in `call(first,\n     second)`; when the delimiter ends its line the
+//! node indents like an `@indent` node. An `@extend` node also holds the
+//! line typed right after it, for languages like Python whose blocks have no
+//! closing delimiter.
+//!
+//! Indentation is relative to the line an enclosing node starts on, so code
+//! with an unusual base indentation keeps it. Languages without an indents
+//! query, and code the parser could not make sense of yet, fall back to a
+//! bracket heuristic.
+
+use crate::syntax::{LanguageId, SyntaxManager};
+use ferrum_core::prelude::*;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor};
+
+const INDENT_CAPTURE: &str = "indent";
+const OUTDENT_CAPTURE: &str = "outdent";
+const ALIGN_CAPTURE: &str = "align";
+const EXTEND_CAPTURE: &str = "extend";
+
+/// How indentation is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndentStyle {
+  /// Width of a tab and of one indentation level, in columns
+  pub tab_size: usize,
+  /// Indent with spaces rather than tabs
+  pub insert_spaces: bool,
+}
+
+impl Default for IndentStyle {
+  fn default() -> Self {
+    Self {
+      tab_size: 4,
+      insert_spaces: true,
+    }
+  }
+}
+
+impl IndentStyle {
+  /// Get one level of indentation
+  pub fn unit(&self) -> String {
+    if self.insert_spaces {
+      " ".repeat(self.tab_size)
+    } else {
+      "\t".to_string()
+    }
+  }
+
+  /// Get the width of leading whitespace, in columns
+  pub fn width(&self, whitespace: &str) -> usize {
+    whitespace.chars().fold(0, |width, c| match c {
+      '\t' => (width / self.tab_size.max(1) + 1) * self.tab_size.max(1),
+      _ => width + 1,
+    })
+  }
+
+  /// Get indentation `columns` wide
+  pub fn render(&self, columns: usize) -> String {
+    if self.insert_spaces {
+      " ".repeat(columns)
+    } else {
+      let tab_size = self.tab_size.max(1);
+      "\t".repeat(columns / tab_size) + &" ".repeat(columns % tab_size)
+    }
+  }
+}
+
+/// Where a line's indentation comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Indent {
+  /// `levels` deeper than the line containing byte `from`
+  Relative { from: usize, levels: usize },
+  /// Lined up with the character at byte `at`
+  Align { at: usize },
+}
+
+/// A compiled indents query
+pub(crate) struct IndentQuery {
+  query: Query,
+  indent_capture: Option<u32>,
+  outdent_capture: Option<u32>,
+  align_capture: Option<u32>,
+  extend_capture: Option<u32>,
+}
+
+/// Ids of the nodes captured around a line
+#[derive(Default)]
+struct Captured {
+  indent: HashSet<usize>,
+  outdent: HashSet<usize>,
+  align: HashSet<usize>,
+  extend: HashSet<usize>,
+}
+
+impl IndentQuery {
+  pub(crate) fn new(language: &tree_sitter::Language, source: &str) -> Result<Self> {
+    let query = Query::new(language, source)
+      .map_err(|e| Error::Internal(format!("Failed to compile indents query: {}", e)))?;
+
+    Ok(Self {
+      indent_capture: query.capture_index_for_name(INDENT_CAPTURE),
+      outdent_capture: query.capture_index_for_name(OUTDENT_CAPTURE),
+      align_capture: query.capture_index_for_name(ALIGN_CAPTURE),
+      extend_capture: query.capture_index_for_name(EXTEND_CAPTURE),
+      query,
+    })
+  }
+
+  /// Work out the indentation of a line
+  ///
+  /// `line_start` is the byte the line starts at, or the cursor for a line
+  /// about to be split off by a newline, and `first` the byte of its first
+  /// non-whitespace character, or its end if it has none. `None` if no
+  /// captured node encloses the line.
+  pub(crate) fn indent(
+    &self,
+    root: Node,
+    source: &[u8],
+    line_start: usize,
+    first: usize,
+  ) -> Option<Indent> {
+    if first == 0 {
+      return None;
+    }
+    let captured = self.captured(root, source, line_start.saturating_sub(1)..first + 1);
+
+    // The line starts with an outdent node, e.g. a closing bracket
+    let mut outdent = false;
+    let mut node = root.descendant_for_byte_range(first, first + 1);
+    while let Some(n) = node.filter(|n| n.start_byte() == first) {
+      outdent |= captured.outdent.contains(&n.id());
+      node = n.parent();
+    }
+
+    let mut node = root.descendant_for_byte_range(first - 1, first);
+    while let Some(n) = node {
+      node = n.parent();
+      let encloses = n.start_byte() < line_start
+        && (n.end_byte() > first
+          || (n.end_byte() == line_start && captured.extend.contains(&n.id())));
+      if !encloses {
+        continue;
+      }
+
+      let levels = if outdent { 0 } else { 1 };
+      if captured.align.contains(&n.id()) {
+        if outdent {
+          return Some(Indent::Relative {
+            from: n.start_byte(),
+            levels,
+          });
+        }
+        // Align to the first child after the opening delimiter, unless the
+        // delimiter ends its line
+        let anchor = n.child(1).filter(|anchor| {
+          anchor.start_position().row == n.start_position().row && anchor.start_byte() < line_start
+        });
+        return Some(match anchor {
+          Some(anchor) => Indent::Align {
+            at: anchor.start_byte(),
+          },
+          None => Indent::Relative {
+            from: n.start_byte(),
+            levels,
+          },
+        });
+      }
+      if captured.indent.contains(&n.id()) {
+        return Some(Indent::Relative {
+          from: n.start_byte(),
+          levels,
+        });
+      }
+    }
+
+    None
+  }
+
+  /// Collect the nodes captured in a byte range
+  fn captured(&self, root: Node, source: &[u8], range: Range<usize>) -> Captured {
+    let mut captured = Captured::default();
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(range);
+
+    for m in cursor.matches(&self.query, root, source) {
+      for capture in m.captures {
+        let set = match Some(capture.index) {
+          i if i == self.indent_capture => &mut captured.indent,
+          i if i == self.outdent_capture => &mut captured.outdent,
+          i if i == self.align_capture => &mut captured.align,
+          i if i == self.extend_capture => &mut captured.extend,
+          _ => continue,
+        };
+        set.insert(capture.node.id());
+      }
+    }
+
+    captured
+  }
+}
+
+/// A text replacement in chars
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndentEdit {
+  pub range: Range<usize>,
+  pub text: String,
+}
+
+/// Works out indentation for the lines of a text
+///
+/// Uses the indents query of the text's syntax tree where there is one,
+/// and the bracket heuristic elsewhere.
+pub struct Indenter<'a> {
+  rope: &'a Rope,
+  source: String,
+  syntax: Option<&'a SyntaxManager>,
+  style: IndentStyle,
+}
+
+impl<'a> Indenter<'a> {
+  /// Create an indenter; `syntax` must be up to date with `rope`
+  pub fn new(rope: &'a Rope, syntax: Option<&'a SyntaxManager>, style: IndentStyle) -> Self {
+    Self {
+      rope,
+      source: rope.to_string(),
+      syntax,
+      style,
+    }
+  }
+
+  /// Break the line at a char offset, indenting the new line
+  ///
+  /// Whitespace around the offset is replaced. Between a pair of brackets,
+  /// the closing one moves to a line of its own below the new line. Returns
+  /// the edit and the char offset of the cursor after it.
+  pub fn newline(&self, char_idx: usize) -> (IndentEdit, usize) {
+    let line = self.rope.char_to_line(char_idx);
+    let line_start = self.rope.line_to_char(line);
+    let line_end = line_start + line_len(self.rope, line);
+
+    let mut start = char_idx.min(line_end);
+    while start > line_start && is_blank(self.rope.char(start - 1)) {
+      start -= 1;
+    }
+    let mut end = char_idx.min(line_end);
+    while end < line_end && is_blank(self.rope.char(end)) {
+      end += 1;
+    }
+
+    let indent = self.indent(
+      self.rope.char_to_byte(start),
+      self.rope.char_to_byte(end),
+      &HashMap::new(),
+    );
+    let before = (start > line_start).then(|| self.rope.char(start - 1));
+    let after = (end < line_end).then(|| self.rope.char(end));
+
+    let pair = matches!(
+      (before, after),
+      (Some(open), Some(close)) if closing_bracket(open) == Some(close)
+    );
+
+    let mut text = match pair {
+      true => format!("\n{}{}", indent, self.style.unit()),
+      false => format!("\n{}", indent),
+    };
+    let cursor = start + text.chars().count();
+    if pair {
+      text.push('\n');
+      text.push_str(&indent);
+    }
+
+    (
+      IndentEdit {
+        range: start..end,
+        text,
+      },
+      cursor,
+    )
+  }
+
+  /// Reindent a range of lines
+  ///
+  /// Blank lines are emptied. Lines already indented right are left alone.
+  pub fn reindent(&self, lines: Range<usize>) -> Vec<IndentEdit> {
+    let mut reindented = HashMap::new();
+    let mut edits = Vec::new();
+
+    for line in lines.start..lines.end.min(self.rope.len_lines()) {
+      let start = self.rope.line_to_char(line);
+      let current = leading_whitespace(self.rope, line);
+      let first = start + current.chars().count();
+      let blank = first == start + line_len(self.rope, line);
+
+      let indent = if blank {
+        String::new()
+      } else {
+        self.indent(
+          self.rope.char_to_byte(start),
+          self.rope.char_to_byte(first),
+          &reindented,
+        )
+      };
+      if indent != current {
+        edits.push(IndentEdit {
+          range: start..first,
+          text: indent.clone(),
+        });
+      }
+      reindented.insert(line, indent);
+    }
+
+    edits
+  }
+
+  /// Paste text over a char range, shifting the indentation of its lines to
+  /// fit where it lands
+  ///
+  /// The pasted lines keep their indentation relative to each other. Pasted
+  /// at the start of a line, the text is indented for that line; pasted
+  /// after other text, its following lines line up with that text's line.
+  /// Returns the edit and the char offset of the cursor after it.
+  pub fn paste(&self, range: Range<usize>, text: &str) -> (IndentEdit, usize) {
+    let pasted: Vec<&str> = text.split('\n').collect();
+    if pasted.len() == 1 {
+      let cursor = range.start + text.chars().count();
+      return (
+        IndentEdit {
+          range,
+          text: text.to_string(),
+        },
+        cursor,
+      );
+    }
+
+    let line = self.rope.char_to_line(range.start);
+    let line_start = self.rope.line_to_char(line);
+    let at_line_start = self
+      .rope
+      .slice(line_start..range.start)
+      .chars()
+      .all(is_blank);
+
+    // Lines whose indentation is shifted, and the indentation they get
+    let (start, shifted, base) = if at_line_start {
+      let end = range.end.min(line_start + line_len(self.rope, line));
+      let mut first = range.start.max(line_start);
+      while first < end && is_blank(self.rope.char(first)) {
+        first += 1;
+      }
+      let base = self.indent(
+        self.rope.char_to_byte(line_start),
+        self.rope.char_to_byte(first),
+        &HashMap::new(),
+      );
+      (line_start, 0, base)
+    } else {
+      (range.start, 1, leading_whitespace(self.rope, line))
+    };
+
+    // A copy starting mid-line leaves the first line without its indentation
+    let measured = match pasted[0].starts_with(is_blank) {
+      true => shifted,
+      false => 1,
+    };
+    let widths = pasted[measured..]
+      .iter()
+      .filter(|l| !l.trim().is_empty())
+      .map(|l| self.style.width(&l[..l.len() - l.trim_start().len()]));
+    let min_width = widths.min().unwrap_or(0);
+
+    let mut lines = Vec::with_capacity(pasted.len());
+    for (i, l) in pasted.iter().enumerate() {
+      let content = l.trim_start();
+      if i < shifted {
+        lines.push(l.to_string());
+      } else if content.is_empty() {
+        lines.push(String::new());
+      } else {
+        let width = self.style.width(&l[..l.len() - content.len()]);
+        let extra = self.style.render(width.saturating_sub(min_width));
+        lines.push(format!("{}{}{}", base, extra, content));
+      }
+    }
+
+    let text = lines.join("\n");
+    let cursor = start + text.chars().count();
+    (
+      IndentEdit {
+        range: start..range.end,
+        text,
+      },
+      cursor,
+    )
+  }
+
+  /// Get the indentation for a line; see [`IndentQuery::indent`]
+  ///
+  /// `reindented` holds the new indentation of lines reindented before it.
+  fn indent(&self, line_start: usize, first: usize, reindented: &HashMap<usize, String>) -> String {
+    let guess = self.heuristic(line_start, first, reindented);
+    // A tree that has not caught up with the text can point past its end
+    let found = self
+      .syntax
+      .and_then(|syntax| syntax.indent(self.source.as_bytes(), line_start, first))
+      .filter(|found| match *found {
+        Indent::Relative { from, .. } => from < self.source.len(),
+        Indent::Align { at } => at < self.source.len(),
+      });
+    let Some(found) = found else {
+      return guess;
+    };
+
+    let (line, indent) = match found {
+      Indent::Relative { from, levels } => {
+        let line = self.rope.byte_to_line(from);
+        let indent = self.line_indent(line, reindented) + &self.style.unit().repeat(levels);
+        (line, indent)
+      },
+      Indent::Align { at } => {
+        let line = self.rope.byte_to_line(at);
+        let start = self.rope.line_to_char(line);
+        let current = leading_whitespace(self.rope, line).chars().count();
+        let column = self.rope.byte_to_char(at) - start - current;
+        (
+          line,
+          self.line_indent(line, reindented) + &" ".repeat(column),
+        )
+      },
+    };
+
+    // Unfinished code often parses as an error, so a bracket just opened
+    // can be missed by the query
+    let previous = self.previous_line(line_start).map(|(line, _)| line);
+    if previous != Some(line)
+      && self.opens_block(line_start, first)
+      && self.style.width(&guess) > self.style.width(&indent)
+    {
+      guess
+    } else {
+      indent
+    }
+  }
+
+  /// Get the indentation of a line, or its new one if it was reindented
+  fn line_indent(&self, line: usize, reindented: &HashMap<usize, String>) -> String {
+    reindented
+      .get(&line)
+      .cloned()
+      .unwrap_or_else(|| leading_whitespace(self.rope, line))
+  }
+
+  /// Find the last line with text before a byte, with that text
+  fn previous_line(&self, byte: usize) -> Option<(usize, String)> {
+    let end = self.rope.byte_to_char(byte);
+    let mut line = self.rope.char_to_line(end);
+    loop {
+      let start = self.rope.line_to_char(line);
+      let stop = end.min(start + line_len(self.rope, line));
+      let text = self.rope.slice(start..stop.max(start)).to_string();
+      if !text.trim().is_empty() {
+        return Some((line, text));
+      }
+      line = line.checked_sub(1)?;
+    }
+  }
+
+  /// Check if the text before a line ends by opening a bracket, or a block
+  /// in languages that open them with a colon
+  fn opens_block(&self, line_start: usize, first: usize) -> bool {
+    let Some((_, text)) = self.previous_line(line_start) else {
+      return false;
+    };
+    let Some(last) = text.trim_end().chars().last() else {
+      return false;
+    };
+    let colon = last == ':'
+      && self
+        .syntax
+        .is_some_and(|s| s.language_at(first) == LanguageId::Python);
+    closing_bracket(last).is_some() || colon
+  }
+
+  /// Guess the indentation of a line from the line before it: one level
+  /// deeper after an opening bracket, one level shallower before a closing
+  /// one
+  fn heuristic(
+    &self,
+    line_start: usize,
+    first: usize,
+    reindented: &HashMap<usize, String>,
+  ) -> String {
+    let Some((line, _)) = self.previous_line(line_start) else {
+      return String::new();
+    };
+    let indent = self.line_indent(line, reindented);
+    let width = self.style.width(&indent);
+    let closes =
+      first < self.source.len() && matches!(self.source.as_bytes()[first], b'}' | b']' | b')');
+
+    match (self.opens_block(line_start, first), closes) {
+      (true, false) => indent + &self.style.unit(),
+      (false, true) => self.style.render(width.saturating_sub(self.style.tab_size)),
+      _ => indent,
+    }
+  }
+}
+
+/// Get the leading whitespace of a line
+fn leading_whitespace(rope: &Rope, line: usize) -> String {
+  rope
+    .line(line)
+    .chars()
+    .take_while(|c| is_blank(*c))
+    .collect()
+}
+
+/// Get the length of a line in chars, without its line break
+fn line_len(rope: &Rope, line: usize) -> usize {
+  let text = rope.line(line);
+  let len = text.len_chars();
+  match (
+    len.checked_sub(2).map(|i| text.char(i)),
+    len.checked_sub(1).map(|i| text.char(i)),
+  ) {
+    (Some('\r'), Some('\n')) => len - 2,
+    (_, Some('\n' | '\r')) => len - 1,
+    _ => len,
+  }
+}
+
+fn is_blank(c: char) -> bool {
+  c == ' ' || c == '\t'
+}
+
+fn closing_bracket(open: char) -> Option<char> {
+  match open {
+    '{' => Some('}'),
+    '[' => Some(']'),
+    '(' => Some(')'),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn with_indenter<R>(
+    language: LanguageId,
+    text: &str,
+    style: IndentStyle,
+    f: impl FnOnce(&Indenter) -> R,
+  ) -> R {
+    let rope = Rope::from_str(text);
+    let syntax = SyntaxManager::new(language).ok();
+    if let Some(syntax) = &syntax {
+      syntax.parse(&rope).unwrap();
+    }
+    f(&Indenter::new(&rope, syntax.as_ref(), style))
+  }
+
+  fn newline(language: LanguageId, text: &str, at: &str) -> (String, usize) {
+    let char_idx = text[..text.find(at).unwrap() + at.len()].chars().count();
+    with_indenter(language, text, IndentStyle::default(), |indenter| {
+      let (edit, cursor) = indenter.newline(char_idx);
+      (edit.text, cursor - edit.range.start)
+    })
+  }
+
+  fn reindent(language: LanguageId, text: &str, style: IndentStyle) -> String {
+    let mut rope = Rope::from_str(text);
+    let edits = with_indenter(language, text, style, |indenter| {
+      indenter.reindent(0..usize::MAX)
+    });
+    for edit in edits.iter().rev() {
+      rope.remove(edit.range.clone());
+      rope.insert(edit.range.start, &edit.text);
+    }
+    rope.to_string()
+  }
+
+  #[test]
+  fn test_newline_in_rust() {
+    let text = "fn main() {\n    let x = 1;\n}\n";
+    assert_eq!(newline(LanguageId::Rust, text, "1;"), ("\n    ".into(), 5));
+    assert_eq!(newline(LanguageId::Rust, text, "}"), ("\n".into(), 1));
+
+    // Between brackets, the closing one gets a line of its own
+    let text = "fn main() {\n    call()\n}\n";
+    assert_eq!(
+      newline(LanguageId::Rust, text, "call("),
+      ("\n        \n    ".into(), 9)
+    );
+
+    // A block still missing its closing bracket
+    assert_eq!(
+      newline(LanguageId::Rust, "fn main() {", "{"),
+      ("\n    ".into(), 5)
+    );
+  }
+
+  #[test]
+  fn test_newline_in_python() {
+    let text = "def f():\n    return x\n";
+    assert_eq!(
+      newline(LanguageId::Python, "def f():\n", ":"),
+      ("\n    ".into(), 5)
+    );
+    assert_eq!(newline(LanguageId::Python, text, "x"), ("\n    ".into(), 5));
+
+    let text = "x = call(first, second)\n";
+    assert_eq!(
+      newline(LanguageId::Python, text, ","),
+      ("\n         ".into(), 10)
+    );
+  }
+
+  #[test]
+  fn test_reindent() {
+    let text = "fn main() {\nif x {\n      call(\n  a,\n    );\n\n  }\n        }\n";
+    let tabs = IndentStyle {
+      tab_size: 4,
+      insert_spaces: false,
+    };
+    assert_eq!(
+      reindent(LanguageId::Rust, text, tabs),
+      "fn main() {\n\tif x {\n\t\tcall(\n\t\t\ta,\n\t\t);\n\n\t}\n}\n"
+    );
+
+    let text = "if x:\n  a\nelse:\n  if y:\n    b\n  c\n";
+    assert_eq!(
+      reindent(LanguageId::Python, text, IndentStyle::default()),
+      "if x:\n    a\nelse:\n    if y:\n        b\n    c\n"
+    );
+
+    let text = "func f() {\nswitch x {\ncase 1:\nf()\ndefault:\n}\n}\n";
+    assert_eq!(
+      reindent(LanguageId::Go, text, tabs),
+      "func f() {\n\tswitch x {\n\tcase 1:\n\t\tf()\n\tdefault:\n\t}\n}\n"
+    );
+  }
+
+  #[test]
+  fn test_reindent_injected_code() {
+    let text = "<div>\n<script>\nif (x) {\ny();\n}\n</script>\n</div>\n";
+    assert_eq!(
+      reindent(LanguageId::Html, text, IndentStyle::default()),
+      "<div>\n    <script>\n        if (x) {\n            y();\n        }\n    </script>\n</div>\n"
+    );
+  }
+
+  #[test]
+  fn test_heuristic_without_query() {
+    let text = "a {\nb\n}\n";
+    assert_eq!(
+      reindent(LanguageId::Unknown, text, IndentStyle::default()),
+      "a {\n    b\n}\n"
+    );
+    assert_eq!(
+      newline(LanguageId::Unknown, "  text", "text"),
+      ("\n  ".into(), 3)
+    );
+  }
+
+  #[test]
+  fn test_paste_shifts_lines() {
+    let text = "fn main() {\n    \n}\n";
+    let pasted = "if x {\n        y();\n    }";
+    let (edit, cursor) =
+      with_indenter(LanguageId::Rust, text, IndentStyle::default(), |indenter| {
+        indenter.paste(16..16, pasted)
+      });
+    assert_eq!(edit.range, 12..16);
+    assert_eq!(edit.text, "    if x {\n        y();\n    }");
+    assert_eq!(cursor, 12 + edit.text.chars().count());
+
+    // After other text, the following lines line up with its line
+    let text = "    let x = ";
+    let (edit, _) = with_indenter(
+      LanguageId::Unknown,
+      text,
+      IndentStyle::default(),
+      |indenter| indenter.paste(12..12, "[\n  1,\n]"),
+    );
+    assert_eq!(edit.text, "[\n      1,\n    ]");
+  }
+}
@@ -0,0 +1,250 @@
+//! Language injections
+//!
+//! Documents often embed code in another language: fenced code blocks in
+//! Markdown, `<script>` and `<style>` in HTML, tagged template literals in
+//! JavaScript. A language's injection query marks these regions, which
+//! `SyntaxManager` then parses as child layers restricted to the injected
+//! ranges.
+//!
+//! Queries follow the tree-sitter conventions: `@injection.content` marks
+//! the injected nodes, and the language comes from an `@injection.language`
+//! capture or an `injection.language` property. `injection.combined` parses
+//! all matches of a pattern as one layer, and `injection.include-children`
+//! keeps the content's child nodes, which are left out by default.
+
+use crate::syntax::LanguageId;
+use ferrum_core::prelude::*;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ops::Range;
+use tree_sitter::{
+  InputEdit, Language, Node, Point as TsPoint, Query, QueryCursor, Range as TsRange,
+};
+
+const CONTENT_CAPTURE: &str = "injection.content";
+const LANGUAGE_CAPTURE: &str = "injection.language";
+const LANGUAGE_PROPERTY: &str = "injection.language";
+const COMBINED_PROPERTY: &str = "injection.combined";
+const INCLUDE_CHILDREN_PROPERTY: &str = "injection.include-children";
+
+/// A region of a document written in another language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Injection {
+  pub language: LanguageId,
+  /// Document ranges holding the injected code, sorted and disjoint
+  pub ranges: Vec<TsRange>,
+}
+
+impl Injection {
+  /// Get the byte range from the start of the first range to the end of
+  /// the last
+  pub fn span(&self) -> Range<usize> {
+    span(&self.ranges)
+  }
+}
+
+/// A compiled injection query
+pub(crate) struct InjectionQuery {
+  query: Query,
+  content_capture: Option<u32>,
+  language_capture: Option<u32>,
+}
+
+impl InjectionQuery {
+  pub(crate) fn new(language: &Language, source: &str) -> Result<Self> {
+    let query = Query::new(language, source)
+      .map_err(|e| Error::Internal(format!("Failed to compile injection query: {}", e)))?;
+
+    Ok(Self {
+      content_capture: query.capture_index_for_name(CONTENT_CAPTURE),
+      language_capture: query.capture_index_for_name(LANGUAGE_CAPTURE),
+      query,
+    })
+  }
+
+  /// Find the injections in a tree, ordered by position
+  ///
+  /// Injections of languages without a grammar are skipped.
+  pub(crate) fn injections(&self, root: Node, source: &[u8]) -> Vec<Injection> {
+    let Some(content_capture) = self.content_capture else {
+      return Vec::new();
+    };
+
+    let mut injections: Vec<Injection> = Vec::new();
+    let mut combined: HashMap<(usize, LanguageId), usize> = HashMap::new();
+    let mut cursor = QueryCursor::new();
+
+    for m in cursor.matches(&self.query, root, source) {
+      let name = m
+        .captures
+        .iter()
+        .find(|c| Some(c.index) == self.language_capture)
+        .and_then(|c| c.node.utf8_text(source).ok())
+        .or_else(|| self.property(m.pattern_index, LANGUAGE_PROPERTY));
+      let Some(language) = name.map(LanguageId::from_name) else {
+        continue;
+      };
+      if language.tree_sitter_language().is_none() {
+        continue;
+      }
+
+      let include_children = self.property_set(m.pattern_index, INCLUDE_CHILDREN_PROPERTY);
+      let mut ranges = Vec::new();
+      for capture in m.captures.iter().filter(|c| c.index == content_capture) {
+        content_ranges(capture.node, include_children, &mut ranges);
+      }
+      if ranges.is_empty() {
+        continue;
+      }
+
+      if self.property_set(m.pattern_index, COMBINED_PROPERTY) {
+        match combined.entry((m.pattern_index, language)) {
+          Entry::Occupied(entry) => {
+            injections[*entry.get()].ranges.extend(ranges);
+            continue;
+          },
+          Entry::Vacant(entry) => {
+            entry.insert(injections.len());
+          },
+        }
+      }
+      injections.push(Injection { language, ranges });
+    }
+
+    // The parser needs sorted, non-overlapping ranges
+    for injection in &mut injections {
+      injection.ranges.sort_by_key(|r| r.start_byte);
+      let mut end = 0;
+      injection.ranges.retain(|r| {
+        let keep = r.start_byte >= end;
+        end = end.max(r.end_byte);
+        keep
+      });
+    }
+    injections.sort_by_key(|injection| injection.span().start);
+    injections
+  }
+
+  fn property(&self, pattern: usize, key: &str) -> Option<&str> {
+    self
+      .query
+      .property_settings(pattern)
+      .iter()
+      .find(|p| &*p.key == key)
+      .and_then(|p| p.value.as_deref())
+  }
+
+  fn property_set(&self, pattern: usize, key: &str) -> bool {
+    self
+      .query
+      .property_settings(pattern)
+      .iter()
+      .any(|p| &*p.key == key)
+  }
+}
+
+/// Add the ranges of `node` to inject, leaving out its children unless
+/// `include_children` is set
+fn content_ranges(node: Node, include_children: bool, ranges: &mut Vec<TsRange>) {
+  let mut start = (node.start_byte(), node.start_position());
+  if !include_children {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+      push_range(ranges, start, (child.start_byte(), child.start_position()));
+      start = (child.end_byte(), child.end_position());
+    }
+  }
+  push_range(ranges, start, (node.end_byte(), node.end_position()));
+}
+
+fn push_range(ranges: &mut Vec<TsRange>, start: (usize, TsPoint), end: (usize, TsPoint)) {
+  if end.0 > start.0 {
+    ranges.push(TsRange {
+      start_byte: start.0,
+      end_byte: end.0,
+      start_point: start.1,
+      end_point: end.1,
+    });
+  }
+}
+
+/// Get the byte span of sorted ranges
+pub(crate) fn span(ranges: &[TsRange]) -> Range<usize> {
+  match (ranges.first(), ranges.last()) {
+    (Some(first), Some(last)) => first.start_byte..last.end_byte,
+    _ => 0..0,
+  }
+}
+
+/// Map a byte offset from before a list of edits to after them
+///
+/// Offsets inside a replaced region move to the end of the replacement.
+pub(crate) fn map_offset(mut offset: usize, edits: &[InputEdit]) -> usize {
+  for edit in edits {
+    if offset <= edit.start_byte {
+      continue;
+    }
+    offset = if offset >= edit.old_end_byte {
+      offset - edit.old_end_byte + edit.new_end_byte
+    } else {
+      edit.new_end_byte
+    };
+  }
+  offset
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tree_sitter::Parser;
+
+  fn find(language: LanguageId, source: &str) -> Vec<Injection> {
+    let ts_lang = language.tree_sitter_language().unwrap();
+    let mut parser = Parser::new();
+    parser.set_language(&ts_lang).unwrap();
+    let tree = parser.parse(source, None).unwrap();
+
+    let query = InjectionQuery::new(&ts_lang, language.injection_query().unwrap()).unwrap();
+    query.injections(tree.root_node(), source.as_bytes())
+  }
+
+  #[test]
+  fn test_markdown_fence_uses_info_string() {
+    let source = "# Title\n\n```rust\nfn main() {}\n```\n\n```nope\nx\n```\n";
+    let injections = find(LanguageId::Markdown, source);
+
+    assert_eq!(injections.len(), 1);
+    assert_eq!(injections[0].language, LanguageId::Rust);
+    assert_eq!(&source[injections[0].span()], "fn main() {}\n");
+  }
+
+  #[test]
+  fn test_template_literal_is_combined_without_substitutions() {
+    let source = "const style = css`color: ${color}; margin: 0;`;";
+    let injections = find(LanguageId::JavaScript, source);
+
+    assert_eq!(injections.len(), 1);
+    assert_eq!(injections[0].language, LanguageId::Css);
+    let texts: Vec<_> = injections[0]
+      .ranges
+      .iter()
+      .map(|r| &source[r.start_byte..r.end_byte])
+      .collect();
+    assert_eq!(texts, vec!["color: ", "; margin: 0;"]);
+  }
+
+  #[test]
+  fn test_map_offset() {
+    let edit = InputEdit {
+      start_byte: 5,
+      old_end_byte: 10,
+      new_end_byte: 7,
+      start_position: TsPoint::default(),
+      old_end_position: TsPoint::default(),
+      new_end_position: TsPoint::default(),
+    };
+    assert_eq!(map_offset(3, &[edit]), 3);
+    assert_eq!(map_offset(8, &[edit]), 7);
+    assert_eq!(map_offset(12, &[edit]), 9);
+  }
+}
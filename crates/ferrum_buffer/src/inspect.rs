@@ -0,0 +1,376 @@
+//! Syntax tree inspection
+//!
+//! Debugging aids for grammar, query and plugin authors: the tree
+//! tree-sitter produced for a buffer, as an S-expression or a node tree,
+//! and the captures of an arbitrary query run against it.
+
+use crate::position::Point;
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use tree_sitter::{Node, Query, QueryCursor};
+
+/// Longest capture text returned, in bytes; longer text is cut off
+const MAX_CAPTURE_TEXT: usize = 256;
+
+/// Matches a query may have in progress at once; past this tree-sitter
+/// drops the earliest ones
+const QUERY_MATCH_LIMIT: u32 = 256;
+
+/// How long a query may run before it is stopped
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Bytes a query is run over in one step; query cursors have no timeout of
+/// their own, so the time is checked between steps
+const QUERY_STEP: usize = 64 * 1024;
+
+/// A node of a syntax tree
+///
+/// Columns are in bytes, as tree-sitter counts them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyntaxNode {
+  pub kind: String,
+  pub named: bool,
+  /// Name of the field of the parent this node is in
+  pub field: Option<String>,
+  pub start_byte: usize,
+  pub end_byte: usize,
+  pub start: Point,
+  pub end: Point,
+  pub is_error: bool,
+  /// Inserted by the parser to recover from an error
+  pub is_missing: bool,
+  pub children: Vec<SyntaxNode>,
+}
+
+impl SyntaxNode {
+  /// Build the tree under a node, leaving out anonymous nodes if
+  /// `named_only` is set
+  pub fn build(node: Node, named_only: bool) -> Self {
+    // Walk with a cursor instead of recursing, as trees can be deep
+    let mut cursor = node.walk();
+    let mut stack = vec![Self::leaf(node, None)];
+    loop {
+      if cursor.goto_first_child() {
+        stack.push(Self::leaf(cursor.node(), cursor.field_name()));
+        continue;
+      }
+
+      loop {
+        if stack.len() == 1 {
+          return stack.pop().unwrap();
+        }
+        let done = stack.pop().unwrap();
+        if done.named || !named_only {
+          stack.last_mut().unwrap().children.push(done);
+        }
+        if cursor.goto_next_sibling() {
+          stack.push(Self::leaf(cursor.node(), cursor.field_name()));
+          break;
+        }
+        cursor.goto_parent();
+      }
+    }
+  }
+
+  fn leaf(node: Node, field: Option<&str>) -> Self {
+    Self {
+      kind: node.kind().to_string(),
+      named: node.is_named(),
+      field: field.map(str::to_string),
+      start_byte: node.start_byte(),
+      end_byte: node.end_byte(),
+      start: point(node.start_position()),
+      end: point(node.end_position()),
+      is_error: node.is_error(),
+      is_missing: node.is_missing(),
+      children: Vec::new(),
+    }
+  }
+}
+
+/// A node captured by a query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryCapture {
+  /// Capture name, without the `@`
+  pub name: String,
+  /// Index of the pattern in the query that matched
+  pub pattern: usize,
+  /// Kind of the captured node
+  pub kind: String,
+  pub start_byte: usize,
+  pub end_byte: usize,
+  pub start: Point,
+  pub end: Point,
+  /// Text of the node, cut off after 256 bytes
+  pub text: String,
+}
+
+/// Captures of a query run against a tree
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryResult {
+  pub captures: Vec<QueryCapture>,
+  /// Set if the query hit the match limit or ran out of time, so some
+  /// captures are missing
+  pub truncated: bool,
+}
+
+/// Run a query against a tree and get its captures in document order
+///
+/// With a byte range, only matches intersecting it are returned. Text
+/// predicates such as `#eq?` and `#match?` are applied. Queries with too
+/// many matches in progress at once or that run too long are cut short
+/// and marked as truncated. The time is checked after each capture and
+/// each 64 KiB of the source, so a query may overrun by one step.
+pub fn run_query(
+  language: &tree_sitter::Language,
+  root: Node,
+  source: &[u8],
+  query: &str,
+  range: Option<Range<usize>>,
+) -> Result<QueryResult> {
+  run_query_within(language, root, source, query, range, QueryLimits::default())
+}
+
+/// Bounds on the work of a query
+#[derive(Debug, Clone, Copy)]
+struct QueryLimits {
+  match_limit: u32,
+  timeout: Duration,
+  step: usize,
+}
+
+impl Default for QueryLimits {
+  fn default() -> Self {
+    Self {
+      match_limit: QUERY_MATCH_LIMIT,
+      timeout: QUERY_TIMEOUT,
+      step: QUERY_STEP,
+    }
+  }
+}
+
+fn run_query_within(
+  language: &tree_sitter::Language,
+  root: Node,
+  source: &[u8],
+  query: &str,
+  range: Option<Range<usize>>,
+  limits: QueryLimits,
+) -> Result<QueryResult> {
+  let query =
+    Query::new(language, query).map_err(|e| Error::Internal(format!("Invalid query: {}", e)))?;
+  let names = query.capture_names();
+
+  let mut cursor = QueryCursor::new();
+  cursor.set_match_limit(limits.match_limit);
+
+  let range = range.unwrap_or(0..source.len());
+  let deadline = Instant::now() + limits.timeout;
+  let mut timed_out = false;
+  let mut exceeded_match_limit = false;
+  let mut captures = Vec::new();
+  let mut start = range.start;
+  loop {
+    let end = start.saturating_add(limits.step.max(1)).min(range.end);
+    cursor.set_byte_range(start..end);
+    for (m, index) in cursor.captures(&query, root, source) {
+      if Instant::now() >= deadline {
+        timed_out = true;
+        break;
+      }
+
+      // A match spanning steps is found in each of them, so take each
+      // capture in the step it starts in
+      let capture = m.captures[index];
+      let node = capture.node;
+      let before = start > range.start && node.start_byte() < start;
+      let after = end < range.end && node.start_byte() >= end;
+      if before || after {
+        continue;
+      }
+
+      let text = &source[node.byte_range()];
+      let mut len = text.len().min(MAX_CAPTURE_TEXT);
+      // Cut at a character boundary
+      while len < text.len() && (text[len] & 0xC0) == 0x80 {
+        len -= 1;
+      }
+
+      captures.push(QueryCapture {
+        name: names[capture.index as usize].to_string(),
+        pattern: m.pattern_index,
+        kind: node.kind().to_string(),
+        start_byte: node.start_byte(),
+        end_byte: node.end_byte(),
+        start: point(node.start_position()),
+        end: point(node.end_position()),
+        text: String::from_utf8_lossy(&text[..len]).into_owned(),
+      });
+    }
+    exceeded_match_limit |= cursor.did_exceed_match_limit();
+
+    if timed_out || end >= range.end {
+      break;
+    }
+    if Instant::now() >= deadline {
+      timed_out = true;
+      break;
+    }
+    start = end;
+  }
+
+  Ok(QueryResult {
+    captures,
+    truncated: timed_out || exceeded_match_limit,
+  })
+}
+
+fn point(point: tree_sitter::Point) -> Point {
+  Point::new(point.row, point.column)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::syntax::LanguageId;
+  use tree_sitter::{Parser, Tree};
+
+  fn parse(source: &str) -> (tree_sitter::Language, Tree) {
+    let ts_lang = LanguageId::Rust.tree_sitter_language().unwrap();
+    let mut parser = Parser::new();
+    parser.set_language(&ts_lang).unwrap();
+    let tree = parser.parse(source, None).unwrap();
+    (ts_lang, tree)
+  }
+
+  #[test]
+  fn test_build_node_tree() {
+    let (_, tree) = parse("fn f() {}");
+    let root = SyntaxNode::build(tree.root_node(), false);
+    assert_eq!(root.kind, "source_file");
+
+    let function = &root.children[0];
+    assert_eq!(function.kind, "function_item");
+    let kinds: Vec<_> = function.children.iter().map(|n| n.kind.as_str()).collect();
+    assert_eq!(kinds, ["fn", "identifier", "parameters", "block"]);
+    assert_eq!(function.children[1].field.as_deref(), Some("name"));
+    assert_eq!(function.children[3].start, Point::new(0, 7));
+
+    let named = SyntaxNode::build(tree.root_node(), true);
+    let kinds: Vec<_> = named.children[0]
+      .children
+      .iter()
+      .map(|n| n.kind.as_str())
+      .collect();
+    assert_eq!(kinds, ["identifier", "parameters", "block"]);
+    assert!(named.children[0].children[1].children.is_empty());
+  }
+
+  #[test]
+  fn test_run_query() {
+    let source = "fn one() {}\nfn two() {}\n";
+    let (ts_lang, tree) = parse(source);
+    let query = r#"(function_item name: (identifier) @name (#eq? @name "two")) @item"#;
+
+    let result = run_query(&ts_lang, tree.root_node(), source.as_bytes(), query, None).unwrap();
+    assert!(!result.truncated);
+    let captures = result.captures;
+    let names: Vec<_> = captures
+      .iter()
+      .map(|c| (c.name.as_str(), c.text.as_str()))
+      .collect();
+    assert_eq!(names, [("item", "fn two() {}"), ("name", "two")]);
+    assert_eq!(captures[1].start, Point::new(1, 3));
+
+    let in_range = run_query(
+      &ts_lang,
+      tree.root_node(),
+      source.as_bytes(),
+      "(identifier) @id",
+      Some(0..5),
+    )
+    .unwrap()
+    .captures;
+    assert_eq!(in_range.len(), 1);
+    assert_eq!(in_range[0].text, "one");
+
+    let error = run_query(
+      &ts_lang,
+      tree.root_node(),
+      source.as_bytes(),
+      "(nope) @x",
+      None,
+    );
+    assert!(matches!(error, Err(Error::Internal(_))));
+  }
+
+  #[test]
+  fn test_limited_query_is_truncated() {
+    let source = "fn one() {}\nfn two() {}\nfn three() {}\n";
+    let (ts_lang, tree) = parse(source);
+    let run = |query, match_limit, timeout| {
+      run_query_within(
+        &ts_lang,
+        tree.root_node(),
+        source.as_bytes(),
+        query,
+        None,
+        QueryLimits {
+          match_limit,
+          timeout,
+          ..QueryLimits::default()
+        },
+      )
+      .unwrap()
+    };
+
+    let timed_out = run("(identifier) @id", QUERY_MATCH_LIMIT, Duration::ZERO);
+    assert!(timed_out.truncated);
+    assert!(timed_out.captures.is_empty());
+
+    // Every pair of functions is a match in progress until the file ends
+    let pairs = "(source_file (function_item) @a (function_item) @b)";
+    assert!(!run(pairs, QUERY_MATCH_LIMIT, QUERY_TIMEOUT).truncated);
+    assert!(run(pairs, 1, QUERY_TIMEOUT).truncated);
+  }
+
+  #[test]
+  fn test_query_runs_in_steps() {
+    let source = "fn one() {}\nfn two() {}\nfn three() {}\n";
+    let (ts_lang, tree) = parse(source);
+    let run = |query, range, timeout, step| {
+      run_query_within(
+        &ts_lang,
+        tree.root_node(),
+        source.as_bytes(),
+        query,
+        range,
+        QueryLimits {
+          timeout,
+          step,
+          ..QueryLimits::default()
+        },
+      )
+      .unwrap()
+    };
+
+    // Matches spanning steps are not repeated
+    let query = "(function_item name: (identifier) @name) @function (source_file) @file";
+    let whole = run(query, None, QUERY_TIMEOUT, QUERY_STEP);
+    assert_eq!(whole.captures.len(), 7);
+    assert_eq!(run(query, None, QUERY_TIMEOUT, 5), whole);
+    assert_eq!(
+      run(query, Some(14..30), QUERY_TIMEOUT, 5),
+      run(query, Some(14..30), QUERY_TIMEOUT, QUERY_STEP),
+    );
+
+    // A query that captures nothing still stops in time
+    let nothing = "((identifier) @id (#eq? @id \"missing\"))";
+    let timed_out = run(nothing, None, Duration::ZERO, 5);
+    assert!(timed_out.truncated);
+    assert!(timed_out.captures.is_empty());
+    assert!(!run(nothing, None, QUERY_TIMEOUT, 5).truncated);
+  }
+}
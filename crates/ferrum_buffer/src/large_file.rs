@@ -0,0 +1,216 @@
+//! Large-file mode
+//!
+//! Files past `LARGE_FILE_THRESHOLD` are streamed into the rope in fixed-size
+//! chunks instead of being read and decoded in one piece, so peak memory
+//! stays close to the size of the rope itself. Buffers loaded this way skip
+//! syntax and depth analysis, cap their undo history by size, and are meant
+//! to be rendered through `Viewport`s rather than by copying the content.
+
+use encoding_rs::{CoderResult, Encoding, UTF_8};
+use ferrum_core::prelude::*;
+use ferrum_core::task::TaskProgress;
+use ropey::{Rope, RopeBuilder};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+use crate::format::{FileFormat, LineEndingCounts};
+
+/// Bytes read from disk per chunk
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A window of lines for rendering
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Viewport {
+  /// Index of the first line in the window
+  pub first_line: usize,
+  /// Line contents, without their line breaks
+  pub lines: Vec<String>,
+  /// Total number of lines in the buffer
+  pub total_lines: usize,
+  /// Buffer version the lines were read at
+  pub version: u64,
+}
+
+impl Viewport {
+  /// Read up to `line_count` lines starting at `first_line`
+  ///
+  /// A window past the end of the text is empty rather than an error, so
+  /// scrolling can run ahead of edits that shrink the buffer.
+  pub(crate) fn read(rope: &Rope, version: u64, first_line: usize, line_count: usize) -> Self {
+    let total_lines = rope.len_lines();
+    let end = first_line.saturating_add(line_count).min(total_lines);
+    let lines = (first_line.min(end)..end)
+      .map(|idx| {
+        let mut line = rope.line(idx).to_string();
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+        line
+      })
+      .collect();
+
+    Self {
+      first_line,
+      lines,
+      total_lines,
+      version,
+    }
+  }
+}
+
+/// Stream text into a rope in chunks, detecting its format
+///
+/// The encoding is sniffed from the first chunk: a BOM wins, valid UTF-8 is
+/// taken as UTF-8 and anything else is guessed. Sequences split across
+/// chunk boundaries are carried over by the decoder; malformed ones decode
+/// to U+FFFD. Line endings are counted over every chunk. `progress` advances
+/// by the number of bytes read, so callers should set its total to the file
+/// size beforehand.
+pub fn load(mut reader: impl Read, progress: &TaskProgress) -> Result<(Rope, FileFormat)> {
+  let mut chunk = vec![0u8; CHUNK_SIZE];
+  let mut builder = RopeBuilder::new();
+  let mut decoded = String::new();
+  let mut bytes_read = 0u64;
+  let mut had_errors = false;
+
+  let first_len = read_chunk(&mut reader, &mut chunk)?;
+  let (encoding, bom_len) = sniff_encoding(&chunk[..first_len], first_len < CHUNK_SIZE);
+  let mut decoder = encoding.new_decoder_without_bom_handling();
+
+  let mut line_endings = LineEndingCounts::default();
+  let mut len = first_len;
+  let mut start = bom_len;
+  loop {
+    let last = len == 0;
+    let input = &chunk[start..len];
+
+    decoded.clear();
+    if let Some(needed) = decoder.max_utf8_buffer_length(input.len()) {
+      decoded.reserve(needed);
+    }
+    let (result, _, errors) = decoder.decode_to_string(input, &mut decoded, last);
+    debug_assert!(matches!(result, CoderResult::InputEmpty));
+    had_errors |= errors;
+
+    line_endings.add(&decoded);
+    builder.append(&decoded);
+
+    bytes_read += len as u64;
+    progress.set_current(bytes_read);
+    if last {
+      break;
+    }
+
+    len = read_chunk(&mut reader, &mut chunk)?;
+    start = 0;
+  }
+
+  if had_errors {
+    warn!("Malformed {} sequences replaced while loading", encoding.name());
+  }
+
+  let (line_ending, mixed_line_endings) = line_endings.finish();
+  let format = FileFormat {
+    encoding,
+    bom: bom_len > 0,
+    line_ending,
+    mixed_line_endings,
+  };
+  Ok((builder.finish(), format))
+}
+
+/// Fill the buffer as far as possible, returning the number of bytes read
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+  let mut filled = 0;
+  while filled < buf.len() {
+    match reader.read(&mut buf[filled..]) {
+      Ok(0) => break,
+      Ok(n) => filled += n,
+      Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+      Err(e) => return Err(e.into()),
+    }
+  }
+  Ok(filled)
+}
+
+/// Pick the encoding from the first chunk, returning it and the BOM length
+fn sniff_encoding(bytes: &[u8], is_complete: bool) -> (&'static Encoding, usize) {
+  if let Some(found) = Encoding::for_bom(bytes) {
+    return found;
+  }
+
+  // A multi-byte sequence may be cut off at the end of the chunk
+  let valid_utf8 = match std::str::from_utf8(bytes) {
+    Ok(_) => true,
+    Err(e) => e.error_len().is_none() && !is_complete,
+  };
+  if valid_utf8 {
+    return (UTF_8, 0);
+  }
+
+  let mut detector = chardetng::EncodingDetector::new();
+  detector.feed(bytes, is_complete);
+  (detector.guess(None, true), 0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::format::LineEnding;
+
+  #[test]
+  fn test_load_splits_multibyte_across_chunks() {
+    // Put a 4-byte character across the first chunk boundary
+    let mut text = "a".repeat(CHUNK_SIZE - 2);
+    text.push_str("😀\r\nend\r\n");
+
+    let progress = TaskProgress::new();
+    progress.set_total(text.len() as u64);
+    let (rope, format) = load(text.as_bytes(), &progress).unwrap();
+
+    assert_eq!(rope.len_bytes(), text.len());
+    assert_eq!(rope.line(0).chars().nth(CHUNK_SIZE - 2), Some('😀'));
+    assert_eq!(format.line_ending, LineEnding::Crlf);
+    assert_eq!(progress.percentage(), 100);
+  }
+
+  #[test]
+  fn test_load_detects_line_endings_in_every_chunk() {
+    // LF throughout the first chunk, then CRLF, with one split across the
+    // chunk boundary
+    let mut text = "a\n".repeat(CHUNK_SIZE / 2 - 1);
+    text.push_str("a\r\nb\r\n");
+    assert_eq!(text.as_bytes()[CHUNK_SIZE - 1], b'\r');
+
+    let (_, format) = load(text.as_bytes(), &TaskProgress::new()).unwrap();
+    assert!(format.mixed_line_endings);
+    assert_eq!(format.line_ending, LineEnding::Lf);
+
+    let text = format!("{}\r\nb\r\n", "a".repeat(CHUNK_SIZE - 1));
+    let (_, format) = load(text.as_bytes(), &TaskProgress::new()).unwrap();
+    assert_eq!(format.line_ending, LineEnding::Crlf);
+    assert!(!format.mixed_line_endings);
+  }
+
+  #[test]
+  fn test_load_utf16_with_bom() {
+    let mut bytes = vec![0xFF, 0xFE];
+    bytes.extend("héllo\n".encode_utf16().flat_map(|u| u.to_le_bytes()));
+
+    let (rope, format) = load(bytes.as_slice(), &TaskProgress::new()).unwrap();
+    assert_eq!(rope.to_string(), "héllo\n");
+    assert!(format.bom);
+    assert_eq!(format.encoding, encoding_rs::UTF_16LE);
+  }
+
+  #[test]
+  fn test_viewport_window() {
+    let rope = Rope::from_str("one\r\ntwo\nthree");
+
+    let viewport = Viewport::read(&rope, 3, 1, 5);
+    assert_eq!(viewport.lines, vec!["two", "three"]);
+    assert_eq!(viewport.total_lines, 3);
+    assert_eq!(viewport.version, 3);
+
+    assert!(Viewport::read(&rope, 0, 10, 5).lines.is_empty());
+  }
+}
@@ -5,19 +5,48 @@
 //! with tree-sitter for syntax parsing.
 
 pub mod anchor;
+pub mod brackets;
 pub mod buffer;
+pub mod coords;
+pub mod diff;
 pub mod edit;
+pub mod folds;
+pub mod format;
+pub mod grammar;
 pub mod history;
+pub mod imports;
+pub mod indent;
+pub mod injection;
+pub mod inspect;
+pub mod large_file;
+pub mod locals;
 pub mod position;
 pub mod selection;
+pub mod snapshot;
 pub mod syntax;
+pub mod textobject;
+pub mod transaction;
 
 pub use anchor::{Anchor, AnchorBias, AnchorId, AnchorSet};
+pub use brackets::{BracketPair, Brackets};
 pub use buffer::Buffer;
-pub use edit::{Edit, EditKind};
-pub use history::History;
+pub use coords::TextUnit;
+pub use edit::{Change, Edit, EditKind};
+pub use folds::{FoldKind, FoldRange};
+pub use format::{FileFormat, LineEnding};
+pub use grammar::{global_grammar_registry, GrammarDefinition, GrammarRegistry, QueryKind};
+pub use history::{History, HistoryStep, UndoTree, UndoTreeNode};
+pub use imports::{Import, ImportKind, ImportScanner};
+pub use indent::{IndentEdit, IndentStyle, Indenter};
+pub use injection::Injection;
+pub use inspect::{QueryCapture, QueryResult, SyntaxNode};
+pub use large_file::Viewport;
+pub use locals::{LocalKind, Locals};
 pub use position::{Point, Position};
 pub use selection::{Selection, SelectionSet};
+pub use snapshot::BufferSnapshot;
+pub use textobject::{TextObject, TextObjectKind, TextObjectScope};
+pub use transaction::{LineEndingChange, Transaction, TransactionBuilder};
 
 // Re-export syntax types
 pub use syntax::{
@@ -0,0 +1,322 @@
+//! Scope-aware local variable tracking
+//!
+//! A language's locals query marks scopes, the definitions they introduce,
+//! and references to them. Each reference is resolved to the innermost
+//! visible definition of the same name, so it can be highlighted like its
+//! definition, e.g. as a parameter, and all uses of a symbol can be found
+//! without a language server.
+//!
+//! Queries follow the tree-sitter conventions: `@local.scope` marks scopes,
+//! `@local.definition.<kind>` definitions, and `@local.reference`
+//! references. A scope with `(#set! local.scope-inherits false)` hides the
+//! definitions of its enclosing scopes.
+
+use crate::syntax::HighlightKind;
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor};
+
+const SCOPE_CAPTURE: &str = "local.scope";
+const DEFINITION_CAPTURE: &str = "local.definition";
+const REFERENCE_CAPTURE: &str = "local.reference";
+const SCOPE_INHERITS_PROPERTY: &str = "local.scope-inherits";
+
+/// What a local definition introduces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LocalKind {
+  Parameter,
+  Variable,
+  Constant,
+  Import,
+  Function,
+}
+
+impl LocalKind {
+  /// Parse from the suffix of a `local.definition.<kind>` capture
+  pub fn from_capture_suffix(suffix: &str) -> Option<Self> {
+    Some(match suffix {
+      "parameter" => Self::Parameter,
+      "var" | "variable" => Self::Variable,
+      "constant" => Self::Constant,
+      "import" | "namespace" => Self::Import,
+      "function" | "method" => Self::Function,
+      _ => return None,
+    })
+  }
+
+  /// Get the highlight for definitions of this kind and their references
+  pub fn highlight_kind(&self) -> HighlightKind {
+    match self {
+      Self::Parameter => HighlightKind::VariableParameter,
+      Self::Variable => HighlightKind::Variable,
+      Self::Constant => HighlightKind::Constant,
+      Self::Import => HighlightKind::Namespace,
+      Self::Function => HighlightKind::Function,
+    }
+  }
+}
+
+/// A definition found by a locals query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+  pub range: Range<usize>,
+  /// `None` for a plain `@local.definition` or an unknown kind
+  pub kind: Option<LocalKind>,
+}
+
+/// The resolved definitions and references of a tree
+#[derive(Debug, Clone, Default)]
+pub struct Locals {
+  definitions: Vec<Definition>,
+  /// Reference ranges in document order, with the index of their definition
+  references: Vec<(Range<usize>, usize)>,
+}
+
+impl Locals {
+  /// Get the definitions, in document order
+  pub fn definitions(&self) -> &[Definition] {
+    &self.definitions
+  }
+
+  /// Get the kinds of the definitions and resolved references overlapping
+  /// a byte range
+  pub fn kinds_in(
+    &self,
+    range: &Range<usize>,
+  ) -> impl Iterator<Item = (Range<usize>, LocalKind)> + '_ {
+    let definitions = self
+      .definitions
+      .iter()
+      .filter_map(|d| Some((d.range.clone(), d.kind?)));
+    let references = self
+      .references
+      .iter()
+      .filter_map(|(r, d)| Some((r.clone(), self.definitions[*d].kind?)));
+    let range = range.clone();
+    definitions
+      .chain(references)
+      .filter(move |(r, _)| r.start < range.end && range.start < r.end)
+  }
+
+  /// Get the definition of the symbol at a byte offset, along with all its
+  /// references, in document order
+  ///
+  /// Empty if the offset is not on a definition or resolved reference.
+  pub fn references_at(&self, byte: usize) -> Vec<Range<usize>> {
+    let at = |r: &Range<usize>| r.start <= byte && byte <= r.end;
+    let definition = self
+      .definitions
+      .iter()
+      .position(|d| at(&d.range))
+      .or_else(|| self.references.iter().find(|(r, _)| at(r)).map(|(_, d)| *d));
+    let Some(definition) = definition else {
+      return Vec::new();
+    };
+
+    let mut ranges: Vec<_> = self
+      .references
+      .iter()
+      .filter(|(_, d)| *d == definition)
+      .map(|(r, _)| r.clone())
+      .collect();
+    ranges.push(self.definitions[definition].range.clone());
+    ranges.sort_by_key(|r| r.start);
+    ranges
+  }
+}
+
+/// A scope being walked, with the definitions made in it so far
+struct Scope<'a> {
+  end: usize,
+  inherits: bool,
+  names: HashMap<&'a str, usize>,
+}
+
+/// A compiled locals query
+pub(crate) struct LocalsQuery {
+  query: Query,
+  scope_capture: Option<u32>,
+  reference_capture: Option<u32>,
+  /// Definition capture indices and their kinds
+  definition_captures: HashMap<u32, Option<LocalKind>>,
+}
+
+impl LocalsQuery {
+  pub(crate) fn new(language: &tree_sitter::Language, source: &str) -> Result<Self> {
+    let query = Query::new(language, source)
+      .map_err(|e| Error::Internal(format!("Failed to compile locals query: {}", e)))?;
+
+    let definition_captures = query
+      .capture_names()
+      .iter()
+      .enumerate()
+      .filter_map(|(i, name)| {
+        let suffix = name.strip_prefix(DEFINITION_CAPTURE)?;
+        let kind = match suffix.strip_prefix('.') {
+          Some(suffix) => LocalKind::from_capture_suffix(suffix),
+          None if suffix.is_empty() => None,
+          None => return None,
+        };
+        Some((i as u32, kind))
+      })
+      .collect();
+
+    Ok(Self {
+      scope_capture: query.capture_index_for_name(SCOPE_CAPTURE),
+      reference_capture: query.capture_index_for_name(REFERENCE_CAPTURE),
+      definition_captures,
+      query,
+    })
+  }
+
+  /// Resolve the definitions and references in a tree
+  ///
+  /// A reference resolves to the latest definition of its name made before
+  /// it in the innermost scope that has one. Unresolved references, e.g. to
+  /// globals or items of other files, are left out.
+  pub(crate) fn locals(&self, root: Node, source: &[u8]) -> Locals {
+    let mut locals = Locals::default();
+    let mut scopes = vec![Scope {
+      end: usize::MAX,
+      inherits: false,
+      names: HashMap::new(),
+    }];
+    let mut cursor = QueryCursor::new();
+
+    for (m, i) in cursor.captures(&self.query, root, source) {
+      let capture = m.captures[i];
+      let range = capture.node.byte_range();
+      while scopes.len() > 1 && scopes.last().is_some_and(|s| s.end <= range.start) {
+        scopes.pop();
+      }
+
+      if Some(capture.index) == self.scope_capture {
+        let inherits = self
+          .query
+          .property_settings(m.pattern_index)
+          .iter()
+          .find(|p| &*p.key == SCOPE_INHERITS_PROPERTY)
+          .and_then(|p| p.value.as_deref())
+          != Some("false");
+        scopes.push(Scope {
+          end: range.end,
+          inherits,
+          names: HashMap::new(),
+        });
+        continue;
+      }
+
+      let Ok(name) = std::str::from_utf8(&source[range.clone()]) else {
+        continue;
+      };
+
+      if let Some(&kind) = self.definition_captures.get(&capture.index) {
+        // A node matched by several definition patterns is defined once
+        if locals.definitions.last().is_some_and(|d| d.range == range) {
+          continue;
+        }
+        let index = locals.definitions.len();
+        locals.definitions.push(Definition { range, kind });
+        if let Some(scope) = scopes.last_mut() {
+          scope.names.insert(name, index);
+        }
+      } else if Some(capture.index) == self.reference_capture {
+        // Definitions are usually matched by the reference pattern too
+        if locals.definitions.last().is_some_and(|d| d.range == range) {
+          continue;
+        }
+        let mut definition = None;
+        for scope in scopes.iter().rev() {
+          definition = scope.names.get(name).copied();
+          if definition.is_some() || !scope.inherits {
+            break;
+          }
+        }
+        if let Some(definition) = definition {
+          locals.references.push((range, definition));
+        }
+      }
+    }
+
+    locals
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::syntax::LanguageId;
+  use tree_sitter::Parser;
+
+  fn resolve(language: LanguageId, source: &str) -> Locals {
+    let ts_lang = language.tree_sitter_language().unwrap();
+    let mut parser = Parser::new();
+    parser.set_language(&ts_lang).unwrap();
+    let tree = parser.parse(source, None).unwrap();
+
+    let query = LocalsQuery::new(&ts_lang, language.locals_query().unwrap()).unwrap();
+    query.locals(tree.root_node(), source.as_bytes())
+  }
+
+  fn texts<'a>(source: &'a str, ranges: &[Range<usize>]) -> Vec<(usize, &'a str)> {
+    ranges
+      .iter()
+      .map(|r| (r.start, &source[r.clone()]))
+      .collect()
+  }
+
+  #[test]
+  fn test_parameter_references_inherit_kind() {
+    let source = "fn area(width: u32) -> u32 {\n  let height = 2;\n  width * height\n}\n";
+    let locals = resolve(LanguageId::Rust, source);
+
+    let kinds: Vec<_> = locals
+      .kinds_in(&(0..source.len()))
+      .map(|(r, kind)| (&source[r], kind))
+      .collect();
+    assert!(kinds.contains(&("width", LocalKind::Parameter)));
+    assert!(kinds.contains(&("height", LocalKind::Variable)));
+    assert_eq!(kinds.iter().filter(|(name, _)| *name == "width").count(), 2);
+  }
+
+  #[test]
+  fn test_shadowing_resolves_to_innermost_definition() {
+    let source = "fn f(x: i32) {\n  {\n    let x = 1;\n    x;\n  }\n  x;\n}\n";
+    let locals = resolve(LanguageId::Rust, source);
+
+    let param = source.find("x").unwrap();
+    let inner = source.find("let x").unwrap() + 4;
+    let last = source.rfind("x;").unwrap();
+    let inner_use = source[..last].rfind("x;").unwrap();
+
+    assert_eq!(
+      locals.references_at(param),
+      vec![param..param + 1, last..last + 1]
+    );
+    assert_eq!(
+      locals.references_at(inner_use),
+      vec![inner..inner + 1, inner_use..inner_use + 1]
+    );
+  }
+
+  #[test]
+  fn test_python_imports_and_parameters() {
+    let source = "import os\n\ndef join(base, name):\n    return os.path.join(base, name)\n";
+    let locals = resolve(LanguageId::Python, source);
+
+    let os = source.find("os").unwrap();
+    assert_eq!(
+      texts(source, &locals.references_at(os)),
+      vec![(os, "os"), (source.rfind("os.").unwrap(), "os")]
+    );
+    assert!(locals
+      .definitions()
+      .iter()
+      .any(|d| &source[d.range.clone()] == "base" && d.kind == Some(LocalKind::Parameter)));
+    assert!(locals
+      .references_at(source.find("path").unwrap())
+      .is_empty());
+  }
+}
@@ -33,6 +33,20 @@ impl Default for Point {
   }
 }
 
+/// Tree-sitter points count columns in bytes
+impl From<tree_sitter::Point> for Point {
+  fn from(point: tree_sitter::Point) -> Self {
+    Self::new(point.row, point.column)
+  }
+}
+
+/// The column must be in bytes
+impl From<Point> for tree_sitter::Point {
+  fn from(point: Point) -> Self {
+    tree_sitter::Point::new(point.line, point.column)
+  }
+}
+
 impl PartialOrd for Point {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
     Some(self.cmp(other))
@@ -0,0 +1,161 @@
+//! Immutable buffer snapshots
+//!
+//! A snapshot captures the buffer content at one version. It shares the rope
+//! with the buffer instead of copying it, so taking one is cheap, and it
+//! never changes afterwards, so background work (parsing, search, LSP sync)
+//! can read it without holding the buffer lock.
+
+use ferrum_core::id::BufferId;
+use ferrum_core::prelude::*;
+use ropey::Rope;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::buffer::Buffer;
+use crate::large_file::Viewport;
+
+/// An immutable view of a buffer at a single version
+#[derive(Debug, Clone)]
+pub struct BufferSnapshot {
+  id: BufferId,
+  rope: Arc<Rope>,
+  version: u64,
+  language_id: Option<String>,
+  file_path: Option<PathBuf>,
+}
+
+impl BufferSnapshot {
+  pub(crate) fn new(
+    id: BufferId,
+    rope: Rope,
+    version: u64,
+    language_id: Option<String>,
+    file_path: Option<PathBuf>,
+  ) -> Self {
+    Self {
+      id,
+      rope: Arc::new(rope),
+      version,
+      language_id,
+      file_path,
+    }
+  }
+
+  /// Get the ID of the buffer this snapshot was taken from
+  pub fn id(&self) -> BufferId {
+    self.id
+  }
+
+  /// Get the buffer version at the time of the snapshot
+  pub fn version(&self) -> u64 {
+    self.version
+  }
+
+  /// Get the language ID
+  pub fn language_id(&self) -> Option<&str> {
+    self.language_id.as_deref()
+  }
+
+  /// Get the file path, if the buffer had one
+  pub fn file_path(&self) -> Option<&PathBuf> {
+    self.file_path.as_ref()
+  }
+
+  /// Get the underlying rope
+  pub fn rope(&self) -> &Rope {
+    &self.rope
+  }
+
+  /// Check if the buffer has changed since this snapshot was taken
+  pub fn is_stale(&self, buffer: &Buffer) -> bool {
+    buffer.id() != self.id || buffer.version() != self.version
+  }
+
+  /// Get the total number of characters
+  pub fn len_chars(&self) -> usize {
+    self.rope.len_chars()
+  }
+
+  /// Get the total number of bytes
+  pub fn len_bytes(&self) -> usize {
+    self.rope.len_bytes()
+  }
+
+  /// Get the total number of lines
+  pub fn len_lines(&self) -> usize {
+    self.rope.len_lines()
+  }
+
+  /// Check if the snapshot is empty
+  pub fn is_empty(&self) -> bool {
+    self.rope.len_chars() == 0
+  }
+
+  /// Get a specific line
+  pub fn line(&self, line_idx: usize) -> Option<String> {
+    if line_idx >= self.rope.len_lines() {
+      return None;
+    }
+    Some(self.rope.line(line_idx).to_string())
+  }
+
+  /// Read a window of lines for rendering
+  pub fn viewport(&self, first_line: usize, line_count: usize) -> Viewport {
+    Viewport::read(&self.rope, self.version, first_line, line_count)
+  }
+
+  /// Get a slice of the content
+  pub fn slice(&self, start: usize, end: usize) -> Result<String> {
+    if start > end || end > self.rope.len_chars() {
+      return Err(Error::InvalidRange { start, end });
+    }
+    Ok(self.rope.slice(start..end).to_string())
+  }
+}
+
+impl fmt::Display for BufferSnapshot {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for chunk in self.rope.chunks() {
+      f.write_str(chunk)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_snapshot_is_immutable() {
+    let buffer = Buffer::with_content("Hello");
+    let snapshot = buffer.snapshot();
+
+    buffer.insert(5, " World").unwrap();
+
+    assert_eq!(snapshot.to_string(), "Hello");
+    assert_eq!(buffer.snapshot().to_string(), "Hello World");
+  }
+
+  #[test]
+  fn test_snapshot_staleness() {
+    let buffer = Buffer::with_content("Hello");
+    let snapshot = buffer.snapshot();
+    assert!(!snapshot.is_stale(&buffer));
+
+    buffer.insert(0, ">").unwrap();
+    assert!(snapshot.is_stale(&buffer));
+
+    let other = Buffer::with_content("Hello");
+    assert!(buffer.snapshot().is_stale(&other));
+  }
+
+  #[test]
+  fn test_snapshot_slice() {
+    let snapshot = Buffer::with_content("Hello World").snapshot();
+    assert_eq!(snapshot.slice(6, 11).unwrap(), "World");
+    assert!(snapshot.slice(6, 20).is_err());
+    assert_eq!(snapshot.line(0).as_deref(), Some("Hello World"));
+  }
+}
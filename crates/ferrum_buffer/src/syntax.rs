@@ -7,16 +7,36 @@
 //! - **Async Parsing**: Background thread parsing for large files
 //! - **Language Support**: Multiple languages via tree-sitter grammars
 //! - **Rope Integration**: Direct reading from ropey Rope chunks
-
+//! - **Injections**: Embedded languages parsed as child layers
+//! - **Locals**: References highlighted like their definitions
+
+use crate::brackets::{self, Brackets};
+use crate::coords;
+use crate::edit::Change;
+use crate::folds::{self, FoldRange, FoldsQuery};
+use crate::grammar::{global_grammar_registry, QueryKind};
+use crate::imports::{Import, ImportsQuery};
+use crate::indent::{Indent, IndentQuery};
+use crate::injection::{self, InjectionQuery};
+use crate::inspect::{self, QueryResult, SyntaxNode};
+use crate::locals::{Locals, LocalsQuery};
+use crate::textobject::{TextObject, TextObjectKind, TextObjectQuery};
 use ferrum_core::prelude::*;
 use parking_lot::{Mutex, RwLock};
 use ropey::Rope;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ops::Range;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tracing::{debug, trace, warn};
-use tree_sitter::{InputEdit, Language, Node, Parser, Point as TsPoint, Query, QueryCursor, Tree};
+use tree_sitter::{
+  InputEdit, Language, Node, Parser, Point as TsPoint, Query, QueryCursor, Range as TsRange, Tree,
+};
+
+/// How deep injections may nest, e.g. Markdown > HTML > JavaScript
+const MAX_INJECTION_DEPTH: usize = 3;
 
 // ============================================================================
 // Language Registry
@@ -37,6 +57,8 @@ pub enum LanguageId {
   Html,
   Css,
   Markdown,
+  /// A grammar loaded at runtime, see `grammar::GrammarRegistry`
+  Runtime(u32),
   Unknown,
 }
 
@@ -56,10 +78,55 @@ impl LanguageId {
       "html" | "htm" => Self::Html,
       "css" => Self::Css,
       "md" | "markdown" => Self::Markdown,
-      _ => Self::Unknown,
+      other => global_grammar_registry()
+        .by_extension(other)
+        .unwrap_or(Self::Unknown),
+    }
+  }
+
+  /// Detect language from a name, as used in Markdown code fences and
+  /// injection queries
+  pub fn from_name(name: &str) -> Self {
+    match name.trim().to_lowercase().as_str() {
+      "rust" => Self::Rust,
+      "typescript" => Self::TypeScript,
+      "typescriptreact" => Self::TypeScriptReact,
+      "javascript" | "node" => Self::JavaScript,
+      "javascriptreact" => Self::JavaScriptReact,
+      "python" => Self::Python,
+      "golang" => Self::Go,
+      "markdown" => Self::Markdown,
+      other => global_grammar_registry()
+        .by_name(other)
+        .unwrap_or_else(|| Self::from_extension(other)),
     }
   }
 
+  /// Get the language name, as understood by `from_name`
+  pub fn name(&self) -> Cow<'static, str> {
+    Cow::Borrowed(match self {
+      Self::Rust => "rust",
+      Self::TypeScript => "typescript",
+      Self::TypeScriptReact => "typescriptreact",
+      Self::JavaScript => "javascript",
+      Self::JavaScriptReact => "javascriptreact",
+      Self::Python => "python",
+      Self::Go => "go",
+      Self::Json => "json",
+      Self::Toml => "toml",
+      Self::Html => "html",
+      Self::Css => "css",
+      Self::Markdown => "markdown",
+      Self::Runtime(_) => {
+        return global_grammar_registry()
+          .name(*self)
+          .map(Cow::Owned)
+          .unwrap_or(Cow::Borrowed("unknown"))
+      },
+      Self::Unknown => "unknown",
+    })
+  }
+
   /// Get the tree-sitter Language for this ID
   pub fn tree_sitter_language(&self) -> Option<Language> {
     match self {
@@ -75,11 +142,30 @@ impl LanguageId {
       Self::Html => Some(tree_sitter_html::LANGUAGE.into()),
       Self::Css => Some(tree_sitter_css::LANGUAGE.into()),
       Self::Markdown => Some(tree_sitter_md::LANGUAGE.into()),
+      Self::Runtime(_) => global_grammar_registry().language(*self),
       Self::Unknown => None,
     }
   }
 
-  /// Get highlight query for this language
+  /// Get a query for this language: one loaded from disk, or else the
+  /// bundled one
+  pub fn query(&self, kind: QueryKind) -> Option<Cow<'static, str>> {
+    if let Some(query) = global_grammar_registry().query(*self, kind) {
+      return Some(Cow::Owned(query.to_string()));
+    }
+
+    match kind {
+      QueryKind::Highlights => self.highlight_query().map(Cow::Borrowed),
+      QueryKind::Injections => self.injection_query().map(Cow::Borrowed),
+      QueryKind::Locals => self.locals_query().map(Cow::Borrowed),
+      QueryKind::Indents => self.indent_query().map(Cow::Borrowed),
+      QueryKind::Folds => self.fold_query().map(Cow::Borrowed),
+      QueryKind::TextObjects => self.textobject_query().map(Cow::Borrowed),
+      QueryKind::Imports => self.import_query().map(Cow::Borrowed),
+    }
+  }
+
+  /// Get the bundled highlight query for this language
   pub fn highlight_query(&self) -> Option<&'static str> {
     match self {
       Self::Rust => Some(include_str!("../queries/rust/highlights.scm")),
@@ -96,7 +182,116 @@ impl LanguageId {
       Self::Html => Some(include_str!("../queries/html/highlights.scm")),
       Self::Css => Some(include_str!("../queries/css/highlights.scm")),
       Self::Markdown => Some(include_str!("../queries/markdown/highlights.scm")),
-      Self::Unknown => None,
+      Self::Runtime(_) | Self::Unknown => None,
+    }
+  }
+
+  /// Get the bundled query marking code in other languages embedded in this
+  /// one
+  pub fn injection_query(&self) -> Option<&'static str> {
+    match self {
+      Self::TypeScript | Self::TypeScriptReact => {
+        Some(include_str!("../queries/typescript/injections.scm"))
+      },
+      Self::JavaScript | Self::JavaScriptReact => {
+        Some(include_str!("../queries/javascript/injections.scm"))
+      },
+      Self::Html => Some(include_str!("../queries/html/injections.scm")),
+      Self::Markdown => Some(include_str!("../queries/markdown/injections.scm")),
+      _ => None,
+    }
+  }
+
+  /// Get the bundled query marking the scopes, definitions and references
+  /// of local names
+  pub fn locals_query(&self) -> Option<&'static str> {
+    match self {
+      Self::Rust => Some(include_str!("../queries/rust/locals.scm")),
+      Self::TypeScript | Self::TypeScriptReact => {
+        Some(include_str!("../queries/typescript/locals.scm"))
+      },
+      Self::JavaScript | Self::JavaScriptReact => {
+        Some(include_str!("../queries/javascript/locals.scm"))
+      },
+      Self::Python => Some(include_str!("../queries/python/locals.scm")),
+      Self::Go => Some(include_str!("../queries/go/locals.scm")),
+      _ => None,
+    }
+  }
+
+  /// Get the bundled query marking the nodes that indent their lines
+  pub fn indent_query(&self) -> Option<&'static str> {
+    match self {
+      Self::Rust => Some(include_str!("../queries/rust/indents.scm")),
+      Self::TypeScript | Self::TypeScriptReact => {
+        Some(include_str!("../queries/typescript/indents.scm"))
+      },
+      Self::JavaScript | Self::JavaScriptReact => {
+        Some(include_str!("../queries/javascript/indents.scm"))
+      },
+      Self::Python => Some(include_str!("../queries/python/indents.scm")),
+      Self::Go => Some(include_str!("../queries/go/indents.scm")),
+      Self::Json => Some(include_str!("../queries/json/indents.scm")),
+      Self::Toml => Some(include_str!("../queries/toml/indents.scm")),
+      Self::Html => Some(include_str!("../queries/html/indents.scm")),
+      Self::Css => Some(include_str!("../queries/css/indents.scm")),
+      Self::Markdown => Some(include_str!("../queries/markdown/indents.scm")),
+      Self::Runtime(_) | Self::Unknown => None,
+    }
+  }
+
+  /// Get the bundled query marking the nodes, comments and imports that fold
+  pub fn fold_query(&self) -> Option<&'static str> {
+    match self {
+      Self::Rust => Some(include_str!("../queries/rust/folds.scm")),
+      Self::TypeScript | Self::TypeScriptReact => {
+        Some(include_str!("../queries/typescript/folds.scm"))
+      },
+      Self::JavaScript | Self::JavaScriptReact => {
+        Some(include_str!("../queries/javascript/folds.scm"))
+      },
+      Self::Python => Some(include_str!("../queries/python/folds.scm")),
+      Self::Go => Some(include_str!("../queries/go/folds.scm")),
+      Self::Json => Some(include_str!("../queries/json/folds.scm")),
+      Self::Toml => Some(include_str!("../queries/toml/folds.scm")),
+      Self::Html => Some(include_str!("../queries/html/folds.scm")),
+      Self::Css => Some(include_str!("../queries/css/folds.scm")),
+      Self::Markdown => Some(include_str!("../queries/markdown/folds.scm")),
+      Self::Runtime(_) | Self::Unknown => None,
+    }
+  }
+
+  /// Get the bundled query marking functions, classes, parameters and
+  /// comments as text objects
+  pub fn textobject_query(&self) -> Option<&'static str> {
+    match self {
+      Self::Rust => Some(include_str!("../queries/rust/textobjects.scm")),
+      Self::TypeScript | Self::TypeScriptReact => {
+        Some(include_str!("../queries/typescript/textobjects.scm"))
+      },
+      Self::JavaScript | Self::JavaScriptReact => {
+        Some(include_str!("../queries/javascript/textobjects.scm"))
+      },
+      Self::Python => Some(include_str!("../queries/python/textobjects.scm")),
+      Self::Go => Some(include_str!("../queries/go/textobjects.scm")),
+      _ => None,
+    }
+  }
+
+  /// Get the bundled query marking import statements and the modules they
+  /// import from
+  pub fn import_query(&self) -> Option<&'static str> {
+    match self {
+      Self::Rust => Some(include_str!("../queries/rust/imports.scm")),
+      Self::TypeScript | Self::TypeScriptReact => {
+        Some(include_str!("../queries/typescript/imports.scm"))
+      },
+      Self::JavaScript | Self::JavaScriptReact => {
+        Some(include_str!("../queries/javascript/imports.scm"))
+      },
+      Self::Python => Some(include_str!("../queries/python/imports.scm")),
+      Self::Go => Some(include_str!("../queries/go/imports.scm")),
+      _ => None,
     }
   }
 }
@@ -111,11 +306,19 @@ pub struct Highlight {
   pub start: usize,
   pub end: usize,
   pub kind: HighlightKind,
+  /// Semantic token modifiers from a language server, e.g. `mutable`
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub modifiers: Vec<String>,
 }
 
 impl Highlight {
   pub fn new(start: usize, end: usize, kind: HighlightKind) -> Self {
-    Self { start, end, kind }
+    Self {
+      start,
+      end,
+      kind,
+      modifiers: Vec::new(),
+    }
   }
 
   /// Get the byte range
@@ -283,16 +486,38 @@ impl ParseResult {
 // ============================================================================
 
 /// Manages syntax parsing for a single buffer
+///
+/// Code embedded in another language gets a child manager of its own,
+/// parsing only the injected ranges of the same source. Child layers are
+/// updated incrementally along with the parent.
 pub struct SyntaxManager {
   language: LanguageId,
   parser: Mutex<Parser>,
   tree: RwLock<Option<Tree>>,
   highlight_query: Option<Query>,
+  injection_query: Option<InjectionQuery>,
+  locals_query: Option<LocalsQuery>,
+  indent_query: Option<IndentQuery>,
+  fold_query: Option<FoldsQuery>,
+  textobject_query: Option<TextObjectQuery>,
+  import_query: Option<ImportsQuery>,
+  /// Resolved local definitions and references of the current tree
+  locals: RwLock<Locals>,
+  /// Ranges the parser is restricted to; empty for the whole source
+  included_ranges: RwLock<Vec<TsRange>>,
+  /// Child layers for injected code, ordered by position
+  injections: RwLock<Vec<SyntaxManager>>,
+  /// Injection nesting level, 0 for the document's own language
+  depth: usize,
 }
 
 impl SyntaxManager {
   /// Create a new syntax manager for the given language
   pub fn new(language: LanguageId) -> Result<Self> {
+    Self::with_depth(language, 0)
+  }
+
+  fn with_depth(language: LanguageId, depth: usize) -> Result<Self> {
     let ts_lang = language
       .tree_sitter_language()
       .ok_or_else(|| Error::Internal(format!("Unsupported language: {:?}", language)))?;
@@ -302,19 +527,66 @@ impl SyntaxManager {
       .set_language(&ts_lang)
       .map_err(|e| Error::Internal(format!("Failed to set language: {}", e)))?;
 
+    #[cfg(feature = "wasm")]
+    if ts_lang.is_wasm() {
+      parser
+        .set_wasm_store(global_grammar_registry().wasm_store()?)
+        .map_err(|e| Error::Internal(format!("Failed to set WASM store: {}", e)))?;
+    }
+
     let highlight_query = language
-      .highlight_query()
+      .query(QueryKind::Highlights)
       .map(|query_str| {
-        Query::new(&ts_lang, query_str)
+        Query::new(&ts_lang, &query_str)
           .map_err(|e| Error::Internal(format!("Failed to compile query: {}", e)))
       })
       .transpose()?;
 
+    let injection_query = language
+      .query(QueryKind::Injections)
+      .map(|query_str| InjectionQuery::new(&ts_lang, &query_str))
+      .transpose()?;
+
+    let locals_query = language
+      .query(QueryKind::Locals)
+      .map(|query_str| LocalsQuery::new(&ts_lang, &query_str))
+      .transpose()?;
+
+    let indent_query = language
+      .query(QueryKind::Indents)
+      .map(|query_str| IndentQuery::new(&ts_lang, &query_str))
+      .transpose()?;
+
+    let fold_query = language
+      .query(QueryKind::Folds)
+      .map(|query_str| FoldsQuery::new(&ts_lang, &query_str))
+      .transpose()?;
+
+    let textobject_query = language
+      .query(QueryKind::TextObjects)
+      .map(|query_str| TextObjectQuery::new(&ts_lang, &query_str))
+      .transpose()?;
+
+    let import_query = language
+      .query(QueryKind::Imports)
+      .map(|query_str| ImportsQuery::new(&ts_lang, &query_str))
+      .transpose()?;
+
     Ok(Self {
       language,
       parser: Mutex::new(parser),
       tree: RwLock::new(None),
       highlight_query,
+      injection_query,
+      locals_query,
+      indent_query,
+      fold_query,
+      textobject_query,
+      import_query,
+      locals: RwLock::new(Locals::default()),
+      included_ranges: RwLock::new(Vec::new()),
+      injections: RwLock::new(Vec::new()),
+      depth,
     })
   }
 
@@ -327,14 +599,7 @@ impl SyntaxManager {
   pub fn parse(&self, rope: &Rope) -> Result<()> {
     let start = std::time::Instant::now();
 
-    let mut parser = self.parser.lock();
-    let source = rope.to_string();
-
-    let tree = parser
-      .parse(&source, None)
-      .ok_or_else(|| Error::Internal("Parsing failed".to_string()))?;
-
-    *self.tree.write() = Some(tree);
+    self.reparse(&rope.to_string(), None)?;
 
     debug!(
         language = ?self.language,
@@ -367,33 +632,412 @@ impl SyntaxManager {
     let input_edit =
       self.rope_edit_to_input_edit(rope, edit_start_byte, edit_old_end_byte, edit_new_end_byte);
 
-    // Apply edit to existing tree
-    {
-      let mut tree = self.tree.write();
-      if let Some(ref mut t) = *tree {
-        t.edit(&input_edit);
-      }
+    // Re-parse with the edited tree (using string for compatibility)
+    self.reparse(&rope.to_string(), Some(&[input_edit]))?;
+
+    trace!(
+        language = ?self.language,
+        duration_us = start.elapsed().as_micros(),
+        edit_start = edit_start_byte,
+        "Incremental parse complete"
+    );
+
+    Ok(())
+  }
+
+  /// Incrementally update the tree after an ordered list of changes
+  ///
+  /// All changes are applied to the old tree first and the source is
+  /// re-parsed once, so a transaction with many edits costs one parse.
+  pub fn apply_changes(&self, rope: &Rope, changes: &[Change]) -> Result<()> {
+    if changes.is_empty() {
+      return Ok(());
     }
 
-    // Re-parse with the edited tree (using string for compatibility)
+    let start = std::time::Instant::now();
+
+    let edits: Vec<InputEdit> = changes
+      .iter()
+      .map(|change| InputEdit {
+        start_byte: change.start_byte,
+        old_end_byte: change.old_end_byte,
+        new_end_byte: change.new_end_byte,
+        start_position: change.start_point.into(),
+        old_end_position: change.old_end_point.into(),
+        new_end_position: change.new_end_point.into(),
+      })
+      .collect();
+
+    self.reparse(&rope.to_string(), Some(&edits))?;
+
+    trace!(
+        language = ?self.language,
+        duration_us = start.elapsed().as_micros(),
+        changes = changes.len(),
+        "Incremental parse complete"
+    );
+
+    Ok(())
+  }
+
+  /// Parse the source, then refresh the locals and injection layers
+  ///
+  /// With `edits`, the old tree is edited and reused; otherwise the source
+  /// is parsed from scratch.
+  fn reparse(&self, source: &str, edits: Option<&[InputEdit]>) -> Result<()> {
+    let old_tree = match edits {
+      Some(edits) => {
+        let mut tree = self.tree.write();
+        if let Some(ref mut t) = *tree {
+          for edit in edits {
+            t.edit(edit);
+          }
+        }
+        tree.clone()
+      },
+      None => None,
+    };
+
     let mut parser = self.parser.lock();
-    let old_tree = self.tree.read().clone();
+    parser
+      .set_included_ranges(&self.included_ranges.read())
+      .map_err(|e| Error::Internal(format!("Invalid injection ranges: {:?}", e)))?;
+    let tree = parser
+      .parse(source, old_tree.as_ref())
+      .ok_or_else(|| Error::Internal("Parsing failed".to_string()))?;
+    drop(parser);
+
+    if let Some(query) = &self.locals_query {
+      *self.locals.write() = query.locals(tree.root_node(), source.as_bytes());
+    }
+    *self.tree.write() = Some(tree);
+    self.update_injections(source, edits);
+    Ok(())
+  }
+
+  /// Find the injections in the current tree and parse each one as a layer
+  ///
+  /// After edits, an old layer of the same language whose ranges overlap a
+  /// new injection is reused, so its tree is updated incrementally.
+  fn update_injections(&self, source: &str, edits: Option<&[InputEdit]>) {
+    let Some(query) = &self.injection_query else {
+      return;
+    };
+    if self.depth >= MAX_INJECTION_DEPTH {
+      return;
+    }
+
+    let found = match self.tree.read().as_ref() {
+      Some(tree) => query.injections(tree.root_node(), source.as_bytes()),
+      None => return,
+    };
+
+    let mut old = std::mem::take(&mut *self.injections.write());
+    let mut layers = Vec::with_capacity(found.len());
+    for found in found {
+      let span = found.span();
+      let reused = edits.and_then(|edits| {
+        old
+          .iter()
+          .position(|layer| {
+            let old_span = layer.span();
+            let start = injection::map_offset(old_span.start, edits);
+            let end = injection::map_offset(old_span.end, edits);
+            layer.language == found.language && start < span.end && span.start < end
+          })
+          .map(|i| (old.remove(i), edits))
+      });
+
+      let (layer, layer_edits) = match reused {
+        Some((layer, edits)) => (layer, Some(edits)),
+        None => match SyntaxManager::with_depth(found.language, self.depth + 1) {
+          Ok(layer) => (layer, None),
+          Err(e) => {
+            warn!(
+              "Failed to create injection layer for {:?}: {}",
+              found.language, e
+            );
+            continue;
+          },
+        },
+      };
+
+      *layer.included_ranges.write() = found.ranges;
+      match layer.reparse(source, layer_edits) {
+        Ok(()) => layers.push(layer),
+        Err(e) => warn!("Failed to parse {:?} injection: {}", found.language, e),
+      }
+    }
+
+    *self.injections.write() = layers;
+  }
+
+  /// Get the byte span this manager parses; empty for the whole source
+  fn span(&self) -> Range<usize> {
+    injection::span(&self.included_ranges.read())
+  }
+
+  /// Check if a byte offset is inside the ranges this layer parses
+  fn contains(&self, byte: usize) -> bool {
+    self
+      .included_ranges
+      .read()
+      .iter()
+      .any(|r| r.start_byte <= byte && byte < r.end_byte)
+  }
+
+  /// Get the language at a byte offset, looking into injected code
+  pub fn language_at(&self, byte: usize) -> LanguageId {
+    self
+      .injections
+      .read()
+      .iter()
+      .find(|layer| layer.contains(byte))
+      .map(|layer| layer.language_at(byte))
+      .unwrap_or(self.language)
+  }
+
+  /// Get the definition and references of the local symbol at a byte
+  /// offset, looking into injected code
+  ///
+  /// Empty if the offset is not on a local definition or a reference
+  /// resolved to one.
+  pub fn references_at(&self, byte: usize) -> Vec<Range<usize>> {
+    let injections = self.injections.read();
+    match injections.iter().find(|layer| layer.contains(byte)) {
+      Some(layer) => layer.references_at(byte),
+      None => self.locals.read().references_at(byte),
+    }
+  }
+
+  /// Work out the indentation of a line from the indents query, looking
+  /// into injected code; see [`IndentQuery::indent`]
+  ///
+  /// A line at the top level of injected code is indented by the layer
+  /// around it.
+  pub(crate) fn indent(&self, source: &[u8], line_start: usize, first: usize) -> Option<Indent> {
+    let injections = self.injections.read();
+    if let Some(layer) = injections.iter().find(|layer| layer.contains(first)) {
+      if let Some(indent) = layer.indent(source, line_start, first) {
+        return Some(indent);
+      }
+    }
+
+    let query = self.indent_query.as_ref()?;
+    let tree = self.tree.read();
+    query.indent(tree.as_ref()?.root_node(), source, line_start, first)
+  }
+
+  /// Get the fold ranges of the current tree, including those of injected
+  /// code, sorted by start line
+  pub fn folds(&self, rope: &Rope) -> Vec<FoldRange> {
     let source = rope.to_string();
+    let mut ranges = Vec::new();
+    self.collect_folds(source.as_bytes(), &mut ranges);
+    folds::dedup_fold_ranges(&mut ranges);
+    ranges
+  }
+
+  fn collect_folds(&self, source: &[u8], ranges: &mut Vec<FoldRange>) {
+    if let (Some(query), Some(tree)) = (&self.fold_query, self.tree.read().as_ref()) {
+      ranges.extend(query.folds(tree.root_node(), source));
+    }
+    for layer in self.injections.read().iter() {
+      layer.collect_folds(source, ranges);
+    }
+  }
 
-    let new_tree = parser.parse(&source, old_tree.as_ref());
+  /// Get the text objects of a kind, sorted by start, looking into the
+  /// injected code at `byte`
+  pub fn text_objects(&self, rope: &Rope, kind: TextObjectKind, byte: usize) -> Vec<TextObject> {
+    let source = rope.to_string();
+    self.collect_text_objects(source.as_bytes(), kind, byte)
+  }
 
-    if let Some(tree) = new_tree {
-      *self.tree.write() = Some(tree);
+  fn collect_text_objects(
+    &self,
+    source: &[u8],
+    kind: TextObjectKind,
+    byte: usize,
+  ) -> Vec<TextObject> {
+    let injections = self.injections.read();
+    if let Some(layer) = injections.iter().find(|layer| layer.contains(byte)) {
+      let objects = layer.collect_text_objects(source, kind, byte);
+      if !objects.is_empty() {
+        return objects;
+      }
+    }
 
-      trace!(
-          language = ?self.language,
-          duration_us = start.elapsed().as_micros(),
-          edit_start = edit_start_byte,
-          "Incremental parse complete"
-      );
+    let tree = self.tree.read();
+    match (&self.textobject_query, tree.as_ref()) {
+      (Some(query), Some(tree)) => query.objects(tree.root_node(), source, kind),
+      _ => Vec::new(),
     }
+  }
 
-    Ok(())
+  /// Get the imports of the current tree, in document order
+  pub fn imports(&self, rope: &Rope) -> Vec<Import> {
+    let tree = self.tree.read();
+    match (&self.import_query, tree.as_ref()) {
+      (Some(query), Some(tree)) => query.imports(tree.root_node(), rope.to_string().as_bytes()),
+      _ => Vec::new(),
+    }
+  }
+
+  /// Get the smallest named node strictly enclosing a byte range, looking
+  /// into injected code
+  pub fn parent_node(&self, range: Range<usize>) -> Option<Range<usize>> {
+    let injections = self.injections.read();
+    if let Some(layer) = injections.iter().find(|layer| layer.contains(range.start)) {
+      if let Some(parent) = layer.parent_node(range.clone()) {
+        return Some(parent);
+      }
+    }
+
+    let tree = self.tree.read();
+    let mut node = tree
+      .as_ref()?
+      .root_node()
+      .named_descendant_for_byte_range(range.start, range.end)?;
+    while node.byte_range() == range {
+      node = node.parent()?;
+    }
+    Some(node.byte_range())
+  }
+
+  /// Get the named node covering a byte range and its next or previous
+  /// named sibling, skipping comments, looking into injected code
+  ///
+  /// The covering node is the largest of the nodes with the same range, so
+  /// a selected parameter is the parameter and not its only child.
+  pub fn sibling_node(
+    &self,
+    range: Range<usize>,
+    forward: bool,
+  ) -> Option<(Range<usize>, Range<usize>)> {
+    let injections = self.injections.read();
+    if let Some(layer) = injections.iter().find(|layer| layer.contains(range.start)) {
+      if let Some(siblings) = layer.sibling_node(range.clone(), forward) {
+        return Some(siblings);
+      }
+    }
+
+    let tree = self.tree.read();
+    let mut node = tree
+      .as_ref()?
+      .root_node()
+      .named_descendant_for_byte_range(range.start, range.end)?;
+    while let Some(parent) = node.parent() {
+      if parent.byte_range() != node.byte_range() {
+        break;
+      }
+      node = parent;
+    }
+
+    let mut sibling = node;
+    loop {
+      sibling = match forward {
+        true => sibling.next_named_sibling()?,
+        false => sibling.prev_named_sibling()?,
+      };
+      if !sibling.is_extra() {
+        return Some((node.byte_range(), sibling.byte_range()));
+      }
+    }
+  }
+
+  /// Get the bracket pairs and unmatched brackets intersecting a byte
+  /// range, including those of injected code
+  ///
+  /// Only the part of the tree around the range is visited, so this stays
+  /// fast for a viewport of a huge file. The depth of pairs in injected
+  /// code counts from the injection.
+  pub fn brackets(&self, range: Range<usize>) -> Brackets {
+    let mut brackets = Brackets::default();
+    self.collect_brackets(&range, &mut brackets);
+    brackets.sort();
+    brackets
+  }
+
+  fn collect_brackets(&self, range: &Range<usize>, brackets: &mut Brackets) {
+    if let Some(tree) = self.tree.read().as_ref() {
+      brackets::collect(tree.root_node(), range, brackets);
+    }
+    for layer in self.injections.read().iter() {
+      layer.collect_brackets(range, brackets);
+    }
+  }
+
+  /// Get the open and close bracket of the bracket at or just before a
+  /// byte offset, or else of the innermost pair enclosing it, looking into
+  /// injected code
+  pub fn bracket_pair_at(&self, byte: usize) -> Option<(Range<usize>, Range<usize>)> {
+    let injections = self.injections.read();
+    if let Some(layer) = injections.iter().find(|layer| layer.contains(byte)) {
+      if let Some(pair) = layer.bracket_pair_at(byte) {
+        return Some(pair);
+      }
+    }
+
+    let tree = self.tree.read();
+    brackets::pair_at(tree.as_ref()?.root_node(), byte)
+  }
+
+  /// Get the syntax tree as a node tree, leaving out anonymous nodes if
+  /// `named_only` is set
+  ///
+  /// With a byte range, only the subtree of the smallest named node
+  /// covering it is returned, looking into injected code.
+  pub fn syntax_tree(&self, range: Option<Range<usize>>, named_only: bool) -> Option<SyntaxNode> {
+    self.with_node(range, |node| SyntaxNode::build(node, named_only))
+  }
+
+  /// Get the syntax tree as an S-expression of its named nodes
+  ///
+  /// With a byte range, only the subtree of the smallest named node
+  /// covering it is returned, looking into injected code.
+  pub fn sexp(&self, range: Option<Range<usize>>) -> Option<String> {
+    self.with_node(range, |node| node.to_sexp())
+  }
+
+  fn with_node<R>(&self, range: Option<Range<usize>>, f: impl FnOnce(Node) -> R) -> Option<R> {
+    let Some(range) = range else {
+      return self.with_tree(|tree| f(tree.root_node()));
+    };
+
+    let injections = self.injections.read();
+    if let Some(layer) = injections.iter().find(|layer| layer.contains(range.start)) {
+      return layer.with_node(Some(range), f);
+    }
+
+    let tree = self.tree.read();
+    let node = tree
+      .as_ref()?
+      .root_node()
+      .named_descendant_for_byte_range(range.start, range.end)?;
+    Some(f(node))
+  }
+
+  /// Run a query string against the tree and get its captures in
+  /// document order; see [`inspect::run_query`]
+  ///
+  /// The query runs against this language's tree only, not injected code.
+  /// Fails if the query does not compile.
+  pub fn run_query(
+    &self,
+    rope: &Rope,
+    query: &str,
+    range: Option<Range<usize>>,
+  ) -> Result<QueryResult> {
+    let Some(language) = self.language.tree_sitter_language() else {
+      return Ok(QueryResult::default());
+    };
+    let source = rope.to_string();
+    self
+      .with_tree(|tree| {
+        inspect::run_query(&language, tree.root_node(), source.as_bytes(), query, range)
+      })
+      .unwrap_or_else(|| Ok(QueryResult::default()))
   }
 
   /// Convert rope edit to tree-sitter InputEdit
@@ -419,39 +1063,82 @@ impl SyntaxManager {
     }
   }
 
-  /// Get syntax highlights for a range
+  /// Get syntax highlights for a range, including those of injected code
   pub fn highlights(&self, rope: &Rope, range: Range<usize>) -> Vec<Highlight> {
+    let source = rope.to_string();
+    let mut highlights = Vec::new();
+    self.collect_highlights(source.as_bytes(), &range, &mut highlights);
+
+    // Sort and deduplicate overlapping highlights
+    highlights.sort_by_key(|h| (h.start, std::cmp::Reverse(h.end)));
+
+    highlights
+  }
+
+  /// Collect the highlights of this layer and its injections
+  fn collect_highlights(
+    &self,
+    source: &[u8],
+    range: &Range<usize>,
+    highlights: &mut Vec<Highlight>,
+  ) {
     let tree = self.tree.read();
     let Some(tree) = tree.as_ref() else {
-      return Vec::new();
+      return;
     };
 
     let Some(query) = &self.highlight_query else {
-      return Vec::new();
+      return;
     };
 
     let mut cursor = QueryCursor::new();
     cursor.set_byte_range(range.clone());
 
-    let source = rope.to_string();
-    let source_bytes = source.as_bytes();
-
-    let mut highlights = Vec::new();
-
     // Use query cursor to iterate over matches
     // tree-sitter 0.24 uses a different API - we traverse nodes manually
-    self.collect_highlights_recursive(
-      tree.root_node(),
-      query,
-      source_bytes,
-      &range,
-      &mut highlights,
-    );
+    let first = highlights.len();
+    self.collect_highlights_recursive(tree.root_node(), query, source, range, highlights);
+    self.apply_locals(range, highlights, first);
+
+    for layer in self.injections.read().iter() {
+      let span = layer.span();
+      if span.start <= range.end && range.start <= span.end {
+        layer.collect_highlights(source, range, highlights);
+      }
+    }
+  }
 
-    // Sort and deduplicate overlapping highlights
-    highlights.sort_by_key(|h| (h.start, std::cmp::Reverse(h.end)));
+  /// Give the highlights of this layer from `first` on the kind of the
+  /// local definition they define or refer to
+  ///
+  /// Only plain variable highlights are changed; locals the node-kind
+  /// mapping has no highlight for are added.
+  fn apply_locals(&self, range: &Range<usize>, highlights: &mut Vec<Highlight>, first: usize) {
+    let mut kinds: HashMap<_, _> = self
+      .locals
+      .read()
+      .kinds_in(range)
+      .filter(|(r, _)| r.start >= range.start && r.end <= range.end)
+      .map(|(r, kind)| ((r.start, r.end), (kind.highlight_kind(), false)))
+      .collect();
+    if kinds.is_empty() {
+      return;
+    }
 
-    highlights
+    for highlight in &mut highlights[first..] {
+      if let Some((kind, matched)) = kinds.get_mut(&(highlight.start, highlight.end)) {
+        if highlight.kind == HighlightKind::Variable {
+          highlight.kind = *kind;
+        }
+        *matched = true;
+      }
+    }
+    highlights.extend(
+      kinds
+        .into_iter()
+        .filter(|(_, (_, matched))| !matched)
+        .map(|((start, end), (kind, _))| Highlight::new(start, end, kind)),
+    );
   }
 
   /// Collect highlights by traversing the tree
@@ -1074,21 +1761,11 @@ fn collect_errors(node: Node, rope: &Rope, errors: &mut Vec<SyntaxError>) {
   }
 }
 
-/// Convert byte offset to tree-sitter Point
+/// Convert byte offset to tree-sitter Point, clamped to the end of the text
 fn byte_to_point(rope: &Rope, byte_offset: usize) -> TsPoint {
-  if byte_offset >= rope.len_bytes() {
-    let line = rope.len_lines().saturating_sub(1);
-    let col = rope.line(line).len_bytes();
-    return TsPoint::new(line, col);
-  }
-
-  let char_idx = rope.byte_to_char(byte_offset);
-  let line = rope.char_to_line(char_idx);
-  let line_start_char = rope.line_to_char(line);
-  let line_start_byte = rope.char_to_byte(line_start_char);
-  let col = byte_offset - line_start_byte;
-
-  TsPoint::new(line, col)
+  coords::byte_to_point(rope, byte_offset.min(rope.len_bytes()))
+    .unwrap_or_default()
+    .into()
 }
 
 // ============================================================================
@@ -1253,6 +1930,10 @@ mod tests {
     );
     assert_eq!(LanguageId::from_extension("py"), LanguageId::Python);
     assert_eq!(LanguageId::from_extension("xyz"), LanguageId::Unknown);
+    assert_eq!(
+      LanguageId::from_name(LanguageId::TypeScriptReact.name().as_ref()),
+      LanguageId::TypeScriptReact
+    );
   }
 
   #[test]
@@ -1280,6 +1961,33 @@ mod tests {
     assert!(manager.is_err());
   }
 
+  #[test]
+  fn test_bundled_queries_compile() {
+    for language in [
+      LanguageId::Rust,
+      LanguageId::TypeScript,
+      LanguageId::TypeScriptReact,
+      LanguageId::JavaScript,
+      LanguageId::JavaScriptReact,
+      LanguageId::Python,
+      LanguageId::Go,
+      LanguageId::Json,
+      LanguageId::Toml,
+      LanguageId::Html,
+      LanguageId::Css,
+      LanguageId::Markdown,
+    ] {
+      let ts_lang = language.tree_sitter_language().unwrap();
+      for kind in QueryKind::ALL {
+        if let Some(query) = language.query(kind) {
+          if let Err(e) = Query::new(&ts_lang, &query) {
+            panic!("{:?} {}: {}", language, kind.file_name(), e);
+          }
+        }
+      }
+    }
+  }
+
   #[test]
   fn test_basic_parse() {
     let manager = SyntaxManager::new(LanguageId::Rust).unwrap();
@@ -1317,6 +2025,113 @@ mod tests {
     assert!(!result.highlights.is_empty());
   }
 
+  #[test]
+  fn test_apply_changes_single_reparse() {
+    let manager = SyntaxManager::new(LanguageId::Rust).unwrap();
+    let buffer = crate::Buffer::with_content("fn a() {}\nfn b() {}\n");
+    manager.parse(&Rope::from_str(&buffer.to_string())).unwrap();
+
+    let changes = buffer
+      .transact(|tx| {
+        tx.replace(3, 4, "alpha");
+        tx.replace(13, 14, "beta");
+      })
+      .unwrap();
+
+    let rope = Rope::from_str(&buffer.to_string());
+    manager.apply_changes(&rope, &changes).unwrap();
+
+    let result = manager.parse_result(&rope);
+    assert!(result.errors.is_empty());
+  }
+
+  #[test]
+  fn test_injected_highlights_are_merged() {
+    let manager = SyntaxManager::new(LanguageId::Markdown).unwrap();
+    let source = "# Notes\n\n```rust\nfn main() {}\n```\n";
+    let rope = Rope::from_str(source);
+    manager.parse(&rope).unwrap();
+
+    let fn_start = source.find("fn").unwrap();
+    assert_eq!(manager.language_at(fn_start), LanguageId::Rust);
+    assert_eq!(manager.language_at(0), LanguageId::Markdown);
+
+    let highlights = manager.highlights(&rope, 0..rope.len_bytes());
+    assert!(highlights.contains(&Highlight::new(
+      fn_start,
+      fn_start + 2,
+      HighlightKind::Keyword
+    )));
+  }
+
+  #[test]
+  fn test_injections_follow_edits() {
+    let manager = SyntaxManager::new(LanguageId::Html).unwrap();
+    let buffer = crate::Buffer::with_content("<p>hi</p>\n<script>let a = 1;</script>\n");
+    manager.parse(&Rope::from_str(&buffer.to_string())).unwrap();
+
+    // Edit inside the script, and add a style block in front of it
+    let changes = buffer
+      .transact(|tx| {
+        tx.replace(18, 21, "const");
+        tx.insert(10, "<style>p { color: red; }</style>\n");
+      })
+      .unwrap();
+    let rope = Rope::from_str(&buffer.to_string());
+    manager.apply_changes(&rope, &changes).unwrap();
+
+    let source = buffer.to_string();
+    let const_start = source.find("const").unwrap();
+    assert_eq!(manager.language_at(const_start), LanguageId::JavaScript);
+    assert_eq!(
+      manager.language_at(source.find("color").unwrap()),
+      LanguageId::Css
+    );
+
+    let highlights = manager.highlights(&rope, 0..rope.len_bytes());
+    assert!(highlights.contains(&Highlight::new(
+      const_start,
+      const_start + 5,
+      HighlightKind::Keyword
+    )));
+  }
+
+  #[test]
+  fn test_locals_refine_highlights() {
+    let manager = SyntaxManager::new(LanguageId::Rust).unwrap();
+    let source = "const LIMIT: u32 = 3;\nfn clamp(value: u32) -> u32 {\n  value.min(LIMIT)\n}\n";
+    let rope = Rope::from_str(source);
+    manager.parse(&rope).unwrap();
+
+    let highlights = manager.highlights(&rope, 0..rope.len_bytes());
+    let use_start = source.find("value.").unwrap();
+    assert!(highlights.contains(&Highlight::new(
+      use_start,
+      use_start + 5,
+      HighlightKind::VariableParameter
+    )));
+    assert!(!highlights.contains(&Highlight::new(
+      use_start,
+      use_start + 5,
+      HighlightKind::Variable
+    )));
+    let limit_use = source.rfind("LIMIT").unwrap();
+    assert!(highlights.contains(&Highlight::new(
+      limit_use,
+      limit_use + 5,
+      HighlightKind::Constant
+    )));
+
+    let param = source.find("value").unwrap();
+    assert_eq!(
+      manager.references_at(use_start + 2),
+      vec![param..param + 5, use_start..use_start + 5]
+    );
+    assert!(manager
+      .references_at(source.find("min").unwrap())
+      .is_empty());
+  }
+
   #[test]
   fn test_byte_to_point() {
     let rope = Rope::from_str("hello\nworld\n!");
@@ -0,0 +1,282 @@
+//! Structural text objects
+//!
+//! A language's text objects query marks the nodes editing commands select
+//! as a unit. Captures follow the Helix conventions: `@function.around`
+//! covers a whole function and `@function.inside` its body, and likewise
+//! for `class`, `parameter` (parameters and arguments) and `comment`.
+//! Several nodes captured under the same name in one match form a single
+//! object, e.g. a parameter with its trailing comma. Comments on
+//! consecutive lines form a single comment object around.
+
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor};
+
+/// What a text object covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextObjectKind {
+  Function,
+  Class,
+  Parameter,
+  Comment,
+}
+
+impl TextObjectKind {
+  /// Parse from the first part of a `<kind>.<scope>` capture
+  pub fn from_name(name: &str) -> Option<Self> {
+    Some(match name {
+      "function" => Self::Function,
+      "class" => Self::Class,
+      "parameter" | "argument" => Self::Parameter,
+      "comment" => Self::Comment,
+      _ => return None,
+    })
+  }
+}
+
+/// Which part of a text object to select
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextObjectScope {
+  /// The contents, e.g. a function's body
+  Inside,
+  /// The whole object, e.g. a parameter with its separator
+  Around,
+}
+
+/// A text object found by a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextObject {
+  pub kind: TextObjectKind,
+  pub around: Range<usize>,
+  /// `None` if the query marks no contents, e.g. for an empty body
+  pub inside: Option<Range<usize>>,
+}
+
+impl TextObject {
+  /// Get the byte range of a part of this object
+  ///
+  /// Objects without contents select the whole object for either scope.
+  pub fn range(&self, scope: TextObjectScope) -> Range<usize> {
+    match (scope, &self.inside) {
+      (TextObjectScope::Inside, Some(inside)) => inside.clone(),
+      _ => self.around.clone(),
+    }
+  }
+}
+
+/// Find the smallest object part enclosing a selection
+///
+/// An object part equal to the selection does not count, so selecting
+/// again grows the selection to the next enclosing object.
+pub fn select(
+  objects: &[TextObject],
+  scope: TextObjectScope,
+  selection: &Range<usize>,
+) -> Option<Range<usize>> {
+  objects
+    .iter()
+    .map(|object| object.range(scope))
+    .filter(|r| r.start <= selection.start && selection.end <= r.end && r != selection)
+    .min_by_key(|r| r.len())
+}
+
+/// Find the next object part starting after `byte`, or the previous one
+/// starting before it
+///
+/// `objects` must be sorted by start.
+pub fn next(
+  objects: &[TextObject],
+  scope: TextObjectScope,
+  byte: usize,
+  forward: bool,
+) -> Option<Range<usize>> {
+  let mut ranges = objects.iter().map(|object| object.range(scope));
+  match forward {
+    true => ranges
+      .filter(|r| r.start > byte)
+      .min_by_key(|r| (r.start, std::cmp::Reverse(r.end))),
+    false => ranges.rfind(|r| r.start < byte),
+  }
+}
+
+/// A compiled text objects query
+pub(crate) struct TextObjectQuery {
+  query: Query,
+  /// Kind and scope of each capture, by capture index
+  captures: Vec<Option<(TextObjectKind, TextObjectScope)>>,
+}
+
+impl TextObjectQuery {
+  pub(crate) fn new(language: &tree_sitter::Language, source: &str) -> Result<Self> {
+    let query = Query::new(language, source)
+      .map_err(|e| Error::Internal(format!("Failed to compile text objects query: {}", e)))?;
+
+    let captures = query
+      .capture_names()
+      .iter()
+      .map(|name| {
+        let (kind, scope) = name.split_once('.')?;
+        let scope = match scope {
+          "inside" => TextObjectScope::Inside,
+          "around" => TextObjectScope::Around,
+          _ => return None,
+        };
+        Some((TextObjectKind::from_name(kind)?, scope))
+      })
+      .collect();
+
+    Ok(Self { query, captures })
+  }
+
+  /// Find the objects of a kind in a tree, sorted by start
+  pub(crate) fn objects(&self, root: Node, source: &[u8], kind: TextObjectKind) -> Vec<TextObject> {
+    let mut objects = Vec::new();
+    let mut cursor = QueryCursor::new();
+
+    for m in cursor.matches(&self.query, root, source) {
+      let mut around: Option<Range<usize>> = None;
+      let mut inside: Option<Range<usize>> = None;
+      for capture in m.captures {
+        let Some(Some((capture_kind, scope))) = self.captures.get(capture.index as usize) else {
+          continue;
+        };
+        if *capture_kind != kind {
+          continue;
+        }
+        let range = match scope {
+          TextObjectScope::Around => &mut around,
+          TextObjectScope::Inside => &mut inside,
+        };
+        let node = capture.node.byte_range();
+        *range = Some(match range.take() {
+          Some(r) => r.start.min(node.start)..r.end.max(node.end),
+          None => node,
+        });
+      }
+
+      if let Some(around) = around.clone().or_else(|| inside.clone()) {
+        objects.push(TextObject {
+          kind,
+          around,
+          inside,
+        });
+      }
+    }
+
+    objects.sort_by_key(|o| (o.around.start, std::cmp::Reverse(o.around.end)));
+    objects.dedup();
+    if kind == TextObjectKind::Comment {
+      join_comment_blocks(&mut objects, source);
+    }
+    objects
+  }
+}
+
+/// Give each comment the range of the block of comments around it
+///
+/// Comments separated by nothing but whitespace with at most one line
+/// break form a block.
+fn join_comment_blocks(comments: &mut [TextObject], source: &[u8]) {
+  let mut start = 0;
+  for i in 1..=comments.len() {
+    let joined = i < comments.len() && {
+      let gap = &source
+        [comments[i - 1].around.end..comments[i].around.start.max(comments[i - 1].around.end)];
+      gap.iter().all(|b| b.is_ascii_whitespace())
+        && gap.iter().filter(|b| **b == b'\n').count() <= 1
+    };
+    if !joined {
+      let block = comments[start].around.start..comments[i - 1].around.end;
+      for comment in &mut comments[start..i] {
+        comment.inside.get_or_insert_with(|| comment.around.clone());
+        comment.around = block.clone();
+      }
+      start = i;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::syntax::LanguageId;
+  use tree_sitter::Parser;
+
+  fn objects(language: LanguageId, source: &str, kind: TextObjectKind) -> Vec<TextObject> {
+    let ts_lang = language.tree_sitter_language().unwrap();
+    let mut parser = Parser::new();
+    parser.set_language(&ts_lang).unwrap();
+    let tree = parser.parse(source, None).unwrap();
+
+    let query = TextObjectQuery::new(&ts_lang, language.textobject_query().unwrap()).unwrap();
+    query.objects(tree.root_node(), source.as_bytes(), kind)
+  }
+
+  #[test]
+  fn test_select_function() {
+    let source = "fn outer() {\n  let f = |x| x + 1;\n}\n";
+    let functions = objects(LanguageId::Rust, source, TextObjectKind::Function);
+    let closure = source.find('|').unwrap();
+
+    // Inside the closure body, then the closure, then the outer function
+    let body = select(
+      &functions,
+      TextObjectScope::Inside,
+      &(closure + 4..closure + 4),
+    );
+    assert_eq!(body, Some(closure + 4..closure + 9));
+    let closure_range = select(&functions, TextObjectScope::Around, &body.unwrap());
+    assert_eq!(closure_range, Some(closure..closure + 9));
+    let around = select(&functions, TextObjectScope::Around, &closure_range.unwrap());
+    assert_eq!(around, Some(0..source.len() - 1));
+  }
+
+  #[test]
+  fn test_comment_blocks() {
+    let source = "// one\n// two\n\n// three\nfn f() {}\n";
+    let comments = objects(LanguageId::Rust, source, TextObjectKind::Comment);
+    let two = source.find("two").unwrap();
+
+    assert_eq!(
+      select(&comments, TextObjectScope::Inside, &(two..two)),
+      Some(two - 3..two + 3)
+    );
+    assert_eq!(
+      select(&comments, TextObjectScope::Around, &(two..two)),
+      Some(0..two + 3)
+    );
+    let three = next(&comments, TextObjectScope::Around, 0, true).unwrap();
+    assert_eq!(three.start, source.find("// three").unwrap());
+  }
+
+  #[test]
+  fn test_parameters_include_separator() {
+    let source = "def f(a, b=1, *c):\n    return g(a, b)\n";
+    let params = objects(LanguageId::Python, source, TextObjectKind::Parameter);
+    let a = source.find('a').unwrap();
+
+    assert_eq!(
+      select(&params, TextObjectScope::Inside, &(a..a)),
+      Some(a..a + 1)
+    );
+    assert_eq!(
+      select(&params, TextObjectScope::Around, &(a..a)),
+      Some(a..a + 2)
+    );
+
+    // Step through the parameters, then on into the call's arguments
+    let b = next(&params, TextObjectScope::Inside, a, true).unwrap();
+    assert_eq!(&source[b.clone()], "b=1");
+    let c = next(&params, TextObjectScope::Inside, b.start, true).unwrap();
+    assert_eq!(&source[c.clone()], "*c");
+    let arg = next(&params, TextObjectScope::Inside, c.start, true).unwrap();
+    assert_eq!(arg.start, source.rfind("a,").unwrap());
+    assert_eq!(
+      next(&params, TextObjectScope::Inside, c.start, false),
+      Some(b)
+    );
+  }
+}
@@ -0,0 +1,274 @@
+//! Transactions - groups of edits applied and undone as one step
+//!
+//! A transaction is built against a single snapshot of the buffer: every
+//! range passed to the builder refers to the text as it was when the
+//! transaction started, no matter how many edits come before it. This is the
+//! shape multi-cursor edits and LSP `WorkspaceEdit`s arrive in.
+
+use ferrum_core::id::TransactionId;
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::edit::{Edit, EditKind};
+use crate::format::LineEnding;
+
+/// A recorded group of edits that is undone and redone as one step
+///
+/// Edits are stored in application order; each edit's position is relative
+/// to the text produced by the edits before it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+  id: TransactionId,
+  edits: Vec<Edit>,
+  /// Whether following typing may be merged into this transaction
+  mergeable: bool,
+  /// Line ending the buffer switched to along with the edits
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  line_endings: Option<LineEndingChange>,
+}
+
+/// A switch of the line ending a buffer saves with, undone and redone with
+/// the edits converting its text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineEndingChange {
+  pub old: LineEnding,
+  /// Whether the text mixed line endings before the switch
+  pub old_mixed: bool,
+  pub new: LineEnding,
+}
+
+impl Transaction {
+  /// Create a transaction from edits in application order
+  pub fn new(edits: Vec<Edit>) -> Self {
+    Self {
+      id: TransactionId::new(),
+      edits,
+      mergeable: false,
+      line_endings: None,
+    }
+  }
+
+  /// Create a transaction holding a single edit that later typing may
+  /// merge into
+  pub fn single(edit: Edit) -> Self {
+    Self {
+      mergeable: true,
+      ..Self::new(vec![edit])
+    }
+  }
+
+  /// Get the transaction ID
+  pub fn id(&self) -> TransactionId {
+    self.id
+  }
+
+  /// Get the edits in application order
+  pub fn edits(&self) -> &[Edit] {
+    &self.edits
+  }
+
+  /// Get the line ending switch made along with the edits, if any
+  pub fn line_endings(&self) -> Option<LineEndingChange> {
+    self.line_endings
+  }
+
+  /// Get the number of edits
+  pub fn len(&self) -> usize {
+    self.edits.len()
+  }
+
+  /// Check if the transaction has no edits
+  pub fn is_empty(&self) -> bool {
+    self.edits.is_empty()
+  }
+
+  /// Get the number of bytes of text held by the edits
+  pub fn byte_size(&self) -> usize {
+    self
+      .edits
+      .iter()
+      .map(|e| e.old_text().len() + e.new_text().len())
+      .sum()
+  }
+
+  /// Check if this transaction can absorb a following single edit
+  pub fn can_merge_with(&self, edit: &Edit) -> bool {
+    match self.edits.as_slice() {
+      [last] if self.mergeable => last.can_merge_with(edit),
+      _ => false,
+    }
+  }
+
+  /// Merge a following single edit into this transaction
+  pub fn merge(&mut self, edit: Edit) {
+    if let Some(last) = self.edits.last_mut() {
+      last.merge(edit);
+    }
+  }
+}
+
+/// A pending edit, in snapshot coordinates
+#[derive(Debug, Clone)]
+struct PendingEdit {
+  start: usize,
+  end: usize,
+  text: String,
+}
+
+/// Collects range edits for `Buffer::transact`
+///
+/// All offsets are char offsets into the buffer as it was when the
+/// transaction started. Ranges must not overlap; several inserts at the same
+/// offset are applied in the order they were added.
+#[derive(Debug, Default)]
+pub struct TransactionBuilder {
+  edits: Vec<PendingEdit>,
+  line_endings: Option<LineEndingChange>,
+}
+
+impl TransactionBuilder {
+  /// Create an empty builder
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Insert text at a char offset
+  pub fn insert(&mut self, char_idx: usize, text: impl Into<String>) -> &mut Self {
+    self.replace(char_idx, char_idx, text)
+  }
+
+  /// Delete the chars in `start..end`
+  pub fn delete(&mut self, start: usize, end: usize) -> &mut Self {
+    self.replace(start, end, String::new())
+  }
+
+  /// Replace the chars in `start..end` with text
+  pub fn replace(&mut self, start: usize, end: usize, text: impl Into<String>) -> &mut Self {
+    self.edits.push(PendingEdit {
+      start,
+      end,
+      text: text.into(),
+    });
+    self
+  }
+
+  /// Record a switch of the line ending along with the edits
+  pub fn set_line_endings(&mut self, change: LineEndingChange) -> &mut Self {
+    self.line_endings = Some(change);
+    self
+  }
+
+  /// Get the number of pending edits
+  pub fn len(&self) -> usize {
+    self.edits.len()
+  }
+
+  /// Check if no edits were added
+  pub fn is_empty(&self) -> bool {
+    self.edits.is_empty()
+  }
+
+  /// Validate the pending edits against a snapshot of `len_chars` chars and
+  /// resolve them into a transaction, with edits in application order
+  ///
+  /// `slice` returns the snapshot text for a char range and is used to record
+  /// the removed text for undo.
+  pub(crate) fn build(
+    self,
+    len_chars: usize,
+    slice: impl Fn(usize, usize) -> String,
+  ) -> Result<Transaction> {
+    let line_endings = self.line_endings;
+    let edits = self.resolve(len_chars, slice)?;
+    Ok(Transaction {
+      line_endings,
+      ..Transaction::new(edits)
+    })
+  }
+
+  fn resolve(
+    mut self,
+    len_chars: usize,
+    slice: impl Fn(usize, usize) -> String,
+  ) -> Result<Vec<Edit>> {
+    // Stable sort keeps same-offset inserts in insertion order, ahead of any
+    // range starting at that offset
+    self.edits.sort_by_key(|e| (e.start, e.end));
+
+    let mut prev_end = 0;
+    for edit in &self.edits {
+      if edit.start > edit.end || edit.end > len_chars || edit.start < prev_end {
+        return Err(Error::InvalidRange {
+          start: edit.start,
+          end: edit.end,
+        });
+      }
+      prev_end = edit.end;
+    }
+
+    // Shift each range by the net length change of the edits before it
+    let mut delta: isize = 0;
+    let mut resolved = Vec::with_capacity(self.edits.len());
+    for edit in self.edits {
+      let old_len = edit.end - edit.start;
+      let new_len = edit.text.chars().count();
+      if old_len == 0 && new_len == 0 {
+        continue;
+      }
+
+      let old_text = slice(edit.start, edit.end);
+      let kind = match (old_len, new_len) {
+        (0, _) => EditKind::Insert,
+        (_, 0) => EditKind::Delete,
+        _ => EditKind::Replace,
+      };
+      let len = if kind == EditKind::Insert { new_len } else { old_len };
+      let position = edit.start.checked_add_signed(delta).unwrap_or(0);
+
+      resolved.push(Edit::new(kind, position, len, old_text, edit.text));
+      delta += new_len as isize - old_len as isize;
+    }
+
+    Ok(resolved)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn resolve(tx: TransactionBuilder, text: &str) -> Result<Vec<Edit>> {
+    let chars: Vec<char> = text.chars().collect();
+    tx.resolve(chars.len(), |s, e| chars[s..e].iter().collect())
+  }
+
+  #[test]
+  fn test_resolve_shifts_later_edits() {
+    let mut tx = TransactionBuilder::new();
+    tx.replace(6, 11, "Rust").insert(0, ">> ");
+
+    let edits = resolve(tx, "Hello World").unwrap();
+    assert_eq!(edits.len(), 2);
+    assert_eq!(edits[0].kind(), EditKind::Insert);
+    assert_eq!(edits[0].position(), 0);
+    assert_eq!(edits[1].kind(), EditKind::Replace);
+    assert_eq!(edits[1].position(), 9);
+    assert_eq!(edits[1].old_text(), "World");
+  }
+
+  #[test]
+  fn test_resolve_rejects_overlap() {
+    let mut tx = TransactionBuilder::new();
+    tx.delete(0, 5).delete(3, 8);
+
+    assert!(resolve(tx, "Hello World").is_err());
+  }
+
+  #[test]
+  fn test_resolve_rejects_out_of_bounds() {
+    let mut tx = TransactionBuilder::new();
+    tx.insert(20, "x");
+
+    assert!(resolve(tx, "Hello").is_err());
+  }
+}
@@ -25,6 +25,15 @@ pub enum Error {
   #[error("Directory operation failed: {0}")]
   DirectoryError(String),
 
+  #[error("File changed on disk since it was loaded: {path}")]
+  FileChangedOnDisk { path: PathBuf },
+
+  #[error("File already exists: {path}")]
+  FileExists { path: PathBuf },
+
+  #[error("Path is outside the project: {path}")]
+  OutsideProject { path: PathBuf },
+
   // ===== Buffer Errors =====
   #[error("Buffer not found: {0}")]
   BufferNotFound(crate::id::BufferId),
@@ -38,6 +47,18 @@ pub enum Error {
   #[error("Invalid range: {start}..{end}")]
   InvalidRange { start: usize, end: usize },
 
+  #[error("Unknown encoding: {label}")]
+  UnknownEncoding { label: String },
+
+  #[error("Text cannot be encoded as {encoding}")]
+  UnencodableText { encoding: String },
+
+  #[error("Failed to load grammar {name}: {reason}")]
+  GrammarLoadFailed { name: String, reason: String },
+
+  #[error("Buffer has unsaved changes: {path}")]
+  UnsavedChanges { path: PathBuf },
+
   // ===== Editor Errors =====
   #[error("No active editor")]
   NoActiveEditor,
@@ -58,6 +79,12 @@ pub enum Error {
   #[error("LSP initialization failed: {reason}")]
   LspInitFailed { reason: String },
 
+  #[error("LSP server exited for language: {language}")]
+  LspServerExited { language: String },
+
+  #[error("LSP edit is for version {version} of {path}, which has since changed")]
+  LspStaleEdit { path: PathBuf, version: i32 },
+
   // ===== Git Errors =====
   #[error("Not a git repository: {path}")]
   NotGitRepo { path: PathBuf },
@@ -126,18 +153,27 @@ impl Error {
       | Error::FileTooLarge { .. }
       | Error::InvalidEncoding { .. }
       | Error::DirectoryError(_)
+      | Error::FileChangedOnDisk { .. }
+      | Error::FileExists { .. }
+      | Error::OutsideProject { .. }
       | Error::Io(_) => ErrorKind::FileSystem,
 
       Error::BufferNotFound(_)
       | Error::BufferReadOnly
       | Error::InvalidPosition { .. }
-      | Error::InvalidRange { .. } => ErrorKind::Buffer,
+      | Error::InvalidRange { .. }
+      | Error::UnknownEncoding { .. }
+      | Error::UnencodableText { .. }
+      | Error::GrammarLoadFailed { .. }
+      | Error::UnsavedChanges { .. } => ErrorKind::Buffer,
 
       Error::NoActiveEditor | Error::TabNotFound(_) | Error::ViewNotFound(_) => ErrorKind::Editor,
 
-      Error::LspNotRunning { .. } | Error::LspTimeout { .. } | Error::LspInitFailed { .. } => {
-        ErrorKind::Lsp
-      },
+      Error::LspNotRunning { .. }
+      | Error::LspTimeout { .. }
+      | Error::LspInitFailed { .. }
+      | Error::LspServerExited { .. }
+      | Error::LspStaleEdit { .. } => ErrorKind::Lsp,
 
       Error::NotGitRepo { .. } | Error::GitOperationFailed { .. } => ErrorKind::Git,
 
@@ -159,8 +195,11 @@ impl Error {
       self,
       Error::FileNotFound { .. }
         | Error::PermissionDenied { .. }
+        | Error::FileChangedOnDisk { .. }
         | Error::BufferNotFound(_)
         | Error::LspTimeout { .. }
+        | Error::LspServerExited { .. }
+        | Error::LspStaleEdit { .. }
         | Error::IpcTimeout { .. }
     )
   }
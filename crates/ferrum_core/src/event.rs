@@ -174,14 +174,40 @@ impl Event for ActiveTabChanged {}
 /// Event emitted when LSP diagnostics are received
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiagnosticsReceived {
-  pub file_id: FileId,
+  pub path: std::path::PathBuf,
+  /// All diagnostics of the file, replacing those received before
   pub diagnostics: Vec<Diagnostic>,
 }
 impl Event for DiagnosticsReceived {}
 
+/// Event emitted when a language server reports progress on long-running
+/// work, such as indexing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LspProgress {
+  pub language: String,
+  /// Token the server reports the work under
+  pub token: String,
+  pub title: String,
+  pub message: Option<String>,
+  /// Percentage done, if the server knows it
+  pub percentage: Option<u8>,
+  /// Whether the work has ended
+  pub done: bool,
+}
+impl Event for LspProgress {}
+
+/// Event emitted when a language server asks for semantic tokens to be
+/// requested again, e.g. after a project-wide change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SemanticTokensRefreshed {
+  pub language: String,
+}
+impl Event for SemanticTokensRefreshed {}
+
 /// A diagnostic message
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Diagnostic {
+  /// Byte range in the file
   pub range: std::ops::Range<usize>,
   pub severity: DiagnosticSeverity,
   pub message: String,
@@ -190,7 +216,7 @@ pub struct Diagnostic {
 }
 
 /// Diagnostic severity level
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum DiagnosticSeverity {
   Error,
   Warning,
@@ -0,0 +1,62 @@
+//! Content hashing
+//!
+//! Stable hashes for detecting whether file content changed between
+//! sessions. Unlike `std::hash`, the output is identical across builds and
+//! platforms, so it is safe to persist.
+
+use std::io::Read;
+
+/// Hash content, returning a lowercase hex digest
+pub fn content_hash(bytes: impl AsRef<[u8]>) -> String {
+  blake3::hash(bytes.as_ref()).to_hex().to_string()
+}
+
+/// Hashes content as it is read through it, for content too large to hold
+/// in memory at once
+pub struct HashingReader<R> {
+  inner: R,
+  hasher: blake3::Hasher,
+}
+
+impl<R> HashingReader<R> {
+  /// Wrap a reader
+  pub fn new(inner: R) -> Self {
+    Self {
+      inner,
+      hasher: blake3::Hasher::new(),
+    }
+  }
+
+  /// Get the hash of the content read so far, as `content_hash` gives it
+  pub fn hash(&self) -> String {
+    self.hasher.finalize().to_hex().to_string()
+  }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.hasher.update(&buf[..n]);
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_content_hash_is_stable() {
+    assert_eq!(content_hash("hello"), content_hash(b"hello"));
+    assert_ne!(content_hash("hello"), content_hash("hello "));
+    assert_eq!(content_hash("").len(), 64);
+  }
+
+  #[test]
+  fn test_hashing_reader_matches_content_hash() {
+    let mut reader = HashingReader::new(&b"hello world"[..]);
+    let mut chunk = [0u8; 4];
+    while reader.read(&mut chunk).unwrap() > 0 {}
+    assert_eq!(reader.hash(), content_hash("hello world"));
+  }
+}
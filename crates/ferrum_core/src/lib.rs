@@ -5,6 +5,7 @@
 
 pub mod error;
 pub mod event;
+pub mod hash;
 pub mod id;
 pub mod result;
 pub mod task;
@@ -23,14 +24,61 @@ pub mod constants {
   /// Maximum undo history entries per buffer
   pub const MAX_UNDO_HISTORY: usize = 1000;
 
+  /// File size at which buffers open in large-file mode (50MB)
+  pub const LARGE_FILE_THRESHOLD: u64 = 50 * 1024 * 1024;
+
+  /// Maximum undo history size for a large-file buffer (16MB of edited text)
+  pub const LARGE_FILE_MAX_HISTORY_BYTES: usize = 16 * 1024 * 1024;
+
   /// Debounce interval for input (milliseconds)
   pub const INPUT_DEBOUNCE_MS: u64 = 16;
 
   /// Debounce interval for idle operations (milliseconds)
   pub const IDLE_DEBOUNCE_MS: u64 = 100;
 
+  /// Debounce interval for sending document changes to language servers
+  /// (milliseconds)
+  pub const LSP_CHANGE_DEBOUNCE_MS: u64 = 50;
+
+  /// Timeout for LSP requests without one of their own (milliseconds)
+  pub const LSP_REQUEST_TIMEOUT_MS: u64 = 30_000;
+
+  /// Timeout for a language server to initialize (milliseconds)
+  pub const LSP_INITIALIZE_TIMEOUT_MS: u64 = 120_000;
+
+  /// Timeout for a language server to shut down before it is killed
+  /// (milliseconds)
+  pub const LSP_SHUTDOWN_TIMEOUT_MS: u64 = 5_000;
+
+  /// Delay before restarting a crashed language server the first time,
+  /// doubled for each further crash (milliseconds)
+  pub const LSP_RESTART_BACKOFF_MS: u64 = 500;
+
+  /// Longest delay before restarting a crashed language server
+  /// (milliseconds)
+  pub const LSP_RESTART_BACKOFF_MAX_MS: u64 = 30_000;
+
+  /// Crashes of a language server within `LSP_CRASH_WINDOW_MS` after which
+  /// it is no longer restarted
+  pub const LSP_MAX_CRASHES: usize = 5;
+
+  /// Window in which language server crashes count towards the limit
+  /// (milliseconds)
+  pub const LSP_CRASH_WINDOW_MS: u64 = 180_000;
+
+  /// Versions of a document kept to place the diagnostics a language server
+  /// publishes for them
+  pub const LSP_DIAGNOSED_VERSIONS: usize = 8;
+
+  /// Time the user has to confirm a workspace edit that deletes or
+  /// overwrites files (milliseconds)
+  pub const EDIT_CONFIRMATION_TIMEOUT_MS: u64 = 120_000;
+
   /// Default autosave interval (milliseconds)
   pub const AUTOSAVE_INTERVAL_MS: u64 = 30_000;
+
+  /// Undo history entries kept in a buffer's recovery file
+  pub const RECOVERY_HISTORY_ENTRIES: usize = 100;
 }
 
 /// Prelude module for common imports
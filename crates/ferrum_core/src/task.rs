@@ -126,6 +126,11 @@ impl TaskProgress {
     self.message.lock().clone()
   }
 
+  /// Set the current message
+  pub fn set_message(&self, message: Option<String>) {
+    *self.message.lock() = message;
+  }
+
   /// Set total units of work
   pub fn set_total(&self, total: u64) {
     self.total.store(total, Ordering::SeqCst);
@@ -0,0 +1,621 @@
+//! Project-wide dependency graph
+//!
+//! The graph has an edge from each source file to each file its imports
+//! resolve to; see `ImportResolver` for the languages and rules. It is
+//! built by scanning the project once, and then kept up to date from file
+//! system events: a changed file is scanned again, and as resolution only
+//! looks at the set of files and the project configs, adding or removing a
+//! file re-resolves the imports that could refer to it, and changing a
+//! config re-resolves all of them, without reading any other file.
+
+use crate::import_resolver::{ImportResolver, normalize};
+use ferrum_buffer::syntax::LanguageId;
+use ferrum_buffer::{Import, ImportKind, ImportScanner};
+use ferrum_core::prelude::*;
+use ferrum_fs::FileWalker;
+use ferrum_fs::watcher::FsEvent;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Files larger than this, e.g. bundles, are not scanned
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Directories of dependencies and build output, skipped even when a
+/// project does not ignore them
+const SKIPPED_DIRS: [&str; 3] = [".git", "node_modules", "target"];
+
+/// An import of a file and the files it resolves to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileImport {
+  pub kind: ImportKind,
+  /// Module imported from as written
+  pub source: String,
+  pub line: usize,
+  /// Empty for imports from outside the project, e.g. packages
+  pub targets: Vec<PathBuf>,
+}
+
+/// An edge of the dependency graph: a file importing another
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DependencyEdge {
+  pub from: PathBuf,
+  pub to: PathBuf,
+  /// Module imported from as written
+  pub source: String,
+  /// Line of the import in `from`
+  pub line: usize,
+}
+
+/// A scanned source file
+struct FileEntry {
+  language: LanguageId,
+  imports: Vec<Import>,
+  resolved: Vec<FileImport>,
+}
+
+#[derive(Default)]
+struct GraphState {
+  resolver: ImportResolver,
+  /// Paths of the scanned files, sorted so a directory's files are a range
+  paths: BTreeSet<PathBuf>,
+  files: HashMap<PathBuf, FileEntry>,
+  /// Files importing each file
+  dependents: HashMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl GraphState {
+  /// Resolve the imports of a file again and update the reverse edges
+  fn resolve(&mut self, path: &Path) {
+    let Some(entry) = self.files.get(path) else {
+      return;
+    };
+    let resolved: Vec<FileImport> = entry
+      .imports
+      .iter()
+      .map(|import| FileImport {
+        kind: import.kind,
+        source: import.source.clone(),
+        line: import.line,
+        targets: self
+          .resolver
+          .resolve(path, entry.language, import, &self.paths),
+      })
+      .collect();
+
+    self.unlink(path);
+    for target in resolved.iter().flat_map(|import| &import.targets) {
+      self
+        .dependents
+        .entry(target.clone())
+        .or_default()
+        .insert(path.to_path_buf());
+    }
+    if let Some(entry) = self.files.get_mut(path) {
+      entry.resolved = resolved;
+    }
+  }
+
+  /// Resolve again the imports of the files that adding or removing files
+  /// may affect
+  fn resolve_affected(&mut self, changed: &[PathBuf]) {
+    let mut affected: BTreeSet<PathBuf> = changed
+      .iter()
+      .filter_map(|path| self.dependents.get(path))
+      .flatten()
+      .cloned()
+      .collect();
+    affected.extend(
+      self
+        .files
+        .iter()
+        .filter(|(path, entry)| {
+          !changed.contains(path)
+            && changed
+              .iter()
+              .any(|file| self.resolver.may_affect(file, path, &entry.imports))
+        })
+        .map(|(path, _)| path.clone()),
+    );
+    for path in affected {
+      self.resolve(&path);
+    }
+  }
+
+  fn resolve_all(&mut self) {
+    self.dependents.clear();
+    let paths: Vec<_> = self.paths.iter().cloned().collect();
+    for path in paths {
+      self.resolve(&path);
+    }
+  }
+
+  /// Remove the reverse edges of a file's imports
+  fn unlink(&mut self, path: &Path) {
+    let Some(entry) = self.files.get(path) else {
+      return;
+    };
+    for target in entry.resolved.iter().flat_map(|import| &import.targets) {
+      if let Some(dependents) = self.dependents.get_mut(target) {
+        dependents.remove(path);
+        if dependents.is_empty() {
+          self.dependents.remove(target);
+        }
+      }
+    }
+  }
+
+  /// Get the files each file depends on, by index into the sorted paths
+  fn adjacency(&self) -> (Vec<&PathBuf>, Vec<Vec<usize>>) {
+    let nodes: Vec<&PathBuf> = self.paths.iter().collect();
+    let index: HashMap<&PathBuf, usize> = nodes.iter().enumerate().map(|(i, p)| (*p, i)).collect();
+    let edges = nodes
+      .iter()
+      .map(|path| {
+        let mut targets: Vec<usize> = self.files[*path]
+          .resolved
+          .iter()
+          .flat_map(|import| &import.targets)
+          .filter_map(|target| index.get(target).copied())
+          .collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+      })
+      .collect();
+    (nodes, edges)
+  }
+}
+
+/// Dependency graph of the source files under a project root
+pub struct DependencyGraph {
+  root: PathBuf,
+  scanner: Mutex<ImportScanner>,
+  state: RwLock<GraphState>,
+}
+
+impl DependencyGraph {
+  /// Create an empty graph for a project; see [`build`](Self::build)
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self {
+      root: normalize(&root.into()),
+      scanner: Mutex::new(ImportScanner::new()),
+      state: RwLock::new(GraphState::default()),
+    }
+  }
+
+  /// Get the project root
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// Scan the project's files and resolve their imports, replacing the
+  /// graph
+  ///
+  /// Files ignored by `.gitignore`, hidden files and files under
+  /// dependency and build directories are skipped.
+  pub fn build(&self) -> Result<()> {
+    let start = std::time::Instant::now();
+    let walked = FileWalker::new(&self.root).walk()?;
+
+    let mut state = GraphState::default();
+    let mut scanner = self.scanner.lock();
+    let files = walked
+      .into_iter()
+      .filter(|info| !info.is_dir && self.in_project(&info.path));
+    for info in files {
+      if ImportResolver::is_config(&info.path) {
+        state.resolver.load_config(&info.path);
+      } else if let Some(entry) = scan(&mut scanner, &info.path) {
+        state.paths.insert(info.path.clone());
+        state.files.insert(info.path, entry);
+      }
+    }
+    drop(scanner);
+    state.resolve_all();
+
+    debug!(
+      "Built dependency graph of {} files in {:?}",
+      state.paths.len(),
+      start.elapsed()
+    );
+    *self.state.write() = state;
+    Ok(())
+  }
+
+  /// Update the graph for a file system event
+  ///
+  /// Returns whether the graph may have changed.
+  pub fn handle_event(&self, event: &FsEvent) -> bool {
+    match event {
+      FsEvent::Created(path) | FsEvent::Modified(path) => self.file_changed(path),
+      FsEvent::Deleted(path) => self.file_removed(path),
+      FsEvent::Renamed { from, to } => {
+        let removed = self.file_removed(from);
+        self.file_changed(to) || removed
+      },
+    }
+  }
+
+  /// Scan a created or modified file again
+  ///
+  /// Returns whether the graph may have changed.
+  pub fn file_changed(&self, path: &Path) -> bool {
+    let path = normalize(path);
+    if !self.is_tracked(&path) {
+      return false;
+    }
+    if ImportResolver::is_config(&path) {
+      let mut state = self.state.write();
+      state.resolver.load_config(&path);
+      state.resolve_all();
+      return true;
+    }
+
+    let Some(entry) = scan(&mut self.scanner.lock(), &path) else {
+      // No longer a file we can scan, e.g. it grew too large
+      return self.file_removed(&path);
+    };
+    let mut state = self.state.write();
+    let imports_changed = match state.files.get(&path) {
+      Some(old) => old.imports != entry.imports,
+      None => true,
+    };
+    if !imports_changed {
+      return false;
+    }
+
+    let added = state.paths.insert(path.clone());
+    state.unlink(&path);
+    state.files.insert(path.clone(), entry);
+    state.resolve(&path);
+    if added {
+      // Imports may resolve to the new file instead
+      state.resolve_affected(std::slice::from_ref(&path));
+    }
+    true
+  }
+
+  /// Remove a deleted file, or the files under a deleted directory
+  ///
+  /// Returns whether the graph changed.
+  pub fn file_removed(&self, path: &Path) -> bool {
+    let path = normalize(path);
+    let mut state = self.state.write();
+    if ImportResolver::is_config(&path) && self.is_tracked(&path) {
+      state.resolver.load_config(&path);
+      state.resolve_all();
+      return true;
+    }
+
+    let removed: Vec<PathBuf> = state
+      .paths
+      .range(path.clone()..)
+      .take_while(|p| p.starts_with(&path))
+      .cloned()
+      .collect();
+    if removed.is_empty() {
+      return false;
+    }
+    for removed in &removed {
+      state.unlink(removed);
+      state.paths.remove(removed);
+      state.files.remove(removed);
+    }
+    state.resolve_affected(&removed);
+    true
+  }
+
+  /// Check if a path is one [`build`](Self::build) scans: in the project
+  /// and neither hidden nor ignored
+  fn is_tracked(&self, path: &Path) -> bool {
+    self.in_project(path) && !FileWalker::new(&self.root).is_ignored(path)
+  }
+
+  /// Check if a path is in the project and not under a skipped directory
+  fn in_project(&self, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(&self.root) else {
+      return false;
+    };
+    !relative.components().any(|c| {
+      c.as_os_str()
+        .to_str()
+        .is_some_and(|name| SKIPPED_DIRS.contains(&name))
+    })
+  }
+
+  /// Get the number of files in the graph
+  pub fn file_count(&self) -> usize {
+    self.state.read().paths.len()
+  }
+
+  /// Get the imports of a file and what they resolve to
+  pub fn imports(&self, path: &Path) -> Vec<FileImport> {
+    self
+      .state
+      .read()
+      .files
+      .get(&normalize(path))
+      .map(|entry| entry.resolved.clone())
+      .unwrap_or_default()
+  }
+
+  /// Get the files a file imports
+  pub fn dependencies(&self, path: &Path) -> Vec<PathBuf> {
+    let mut targets: Vec<PathBuf> = self
+      .imports(path)
+      .into_iter()
+      .flat_map(|import| import.targets)
+      .collect();
+    targets.sort();
+    targets.dedup();
+    targets
+  }
+
+  /// Get the imports of other files that resolve to a file
+  pub fn dependents(&self, path: &Path) -> Vec<DependencyEdge> {
+    let path = normalize(path);
+    let state = self.state.read();
+    let Some(dependents) = state.dependents.get(&path) else {
+      return Vec::new();
+    };
+    dependents
+      .iter()
+      .flat_map(|from| edges_of(from, &state.files[from]))
+      .filter(|edge| edge.to == path)
+      .collect()
+  }
+
+  /// Get all edges of the graph
+  pub fn edges(&self) -> Vec<DependencyEdge> {
+    let state = self.state.read();
+    state
+      .paths
+      .iter()
+      .flat_map(|path| edges_of(path, &state.files[path]))
+      .collect()
+  }
+
+  /// Find the groups of files that depend on each other in a cycle
+  ///
+  /// Each group is sorted, and the groups are sorted by their first file.
+  pub fn cycles(&self) -> Vec<Vec<PathBuf>> {
+    let state = self.state.read();
+    let (nodes, edges) = state.adjacency();
+    let mut cycles: Vec<Vec<PathBuf>> = strongly_connected(&edges)
+      .into_iter()
+      .filter(|component| component.len() > 1)
+      .map(|component| {
+        let mut files: Vec<PathBuf> = component.into_iter().map(|i| nodes[i].clone()).collect();
+        files.sort();
+        files
+      })
+      .collect();
+    cycles.sort();
+    cycles
+  }
+
+  /// Find a shortest chain of imports from one file to another
+  ///
+  /// The path starts with `from` and ends with `to`.
+  pub fn shortest_path(&self, from: &Path, to: &Path) -> Option<Vec<PathBuf>> {
+    let state = self.state.read();
+    let (nodes, edges) = state.adjacency();
+    let find = |path: &Path| nodes.iter().position(|node| node.as_path() == path);
+    let (from, to) = (find(&normalize(from))?, find(&normalize(to))?);
+
+    let mut previous: Vec<Option<usize>> = vec![None; nodes.len()];
+    let mut queue = VecDeque::from([from]);
+    previous[from] = Some(from);
+    while let Some(node) = queue.pop_front() {
+      if node == to {
+        let mut path = vec![nodes[to].clone()];
+        let mut current = to;
+        while current != from {
+          current = previous[current]?;
+          path.push(nodes[current].clone());
+        }
+        path.reverse();
+        return Some(path);
+      }
+      for &next in &edges[node] {
+        if previous[next].is_none() {
+          previous[next] = Some(node);
+          queue.push_back(next);
+        }
+      }
+    }
+    None
+  }
+}
+
+/// Read and scan a source file, if it is one the graph covers
+fn scan(scanner: &mut ImportScanner, path: &Path) -> Option<FileEntry> {
+  let language = ImportResolver::language(path)?;
+  let metadata = std::fs::metadata(path).ok()?;
+  if !metadata.is_file() || metadata.len() > MAX_FILE_SIZE {
+    return None;
+  }
+  let content = std::fs::read_to_string(path).ok()?;
+  Some(FileEntry {
+    language,
+    imports: scanner.scan(language, &content),
+    resolved: Vec::new(),
+  })
+}
+
+fn edges_of<'a>(from: &'a Path, entry: &'a FileEntry) -> impl Iterator<Item = DependencyEdge> + 'a {
+  entry.resolved.iter().flat_map(move |import| {
+    import.targets.iter().map(move |to| DependencyEdge {
+      from: from.to_path_buf(),
+      to: to.clone(),
+      source: import.source.clone(),
+      line: import.line,
+    })
+  })
+}
+
+/// Find the strongly connected components of a graph with Tarjan's
+/// algorithm, without recursion as import chains can be long
+fn strongly_connected(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+  const UNVISITED: usize = usize::MAX;
+  let mut index = vec![UNVISITED; edges.len()];
+  let mut low = vec![0; edges.len()];
+  let mut on_stack = vec![false; edges.len()];
+  let mut stack = Vec::new();
+  let mut next = 0;
+  let mut components = Vec::new();
+
+  for start in 0..edges.len() {
+    if index[start] != UNVISITED {
+      continue;
+    }
+    // Nodes being visited and the index of the next edge to follow
+    let mut work = vec![(start, 0)];
+    index[start] = next;
+    low[start] = next;
+    next += 1;
+    stack.push(start);
+    on_stack[start] = true;
+
+    while let Some((node, edge)) = work.pop() {
+      if let Some(&target) = edges[node].get(edge) {
+        work.push((node, edge + 1));
+        if index[target] == UNVISITED {
+          index[target] = next;
+          low[target] = next;
+          next += 1;
+          stack.push(target);
+          on_stack[target] = true;
+          work.push((target, 0));
+        } else if on_stack[target] {
+          low[node] = low[node].min(index[target]);
+        }
+        continue;
+      }
+
+      if low[node] == index[node] {
+        let mut component = Vec::new();
+        while let Some(member) = stack.pop() {
+          on_stack[member] = false;
+          component.push(member);
+          if member == node {
+            break;
+          }
+        }
+        components.push(component);
+      }
+      if let Some(&(parent, _)) = work.last() {
+        low[parent] = low[parent].min(low[node]);
+      }
+    }
+  }
+  components
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn write(root: &Path, path: &str, content: &str) -> PathBuf {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, content).unwrap();
+    path
+  }
+
+  #[test]
+  fn test_build_and_query() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let a = write(
+      &root,
+      "src/a.ts",
+      "import { b } from './b';\nimport x from 'react';\n",
+    );
+    let b = write(&root, "src/b.ts", "import { c } from './c';\n");
+    let c = write(&root, "src/c.ts", "import { b } from './b';\n");
+    write(&root, "node_modules/react/index.js", "export default 1;\n");
+
+    let graph = DependencyGraph::new(&root);
+    graph.build().unwrap();
+    assert_eq!(graph.file_count(), 3);
+
+    let imports = graph.imports(&a);
+    assert_eq!(imports[0].targets, std::slice::from_ref(&b));
+    assert!(imports[1].targets.is_empty());
+    let dependents: Vec<_> = graph.dependents(&b).into_iter().map(|e| e.from).collect();
+    assert_eq!(dependents, [a.clone(), c.clone()]);
+
+    assert_eq!(graph.cycles(), [vec![b.clone(), c.clone()]]);
+    assert_eq!(
+      graph.shortest_path(&a, &c),
+      Some(vec![a.clone(), b.clone(), c.clone()])
+    );
+    assert_eq!(graph.shortest_path(&c, &a), None);
+  }
+
+  #[test]
+  fn test_follows_file_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let main = write(&root, "main.py", "from util import helper\n");
+    let graph = DependencyGraph::new(&root);
+    graph.build().unwrap();
+    assert!(graph.dependencies(&main).is_empty());
+
+    // A new file resolves the import that did not resolve before
+    let util = write(&root, "util.py", "import os\n");
+    assert!(graph.handle_event(&FsEvent::Created(util.clone())));
+    assert_eq!(graph.dependencies(&main), std::slice::from_ref(&util));
+
+    // Saving without changing the imports leaves the graph alone
+    write(&root, "util.py", "import os\n\nX = 1\n");
+    assert!(!graph.handle_event(&FsEvent::Modified(util.clone())));
+
+    let renamed = root.join("tools.py");
+    std::fs::rename(&util, &renamed).unwrap();
+    assert!(graph.handle_event(&FsEvent::Renamed {
+      from: util.clone(),
+      to: renamed.clone(),
+    }));
+    assert!(graph.dependencies(&main).is_empty());
+
+    write(&root, "main.py", "import tools\n");
+    assert!(graph.handle_event(&FsEvent::Modified(main.clone())));
+    assert_eq!(graph.dependents(&renamed)[0].from, main);
+  }
+
+  #[test]
+  fn test_events_re_resolve_affected_imports() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    write(&root, ".gitignore", "dist/\n");
+    let app = write(&root, "src/app.ts", "import { b } from './b';\n");
+    let other = write(&root, "src/other.ts", "import { c } from './c';\n");
+    let index = write(&root, "src/b/index.ts", "export const b = 1;\n");
+    let graph = DependencyGraph::new(&root);
+    graph.build().unwrap();
+    assert_eq!(graph.dependencies(&app), std::slice::from_ref(&index));
+
+    // A file taking precedence over the one an import resolved to
+    let b = write(&root, "src/b.ts", "export const b = 2;\n");
+    assert!(graph.handle_event(&FsEvent::Created(b.clone())));
+    assert_eq!(graph.dependencies(&app), std::slice::from_ref(&b));
+    assert!(graph.dependencies(&other).is_empty());
+
+    std::fs::remove_file(&b).unwrap();
+    assert!(graph.handle_event(&FsEvent::Deleted(b.clone())));
+    assert_eq!(graph.dependencies(&app), std::slice::from_ref(&index));
+    assert!(graph.dependents(&b).is_empty());
+
+    // Ignored and hidden files stay out of the graph, as when building it
+    let built = write(&root, "dist/c.ts", "import { b } from '../src/b';\n");
+    assert!(!graph.handle_event(&FsEvent::Created(built)));
+    let hidden = write(&root, ".cache/c.ts", "import { b } from '../src/b';\n");
+    assert!(!graph.handle_event(&FsEvent::Created(hidden)));
+    assert_eq!(graph.file_count(), 3);
+  }
+}
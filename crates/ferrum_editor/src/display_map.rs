@@ -199,6 +199,12 @@ impl DisplayMap {
     self.invalidate_cache();
   }
 
+  /// Replace all folds, e.g. with a buffer's collapsed fold ranges
+  pub fn set_folds(&self, folds: impl IntoIterator<Item = Fold>) {
+    *self.folds.write() = folds.into_iter().map(|f| (f.start, f)).collect();
+    self.invalidate_cache();
+  }
+
   /// Unfold all folds
   pub fn unfold_all(&self) {
     self.folds.write().clear();
@@ -1,18 +1,33 @@
 //! Core editor implementation
 
 use crate::depth::DepthAnalyzer;
+use crate::display_map::{DisplayMap, Fold};
 use crate::fold::FoldState;
+use crate::fold_store::FoldStore;
+use crate::recovery::{RecoveryEntry, RecoveryStore};
+use crate::semantic::{self, SemanticLayer};
+use crate::undo_store::UndoStore;
 use dashmap::DashMap;
 use ferrum_buffer::position::Range;
 use ferrum_buffer::syntax::{LanguageId, ParseResult, SyntaxManager};
-use ferrum_buffer::Buffer;
+use ferrum_buffer::textobject;
+use ferrum_buffer::{
+  Brackets, Buffer, BufferSnapshot, Change, FoldRange, IndentEdit, IndentStyle, Indenter,
+  QueryResult, SyntaxNode, TextObjectKind, TextObjectScope,
+};
 use ferrum_core::prelude::*;
+use ferrum_core::task::TaskProgress;
+use ferrum_fs::save::HookEdit;
+use ferrum_fs::{DiskState, PreSaveHooks};
+use ferrum_lsp::{DiagnosticStore, DocumentSync, SemanticToken};
 use parking_lot::RwLock;
-use ropey::Rope;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// A text range as (start_line, start_col, end_line, end_col, start_byte, end_byte)
+pub type TextRange = (usize, usize, usize, usize, usize, usize);
+
 /// The main editor state
 pub struct Editor {
   /// All open buffers
@@ -27,8 +42,24 @@ pub struct Editor {
   depth_analyzers: DashMap<BufferId, DepthAnalyzer>,
   /// Fold state for each buffer
   fold_states: DashMap<BufferId, parking_lot::RwLock<FoldState>>,
+  /// Display map for each buffer with folds
+  display_maps: DashMap<BufferId, DisplayMap>,
+  /// Language server semantic tokens for each buffer
+  semantic_layers: DashMap<BufferId, SemanticLayer>,
   /// Current project root
   project_root: RwLock<Option<PathBuf>>,
+  /// Store for undo history persisted across sessions
+  undo_store: RwLock<Option<UndoStore>>,
+  /// Store for collapsed folds persisted across sessions
+  fold_store: RwLock<Option<FoldStore>>,
+  /// Store for dirty buffers kept for crash recovery and hot exit
+  recovery_store: RwLock<Option<RecoveryStore>>,
+  /// Disk state of each file buffer as last loaded or saved
+  disk_states: DashMap<BufferId, DiskState>,
+  /// Store of language server diagnostics, moved with edits to open files
+  diagnostic_store: RwLock<Option<Arc<DiagnosticStore>>>,
+  /// Store keeping files open in language servers in sync with their edits
+  document_sync: RwLock<Option<Arc<DocumentSync>>>,
 }
 
 impl Editor {
@@ -41,7 +72,15 @@ impl Editor {
       syntax_managers: DashMap::new(),
       depth_analyzers: DashMap::new(),
       fold_states: DashMap::new(),
+      display_maps: DashMap::new(),
+      semantic_layers: DashMap::new(),
       project_root: RwLock::new(None),
+      undo_store: RwLock::new(None),
+      fold_store: RwLock::new(None),
+      recovery_store: RwLock::new(None),
+      disk_states: DashMap::new(),
+      diagnostic_store: RwLock::new(None),
+      document_sync: RwLock::new(None),
     }
   }
 
@@ -64,7 +103,53 @@ impl Editor {
   /// Open a file into a buffer
   pub fn open_file(&self, path: impl Into<PathBuf>, content: &str) -> Arc<Buffer> {
     let path = path.into();
+    let buffer = self.open_file_with(path.clone(), |path| Buffer::from_file(path, content));
+    self
+      .disk_states
+      .entry(buffer.id())
+      .or_insert_with(|| DiskState::from_bytes(&path, content.as_bytes()));
+    buffer
+  }
+
+  /// Open a file into a buffer from its raw bytes, detecting the encoding,
+  /// BOM and line endings
+  pub fn open_file_bytes(&self, path: impl Into<PathBuf>, bytes: &[u8]) -> Arc<Buffer> {
+    let path = path.into();
+    let buffer = self.open_file_with(path.clone(), |path| Buffer::from_file_bytes(path, bytes));
+    self
+      .disk_states
+      .entry(buffer.id())
+      .or_insert_with(|| DiskState::from_bytes(&path, bytes));
+    buffer
+  }
+
+  /// Open a file in large-file mode, streaming it from disk
+  ///
+  /// `progress` counts bytes read. Syntax highlighting and depth analysis
+  /// are never run for the buffer.
+  pub fn open_large_file(
+    &self,
+    path: impl Into<PathBuf>,
+    progress: &TaskProgress,
+  ) -> Result<Arc<Buffer>> {
+    let path = path.into();
+    if let Some(buffer) = self.buffer_by_path(&path) {
+      return Ok(buffer);
+    }
+
+    // Taken before reading, so a change while loading isn't missed
+    let metadata = std::fs::metadata(&path)?;
+    let (buffer, hash) = Buffer::load_large_file(&path, progress)?;
+    let buffer = self.open_file_with(path, move |_| buffer);
+    self.disk_states.entry(buffer.id()).or_insert(DiskState {
+      modified: metadata.modified().ok(),
+      len: metadata.len(),
+      hash,
+    });
+    Ok(buffer)
+  }
 
+  fn open_file_with(&self, path: PathBuf, create: impl FnOnce(&PathBuf) -> Buffer) -> Arc<Buffer> {
     // Check if already open
     if let Some(buffer_id) = self.path_to_buffer.get(&path) {
       if let Some(buffer) = self.buffers.get(&buffer_id) {
@@ -72,7 +157,17 @@ impl Editor {
       }
     }
 
-    let buffer = Arc::new(Buffer::from_file(&path, content));
+    let buffer = Arc::new(create(&path));
+    if let Some(store) = self.undo_store.read().as_ref() {
+      if let Err(e) = store.restore(&buffer) {
+        warn!("Failed to restore undo history for {:?}: {}", path, e);
+      }
+    }
+
+    if let Some(store) = self.diagnostic_store.read().as_ref() {
+      store.open(&path, buffer.snapshot().rope());
+    }
+
     let id = buffer.id();
     self.buffers.insert(id, buffer.clone());
     self.path_to_buffer.insert(path, id);
@@ -100,6 +195,32 @@ impl Editor {
     // Remove from path mapping if it has a file
     if let Some(path) = buffer.file_path() {
       self.path_to_buffer.remove(&path);
+      if let Some(store) = self.diagnostic_store.read().as_ref() {
+        store.close(&path);
+      }
+      if let Some(sync) = self.document_sync.read().as_ref() {
+        if let Err(e) = sync.close(&path) {
+          warn!("Failed to close {:?} in its language server: {}", path, e);
+        }
+      }
+    }
+    self.disk_states.remove(&id);
+    self.semantic_layers.remove(&id);
+
+    if let Some(store) = self.undo_store.read().as_ref() {
+      if let Err(e) = store.save(&buffer) {
+        warn!("Failed to save undo history for {:?}: {}", buffer.file_path(), e);
+      }
+    }
+    if let Err(e) = self.save_fold_state(&buffer) {
+      warn!("Failed to save folds for {:?}: {}", buffer.file_path(), e);
+    }
+    self.fold_states.remove(&id);
+    self.display_maps.remove(&id);
+    if let Some(store) = self.recovery_store.read().as_ref() {
+      if let Err(e) = store.remove(id) {
+        warn!("Failed to remove recovery entry for {}: {}", id, e);
+      }
     }
 
     // Clear active if this was it
@@ -111,6 +232,149 @@ impl Editor {
     Some(buffer)
   }
 
+  /// Save a buffer to its file
+  ///
+  /// Pre-save hooks are applied to the text that is written. The file is
+  /// written atomically and, unless `force` is set, only if it has not
+  /// changed on disk since it was loaded or last saved; otherwise
+  /// `FileChangedOnDisk` is returned and nothing is written. Only once the
+  /// file is written do the hooks' edits land in the buffer, as one undoable
+  /// edit, so a refused or failed save leaves it as it was. Returns the
+  /// changes the hooks made.
+  pub fn save_buffer(&self, id: BufferId, hooks: PreSaveHooks, force: bool) -> Result<Vec<Change>> {
+    let buffer = self.buffer(id).ok_or(Error::BufferNotFound(id))?;
+    let path = buffer
+      .file_path()
+      .ok_or_else(|| Error::Internal(format!("Buffer {} has no file path", id)))?;
+
+    let expected = if force {
+      None
+    } else {
+      self.disk_states.get(&id).map(|state| state.clone())
+    };
+    let (snapshot, edits, state) = self.write_buffer(&buffer, &path, hooks, expected.as_ref())?;
+    self.disk_states.insert(id, state);
+    self.finish_save(&buffer, &snapshot, edits)
+  }
+
+  /// Save a buffer to a new file, which the buffer then belongs to
+  ///
+  /// The buffer keeps its old path if the file can't be written.
+  pub fn save_buffer_as(
+    &self,
+    id: BufferId,
+    path: impl Into<PathBuf>,
+    hooks: PreSaveHooks,
+  ) -> Result<Vec<Change>> {
+    let buffer = self.buffer(id).ok_or(Error::BufferNotFound(id))?;
+    let path = path.into();
+
+    let (snapshot, edits, state) = self.write_buffer(&buffer, &path, hooks, None)?;
+    self.set_buffer_path(id, path)?;
+    self.disk_states.insert(id, state);
+    self.finish_save(&buffer, &snapshot, edits)
+  }
+
+  /// Write a buffer's text to `path` with the hooks' edits applied, leaving
+  /// the buffer itself as it is
+  ///
+  /// Returns the snapshot that was written, the hooks' edits of it and the
+  /// state of the written file.
+  fn write_buffer(
+    &self,
+    buffer: &Buffer,
+    path: &Path,
+    hooks: PreSaveHooks,
+    expected: Option<&DiskState>,
+  ) -> Result<(BufferSnapshot, Vec<HookEdit>, DiskState)> {
+    let snapshot = buffer.snapshot();
+    let format = buffer.format();
+    let mut text = snapshot.rope().clone();
+    let edits = match hooks.is_empty() {
+      true => Vec::new(),
+      false => hooks.edits(&text, format.line_ending.as_str()),
+    };
+    // Edits are sorted, so applying them last to first keeps offsets valid
+    for edit in edits.iter().rev() {
+      text.remove(edit.start..edit.end);
+      text.insert(edit.start, &edit.text);
+    }
+
+    let bytes = ferrum_buffer::format::encode(&text.to_string(), &format)?;
+    let state = ferrum_fs::save::save(path, &bytes, expected)?;
+    Ok((snapshot, edits, state))
+  }
+
+  /// Land the hooks' edits of a saved snapshot in its buffer and mark it
+  /// saved, unless it was edited while saving
+  fn finish_save(
+    &self,
+    buffer: &Buffer,
+    snapshot: &BufferSnapshot,
+    edits: Vec<HookEdit>,
+  ) -> Result<Vec<Change>> {
+    // Edited while saving, so the buffer doesn't match the file
+    if buffer.version() != snapshot.version() {
+      return Ok(Vec::new());
+    }
+    let id = buffer.id();
+    let changes = buffer.transact(|tx| {
+      for edit in edits {
+        tx.replace(edit.start, edit.end, edit.text);
+      }
+    })?;
+    self.update_syntax_after_changes(id, &changes)?;
+
+    buffer.mark_saved();
+    if let Err(e) = self.save_undo_history(id) {
+      warn!(
+        "Failed to save undo history for {:?}: {}",
+        buffer.file_path(),
+        e
+      );
+    }
+
+    Ok(changes)
+  }
+
+  /// The disk state of a buffer's file as of its last load or save
+  pub fn disk_state(&self, id: BufferId) -> Option<DiskState> {
+    self.disk_states.get(&id).map(|state| state.clone())
+  }
+
+  /// Record the disk state of a buffer's file, e.g. after reloading it
+  pub fn set_disk_state(&self, id: BufferId, state: DiskState) {
+    self.disk_states.insert(id, state);
+  }
+
+  /// Point a buffer at a new file path, e.g. after the file was renamed
+  ///
+  /// Syntax state is dropped so it is rebuilt for the new language.
+  pub fn set_buffer_path(&self, id: BufferId, path: impl Into<PathBuf>) -> Result<()> {
+    let buffer = self.buffer(id).ok_or(Error::BufferNotFound(id))?;
+    let path = path.into();
+
+    let store = self.diagnostic_store.read().clone();
+    if let Some(old_path) = buffer.file_path() {
+      self.path_to_buffer.remove(&old_path);
+      if let Some(store) = &store {
+        store.close(&old_path);
+      }
+      if let Some(sync) = self.document_sync.read().as_ref() {
+        sync.close(&old_path)?;
+      }
+    }
+    buffer.set_file_path(&path);
+    if let Some(store) = &store {
+      store.open(&path, buffer.snapshot().rope());
+    }
+    self.path_to_buffer.insert(path, id);
+
+    self.syntax_managers.remove(&id);
+    self.depth_analyzers.remove(&id);
+    Ok(())
+  }
+
   /// Set the active buffer
   pub fn set_active(&self, id: BufferId) -> Result<()> {
     if !self.buffers.contains_key(&id) {
@@ -170,6 +434,172 @@ impl Editor {
     self.project_root.read().clone()
   }
 
+  /// Set the store used to persist undo history across sessions
+  pub fn set_undo_store(&self, store: UndoStore) {
+    *self.undo_store.write() = Some(store);
+  }
+
+  /// Persist the undo history of a buffer
+  pub fn save_undo_history(&self, id: BufferId) -> Result<()> {
+    let buffer = self.buffer(id).ok_or(Error::BufferNotFound(id))?;
+    match self.undo_store.read().as_ref() {
+      Some(store) => store.save(&buffer),
+      None => Ok(()),
+    }
+  }
+
+  /// Persist the undo history of every open file buffer
+  pub fn save_all_undo_history(&self) {
+    let Some(store) = self.undo_store.read().clone() else {
+      return;
+    };
+
+    for entry in self.buffers.iter() {
+      if let Err(e) = store.save(entry.value()) {
+        warn!("Failed to save undo history for {:?}: {}", entry.file_path(), e);
+      }
+    }
+  }
+
+  /// Set the store used to persist collapsed folds across sessions
+  pub fn set_fold_store(&self, store: FoldStore) {
+    *self.fold_store.write() = Some(store);
+  }
+
+  /// Persist the collapsed folds of a buffer
+  fn save_fold_state(&self, buffer: &Buffer) -> Result<()> {
+    let Some(store) = self.fold_store.read().clone() else {
+      return Ok(());
+    };
+    let collapsed: Vec<usize> = match self.fold_states.get(&buffer.id()) {
+      Some(state) => state.read().collapsed.iter().copied().collect(),
+      None => return Ok(()),
+    };
+    store.save(buffer, &collapsed)
+  }
+
+  /// Persist the collapsed folds of every open file buffer
+  pub fn save_all_fold_states(&self) {
+    for entry in self.buffers.iter() {
+      if let Err(e) = self.save_fold_state(entry.value()) {
+        warn!("Failed to save folds for {:?}: {}", entry.file_path(), e);
+      }
+    }
+  }
+
+  /// Set the store of language server diagnostics
+  ///
+  /// Files opened from then on have their diagnostics moved with edits.
+  pub fn set_diagnostic_store(&self, store: Arc<DiagnosticStore>) {
+    *self.diagnostic_store.write() = Some(store);
+  }
+
+  /// Set the store keeping files open in language servers in sync
+  ///
+  /// Edits to buffers of files opened through it are sent to their server.
+  pub fn set_document_sync(&self, sync: Arc<DocumentSync>) {
+    *self.document_sync.write() = Some(sync);
+  }
+
+  /// Get the store keeping files open in language servers in sync, if set
+  pub fn document_sync(&self) -> Option<Arc<DocumentSync>> {
+    self.document_sync.read().clone()
+  }
+
+  /// Set the store used to keep dirty buffers for recovery
+  pub fn set_recovery_store(&self, store: RecoveryStore) {
+    *self.recovery_store.write() = Some(store);
+  }
+
+  /// Persist every dirty buffer for recovery and drop entries of clean ones
+  ///
+  /// Called periodically, and with `hot_exit` set when the app exits while
+  /// keeping unsaved changes for the next launch.
+  pub fn persist_recovery(&self, hot_exit: bool) {
+    let store = self.recovery_store.read();
+    let Some(store) = store.as_ref() else {
+      return;
+    };
+
+    for entry in self.buffers.iter() {
+      if let Err(e) = store.save(entry.value(), hot_exit) {
+        warn!("Failed to persist buffer {} for recovery: {}", entry.key(), e);
+      }
+    }
+  }
+
+  /// Get the recovery entries left by previous sessions
+  pub fn recovery_entries(&self) -> Result<Vec<RecoveryEntry>> {
+    let store = self.recovery_store.read();
+    let Some(store) = store.as_ref() else {
+      return Ok(Vec::new());
+    };
+
+    let mut entries = store.entries()?;
+    entries.retain(|entry| !self.buffers.contains_key(&entry.buffer_id));
+    Ok(entries)
+  }
+
+  /// Recover a buffer from an entry left by a previous session
+  ///
+  /// The buffer comes back dirty, with its content, format and undo history
+  /// as they were. If its file is already open, that buffer is returned
+  /// unchanged. The entry is removed either way.
+  pub fn recover_buffer(&self, entry: &RecoveryEntry) -> Result<Arc<Buffer>> {
+    let buffer = match &entry.path {
+      Some(path) => match self.buffer_by_path(path) {
+        Some(buffer) => buffer,
+        None => {
+          let buffer = self.open_file_with(path.clone(), |path| {
+            Buffer::from_file(path, &entry.content)
+          });
+          // The saved content is whatever is on disk now, so that a
+          // changed or deleted file is reconciled as usual
+          match std::fs::read(path) {
+            Ok(bytes) => buffer.set_saved_content(&ferrum_buffer::format::decode(&bytes).0),
+            Err(_) => buffer.set_saved_content(""),
+          }
+          self.restore_recovered_state(&buffer, entry);
+          buffer
+        },
+      },
+      None => {
+        let buffer = self.create_buffer_with_content(&entry.content);
+        buffer.set_saved_content("");
+        self.restore_recovered_state(&buffer, entry);
+        buffer
+      },
+    };
+
+    self.discard_recovery(entry.buffer_id)?;
+    Ok(buffer)
+  }
+
+  fn restore_recovered_state(&self, buffer: &Buffer, entry: &RecoveryEntry) {
+    buffer.set_format(entry.file_format());
+    buffer.restore_history(entry.history.clone());
+    buffer.mark_dirty();
+  }
+
+  /// Delete a recovery entry without recovering it
+  pub fn discard_recovery(&self, buffer_id: BufferId) -> Result<()> {
+    match self.recovery_store.read().as_ref() {
+      Some(store) => store.remove(buffer_id),
+      None => Ok(()),
+    }
+  }
+
+  /// Delete the recovery entries this session wrote, e.g. on an exit
+  /// without hot exit
+  ///
+  /// Entries left by a previous crash are kept for the next launch.
+  pub fn clear_recovery(&self) -> Result<()> {
+    match self.recovery_store.read().as_ref() {
+      Some(store) => store.clear(),
+      None => Ok(()),
+    }
+  }
+
   /// Get syntax highlights for a buffer
   pub fn get_syntax_highlights(
     &self,
@@ -177,69 +607,128 @@ impl Editor {
     start_byte: Option<usize>,
     end_byte: Option<usize>,
   ) -> Result<ParseResult> {
+    let snapshot = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?
+      .snapshot();
+    self.snapshot_highlights(&snapshot, start_byte, end_byte)
+  }
+
+  /// Get syntax highlights for a snapshot of a buffer
+  ///
+  /// Semantic tokens are only layered over them if they were computed for
+  /// the snapshot's version.
+  pub fn snapshot_highlights(
+    &self,
+    snapshot: &BufferSnapshot,
+    start_byte: Option<usize>,
+    end_byte: Option<usize>,
+  ) -> Result<ParseResult> {
+    let buffer_id = snapshot.id();
     let buffer = self
       .buffers
       .get(&buffer_id)
       .ok_or(Error::BufferNotFound(buffer_id))?;
 
+    if buffer.is_large_file() {
+      return Ok(ParseResult::empty());
+    }
+
     // Get or create syntax manager
-    let language_str = buffer.language_id().unwrap_or_default();
-    let language = match language_str.as_str() {
-      "rust" => LanguageId::Rust,
-      "typescript" => LanguageId::TypeScript,
-      "javascript" => LanguageId::JavaScript,
-      "python" => LanguageId::Python,
-      "go" => LanguageId::Go,
-      "json" => LanguageId::Json,
-      "toml" => LanguageId::Toml,
-      "html" => LanguageId::Html,
-      "css" => LanguageId::Css,
-      "markdown" => LanguageId::Markdown,
-      _ => return Ok(ParseResult::empty()),
-    };
+    let language = LanguageId::from_name(snapshot.language_id().unwrap_or_default());
+    if language.tree_sitter_language().is_none() {
+      return Ok(ParseResult::empty());
+    }
 
     // Get or create syntax manager for this buffer
     if !self.syntax_managers.contains_key(&buffer_id) {
       let manager = SyntaxManager::new(language)?;
       // Parse the buffer content
-      let rope = Rope::from_str(&buffer.to_string());
-      manager.parse(&rope)?;
+      manager.parse(snapshot.rope())?;
       self.syntax_managers.insert(buffer_id, manager);
 
       // Initialize depth analyzer
       let mut depth_analyzer = DepthAnalyzer::new();
-      if let Some(buffer) = self.buffers.get(&buffer_id) {
-        self
-          .syntax_managers
-          .get(&buffer_id)
-          .unwrap()
-          .with_tree(|tree| {
-            let text = buffer.to_string();
-            depth_analyzer.analyze(tree, text.as_bytes());
-          });
-      }
+      self
+        .syntax_managers
+        .get(&buffer_id)
+        .unwrap()
+        .with_tree(|tree| {
+          let text = snapshot.to_string();
+          depth_analyzer.analyze(tree, text.as_bytes());
+        });
       self.depth_analyzers.insert(buffer_id, depth_analyzer);
 
-      // Initialize fold state
-      let fold_state_lock = parking_lot::RwLock::new(FoldState::new());
-
-      // Calculate folds from syntax tree
-      if let Some(manager) = self.syntax_managers.get(&buffer_id) {
-        manager.with_tree(|tree| {
-          fold_state_lock.write().calculate_folds(tree);
-        });
-      }
-      self.fold_states.insert(buffer_id, fold_state_lock);
+      // Calculate folds from the syntax tree, collapsing those collapsed
+      // when the file was last closed
+      let folds = self
+        .syntax_managers
+        .get(&buffer_id)
+        .unwrap()
+        .folds(snapshot.rope());
+      let fold_state = self.fold_states.entry(buffer_id).or_insert_with(|| {
+        let mut state = FoldState::new();
+        if let Some(store) = self.fold_store.read().as_ref() {
+          match store.restore(&buffer) {
+            Ok(collapsed) => state.collapsed.extend(collapsed),
+            Err(e) => warn!(
+              "Failed to restore folds for {:?}: {}",
+              buffer.file_path(),
+              e
+            ),
+          }
+        }
+        parking_lot::RwLock::new(state)
+      });
+      let mut fold_state = fold_state.write();
+      fold_state.set_syntax_ranges(folds);
+      self.sync_display_folds(buffer_id, snapshot.rope(), &fold_state);
     }
 
     let manager = self.syntax_managers.get(&buffer_id).unwrap();
-    let rope = Rope::from_str(&buffer.to_string());
 
     // Get range for parsing
     let _start = start_byte.unwrap_or(0);
-    let _end = end_byte.unwrap_or(rope.len_bytes());
+    let _end = end_byte.unwrap_or(snapshot.len_bytes());
+
+    let mut result = manager.parse_result(snapshot.rope());
+    if let Some(layer) = self.semantic_layers.get(&buffer_id) {
+      if layer.version == snapshot.version() {
+        result.highlights = semantic::merge_semantic_tokens(result.highlights, &layer.tokens);
+      }
+    }
 
-    Ok(manager.parse_result(&rope))
+    Ok(result)
+  }
+
+  /// Set the semantic tokens of a buffer, to be layered over its syntax
+  /// highlights
+  ///
+  /// `version` is the buffer version the tokens were requested for. Tokens
+  /// for an older version are ignored and `false` is returned.
+  pub fn set_semantic_tokens(
+    &self,
+    buffer_id: BufferId,
+    version: u64,
+    tokens: Vec<SemanticToken>,
+  ) -> Result<bool> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    if buffer.version() != version {
+      return Ok(false);
+    }
+
+    self
+      .semantic_layers
+      .insert(buffer_id, SemanticLayer { version, tokens });
+    Ok(true)
+  }
+
+  /// Drop the semantic tokens of a buffer, e.g. when its language server
+  /// stops
+  pub fn clear_semantic_tokens(&self, buffer_id: BufferId) {
+    self.semantic_layers.remove(&buffer_id);
   }
 
   /// Update syntax after buffer edit
@@ -250,32 +739,106 @@ impl Editor {
     old_end_byte: usize,
     new_end_byte: usize,
   ) -> Result<()> {
+    if let Some(mut layer) = self.semantic_layers.get_mut(&buffer_id) {
+      layer.shift(start_byte, old_end_byte, new_end_byte);
+      if let Some(buffer) = self.buffers.get(&buffer_id) {
+        layer.version = buffer.version();
+      }
+    }
+    if let Some(buffer) = self.buffers.get(&buffer_id) {
+      if let Some(path) = buffer.file_path() {
+        let snapshot = buffer.snapshot();
+        if let Some(store) = self.diagnostic_store.read().as_ref() {
+          store.apply_edit(
+            &path,
+            snapshot.rope(),
+            buffer.version(),
+            start_byte,
+            old_end_byte,
+            new_end_byte,
+          );
+        }
+        if let Some(sync) = self.document_sync.read().as_ref() {
+          sync.apply_edit(
+            &path,
+            snapshot.rope(),
+            buffer.version(),
+            start_byte,
+            old_end_byte,
+            new_end_byte,
+          );
+        }
+      }
+    }
+
     if let Some(manager) = self.syntax_managers.get(&buffer_id) {
       if let Some(buffer) = self.buffers.get(&buffer_id) {
-        let rope = Rope::from_str(&buffer.to_string());
-        manager.edit(&rope, start_byte, old_end_byte, new_end_byte)?;
+        let snapshot = buffer.snapshot();
+        manager.edit(snapshot.rope(), start_byte, old_end_byte, new_end_byte)?;
 
         // Update depth map
         if let Some(mut depth_analyzer) = self.depth_analyzers.get_mut(&buffer_id) {
-          if let Some(buffer) = self.buffers.get(&buffer_id) {
-            manager.with_tree(|tree| {
-              let text = buffer.to_string();
-              depth_analyzer.analyze(tree, text.as_bytes());
-            });
-          }
+          manager.with_tree(|tree| {
+            let text = snapshot.to_string();
+            depth_analyzer.analyze(tree, text.as_bytes());
+          });
         }
 
         // Update fold ranges
         if let Some(fold_state) = self.fold_states.get(&buffer_id) {
           let mut fold_state = fold_state.write();
-          manager.with_tree(|tree| {
-            fold_state.calculate_folds(tree);
-          });
+          fold_state.set_syntax_ranges(manager.folds(snapshot.rope()));
+          self.sync_display_folds(buffer_id, snapshot.rope(), &fold_state);
         }
       }
     }
     Ok(())
   }
+
+  /// Update syntax after a transaction's changes
+  ///
+  /// The tree is re-parsed once for the whole change list.
+  pub fn update_syntax_after_changes(&self, buffer_id: BufferId, changes: &[Change]) -> Result<()> {
+    let Some(buffer) = self.buffer(buffer_id) else {
+      return Ok(());
+    };
+    if let Some(mut layer) = self.semantic_layers.get_mut(&buffer_id) {
+      layer.apply_changes(changes, buffer.version());
+    }
+
+    let snapshot = buffer.snapshot();
+    if let Some(path) = buffer.file_path() {
+      if let Some(store) = self.diagnostic_store.read().as_ref() {
+        store.apply_changes(&path, snapshot.rope(), buffer.version(), changes);
+      }
+      if let Some(sync) = self.document_sync.read().as_ref() {
+        sync.apply_changes(&path, snapshot.rope(), buffer.version(), changes);
+      }
+    }
+    let manager = self.syntax_managers.get(&buffer_id);
+    if let Some(manager) = &manager {
+      manager.apply_changes(snapshot.rope(), changes)?;
+    }
+
+    if let (Some(manager), Some(mut depth_analyzer)) =
+      (&manager, self.depth_analyzers.get_mut(&buffer_id))
+    {
+      let text = snapshot.to_string();
+      manager.with_tree(|tree| depth_analyzer.analyze(tree, text.as_bytes()));
+    }
+
+    if let Some(fold_state) = self.fold_states.get(&buffer_id) {
+      let mut fold_state = fold_state.write();
+      fold_state.apply_changes(changes);
+      if let Some(manager) = &manager {
+        fold_state.set_syntax_ranges(manager.folds(snapshot.rope()));
+      }
+      self.sync_display_folds(buffer_id, snapshot.rope(), &fold_state);
+    }
+
+    Ok(())
+  }
+
   /// Get depth map for a buffer
   pub fn get_depth_map(&self, buffer_id: BufferId) -> Option<HashMap<Range, u32>> {
     self
@@ -292,15 +855,87 @@ impl Editor {
 
   /// Toggle fold at a specific line
   pub fn toggle_fold(&self, buffer_id: BufferId, line: usize) -> Result<bool> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
     if let Some(fold_state) = self.fold_states.get(&buffer_id) {
       let mut fold_state = fold_state.write();
       let changed = fold_state.toggle_fold(line);
+      self.sync_display_folds(buffer_id, buffer.snapshot().rope(), &fold_state);
       Ok(changed)
     } else {
       Err(Error::BufferNotFound(buffer_id))
     }
   }
 
+  /// Set the folding ranges a language server sent for a buffer, to be
+  /// merged with those of its folds query
+  ///
+  /// `version` is the buffer version the ranges were requested for. Ranges
+  /// for an older version are ignored and `false` is returned.
+  pub fn set_lsp_folds(
+    &self,
+    buffer_id: BufferId,
+    version: u64,
+    ranges: Vec<FoldRange>,
+  ) -> Result<bool> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    if buffer.version() != version {
+      return Ok(false);
+    }
+
+    let fold_state = self
+      .fold_states
+      .entry(buffer_id)
+      .or_insert_with(|| parking_lot::RwLock::new(FoldState::new()));
+    let mut fold_state = fold_state.write();
+    fold_state.set_lsp_ranges(ranges);
+    self.sync_display_folds(buffer_id, buffer.snapshot().rope(), &fold_state);
+    Ok(true)
+  }
+
+  /// Run a function with the display map of a buffer
+  pub fn with_display_map<R>(
+    &self,
+    buffer_id: BufferId,
+    f: impl FnOnce(&DisplayMap) -> R,
+  ) -> Option<R> {
+    self.display_maps.get(&buffer_id).map(|map| f(&map))
+  }
+
+  /// Mirror the collapsed folds of a buffer into its display map
+  ///
+  /// Each fold covers the text from the end of its start line to the end of
+  /// its last line.
+  fn sync_display_folds(&self, buffer_id: BufferId, rope: &ropey::Rope, fold_state: &FoldState) {
+    let line_end = |line: usize| {
+      let line = line.min(rope.len_lines() - 1);
+      let text = rope.line(line);
+      let breaks = text
+        .chars_at(text.len_chars())
+        .reversed()
+        .take(2)
+        .take_while(|c| *c == '\n' || *c == '\r')
+        .count();
+      rope.line_to_char(line) + text.len_chars() - breaks
+    };
+    let folds = fold_state.collapsed_regions().map(|region| {
+      Fold::new(
+        line_end(region.start_line),
+        line_end(region.end_line),
+        "...",
+      )
+    });
+
+    self
+      .display_maps
+      .entry(buffer_id)
+      .or_default()
+      .set_folds(folds);
+  }
+
   /// Expand selection to the next larger syntax node
   /// Returns (start_line, start_col, end_line, end_col, start_byte, end_byte)
   pub fn expand_selection(
@@ -330,7 +965,8 @@ impl Editor {
         let buffer = self
           .buffer(buffer_id)
           .ok_or(Error::BufferNotFound(buffer_id))?;
-        let rope = Rope::from_str(&buffer.to_string());
+        let snapshot = buffer.snapshot();
+        let rope = snapshot.rope();
         let start_line = rope.byte_to_line(start_byte.min(rope.len_bytes()));
         let end_line = rope.byte_to_line(end_byte.min(rope.len_bytes()));
         let start_col = start_byte - rope.line_to_byte(start_line);
@@ -371,7 +1007,8 @@ impl Editor {
         let buffer = self
           .buffer(buffer_id)
           .ok_or(Error::BufferNotFound(buffer_id))?;
-        let rope = Rope::from_str(&buffer.to_string());
+        let snapshot = buffer.snapshot();
+        let rope = snapshot.rope();
         let start_line = rope.byte_to_line(start_byte.min(rope.len_bytes()));
         let end_line = rope.byte_to_line(end_byte.min(rope.len_bytes()));
         let start_col = start_byte - rope.line_to_byte(start_line);
@@ -383,6 +1020,438 @@ impl Editor {
     }
   }
 
+  /// Find the definition and references of the local symbol at a byte
+  /// offset, for highlighting all its occurrences
+  pub fn symbol_references(&self, buffer_id: BufferId, byte: usize) -> Result<Vec<TextRange>> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let Some(manager) = self.syntax_managers.get(&buffer_id) else {
+      return Ok(Vec::new());
+    };
+
+    let snapshot = buffer.snapshot();
+    let rope = snapshot.rope();
+    let ranges = manager
+      .references_at(byte)
+      .into_iter()
+      .filter(|r| r.end <= rope.len_bytes())
+      .map(|r| text_range(rope, r))
+      .collect();
+
+    Ok(ranges)
+  }
+
+  /// Select the text object of a kind enclosing each selection
+  ///
+  /// Selections are byte ranges. Selecting again grows a selection to the
+  /// next enclosing object; selections no object encloses are kept.
+  pub fn select_text_object(
+    &self,
+    buffer_id: BufferId,
+    selections: &[std::ops::Range<usize>],
+    kind: TextObjectKind,
+    scope: TextObjectScope,
+  ) -> Result<Vec<TextRange>> {
+    self.map_selections(buffer_id, selections, |manager, rope, selection| {
+      let objects = manager.text_objects(rope, kind, selection.start);
+      textobject::select(&objects, scope, selection)
+    })
+  }
+
+  /// Move each selection to the next or previous text object of a kind,
+  /// selecting it
+  pub fn goto_text_object(
+    &self,
+    buffer_id: BufferId,
+    selections: &[std::ops::Range<usize>],
+    kind: TextObjectKind,
+    scope: TextObjectScope,
+    forward: bool,
+  ) -> Result<Vec<TextRange>> {
+    self.map_selections(buffer_id, selections, |manager, rope, selection| {
+      let objects = manager.text_objects(rope, kind, selection.start);
+      textobject::next(&objects, scope, selection.start, forward)
+    })
+  }
+
+  /// Select the syntax node enclosing each selection
+  pub fn select_parent_node(
+    &self,
+    buffer_id: BufferId,
+    selections: &[std::ops::Range<usize>],
+  ) -> Result<Vec<TextRange>> {
+    self.map_selections(buffer_id, selections, |manager, _, selection| {
+      manager.parent_node(selection.clone())
+    })
+  }
+
+  /// Select the next or previous sibling of the syntax node at each
+  /// selection
+  pub fn select_sibling_node(
+    &self,
+    buffer_id: BufferId,
+    selections: &[std::ops::Range<usize>],
+    forward: bool,
+  ) -> Result<Vec<TextRange>> {
+    self.map_selections(buffer_id, selections, |manager, _, selection| {
+      Some(manager.sibling_node(selection.clone(), forward)?.1)
+    })
+  }
+
+  /// Move each selection's cursor to the bracket matching the one at or
+  /// just before it, or else to the open bracket of the innermost pair
+  /// around it
+  ///
+  /// Selections are byte ranges; the cursor is their end.
+  pub fn jump_to_matching_bracket(
+    &self,
+    buffer_id: BufferId,
+    selections: &[std::ops::Range<usize>],
+  ) -> Result<Vec<TextRange>> {
+    self.map_selections(buffer_id, selections, |manager, _, selection| {
+      let cursor = selection.end;
+      let (open, close) = manager.bracket_pair_at(cursor)?;
+      let target = match open.start <= cursor && cursor <= open.end {
+        true => close.start,
+        false => open.start,
+      };
+      Some(target..target)
+    })
+  }
+
+  /// Get the bracket pairs and unmatched brackets intersecting a byte
+  /// range, e.g. the viewport, for bracket pair colorization
+  ///
+  /// Empty if the buffer has not been parsed.
+  pub fn bracket_pairs(
+    &self,
+    buffer_id: BufferId,
+    range: std::ops::Range<usize>,
+  ) -> Result<Brackets> {
+    self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    Ok(
+      self
+        .syntax_managers
+        .get(&buffer_id)
+        .map(|manager| manager.brackets(range))
+        .unwrap_or_default(),
+    )
+  }
+
+  /// Map each selection through a syntax lookup, keeping it where the
+  /// lookup finds nothing
+  fn map_selections(
+    &self,
+    buffer_id: BufferId,
+    selections: &[std::ops::Range<usize>],
+    f: impl Fn(&SyntaxManager, &ropey::Rope, &std::ops::Range<usize>) -> Option<std::ops::Range<usize>>,
+  ) -> Result<Vec<TextRange>> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let snapshot = buffer.snapshot();
+    let rope = snapshot.rope();
+    let manager = self.syntax_managers.get(&buffer_id);
+
+    Ok(
+      selections
+        .iter()
+        .map(|selection| {
+          let selection =
+            selection.start.min(rope.len_bytes())..selection.end.min(rope.len_bytes());
+          let range = manager
+            .as_ref()
+            .and_then(|manager| f(manager, rope, &selection))
+            .unwrap_or(selection);
+          text_range(rope, range)
+        })
+        .collect(),
+    )
+  }
+
+  /// Swap the syntax node at each selection with its next or previous
+  /// sibling, e.g. to move a parameter, as one undoable edit
+  ///
+  /// Returns the changes and the selections, which follow the moved nodes.
+  /// Selections whose swap would overlap an earlier one are kept as they
+  /// are.
+  pub fn swap_sibling_nodes(
+    &self,
+    buffer_id: BufferId,
+    selections: &[std::ops::Range<usize>],
+    forward: bool,
+  ) -> Result<(Vec<Change>, Vec<TextRange>)> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let snapshot = buffer.snapshot();
+    let rope = snapshot.rope();
+
+    let mut swaps: Vec<(std::ops::Range<usize>, std::ops::Range<usize>)> = Vec::new();
+    let mut moved = Vec::with_capacity(selections.len());
+    let manager = self.syntax_managers.get(&buffer_id);
+    for selection in selections {
+      let swap = manager
+        .as_ref()
+        .and_then(|manager| manager.sibling_node(selection.clone(), forward))
+        .filter(|(node, sibling)| {
+          let span = node.start.min(sibling.start)..node.end.max(sibling.end);
+          swaps.iter().all(|(a, b)| {
+            let other = a.start.min(b.start)..a.end.max(b.end);
+            span.end <= other.start || other.end <= span.start
+          })
+        });
+      moved.push(match swap {
+        Some((node, sibling)) => {
+          // The pair keeps its length, so only the pair itself shifts
+          let start = match forward {
+            true => sibling.end - node.len(),
+            false => sibling.start,
+          };
+          swaps.push((node.clone(), sibling));
+          start..start + node.len()
+        },
+        None => selection.clone(),
+      });
+    }
+    drop(manager);
+    if swaps.is_empty() {
+      return Ok((
+        Vec::new(),
+        moved.into_iter().map(|r| text_range(rope, r)).collect(),
+      ));
+    }
+
+    let changes = buffer.transact(|tx| {
+      for (node, sibling) in &swaps {
+        let chars =
+          |r: &std::ops::Range<usize>| rope.byte_to_char(r.start)..rope.byte_to_char(r.end);
+        let (node_chars, sibling_chars) = (chars(node), chars(sibling));
+        let node_text = rope.slice(node_chars.clone()).to_string();
+        let sibling_text = rope.slice(sibling_chars.clone()).to_string();
+        tx.replace(node_chars.start, node_chars.end, sibling_text);
+        tx.replace(sibling_chars.start, sibling_chars.end, node_text);
+      }
+    })?;
+    self.update_syntax_after_changes(buffer_id, &changes)?;
+
+    let snapshot = buffer.snapshot();
+    let rope = snapshot.rope();
+    Ok((
+      changes,
+      moved.into_iter().map(|r| text_range(rope, r)).collect(),
+    ))
+  }
+
+  /// Get the syntax tree of a buffer, or the subtree of the node covering
+  /// a byte range, for inspecting what the parser produced
+  ///
+  /// `None` if the buffer has not been parsed.
+  pub fn syntax_tree(
+    &self,
+    buffer_id: BufferId,
+    range: Option<std::ops::Range<usize>>,
+    named_only: bool,
+  ) -> Result<Option<SyntaxNode>> {
+    self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    Ok(
+      self
+        .syntax_managers
+        .get(&buffer_id)
+        .and_then(|manager| manager.syntax_tree(range, named_only)),
+    )
+  }
+
+  /// Get the syntax tree of a buffer, or the subtree of the node covering
+  /// a byte range, as an S-expression
+  ///
+  /// `None` if the buffer has not been parsed.
+  pub fn syntax_tree_sexp(
+    &self,
+    buffer_id: BufferId,
+    range: Option<std::ops::Range<usize>>,
+  ) -> Result<Option<String>> {
+    self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    Ok(
+      self
+        .syntax_managers
+        .get(&buffer_id)
+        .and_then(|manager| manager.sexp(range)),
+    )
+  }
+
+  /// Run a tree-sitter query against a buffer's syntax tree, optionally
+  /// limited to a byte range
+  ///
+  /// Empty if the buffer has not been parsed. Fails if the query does not
+  /// compile.
+  pub fn run_syntax_query(
+    &self,
+    buffer_id: BufferId,
+    query: &str,
+    range: Option<std::ops::Range<usize>>,
+  ) -> Result<QueryResult> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let Some(manager) = self.syntax_managers.get(&buffer_id) else {
+      return Ok(QueryResult::default());
+    };
+
+    let snapshot = buffer.snapshot();
+    manager.run_query(snapshot.rope(), query, range)
+  }
+
+  /// Replace the chars in `start..end` of a buffer with `text`
+  ///
+  /// Every edit of an open buffer goes through here or another method
+  /// returning its changes, so syntax, folds, diagnostics and language
+  /// servers follow it.
+  pub fn edit(&self, buffer_id: BufferId, start: usize, end: usize, text: &str) -> Result<Change> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let change = buffer.edit(start, end, text)?;
+    self.update_syntax_after_changes(buffer_id, &[change])?;
+    Ok(change)
+  }
+
+  /// Undo the last transaction of a buffer
+  ///
+  /// Returns no changes if there was nothing to undo.
+  pub fn undo(&self, buffer_id: BufferId) -> Result<Vec<Change>> {
+    self.apply_history(buffer_id, |buffer| buffer.undo().unwrap_or_default())
+  }
+
+  /// Redo the last undone transaction of a buffer
+  ///
+  /// Returns no changes if there was nothing to redo.
+  pub fn redo(&self, buffer_id: BufferId) -> Result<Vec<Change>> {
+    self.apply_history(buffer_id, |buffer| buffer.redo().unwrap_or_default())
+  }
+
+  /// Move a buffer to the undo state with the given sequence number
+  ///
+  /// Returns `None` if no such state is retained.
+  pub fn undo_goto(&self, buffer_id: BufferId, seq: usize) -> Result<Option<Vec<Change>>> {
+    let mut found = false;
+    let changes = self.apply_history(buffer_id, |buffer| {
+      let changes = buffer.undo_goto(seq);
+      found = changes.is_some();
+      changes.unwrap_or_default()
+    })?;
+    Ok(found.then_some(changes))
+  }
+
+  /// Move a buffer to the state it was in at the given time, in
+  /// milliseconds since the Unix epoch
+  pub fn undo_goto_time(&self, buffer_id: BufferId, timestamp: u64) -> Result<Vec<Change>> {
+    self.apply_history(buffer_id, |buffer| buffer.undo_goto_time(timestamp))
+  }
+
+  /// Move through a buffer's undo history and update everything following
+  /// its text
+  fn apply_history(
+    &self,
+    buffer_id: BufferId,
+    f: impl FnOnce(&Buffer) -> Vec<Change>,
+  ) -> Result<Vec<Change>> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let changes = f(&buffer);
+    if !changes.is_empty() {
+      self.update_syntax_after_changes(buffer_id, &changes)?;
+    }
+    Ok(changes)
+  }
+
+  /// Break the line at a char offset, indenting the new line for its
+  /// place in the syntax tree
+  ///
+  /// Returns the changes and the char offset of the cursor after them.
+  pub fn insert_newline(
+    &self,
+    buffer_id: BufferId,
+    char_idx: usize,
+    style: IndentStyle,
+  ) -> Result<(Vec<Change>, usize)> {
+    let (edits, cursor) = self.indent_edits(buffer_id, style, |indenter| {
+      let (edit, cursor) = indenter.newline(char_idx);
+      (vec![edit], cursor)
+    })?;
+    Ok((self.apply_indent_edits(buffer_id, edits)?, cursor))
+  }
+
+  /// Reindent a range of lines, e.g. a selection
+  pub fn reindent_lines(
+    &self,
+    buffer_id: BufferId,
+    lines: std::ops::Range<usize>,
+    style: IndentStyle,
+  ) -> Result<Vec<Change>> {
+    let (edits, _) =
+      self.indent_edits(buffer_id, style, |indenter| (indenter.reindent(lines), 0))?;
+    self.apply_indent_edits(buffer_id, edits)
+  }
+
+  /// Paste text over the chars in `start..end`, reindenting its lines to fit
+  /// where it lands
+  ///
+  /// Returns the changes and the char offset of the cursor after them.
+  pub fn paste(
+    &self,
+    buffer_id: BufferId,
+    start: usize,
+    end: usize,
+    text: &str,
+    style: IndentStyle,
+  ) -> Result<(Vec<Change>, usize)> {
+    let (edits, cursor) = self.indent_edits(buffer_id, style, |indenter| {
+      let (edit, cursor) = indenter.paste(start..end, text);
+      (vec![edit], cursor)
+    })?;
+    Ok((self.apply_indent_edits(buffer_id, edits)?, cursor))
+  }
+
+  /// Work out indentation edits for a buffer, using its syntax tree if it
+  /// has been parsed and the bracket heuristic otherwise
+  fn indent_edits<T>(
+    &self,
+    buffer_id: BufferId,
+    style: IndentStyle,
+    f: impl FnOnce(&Indenter) -> (Vec<IndentEdit>, T),
+  ) -> Result<(Vec<IndentEdit>, T)> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let snapshot = buffer.snapshot();
+    let manager = self.syntax_managers.get(&buffer_id);
+    let indenter = Indenter::new(snapshot.rope(), manager.as_deref(), style);
+    Ok(f(&indenter))
+  }
+
+  /// Apply indentation edits as one undoable step
+  fn apply_indent_edits(&self, buffer_id: BufferId, edits: Vec<IndentEdit>) -> Result<Vec<Change>> {
+    let buffer = self
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let changes = buffer.transact(|tx| {
+      for edit in edits {
+        tx.replace(edit.range.start, edit.range.end, edit.text);
+      }
+    })?;
+    self.update_syntax_after_changes(buffer_id, &changes)?;
+    Ok(changes)
+  }
+
   /// Analyze code dependencies in a buffer
   /// Returns Vec<(from_name, from_line, from_col, to_name, to_line, to_col, link_type)>
   pub fn analyze_dependencies(
@@ -425,6 +1494,20 @@ impl Editor {
   }
 }
 
+/// Get the lines and columns of a byte range
+fn text_range(rope: &ropey::Rope, range: std::ops::Range<usize>) -> TextRange {
+  let start_line = rope.byte_to_line(range.start);
+  let end_line = rope.byte_to_line(range.end);
+  (
+    start_line,
+    range.start - rope.line_to_byte(start_line),
+    end_line,
+    range.end - rope.line_to_byte(end_line),
+    range.start,
+    range.end,
+  )
+}
+
 impl Default for Editor {
   fn default() -> Self {
     Self::new()
@@ -471,4 +1554,467 @@ mod tests {
     editor.set_active(buffer.id()).unwrap();
     assert_eq!(editor.active_buffer_id(), Some(buffer.id()));
   }
+
+  #[test]
+  fn test_recover_untitled_buffer() {
+    let dir = tempfile::tempdir().unwrap();
+    let editor = Editor::new();
+    editor.set_recovery_store(RecoveryStore::new(dir.path()));
+
+    let buffer = editor.create_buffer_with_content("draft");
+    buffer.insert(5, " notes").unwrap();
+    editor.persist_recovery(true);
+
+    // Next launch
+    let editor = Editor::new();
+    editor.set_recovery_store(RecoveryStore::new(dir.path()));
+    let entries = editor.recovery_entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].hot_exit);
+
+    let recovered = editor.recover_buffer(&entries[0]).unwrap();
+    assert_eq!(recovered.to_string(), "draft notes");
+    assert!(recovered.is_dirty());
+    assert!(editor.recovery_entries().unwrap().is_empty());
+
+    recovered.undo();
+    assert_eq!(recovered.to_string(), "draft");
+  }
+
+  #[test]
+  fn test_symbol_references() {
+    let editor = Editor::new();
+    let buffer = editor.open_file("/test/lib.rs", "fn double(n: u32) -> u32 {\n  n * 2\n}\n");
+    editor
+      .get_syntax_highlights(buffer.id(), None, None)
+      .unwrap();
+
+    let references = editor.symbol_references(buffer.id(), 30).unwrap();
+    assert_eq!(
+      references,
+      vec![(0, 10, 0, 11, 10, 11), (1, 2, 1, 3, 29, 30)]
+    );
+    assert!(editor.symbol_references(buffer.id(), 0).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_structural_selections() {
+    let editor = Editor::new();
+    let source = "fn f(a: u8, b: u16) {\n  g(a, b);\n}\n";
+    let buffer = editor.open_file("/test/lib.rs", source);
+    editor
+      .get_syntax_highlights(buffer.id(), None, None)
+      .unwrap();
+    let bytes = |r: &TextRange| r.4..r.5;
+    let a = source.find('a').unwrap();
+    let call = source.find("a, b)").unwrap();
+
+    // One selection in the parameters and one in the call's arguments
+    let selected = editor
+      .select_text_object(
+        buffer.id(),
+        &[a..a, call..call],
+        TextObjectKind::Parameter,
+        TextObjectScope::Inside,
+      )
+      .unwrap();
+    assert_eq!(bytes(&selected[0]), a..a + 5);
+    assert_eq!(bytes(&selected[1]), call..call + 1);
+
+    let next = editor
+      .select_sibling_node(buffer.id(), &[bytes(&selected[0])], true)
+      .unwrap();
+    assert_eq!(&source[bytes(&next[0])], "b: u16");
+    let parent = editor
+      .select_parent_node(buffer.id(), &[bytes(&selected[0])])
+      .unwrap();
+    assert_eq!(&source[bytes(&parent[0])], "(a: u8, b: u16)");
+
+    let selections: Vec<_> = selected.iter().map(bytes).collect();
+    let (_, moved) = editor
+      .swap_sibling_nodes(buffer.id(), &selections, true)
+      .unwrap();
+    let text = buffer.to_string();
+    assert_eq!(text, "fn f(b: u16, a: u8) {\n  g(b, a);\n}\n");
+    assert_eq!(&text[bytes(&moved[0])], "a: u8");
+    assert_eq!(&text[bytes(&moved[1])], "a");
+  }
+
+  #[test]
+  fn test_swap_sibling_nodes_without_syntax_keeps_selections() {
+    let editor = Editor::new();
+    let buffer = editor.create_buffer_with_content("plain text\nhere\n");
+
+    let (changes, moved) = editor
+      .swap_sibling_nodes(buffer.id(), &[0..5, 11..15], true)
+      .unwrap();
+    assert!(changes.is_empty());
+    assert_eq!(moved, vec![(0, 0, 0, 5, 0, 5), (1, 0, 1, 4, 11, 15)]);
+    assert_eq!(buffer.to_string(), "plain text\nhere\n");
+  }
+
+  #[test]
+  fn test_bracket_pairs() {
+    let editor = Editor::new();
+    let source = "fn f() { g(\"{\", [1]); }\n";
+    let buffer = editor.open_file("/test/lib.rs", source);
+    editor
+      .get_syntax_highlights(buffer.id(), None, None)
+      .unwrap();
+
+    let brackets = editor.bracket_pairs(buffer.id(), 0..source.len()).unwrap();
+    assert_eq!(brackets.pairs.len(), 4);
+    assert!(brackets.unmatched.is_empty());
+    let square = source.find('[').unwrap();
+    assert_eq!(
+      brackets
+        .pairs
+        .iter()
+        .find(|p| p.open.start == square)
+        .unwrap()
+        .depth,
+      2
+    );
+
+    // From the open brace to the close brace and back, and out of a pair
+    let open = source.find('{').unwrap();
+    let close = source.rfind('}').unwrap();
+    let cursors = [open..open, close + 1..close + 1, square - 1..square - 1];
+    let jumped = editor
+      .jump_to_matching_bracket(buffer.id(), &cursors)
+      .unwrap();
+    assert_eq!(jumped[0].4..jumped[0].5, close..close);
+    assert_eq!(jumped[1].4..jumped[1].5, open..open);
+    assert_eq!(jumped[2].4, source.find("g(").unwrap() + 1);
+  }
+
+  #[test]
+  fn test_inspect_syntax_tree() {
+    let editor = Editor::new();
+    let buffer = editor.open_file("/test/lib.rs", "fn f() { g(1); }\n");
+    assert!(
+      editor
+        .syntax_tree(buffer.id(), None, true)
+        .unwrap()
+        .is_none()
+    );
+    editor
+      .get_syntax_highlights(buffer.id(), None, None)
+      .unwrap();
+
+    let call = editor
+      .syntax_tree_sexp(buffer.id(), Some(9..10))
+      .unwrap()
+      .unwrap();
+    assert_eq!(call, "(identifier)");
+    let tree = editor
+      .syntax_tree(buffer.id(), None, true)
+      .unwrap()
+      .unwrap();
+    assert_eq!(tree.kind, "source_file");
+
+    let result = editor
+      .run_syntax_query(buffer.id(), "(call_expression function: (_) @callee)", None)
+      .unwrap();
+    assert!(!result.truncated);
+    let captures = result.captures;
+    assert_eq!(captures.len(), 1);
+    assert_eq!(captures[0].text, "g");
+    assert!(
+      editor
+        .run_syntax_query(buffer.id(), "(call_expression", None)
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_newline_and_reindent() {
+    let editor = Editor::new();
+    let buffer = editor.open_file("/test/main.rs", "fn main() {\ncall();\n}\n");
+    editor
+      .get_syntax_highlights(buffer.id(), None, None)
+      .unwrap();
+    let style = IndentStyle {
+      tab_size: 2,
+      insert_spaces: true,
+    };
+
+    editor.reindent_lines(buffer.id(), 0..3, style).unwrap();
+    assert_eq!(buffer.to_string(), "fn main() {\n  call();\n}\n");
+
+    let (_, cursor) = editor.insert_newline(buffer.id(), 21, style).unwrap();
+    assert_eq!(buffer.to_string(), "fn main() {\n  call();\n  \n}\n");
+    assert_eq!(cursor, 24);
+
+    // Pasted lines keep their relative indentation until reindented
+    let (_, cursor) = editor
+      .paste(buffer.id(), 24, 24, "if x {\ny();\n}", style)
+      .unwrap();
+    assert_eq!(
+      buffer.to_string(),
+      "fn main() {\n  call();\n  if x {\n  y();\n  }\n}\n"
+    );
+    assert_eq!(cursor, 41);
+    editor.reindent_lines(buffer.id(), 2..5, style).unwrap();
+    assert_eq!(
+      buffer.to_string(),
+      "fn main() {\n  call();\n  if x {\n    y();\n  }\n}\n"
+    );
+  }
+
+  #[test]
+  fn test_diagnostics_follow_edits() {
+    use ferrum_lsp::types::{Diagnostic, Position, PublishDiagnosticsParams, Range as LspRange};
+
+    let store = Arc::new(DiagnosticStore::new());
+    let editor = Editor::new();
+    editor.set_diagnostic_store(store.clone());
+    let path = PathBuf::from("/test/diagnostics.rs");
+    let buffer = editor.open_file(&path, "fn main() {\n  call();\n}\n");
+
+    let diagnostic = Diagnostic {
+      range: LspRange::new(Position::new(1, 2), Position::new(1, 6)),
+      message: "cannot find function `call`".to_string(),
+      ..Default::default()
+    };
+    let uri = "file:///test/diagnostics.rs".parse().unwrap();
+    store.publish(
+      "rust",
+      PublishDiagnosticsParams::new(uri, vec![diagnostic], None),
+    );
+    assert_eq!(store.file(&path)[0].range, 14..18);
+
+    editor.edit(buffer.id(), 12, 12, "  let x = 1;\n").unwrap();
+    let moved = &store.file(&path)[0];
+    assert_eq!(&buffer.to_string()[moved.range.clone()], "call");
+    assert_eq!(moved.lsp_range.start, Position::new(2, 2));
+
+    // Undo and redo move them too
+    editor.undo(buffer.id()).unwrap();
+    assert_eq!(store.file(&path)[0].range, 14..18);
+    editor.redo(buffer.id()).unwrap();
+    assert_eq!(store.file(&path)[0].range, moved.range);
+
+    // Closed files keep their diagnostics where they were
+    editor.close_buffer(buffer.id());
+    assert_eq!(store.file(&path)[0].range, moved.range);
+  }
+
+  #[test]
+  fn test_folds_persist_and_follow_edits() {
+    let dir = tempfile::tempdir().unwrap();
+    let content = "use a;\nuse b;\n\nfn main() {\n  call();\n}\n";
+    let editor = Editor::new();
+    editor.set_fold_store(FoldStore::new(dir.path()));
+    let buffer = editor.open_file("/test/main.rs", content);
+    editor
+      .get_syntax_highlights(buffer.id(), None, None)
+      .unwrap();
+
+    assert!(editor.toggle_fold(buffer.id(), 3).unwrap());
+    let folds = editor
+      .with_display_map(buffer.id(), |map| map.folds())
+      .unwrap();
+    assert_eq!(folds, vec![Fold::new(26, 38, "...")]);
+
+    // Inserting a line above moves the collapsed fold down with its code
+    let changes = buffer
+      .transact(|tx| {
+        tx.insert(0, "\n");
+      })
+      .unwrap();
+    editor
+      .update_syntax_after_changes(buffer.id(), &changes)
+      .unwrap();
+    let state = editor.get_fold_state(buffer.id()).unwrap();
+    assert_eq!(state.collapsed.iter().copied().collect::<Vec<_>>(), vec![4]);
+    assert!(state.is_line_hidden(5));
+    let changes = buffer
+      .transact(|tx| {
+        tx.delete(0, 1);
+      })
+      .unwrap();
+    editor
+      .update_syntax_after_changes(buffer.id(), &changes)
+      .unwrap();
+
+    editor.close_buffer(buffer.id());
+    let buffer = editor.open_file("/test/main.rs", content);
+    editor
+      .get_syntax_highlights(buffer.id(), None, None)
+      .unwrap();
+    let state = editor.get_fold_state(buffer.id()).unwrap();
+    assert!(state.is_line_hidden(4));
+    assert_eq!(
+      state.fold_ranges[&0].kind,
+      Some(ferrum_buffer::FoldKind::Imports)
+    );
+  }
+
+  #[test]
+  fn test_save_buffer_runs_hooks_and_detects_disk_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("main.rs");
+    std::fs::write(&path, "fn main() {}  ").unwrap();
+
+    let editor = Editor::new();
+    let buffer = editor.open_file_bytes(&path, &std::fs::read(&path).unwrap());
+    let hooks = PreSaveHooks {
+      trim_trailing_whitespace: true,
+      insert_final_newline: true,
+    };
+
+    editor.save_buffer(buffer.id(), hooks, false).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "fn main() {}\n");
+    assert!(!buffer.is_dirty());
+
+    std::fs::write(&path, "// changed elsewhere\n").unwrap();
+    buffer.insert(0, "// mine  \n").unwrap();
+    let version = buffer.version();
+    let undo_count = buffer.history().undo_count();
+    let result = editor.save_buffer(buffer.id(), hooks, false);
+    assert!(matches!(result, Err(Error::FileChangedOnDisk { .. })));
+    // A refused save leaves the buffer as it was
+    assert_eq!(buffer.to_string(), "// mine  \nfn main() {}\n");
+    assert_eq!(buffer.version(), version);
+    assert_eq!(buffer.history().undo_count(), undo_count);
+    assert!(buffer.is_dirty());
+
+    let changes = editor.save_buffer(buffer.id(), hooks, true).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "// mine\nfn main() {}\n");
+    assert_eq!(buffer.to_string(), "// mine\nfn main() {}\n");
+    assert!(!buffer.is_dirty());
+  }
+
+  #[test]
+  fn test_failed_save_as_keeps_the_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("main.rs");
+    std::fs::write(&path, "fn main() {}\n").unwrap();
+
+    let editor = Editor::new();
+    let buffer = editor.open_file(&path, "fn main() {}\n");
+    let missing = dir.path().join("missing").join("main.rs");
+    let result = editor.save_buffer_as(buffer.id(), &missing, PreSaveHooks::default());
+    assert!(result.is_err());
+    assert_eq!(buffer.file_path(), Some(path.clone()));
+    assert!(editor.buffer_by_path(&path).is_some());
+
+    let copy = dir.path().join("copy.rs");
+    editor
+      .save_buffer_as(buffer.id(), &copy, PreSaveHooks::default())
+      .unwrap();
+    assert_eq!(buffer.file_path(), Some(copy.clone()));
+    assert_eq!(std::fs::read_to_string(&copy).unwrap(), "fn main() {}\n");
+  }
+
+  #[test]
+  fn test_large_file_saves_detect_disk_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.log");
+    std::fs::write(&path, "line\r\n").unwrap();
+
+    let editor = Editor::new();
+    let buffer = editor.open_large_file(&path, &TaskProgress::new()).unwrap();
+    let state = editor.disk_state(buffer.id()).unwrap();
+    assert_eq!(state.hash, ferrum_core::hash::content_hash("line\r\n"));
+
+    std::fs::write(&path, "changed elsewhere\n").unwrap();
+    buffer.insert(0, "mine\r\n").unwrap();
+    let result = editor.save_buffer(buffer.id(), PreSaveHooks::default(), false);
+    assert!(matches!(result, Err(Error::FileChangedOnDisk { .. })));
+  }
+
+  /// Writer appending to a buffer the test can read back
+  struct Sink(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+  impl std::io::Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.0.lock().extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn sent_messages(bytes: &[u8]) -> Vec<serde_json::Value> {
+    let mut rest = std::str::from_utf8(bytes).unwrap();
+    let mut messages = Vec::new();
+    while let Some((header, body)) = rest.split_once("\r\n\r\n") {
+      let length: usize = header["Content-Length: ".len()..].parse().unwrap();
+      messages.push(serde_json::from_str(&body[..length]).unwrap());
+      rest = &body[length..];
+    }
+    messages
+  }
+
+  #[test]
+  fn test_edits_and_undo_are_synced_to_language_servers() {
+    let editor = Editor::new();
+    let sync = Arc::new(DocumentSync::new());
+    editor.set_document_sync(sync.clone());
+    let buffer = editor.open_file("/test/main.rs", "fn main() {}\n");
+    let path = buffer.file_path().unwrap();
+
+    let output = Arc::new(parking_lot::Mutex::new(Vec::new()));
+    let capabilities = lsp_types::ServerCapabilities {
+      text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Kind(
+        lsp_types::TextDocumentSyncKind::INCREMENTAL,
+      )),
+      ..Default::default()
+    };
+    let client =
+      ferrum_lsp::LspClient::connected("rust", Box::new(Sink(output.clone())), capabilities);
+    sync
+      .open_snapshot(Arc::new(client), &buffer.snapshot(), "rust", 0)
+      .unwrap();
+
+    // Rename `main` by deleting it and typing a char at a time
+    editor.edit(buffer.id(), 3, 7, "").unwrap();
+    for (i, c) in "run".chars().enumerate() {
+      editor
+        .edit(buffer.id(), 3 + i, 3 + i, &c.to_string())
+        .unwrap();
+    }
+    assert_eq!(buffer.to_string(), "fn run() {}\n");
+    editor.undo(buffer.id()).unwrap();
+    assert_eq!(buffer.to_string(), "fn () {}\n");
+
+    let messages = sent_messages(&output.lock());
+    assert_eq!(messages[0]["method"], "textDocument/didOpen");
+    let changes = &messages[1..];
+    assert_eq!(changes.len(), 5);
+    for (i, message) in changes.iter().enumerate() {
+      assert_eq!(message["method"], "textDocument/didChange");
+      assert_eq!(message["params"]["textDocument"]["version"], i as i64 + 1);
+    }
+    let typed = &changes[2]["params"]["contentChanges"][0];
+    assert_eq!(
+      typed["range"]["start"],
+      serde_json::json!({ "line": 0, "character": 4 })
+    );
+    assert_eq!(typed["text"], "u");
+    // Undoing the merged typing removes all of it
+    let undone = &changes[4]["params"]["contentChanges"][0];
+    assert_eq!(
+      undone["range"]["end"],
+      serde_json::json!({ "line": 0, "character": 6 })
+    );
+    assert_eq!(undone["text"], "");
+
+    assert!(sync.is_current(&path, 5, buffer.snapshot().rope()));
+
+    // Edits after a resync from a snapshot still go out as ranges
+    sync.replace_snapshot(&buffer.snapshot()).unwrap();
+    editor.edit(buffer.id(), 3, 3, "go").unwrap();
+    let messages = sent_messages(&output.lock());
+    assert_eq!(messages.len(), 8);
+    assert!(messages[6]["params"]["contentChanges"][0]["range"].is_null());
+    let typed = &messages[7]["params"]["contentChanges"][0];
+    assert_eq!(typed["text"], "go");
+    assert!(sync.is_current(&path, 7, buffer.snapshot().rope()));
+  }
 }
@@ -1,5 +1,15 @@
-use std::collections::{HashMap, HashSet};
+//! Code folding
+//!
+//! Fold ranges come from the language's folds query and, when the language
+//! server supports it, from `textDocument/foldingRange`. Tree-sitter ranges
+//! are recomputed on every parse and win where both start on the same line;
+//! server ranges fill the gaps and are shifted through edits until fresh
+//! ones arrive. Collapsed folds are tracked by start line, so they survive
+//! reparses as long as a range still starts there.
 
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use ferrum_buffer::{Change, FoldKind, FoldRange};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -7,22 +17,42 @@ pub struct FoldRegion {
   pub start_line: usize,
   pub end_line: usize,
   pub is_placeholder: bool, // true if it's just a placeholder for a folded region
+  /// Comment, imports or region; `None` for code
+  #[serde(default)]
+  pub kind: Option<FoldKind>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl From<FoldRange> for FoldRegion {
+  fn from(range: FoldRange) -> Self {
+    Self {
+      start_line: range.start_line,
+      end_line: range.end_line,
+      is_placeholder: false,
+      kind: range.kind,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct FoldState {
   /// Set of lines that are currently folded (hidden)
   pub folded_lines: HashSet<usize>,
   /// Map of start line to fold region info
   pub fold_ranges: HashMap<usize, FoldRegion>,
+  /// Start lines of the collapsed folds
+  #[serde(default)]
+  pub collapsed: BTreeSet<usize>,
+  /// Ranges from the folds query
+  #[serde(skip)]
+  syntax_ranges: Vec<FoldRange>,
+  /// Ranges from the language server
+  #[serde(skip)]
+  lsp_ranges: Vec<FoldRange>,
 }
 
 impl FoldState {
   pub fn new() -> Self {
-    Self {
-      folded_lines: HashSet::new(),
-      fold_ranges: HashMap::new(),
-    }
+    Self::default()
   }
 
   /// Add a potential fold range (calculated from AST)
@@ -30,81 +60,113 @@ impl FoldState {
     if start_line >= end_line {
       return;
     }
-    self.fold_ranges.insert(
-      start_line,
-      FoldRegion {
-        start_line,
-        end_line,
-        is_placeholder: false,
-      },
-    );
+    let range = FoldRange::new(start_line, end_line, None);
+    self.syntax_ranges.push(range);
+    self.fold_ranges.insert(start_line, range.into());
   }
 
-  /// Toggle fold at a specific line
-  pub fn toggle_fold(&mut self, line: usize) -> bool {
-    if let Some(region) = self.fold_ranges.get(&line) {
-      let is_folded = self.folded_lines.contains(&(line + 1));
-
-      if is_folded {
-        // Unfold
-        for i in (line + 1)..=region.end_line {
-          self.folded_lines.remove(&i);
-        }
-      } else {
-        // Fold
-        for i in (line + 1)..=region.end_line {
-          self.folded_lines.insert(i);
-        }
-      }
-      return true;
-    }
-    false
+  /// Set the ranges found by the folds query
+  pub fn set_syntax_ranges(&mut self, ranges: Vec<FoldRange>) {
+    self.syntax_ranges = ranges;
+    self.rebuild();
   }
 
-  /// Check if a line is hidden
-  pub fn is_line_hidden(&self, line: usize) -> bool {
-    self.folded_lines.contains(&line)
+  /// Set the ranges sent by the language server
+  pub fn set_lsp_ranges(&mut self, ranges: Vec<FoldRange>) {
+    self.lsp_ranges = ranges;
+    self.rebuild();
+  }
+
+  /// Collapse the folds starting on `lines`, e.g. when restoring them
+  ///
+  /// Lines no fold starts on are ignored.
+  pub fn collapse(&mut self, lines: impl IntoIterator<Item = usize>) {
+    self.collapsed.extend(lines);
+    self.rebuild();
   }
 
-  /// Clear all fold ranges (e.g., on re-parse)
-  /// Calculate fold ranges from the syntax tree
-  pub fn calculate_folds(&mut self, tree: &tree_sitter::Tree) {
-    let root = tree.root_node();
-    self.fold_ranges.clear();
-    self.traverse(root);
-  }
-
-  fn traverse(&mut self, node: tree_sitter::Node) {
-    // Define foldable node kinds
-    let is_foldable = matches!(
-      node.kind(),
-      "block"
-        | "function_declaration"
-        | "class_declaration"
-        | "impl_item"
-        | "mod_item"
-        | "use_declaration"
-        | "match_expression"
-        | "if_statement"
-        | "while_statement"
-        | "for_statement"
-        | "comment" // Block comments often come as multiple line_comment nodes slightly different
-    );
-
-    if is_foldable {
-      let start = node.start_position();
-      let end = node.end_position();
-
-      // Only fold multi-line regions
-      if end.row > start.row {
-        self.insert_fold_range(start.row, end.row);
+  /// Move fold ranges and collapsed folds through changes made to the
+  /// buffer
+  ///
+  /// Ranges an edit cuts across are dropped. Syntax ranges are replaced on
+  /// the next parse anyway; server ranges are kept until fresh ones arrive.
+  pub fn apply_changes(&mut self, changes: &[Change]) {
+    for change in changes {
+      let start = change.start_point.line;
+      let old_end = change.old_end_point.line;
+      let new_end = change.new_end_point.line;
+      let shift = |line: usize| line - old_end + new_end;
+
+      self.collapsed = std::mem::take(&mut self.collapsed)
+        .into_iter()
+        .filter(|&line| line <= start || line > old_end)
+        .map(|line| if line > old_end { shift(line) } else { line })
+        .collect();
+
+      for ranges in [&mut self.syntax_ranges, &mut self.lsp_ranges] {
+        ranges.retain_mut(|range| {
+          if range.end_line < start {
+            true
+          } else if range.start_line > old_end {
+            range.start_line = shift(range.start_line);
+            range.end_line = shift(range.end_line);
+            true
+          } else if range.start_line <= start && range.end_line >= old_end {
+            range.end_line = shift(range.end_line);
+            true
+          } else {
+            false
+          }
+        });
       }
     }
+    self.rebuild();
+  }
+
+  /// Merge the syntax and server ranges and recompute the hidden lines
+  fn rebuild(&mut self) {
+    self.fold_ranges = self
+      .lsp_ranges
+      .iter()
+      .chain(&self.syntax_ranges)
+      .filter(|range| range.start_line < range.end_line)
+      .map(|&range| (range.start_line, range.into()))
+      .collect();
+
+    let fold_ranges = &self.fold_ranges;
+    self.collapsed.retain(|line| fold_ranges.contains_key(line));
+    self.rebuild_hidden();
+  }
+
+  /// Get the collapsed fold regions in line order
+  pub fn collapsed_regions(&self) -> impl Iterator<Item = &FoldRegion> {
+    self.collapsed.iter().map(|line| &self.fold_ranges[line])
+  }
 
-    let mut cursor = node.walk();
-    for child in node.children(&mut cursor) {
-      self.traverse(child);
+  /// Toggle fold at a specific line
+  pub fn toggle_fold(&mut self, line: usize) -> bool {
+    if !self.fold_ranges.contains_key(&line) {
+      return false;
     }
+    if !self.collapsed.remove(&line) {
+      self.collapsed.insert(line);
+    }
+    self.rebuild_hidden();
+    true
+  }
+
+  fn rebuild_hidden(&mut self) {
+    let fold_ranges = &self.fold_ranges;
+    self.folded_lines = self
+      .collapsed
+      .iter()
+      .flat_map(|line| (line + 1)..=fold_ranges[line].end_line)
+      .collect();
+  }
+
+  /// Check if a line is hidden
+  pub fn is_line_hidden(&self, line: usize) -> bool {
+    self.folded_lines.contains(&line)
   }
 }
 
@@ -188,6 +250,53 @@ mod tests {
     assert!(!state.is_line_hidden(6)); // Outside range
   }
 
+  #[test]
+  fn test_syntax_ranges_win_over_lsp_ranges() {
+    let mut state = FoldState::new();
+    state.set_lsp_ranges(vec![
+      FoldRange::new(0, 3, Some(FoldKind::Imports)),
+      FoldRange::new(5, 9, None),
+    ]);
+    state.set_syntax_ranges(vec![FoldRange::new(5, 8, None)]);
+
+    assert_eq!(state.fold_ranges[&0].kind, Some(FoldKind::Imports));
+    assert_eq!(state.fold_ranges[&5].end_line, 8);
+
+    // A collapsed fold goes away with its range
+    state.toggle_fold(5);
+    state.set_syntax_ranges(Vec::new());
+    assert_eq!(state.fold_ranges[&5].end_line, 9);
+    state.set_lsp_ranges(Vec::new());
+    assert!(state.collapsed.is_empty());
+    assert!(!state.is_line_hidden(6));
+  }
+
+  #[test]
+  fn test_edits_shift_folds() {
+    let mut state = FoldState::new();
+    state.set_lsp_ranges(vec![FoldRange::new(2, 4, None), FoldRange::new(6, 8, None)]);
+    state.toggle_fold(6);
+
+    // Replace line 1 with three lines
+    let change = Change {
+      start: 0,
+      old_len: 0,
+      new_len: 0,
+      start_byte: 0,
+      old_end_byte: 0,
+      new_end_byte: 0,
+      start_point: ferrum_buffer::Point::new(1, 0),
+      old_end_point: ferrum_buffer::Point::new(1, 3),
+      new_end_point: ferrum_buffer::Point::new(3, 1),
+    };
+    state.apply_changes(&[change]);
+
+    assert_eq!(state.fold_ranges[&4].end_line, 6);
+    assert_eq!(state.fold_ranges[&8].end_line, 10);
+    assert!(state.is_line_hidden(10));
+    assert!(!state.is_line_hidden(7));
+  }
+
   #[test]
   fn test_multiple_fold_regions() {
     let mut state = FoldState::new();
@@ -0,0 +1,130 @@
+//! Persistent fold state
+//!
+//! The collapsed folds of a file are stored by start line under a directory
+//! in the app data dir, so reopening the file collapses them again. Line
+//! numbers only hold for the text they were taken from, so folds are
+//! dropped once the file has been changed outside the editor.
+
+use crate::json_store::JsonStore;
+use ferrum_buffer::Buffer;
+use ferrum_core::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever the stored format changes; older entries are discarded
+const FORMAT_VERSION: u32 = 2;
+
+/// Per-file fold state store, holding the start lines of collapsed folds
+#[derive(Debug, Clone)]
+pub struct FoldStore {
+  entries: JsonStore<Vec<usize>>,
+}
+
+impl FoldStore {
+  /// Create a store that keeps entries in `dir`
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self {
+      entries: JsonStore::new(dir, FORMAT_VERSION, "fold state"),
+    }
+  }
+
+  /// Get the store directory
+  pub fn dir(&self) -> &Path {
+    self.entries.dir()
+  }
+
+  /// Persist the collapsed folds of a buffer
+  ///
+  /// Buffers without a file path and large files are skipped. With nothing
+  /// collapsed, the entry is removed. The folds are tied to the content last
+  /// saved, which is what the file is reopened with, so they survive closing
+  /// a buffer with unsaved edits.
+  pub fn save(&self, buffer: &Buffer, collapsed: &[usize]) -> Result<()> {
+    let Some(path) = buffer.file_path() else {
+      return Ok(());
+    };
+    if buffer.is_large_file() {
+      return Ok(());
+    }
+    if collapsed.is_empty() {
+      return self.remove(&path);
+    }
+    self.entries.save(
+      &path.to_string_lossy(),
+      Some(&buffer.saved_content()),
+      &collapsed.to_vec(),
+    )
+  }
+
+  /// Get the collapsed fold lines of a buffer if its content still matches
+  ///
+  /// A stale or unreadable entry is deleted and nothing is returned.
+  pub fn restore(&self, buffer: &Buffer) -> Result<Vec<usize>> {
+    let Some(path) = buffer.file_path() else {
+      return Ok(Vec::new());
+    };
+    if buffer.is_large_file() {
+      return Ok(Vec::new());
+    }
+
+    let collapsed = self
+      .entries
+      .load(&path.to_string_lossy(), Some(&buffer.to_string()))?;
+    Ok(collapsed.unwrap_or_default())
+  }
+
+  /// Delete the stored fold state for a file
+  pub fn remove(&self, path: &Path) -> Result<()> {
+    self.entries.remove(&path.to_string_lossy())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_restore_matching_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = FoldStore::new(dir.path());
+
+    let buffer = Buffer::from_file("/project/main.rs", "fn main() {\n}\n");
+    store.save(&buffer, &[0]).unwrap();
+
+    let reopened = Buffer::from_file("/project/main.rs", "fn main() {\n}\n");
+    assert_eq!(store.restore(&reopened).unwrap(), vec![0]);
+
+    // The file changed outside the editor since the folds were saved
+    store.save(&buffer, &[0]).unwrap();
+    let changed = Buffer::from_file("/project/main.rs", "\nfn main() {\n}\n");
+    assert!(store.restore(&changed).unwrap().is_empty());
+    assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+  }
+
+  #[test]
+  fn test_restore_after_unsaved_edits() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = FoldStore::new(dir.path());
+
+    let buffer = Buffer::from_file(
+      "/project/main.rs",
+      "fn main() {
+}
+",
+    );
+    buffer
+      .insert(
+        14, "// todo
+",
+      )
+      .unwrap();
+    store.save(&buffer, &[0]).unwrap();
+
+    let reopened = Buffer::from_file(
+      "/project/main.rs",
+      "fn main() {
+}
+",
+    );
+    assert_eq!(store.restore(&reopened).unwrap(), vec![0]);
+  }
+}
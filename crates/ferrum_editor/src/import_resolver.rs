@@ -0,0 +1,973 @@
+//! Resolving imports to files
+//!
+//! Imports are resolved against the set of files in the project, without
+//! touching the disk, following the rules of each language:
+//!
+//! - TypeScript and JavaScript: relative specifiers, then the `paths` and
+//!   `baseUrl` of the nearest `tsconfig.json` or `jsconfig.json`, trying
+//!   the usual extensions and `index` files. Other specifiers are packages.
+//! - Rust: `mod` declarations and `use` paths through the module tree of
+//!   the crate of the nearest `Cargo.toml`, and into the project's other
+//!   crates by name. `#[path]` attributes are not followed.
+//! - Python: relative imports from the file's package, and absolute ones
+//!   from the directory above the file's top-level package, then from each
+//!   directory up from the file and its `src` directory.
+//! - Go: packages under the module path of the nearest `go.mod`, as all the
+//!   files of the package directory.
+//!
+//! Anything else, e.g. the standard library or a third-party package, does
+//! not resolve.
+
+use ferrum_buffer::syntax::LanguageId;
+use ferrum_buffer::{Import, ImportKind};
+use ferrum_core::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+/// Files that configure how imports resolve
+pub const CONFIG_FILES: [&str; 4] = ["tsconfig.json", "jsconfig.json", "Cargo.toml", "go.mod"];
+
+/// Extensions tried for a TypeScript or JavaScript specifier, in order
+const TS_EXTENSIONS: [&str; 10] = [
+  "ts", "tsx", "d.ts", "mts", "cts", "js", "jsx", "mjs", "cjs", "json",
+];
+
+/// `extends` chains of TypeScript configs are followed this deep
+const MAX_EXTENDS: usize = 8;
+
+/// Module resolution settings of a TypeScript config
+#[derive(Debug, Clone, Default)]
+struct TsConfig {
+  base_url: Option<PathBuf>,
+  /// Patterns of `paths` and their substitutions
+  paths: Vec<(String, Vec<String>)>,
+  /// Directory the substitutions are relative to
+  paths_base: PathBuf,
+}
+
+/// Resolves the imports of project files to files
+#[derive(Debug, Default)]
+pub struct ImportResolver {
+  /// TypeScript configs by the directory they are in
+  ts_configs: HashMap<PathBuf, TsConfig>,
+  /// Paths of the TypeScript configs, reloaded together as they may
+  /// extend each other
+  ts_config_paths: BTreeSet<PathBuf>,
+  /// Crate names, with `-` as `_`, by the directory of their `Cargo.toml`
+  crates: HashMap<PathBuf, String>,
+  /// Go module paths by the directory of their `go.mod`
+  go_modules: HashMap<PathBuf, String>,
+}
+
+impl ImportResolver {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Check if a path is a file that configures how imports resolve
+  pub fn is_config(path: &Path) -> bool {
+    path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .is_some_and(|name| CONFIG_FILES.contains(&name))
+  }
+
+  /// Get the language of a file whose imports can be resolved
+  pub fn language(path: &Path) -> Option<LanguageId> {
+    let ext = path.extension()?.to_str()?;
+    let language = LanguageId::from_extension(ext);
+    match language {
+      LanguageId::Rust
+      | LanguageId::TypeScript
+      | LanguageId::TypeScriptReact
+      | LanguageId::JavaScript
+      | LanguageId::JavaScriptReact
+      | LanguageId::Python
+      | LanguageId::Go => Some(language),
+      _ => None,
+    }
+  }
+
+  /// Load a config file, or forget it if it can no longer be read
+  pub fn load_config(&mut self, path: &Path) {
+    let Some(dir) = path.parent() else {
+      return;
+    };
+    let content = std::fs::read_to_string(path).ok();
+    match path.file_name().and_then(|name| name.to_str()) {
+      Some("tsconfig.json" | "jsconfig.json") => {
+        match content {
+          Some(_) => self.ts_config_paths.insert(path.to_path_buf()),
+          None => self.ts_config_paths.remove(path),
+        };
+        self.reload_ts_configs();
+      },
+      Some("Cargo.toml") => match content.as_deref().and_then(package_name) {
+        Some(name) => {
+          self.crates.insert(dir.to_path_buf(), name);
+        },
+        None => {
+          self.crates.remove(dir);
+        },
+      },
+      Some("go.mod") => match content.as_deref().and_then(go_module_path) {
+        Some(module) => {
+          self.go_modules.insert(dir.to_path_buf(), module);
+        },
+        None => {
+          self.go_modules.remove(dir);
+        },
+      },
+      _ => {},
+    }
+  }
+
+  fn reload_ts_configs(&mut self) {
+    self.ts_configs.clear();
+    for path in &self.ts_config_paths {
+      let Some(config) = load_ts_config(path, 0) else {
+        warn!("Ignoring unreadable TypeScript config {:?}", path);
+        continue;
+      };
+      let dir = path.parent().unwrap_or(path).to_path_buf();
+      // A tsconfig.json next to a jsconfig.json wins
+      if path.ends_with("tsconfig.json") || !self.ts_configs.contains_key(&dir) {
+        self.ts_configs.insert(dir, config);
+      }
+    }
+  }
+
+  /// Resolve an import of a file to the files it refers to
+  ///
+  /// `files` is the set of source files in the project. Empty if the import
+  /// is of something outside the project.
+  pub fn resolve(
+    &self,
+    file: &Path,
+    language: LanguageId,
+    import: &Import,
+    files: &BTreeSet<PathBuf>,
+  ) -> Vec<PathBuf> {
+    let mut targets = match language {
+      LanguageId::Rust => self.resolve_rust(file, import, files),
+      LanguageId::TypeScript
+      | LanguageId::TypeScriptReact
+      | LanguageId::JavaScript
+      | LanguageId::JavaScriptReact => self
+        .resolve_ts(file, &import.source, files)
+        .into_iter()
+        .collect(),
+      LanguageId::Python => resolve_python(file, import, files),
+      LanguageId::Go => self.resolve_go(file, &import.source, files),
+      _ => Vec::new(),
+    };
+    targets.retain(|target| target != file);
+    targets.sort();
+    targets.dedup();
+    targets
+  }
+
+  /// Check if adding or removing `file` could change what the imports of
+  /// `importer` resolve to
+  ///
+  /// This errs towards `true`: the imports have to mention the name of the
+  /// file or its directory, or `file` has to be a package or crate root
+  /// `importer` is under, or a config has to map an import by its whole name.
+  pub fn may_affect(&self, file: &Path, importer: &Path, imports: &[Import]) -> bool {
+    let Some(dir) = file.parent() else {
+      return true;
+    };
+    let stem = file
+      .file_name()
+      .and_then(|name| name.to_str())
+      .and_then(|name| name.split('.').next())
+      .unwrap_or_default();
+
+    // Roots change how the imports of every file below them resolve
+    let scope = match stem {
+      "lib" | "main" if dir.ends_with("src") => dir.parent(),
+      "index" | "__init__" | "mod" => Some(dir),
+      _ => None,
+    };
+    if scope.is_some_and(|scope| importer.starts_with(scope)) {
+      return true;
+    }
+
+    let mut names: Vec<&str> = vec![stem];
+    names.extend(dir.file_name().and_then(|name| name.to_str()));
+    if let Some(crate_name) = dir.parent().and_then(|dir| self.crates.get(dir)) {
+      names.push(crate_name);
+    }
+    names.extend(self.go_modules.get(dir).map(String::as_str));
+    names.retain(|name| !name.is_empty());
+
+    imports.iter().any(|import| {
+      names
+        .iter()
+        .any(|name| import.source.contains(name) || import.names.iter().any(|n| n.contains(name)))
+        || self.ts_configs.values().any(|config| {
+          config
+            .paths
+            .iter()
+            .any(|(pattern, _)| *pattern == import.source)
+        })
+    })
+  }
+
+  fn resolve_ts(&self, file: &Path, specifier: &str, files: &BTreeSet<PathBuf>) -> Option<PathBuf> {
+    let dir = file.parent()?;
+    if specifier.starts_with("./") || specifier.starts_with("../") || specifier == "." {
+      return ts_candidate(&dir.join(specifier), files);
+    }
+
+    let config = file.ancestors().find_map(|dir| self.ts_configs.get(dir))?;
+
+    // The pattern with the longest prefix before its `*` wins
+    let mut best: Option<(usize, &str, &[String])> = None;
+    for (pattern, substitutions) in &config.paths {
+      let matched = match pattern.split_once('*') {
+        Some((prefix, suffix)) => (specifier.len() >= prefix.len() + suffix.len()
+          && specifier.starts_with(prefix)
+          && specifier.ends_with(suffix))
+        .then(|| {
+          (
+            prefix.len(),
+            &specifier[prefix.len()..specifier.len() - suffix.len()],
+          )
+        }),
+        None => (pattern == specifier).then_some((usize::MAX, "")),
+      };
+      match (matched, best) {
+        (Some((len, _)), Some((best_len, _, _))) if len <= best_len => {},
+        (Some((len, star)), _) => best = Some((len, star, substitutions)),
+        (None, _) => {},
+      }
+    }
+    if let Some((_, star, substitutions)) = best {
+      for substitution in substitutions {
+        let path = config.paths_base.join(substitution.replace('*', star));
+        if let Some(target) = ts_candidate(&path, files) {
+          return Some(target);
+        }
+      }
+    }
+
+    ts_candidate(&config.base_url.as_ref()?.join(specifier), files)
+  }
+
+  fn resolve_rust(&self, file: &Path, import: &Import, files: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let Some(module) = RustModule::of(file, self, files) else {
+      return Vec::new();
+    };
+    if import.kind == ImportKind::Module {
+      let dir = module.children_dir(file);
+      return rust_module_file(&dir, std::slice::from_ref(&import.source), files)
+        .into_iter()
+        .collect();
+    }
+
+    expand_use_tree(&import.source)
+      .into_iter()
+      .filter_map(|path| self.resolve_rust_path(&module, &path, files))
+      .collect()
+  }
+
+  /// Resolve a `use` path to the file of the deepest module along it
+  fn resolve_rust_path(
+    &self,
+    module: &RustModule,
+    path: &[String],
+    files: &BTreeSet<PathBuf>,
+  ) -> Option<PathBuf> {
+    let (first, rest) = path.split_first()?;
+    // Module the path starts from, as a directory of module files, its
+    // root file and the segments below that root
+    let (dir, root_file, mut segments, rest) = match first.as_str() {
+      "crate" => (
+        module.crate_dir.clone(),
+        module.root_file.clone(),
+        Vec::new(),
+        rest,
+      ),
+      "self" | "super" => {
+        let mut segments = module.segments.clone();
+        let mut rest = path;
+        while let Some((segment, tail)) = rest.split_first() {
+          match segment.as_str() {
+            "self" => {},
+            "super" => {
+              segments.pop();
+            },
+            _ => break,
+          }
+          rest = tail;
+        }
+        (
+          module.crate_dir.clone(),
+          module.root_file.clone(),
+          segments,
+          rest,
+        )
+      },
+      name if name == module.crate_name => (
+        module.crate_dir.clone(),
+        module.root_file.clone(),
+        Vec::new(),
+        rest,
+      ),
+      name => {
+        let mut local = module.segments.clone();
+        local.push(name.to_string());
+        if rust_module_file(&module.crate_dir, &local, files).is_some() {
+          // A module declared in the file's module
+          let segments = module.segments.clone();
+          (
+            module.crate_dir.clone(),
+            module.root_file.clone(),
+            segments,
+            path,
+          )
+        } else {
+          // Another crate of the project
+          let dir = self
+            .crates
+            .iter()
+            .find(|(_, crate_name)| *crate_name == name)
+            .map(|(dir, _)| dir.join("src"))?;
+          let root_file = Some(dir.join("lib.rs")).filter(|root| files.contains(root));
+          (dir, root_file, Vec::new(), rest)
+        }
+      },
+    };
+
+    let start = segments.len();
+    segments.extend(rest.iter().filter(|s| *s != "self").cloned());
+    (start..=segments.len()).rev().find_map(|len| match len {
+      0 => root_file.clone(),
+      len => rust_module_file(&dir, &segments[..len], files),
+    })
+  }
+
+  fn resolve_go(&self, file: &Path, specifier: &str, files: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let dir = if specifier.starts_with("./") || specifier.starts_with("../") {
+      file.parent().map(|dir| normalize(&dir.join(specifier)))
+    } else {
+      file.ancestors().find_map(|dir| {
+        let module = self.go_modules.get(dir)?;
+        let rest = specifier.strip_prefix(module.as_str())?;
+        match rest.strip_prefix('/') {
+          Some(rest) => Some(dir.join(rest)),
+          None if rest.is_empty() => Some(dir.to_path_buf()),
+          None => None,
+        }
+      })
+    };
+    let Some(dir) = dir else {
+      return Vec::new();
+    };
+
+    files
+      .range(dir.clone()..)
+      .take_while(|path| path.starts_with(&dir))
+      .filter(|path| {
+        path.parent() == Some(dir.as_path())
+          && path.extension().is_some_and(|ext| ext == "go")
+          && !path.to_string_lossy().ends_with("_test.go")
+      })
+      .cloned()
+      .collect()
+  }
+}
+
+/// Where a Rust file is in the module tree of its crate
+struct RustModule {
+  crate_name: String,
+  /// Directory the module files of the crate are in
+  crate_dir: PathBuf,
+  /// File of the crate root
+  root_file: Option<PathBuf>,
+  /// Path of the file's module from the crate root
+  segments: Vec<String>,
+}
+
+impl RustModule {
+  fn of(file: &Path, resolver: &ImportResolver, files: &BTreeSet<PathBuf>) -> Option<Self> {
+    let (dir, crate_name) = file
+      .ancestors()
+      .find_map(|dir| Some((dir, resolver.crates.get(dir)?)))?;
+    let src = dir.join("src");
+
+    // Files outside `src`, e.g. integration tests, are crate roots
+    let Ok(relative) = file.strip_prefix(&src) else {
+      return Some(Self {
+        crate_name: crate_name.clone(),
+        crate_dir: file.parent()?.to_path_buf(),
+        root_file: Some(file.to_path_buf()),
+        segments: Vec::new(),
+      });
+    };
+
+    let mut segments: Vec<String> = relative
+      .with_extension("")
+      .components()
+      .filter_map(|c| c.as_os_str().to_str().map(str::to_string))
+      .collect();
+    let root_file = match segments.as_slice() {
+      [root] if root == "main" || root == "lib" => Some(file.to_path_buf()),
+      _ => [src.join("lib.rs"), src.join("main.rs")]
+        .into_iter()
+        .find(|root| files.contains(root)),
+    };
+    match segments.as_slice() {
+      [root] if root == "main" || root == "lib" => segments.clear(),
+      [.., last] if last == "mod" => {
+        segments.pop();
+      },
+      _ => {},
+    }
+
+    Some(Self {
+      crate_name: crate_name.clone(),
+      crate_dir: src,
+      root_file,
+      segments,
+    })
+  }
+
+  /// Directory of the files of a file's child modules
+  fn children_dir(&self, file: &Path) -> PathBuf {
+    let dir = file.parent().unwrap_or(file);
+    let is_root = self.root_file.as_deref() == Some(file);
+    match file.file_stem().and_then(|stem| stem.to_str()) {
+      Some(stem) if !is_root && stem != "mod" => dir.join(stem),
+      _ => dir.to_path_buf(),
+    }
+  }
+}
+
+/// Get the file of a module below a directory, `a/b.rs` or `a/b/mod.rs`
+fn rust_module_file(dir: &Path, segments: &[String], files: &BTreeSet<PathBuf>) -> Option<PathBuf> {
+  let (last, parents) = segments.split_last()?;
+  let parent = parents.iter().fold(dir.to_path_buf(), |dir, s| dir.join(s));
+  [
+    parent.join(format!("{}.rs", last)),
+    parent.join(last).join("mod.rs"),
+  ]
+  .into_iter()
+  .find(|path| files.contains(path))
+}
+
+/// Expand a Rust use tree into the paths it imports
+///
+/// `a::{b, c::{d as e, *}}` becomes `a::b`, `a::c::d` and `a::c`, as a glob
+/// imports from its module.
+pub fn expand_use_tree(tree: &str) -> Vec<Vec<String>> {
+  let mut tokens = Vec::new();
+  let mut chars = tree.char_indices().peekable();
+  while let Some((start, c)) = chars.next() {
+    match c {
+      '{' | '}' | ',' | '*' => tokens.push(&tree[start..start + 1]),
+      ':' if chars.peek().is_some_and(|(_, c)| *c == ':') => {
+        chars.next();
+        tokens.push("::");
+      },
+      c if c.is_alphanumeric() || c == '_' => {
+        let mut end = start + c.len_utf8();
+        while let Some((i, c)) = chars.peek().copied() {
+          if !(c.is_alphanumeric() || c == '_' || c == '#') {
+            break;
+          }
+          end = i + c.len_utf8();
+          chars.next();
+        }
+        tokens.push(&tree[start..end]);
+      },
+      _ => {},
+    }
+  }
+
+  let mut paths = Vec::new();
+  let mut i = 0;
+  expand_tokens(&tokens, &mut i, Vec::new(), &mut paths);
+  paths
+}
+
+fn expand_tokens(
+  tokens: &[&str],
+  i: &mut usize,
+  mut path: Vec<String>,
+  paths: &mut Vec<Vec<String>>,
+) {
+  while let Some(token) = tokens.get(*i) {
+    *i += 1;
+    match *token {
+      "::" => {},
+      "{" => {
+        while let Some(token) = tokens.get(*i) {
+          match *token {
+            "}" => {
+              *i += 1;
+              break;
+            },
+            "," => *i += 1,
+            _ => expand_tokens(tokens, i, path.clone(), paths),
+          }
+        }
+        return;
+      },
+      "*" => {
+        paths.push(path);
+        return;
+      },
+      "}" | "," => {
+        *i -= 1;
+        break;
+      },
+      name => {
+        path.push(name.trim_start_matches("r#").to_string());
+        match tokens.get(*i) {
+          Some(&"::") => continue,
+          Some(&"as") => *i += 2,
+          _ => {},
+        }
+        break;
+      },
+    }
+  }
+  if !path.is_empty() {
+    paths.push(path);
+  }
+}
+
+/// Find the file a TypeScript or JavaScript path refers to
+fn ts_candidate(path: &Path, files: &BTreeSet<PathBuf>) -> Option<PathBuf> {
+  let path = normalize(path);
+  if files.contains(&path) {
+    return Some(path);
+  }
+
+  let with_extension = |path: &Path, ext: &str| {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+  };
+  // ESM code imports TypeScript files by the name they compile to
+  let compiled = match path.extension().and_then(|ext| ext.to_str()) {
+    Some("js" | "jsx") => vec![path.with_extension("ts"), path.with_extension("tsx")],
+    Some("mjs") => vec![path.with_extension("mts")],
+    Some("cjs") => vec![path.with_extension("cts")],
+    _ => Vec::new(),
+  };
+
+  TS_EXTENSIONS
+    .iter()
+    .map(|ext| with_extension(&path, ext))
+    .chain(compiled)
+    .chain(
+      TS_EXTENSIONS
+        .iter()
+        .map(|ext| with_extension(&path.join("index"), ext)),
+    )
+    .find(|candidate| files.contains(candidate))
+}
+
+fn resolve_python(file: &Path, import: &Import, files: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+  let Some(dir) = file.parent() else {
+    return Vec::new();
+  };
+  let source = import.source.as_str();
+  let dots = source.len() - source.trim_start_matches('.').len();
+  let segments: Vec<&str> = source[dots..]
+    .split('.')
+    .filter(|s| !s.is_empty())
+    .collect();
+
+  let bases: Vec<PathBuf> = match dots {
+    0 => {
+      // Above the file's top-level package, then up from the file
+      let mut package_root = dir;
+      while files.contains(&package_root.join("__init__.py")) {
+        match package_root.parent() {
+          Some(parent) => package_root = parent,
+          None => break,
+        }
+      }
+      let mut bases = vec![package_root.to_path_buf()];
+      for ancestor in dir.ancestors() {
+        bases.push(ancestor.to_path_buf());
+        bases.push(ancestor.join("src"));
+      }
+      bases
+    },
+    dots => {
+      let base = (1..dots).try_fold(dir, |dir, _| dir.parent());
+      base
+        .map(|base| vec![base.to_path_buf()])
+        .unwrap_or_default()
+    },
+  };
+
+  for base in bases {
+    let module = segments.iter().fold(base.clone(), |dir, s| dir.join(s));
+    let module_file = python_module_file(&module, files);
+
+    let mut targets: Vec<PathBuf> = import
+      .names
+      .iter()
+      .filter_map(|name| python_module_file(&module.join(name), files))
+      .collect();
+    let all_modules = !import.names.is_empty() && targets.len() == import.names.len();
+    if let Some(module_file) = module_file {
+      if !all_modules {
+        targets.push(module_file);
+      }
+    } else if targets.is_empty() && dots == 0 {
+      // `import a.b.c` also imports its parent packages
+      if let Some(target) = (1..segments.len()).rev().find_map(|len| {
+        let module = segments[..len]
+          .iter()
+          .fold(base.clone(), |dir, s| dir.join(s));
+        python_module_file(&module, files)
+      }) {
+        targets.push(target);
+      }
+    }
+    if !targets.is_empty() {
+      return targets;
+    }
+  }
+  Vec::new()
+}
+
+/// Get the file of a Python module, `a/b.py` or `a/b/__init__.py`
+fn python_module_file(module: &Path, files: &BTreeSet<PathBuf>) -> Option<PathBuf> {
+  let mut file = module.as_os_str().to_os_string();
+  file.push(".py");
+  [PathBuf::from(file), module.join("__init__.py")]
+    .into_iter()
+    .find(|path| files.contains(path))
+}
+
+/// Load a TypeScript config, following relative `extends`
+fn load_ts_config(path: &Path, depth: usize) -> Option<TsConfig> {
+  let dir = path.parent()?;
+  let content = std::fs::read_to_string(path).ok()?;
+  let json: serde_json::Value = serde_json::from_str(&strip_jsonc(&content)).ok()?;
+
+  let mut config = json
+    .get("extends")
+    .and_then(|extends| extends.as_str())
+    .filter(|extends| extends.starts_with('.') && depth < MAX_EXTENDS)
+    .and_then(|extends| {
+      // As in TypeScript, `.json` is added unless the path already has it
+      let mut parent = normalize(&dir.join(extends)).into_os_string();
+      if !extends.ends_with(".json") {
+        parent.push(".json");
+      }
+      load_ts_config(Path::new(&parent), depth + 1)
+    })
+    .unwrap_or_else(|| TsConfig {
+      paths_base: dir.to_path_buf(),
+      ..Default::default()
+    });
+
+  let options = json.get("compilerOptions");
+  if let Some(base_url) = options
+    .and_then(|o| o.get("baseUrl"))
+    .and_then(|b| b.as_str())
+  {
+    let base_url = normalize(&dir.join(base_url));
+    config.paths_base = base_url.clone();
+    config.base_url = Some(base_url);
+  }
+  if let Some(paths) = options
+    .and_then(|o| o.get("paths"))
+    .and_then(|p| p.as_object())
+  {
+    config.paths = paths
+      .iter()
+      .map(|(pattern, substitutions)| {
+        let substitutions = substitutions
+          .as_array()
+          .map(|s| {
+            s.iter()
+              .filter_map(|s| s.as_str().map(str::to_string))
+              .collect()
+          })
+          .unwrap_or_default();
+        (pattern.clone(), substitutions)
+      })
+      .collect();
+    if config.base_url.is_none() {
+      config.paths_base = dir.to_path_buf();
+    }
+  }
+
+  Some(config)
+}
+
+/// Turn JSON with comments and trailing commas, as in TypeScript configs,
+/// into JSON
+fn strip_jsonc(content: &str) -> String {
+  let mut out = String::with_capacity(content.len());
+  let mut chars = content.chars().peekable();
+  let mut in_string = false;
+  while let Some(c) = chars.next() {
+    if in_string {
+      out.push(c);
+      match c {
+        '\\' => out.extend(chars.next()),
+        '"' => in_string = false,
+        _ => {},
+      }
+      continue;
+    }
+    match (c, chars.peek()) {
+      ('"', _) => {
+        in_string = true;
+        out.push(c);
+      },
+      ('/', Some('/')) => {
+        for c in chars.by_ref() {
+          if c == '\n' {
+            out.push(c);
+            break;
+          }
+        }
+      },
+      ('/', Some('*')) => {
+        chars.next();
+        let mut last = ' ';
+        for c in chars.by_ref() {
+          if last == '*' && c == '/' {
+            break;
+          }
+          last = c;
+        }
+      },
+      (',', _) => {
+        // Drop the comma if only whitespace comes before a closing bracket
+        let rest: String = chars.clone().take_while(|c| c.is_whitespace()).collect();
+        let next = chars.clone().nth(rest.chars().count());
+        if !matches!(next, Some('}' | ']')) {
+          out.push(c);
+        }
+      },
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+/// Get the package name of a `Cargo.toml`, with `-` as `_`
+fn package_name(content: &str) -> Option<String> {
+  let mut in_package = false;
+  for line in content.lines().map(str::trim) {
+    if line.starts_with('[') {
+      in_package = line == "[package]";
+      continue;
+    }
+    let Some(value) = line.strip_prefix("name") else {
+      continue;
+    };
+    if in_package {
+      let value = value.trim_start().strip_prefix('=')?.trim();
+      return Some(value.trim_matches('"').replace('-', "_"));
+    }
+  }
+  None
+}
+
+/// Get the module path of a `go.mod`
+fn go_module_path(content: &str) -> Option<String> {
+  content.lines().find_map(|line| {
+    let module = line.trim().strip_prefix("module")?;
+    let module = module.trim().trim_matches('"');
+    (!module.is_empty()).then(|| module.to_string())
+  })
+}
+
+/// Resolve `.` and `..` in a path without touching the disk
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => {},
+      Component::ParentDir => {
+        normalized.pop();
+      },
+      component => normalized.push(component),
+    }
+  }
+  normalized
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn import(source: &str, names: &[&str]) -> Import {
+    Import {
+      kind: ImportKind::Import,
+      source: source.to_string(),
+      names: names.iter().map(|n| n.to_string()).collect(),
+      line: 0,
+      range: 0..0,
+    }
+  }
+
+  fn files(paths: &[&str]) -> BTreeSet<PathBuf> {
+    paths.iter().map(PathBuf::from).collect()
+  }
+
+  #[test]
+  fn test_expand_use_tree() {
+    let paths: Vec<_> = expand_use_tree("crate::a::{self, b as c, d::{e, *}}")
+      .into_iter()
+      .map(|p| p.join("::"))
+      .collect();
+    assert_eq!(
+      paths,
+      [
+        "crate::a::self",
+        "crate::a::b",
+        "crate::a::d::e",
+        "crate::a::d"
+      ]
+    );
+    assert_eq!(expand_use_tree("std::io"), [vec!["std", "io"]]);
+  }
+
+  #[test]
+  fn test_resolve_rust() {
+    let mut resolver = ImportResolver::new();
+    resolver.crates.insert("/p".into(), "app".into());
+    resolver.crates.insert("/p/util".into(), "util".into());
+    let files = files(&[
+      "/p/src/lib.rs",
+      "/p/src/a.rs",
+      "/p/src/a/b.rs",
+      "/p/src/c/mod.rs",
+      "/p/util/src/lib.rs",
+      "/p/util/src/fmt.rs",
+    ]);
+    let resolve = |file: &str, import: Import| {
+      resolver.resolve(Path::new(file), LanguageId::Rust, &import, &files)
+    };
+
+    let module = Import {
+      kind: ImportKind::Module,
+      ..import("b", &[])
+    };
+    assert_eq!(
+      resolve("/p/src/a.rs", module),
+      [PathBuf::from("/p/src/a/b.rs")]
+    );
+    assert_eq!(
+      resolve("/p/src/a/b.rs", import("crate::{c::Thing, a::b::f}", &[])),
+      [PathBuf::from("/p/src/c/mod.rs")]
+    );
+    assert_eq!(
+      resolve("/p/src/a/b.rs", import("super::super::c", &[])),
+      [PathBuf::from("/p/src/c/mod.rs")]
+    );
+    assert_eq!(
+      resolve("/p/src/lib.rs", import("util::fmt::pad", &[])),
+      [PathBuf::from("/p/util/src/fmt.rs")]
+    );
+    assert!(resolve("/p/src/lib.rs", import("std::fmt", &[])).is_empty());
+  }
+
+  #[test]
+  fn test_resolve_typescript() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(
+      root.join("tsconfig.base.json"),
+      r#"{ "compilerOptions": { "baseUrl": ".", "paths": { "@/*": ["src/*"], }, } }"#,
+    )
+    .unwrap();
+    std::fs::write(
+      root.join("tsconfig.json"),
+      "// Project config\n{ \"extends\": \"./tsconfig.base\" }",
+    )
+    .unwrap();
+
+    let mut resolver = ImportResolver::new();
+    resolver.load_config(&root.join("tsconfig.json"));
+    let files: BTreeSet<PathBuf> = ["src/app.tsx", "src/ui/index.ts", "src/util.ts", "lib/x.js"]
+      .iter()
+      .map(|p| root.join(p))
+      .collect();
+    let resolve = |specifier: &str| {
+      resolver.resolve(
+        &root.join("src/app.tsx"),
+        LanguageId::TypeScriptReact,
+        &import(specifier, &[]),
+        &files,
+      )
+    };
+
+    assert_eq!(resolve("./ui"), [root.join("src/ui/index.ts")]);
+    assert_eq!(resolve("./util.js"), [root.join("src/util.ts")]);
+    assert_eq!(resolve("@/util"), [root.join("src/util.ts")]);
+    assert_eq!(resolve("lib/x"), [root.join("lib/x.js")]);
+    assert!(resolve("react").is_empty());
+  }
+
+  #[test]
+  fn test_resolve_python_and_go() {
+    let mut resolver = ImportResolver::new();
+    resolver
+      .go_modules
+      .insert("/g".into(), "example.com/app".into());
+    let files = files(&[
+      "/py/pkg/__init__.py",
+      "/py/pkg/a.py",
+      "/py/pkg/sub/__init__.py",
+      "/py/pkg/sub/b.py",
+      "/g/main.go",
+      "/g/store/db.go",
+      "/g/store/db_test.go",
+      "/g/store/cache/lru.go",
+    ]);
+    let python = |file: &str, import: Import| {
+      resolver.resolve(Path::new(file), LanguageId::Python, &import, &files)
+    };
+
+    assert_eq!(
+      python("/py/pkg/sub/b.py", import("..", &["a"])),
+      [PathBuf::from("/py/pkg/a.py")]
+    );
+    assert_eq!(
+      python("/py/pkg/a.py", import("pkg.sub", &["b", "helper"])),
+      [
+        PathBuf::from("/py/pkg/sub/__init__.py"),
+        PathBuf::from("/py/pkg/sub/b.py")
+      ]
+    );
+    assert_eq!(
+      python("/py/pkg/a.py", import("pkg.sub.missing", &[])),
+      [PathBuf::from("/py/pkg/sub/__init__.py")]
+    );
+
+    assert_eq!(
+      resolver.resolve(
+        Path::new("/g/main.go"),
+        LanguageId::Go,
+        &import("example.com/app/store", &[]),
+        &files
+      ),
+      [PathBuf::from("/g/store/db.go")]
+    );
+  }
+
+  #[test]
+  fn test_strip_jsonc() {
+    let json = strip_jsonc("{\n  // c\n  \"a\": \"//x\", /* b */\n  \"b\": [1, 2,],\n}");
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["a"], "//x");
+    assert_eq!(value["b"], serde_json::json!([1, 2]));
+  }
+}
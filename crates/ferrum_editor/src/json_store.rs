@@ -0,0 +1,205 @@
+//! Directories of JSON entries
+//!
+//! Undo history, fold state and recovery entries are each kept as one JSON
+//! file per key in a directory under the app data dir. Files are named by a
+//! hash of their key and written to a temp file first, so a crash never
+//! leaves a torn entry. An entry can be tied to the content of a file; it is
+//! then only read back while the file has exactly that content. Entries that
+//! are unreadable, of another format version or stale are deleted when read.
+
+use ferrum_core::hash::content_hash;
+use ferrum_core::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// An entry as written to disk
+#[derive(Serialize, Deserialize)]
+struct Stored<T> {
+  format_version: u32,
+  /// Key the entry is stored under, to guard against hash collisions
+  key: String,
+  /// Hash of the content the value is tied to, if any
+  content_hash: Option<String>,
+  value: T,
+}
+
+/// A directory of JSON values, one file per key
+#[derive(Debug, Clone)]
+pub(crate) struct JsonStore<T> {
+  dir: PathBuf,
+  /// Format version of the values; entries of other versions are discarded
+  format_version: u32,
+  /// What the values are, for log messages
+  kind: &'static str,
+  value: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> JsonStore<T> {
+  /// Create a store that keeps entries in `dir`
+  pub(crate) fn new(dir: impl Into<PathBuf>, format_version: u32, kind: &'static str) -> Self {
+    Self {
+      dir: dir.into(),
+      format_version,
+      kind,
+      value: PhantomData,
+    }
+  }
+
+  /// Get the store directory
+  pub(crate) fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  /// Write the value for a key, tied to `content` if given
+  pub(crate) fn save(&self, key: &str, content: Option<&str>, value: &T) -> Result<()> {
+    let stored = Stored {
+      format_version: self.format_version,
+      key: key.to_string(),
+      content_hash: content.map(content_hash),
+      value,
+    };
+    let bytes = serde_json::to_vec(&stored)?;
+
+    std::fs::create_dir_all(&self.dir)?;
+    let entry_path = self.entry_path(key);
+    let tmp_path = entry_path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, bytes)?;
+    std::fs::rename(&tmp_path, &entry_path)?;
+    Ok(())
+  }
+
+  /// Read the value for a key
+  ///
+  /// A value tied to content is only returned if `content` is the same;
+  /// otherwise the entry is stale and deleted.
+  pub(crate) fn load(&self, key: &str, content: Option<&str>) -> Result<Option<T>> {
+    let entry_path = self.entry_path(key);
+    let Some(stored) = self.read(&entry_path)? else {
+      return Ok(None);
+    };
+
+    let stale = match (&stored.content_hash, content) {
+      (Some(hash), Some(content)) => *hash != content_hash(content),
+      _ => false,
+    };
+    if stored.key != key || stale {
+      debug!("Discarding stale {} for {}", self.kind, key);
+      self.remove(key)?;
+      return Ok(None);
+    }
+    Ok(Some(stored.value))
+  }
+
+  /// Read the values of every entry
+  pub(crate) fn load_all(&self) -> Result<Vec<T>> {
+    let dir = match std::fs::read_dir(&self.dir) {
+      Ok(dir) => dir,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(e) => return Err(e.into()),
+    };
+
+    let mut values = Vec::new();
+    for file in dir {
+      let path = file?.path();
+      if path.extension().and_then(|e| e.to_str()) != Some("json") {
+        continue;
+      }
+      if let Some(stored) = self.read(&path)? {
+        values.push(stored.value);
+      }
+    }
+    Ok(values)
+  }
+
+  /// Delete the entry for a key
+  pub(crate) fn remove(&self, key: &str) -> Result<()> {
+    remove_file(&self.entry_path(key))
+  }
+
+  /// Read an entry file, deleting it if it is unreadable or outdated
+  fn read(&self, entry_path: &Path) -> Result<Option<Stored<T>>> {
+    let bytes = match std::fs::read(entry_path) {
+      Ok(bytes) => bytes,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e.into()),
+    };
+
+    match serde_json::from_slice::<Stored<T>>(&bytes) {
+      Ok(stored) if stored.format_version == self.format_version => Ok(Some(stored)),
+      Ok(_) => {
+        debug!("Discarding outdated {} {:?}", self.kind, entry_path);
+        remove_file(entry_path)?;
+        Ok(None)
+      },
+      Err(e) => {
+        warn!(
+          "Discarding unreadable {} {:?}: {}",
+          self.kind, entry_path, e
+        );
+        remove_file(entry_path)?;
+        Ok(None)
+      },
+    }
+  }
+
+  /// Entry file for a key, named by the hash of the key
+  fn entry_path(&self, key: &str) -> PathBuf {
+    self.dir.join(format!("{}.json", content_hash(key)))
+  }
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+  match std::fs::remove_file(path) {
+    Ok(()) => Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(e.into()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn is_empty(dir: &Path) -> bool {
+    std::fs::read_dir(dir).unwrap().next().is_none()
+  }
+
+  #[test]
+  fn test_values_tied_to_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = JsonStore::<Vec<usize>>::new(dir.path(), 1, "test entry");
+
+    store.save("/a.rs", Some("fn a() {}"), &vec![1, 2]).unwrap();
+    store.save("/b.rs", None, &vec![3]).unwrap();
+    assert_eq!(
+      store.load("/a.rs", Some("fn a() {}")).unwrap(),
+      Some(vec![1, 2])
+    );
+    assert_eq!(
+      store.load("/b.rs", Some("anything")).unwrap(),
+      Some(vec![3])
+    );
+    assert_eq!(store.load_all().unwrap().len(), 2);
+
+    // Changed content makes the entry stale
+    assert_eq!(store.load("/a.rs", Some("fn b() {}")).unwrap(), None);
+    assert_eq!(store.load("/a.rs", Some("fn a() {}")).unwrap(), None);
+    store.remove("/b.rs").unwrap();
+    assert!(is_empty(dir.path()));
+  }
+
+  #[test]
+  fn test_outdated_and_unreadable_entries_are_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    JsonStore::<String>::new(dir.path(), 1, "test entry")
+      .save("old", None, &"value".to_string())
+      .unwrap();
+    std::fs::write(dir.path().join("garbage.json"), "{").unwrap();
+
+    let store = JsonStore::<String>::new(dir.path(), 2, "test entry");
+    assert!(store.load_all().unwrap().is_empty());
+    assert!(is_empty(dir.path()));
+  }
+}
@@ -2,14 +2,30 @@
 //!
 //! Editor state management, tabs, views, and workspace coordination.
 
+pub mod dependency_graph;
 pub mod depth;
 pub mod display_map;
 pub mod editor;
 pub mod fold;
+pub mod fold_store;
+pub mod import_resolver;
+mod json_store;
+pub mod reconcile;
+pub mod recovery;
+pub mod semantic;
 pub mod tabs;
+pub mod undo_store;
 pub mod workspace;
+pub mod workspace_edit;
 
+pub use dependency_graph::{DependencyEdge, DependencyGraph, FileImport};
 pub use display_map::{DisplayMap, DisplayMapConfig, DisplayPoint, Fold};
 pub use editor::Editor;
+pub use fold_store::FoldStore;
+pub use import_resolver::ImportResolver;
+pub use reconcile::{ConflictResolution, DiskConflict, Reconciler, Reconciliation};
+pub use recovery::{RecoveryEntry, RecoveryStore};
 pub use tabs::{Tab, TabGroup};
+pub use undo_store::UndoStore;
 pub use workspace::Workspace;
+pub use workspace_edit::{destructive_operations, AppliedEdit, WorkspaceEditApplier};
@@ -0,0 +1,385 @@
+//! Reconciling open buffers with changes on disk
+//!
+//! When a file changes outside the editor, a clean buffer is reloaded
+//! through a diff so cursors and anchors outside the changed regions stay
+//! put. A dirty buffer is left alone and a three-way conflict between the
+//! last saved content, the disk and the buffer is reported instead. Deleted
+//! files are remembered by content hash, so that a file reappearing under
+//! another name is recognised as a rename and the buffer follows it.
+
+use crate::editor::Editor;
+use dashmap::DashMap;
+use ferrum_buffer::diff::{self, Merge};
+use ferrum_buffer::format;
+use ferrum_buffer::{Change, FileFormat};
+use ferrum_core::hash::content_hash;
+use ferrum_core::prelude::*;
+use ferrum_fs::DiskState;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// What happened to a buffer after its file changed on disk
+#[derive(Debug, Clone)]
+pub enum Reconciliation {
+  /// The buffer was clean and has been reloaded
+  Reloaded {
+    buffer_id: BufferId,
+    changes: Vec<Change>,
+  },
+  /// The buffer has unsaved changes that clash with the disk version
+  Conflict(DiskConflict),
+  /// The file was deleted; the buffer keeps its content
+  Deleted { buffer_id: BufferId },
+  /// The file was renamed and the buffer now points at the new path
+  Renamed {
+    buffer_id: BufferId,
+    from: PathBuf,
+    to: PathBuf,
+  },
+}
+
+/// The three versions of a file whose buffer and disk both changed
+#[derive(Debug, Clone)]
+pub struct DiskConflict {
+  pub buffer_id: BufferId,
+  pub path: PathBuf,
+  /// Content as last loaded or saved
+  pub base: String,
+  /// Content now on disk
+  pub disk: String,
+  /// Content of the buffer
+  pub buffer: String,
+  /// Format of the disk version
+  pub disk_format: FileFormat,
+}
+
+impl DiskConflict {
+  /// Merge the buffer and disk versions line by line
+  pub fn merge(&self) -> Merge {
+    diff::merge3(&self.base, &self.buffer, &self.disk, "buffer", "disk")
+  }
+}
+
+/// How to settle a `DiskConflict`
+#[derive(Debug, Clone)]
+pub enum ConflictResolution {
+  /// Keep the buffer; saving it will overwrite the disk version
+  KeepBuffer,
+  /// Discard unsaved changes and reload from disk
+  TakeDisk,
+  /// Replace the buffer with merged text, e.g. from `DiskConflict::merge`
+  Merged(String),
+}
+
+/// Reconciles open buffers with file system events
+pub struct Reconciler {
+  editor: Arc<Editor>,
+  /// Buffers whose file was deleted, with the hash of their saved content
+  orphans: DashMap<BufferId, String>,
+  /// Conflicts awaiting resolution
+  conflicts: DashMap<BufferId, DiskConflict>,
+}
+
+impl Reconciler {
+  /// Create a reconciler for the editor's buffers
+  pub fn new(editor: Arc<Editor>) -> Self {
+    Self {
+      editor,
+      orphans: DashMap::new(),
+      conflicts: DashMap::new(),
+    }
+  }
+
+  /// Get the editor whose buffers are reconciled
+  pub fn editor(&self) -> &Arc<Editor> {
+    &self.editor
+  }
+
+  /// Handle a file being modified
+  ///
+  /// Returns `None` if no buffer is open for the file or nothing changed,
+  /// which includes the events caused by the editor's own saves.
+  pub fn file_modified(&self, path: &Path) -> Result<Option<Reconciliation>> {
+    let Some(buffer) = self.editor.buffer_by_path(&path.to_path_buf()) else {
+      return Ok(None);
+    };
+    if buffer.is_large_file() {
+      debug!("Not reconciling large file {:?}", path);
+      return Ok(None);
+    }
+
+    let bytes = match std::fs::read(path) {
+      Ok(bytes) => bytes,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(self.file_deleted(path)),
+      Err(e) => return Err(e.into()),
+    };
+    let buffer_id = buffer.id();
+    self.orphans.remove(&buffer_id);
+
+    // The editor's own save, or a change already seen. Compared as bytes,
+    // since the saved text may differ from the encoded file, e.g. in its
+    // line endings
+    let disk_state = DiskState::from_bytes(path, &bytes);
+    if self
+      .editor
+      .disk_state(buffer_id)
+      .is_some_and(|known| known.hash == disk_state.hash)
+    {
+      return Ok(None);
+    }
+
+    let (disk, disk_format) = format::decode(&bytes);
+    let base = buffer.saved_content();
+    if disk == base {
+      self.editor.set_disk_state(buffer_id, disk_state);
+      return Ok(None);
+    }
+
+    let snapshot = buffer.snapshot();
+    let current = snapshot.to_string();
+    if disk == current {
+      // The disk caught up with the buffer, e.g. a formatter ran
+      buffer.set_saved_content(&disk);
+      if snapshot.version() == buffer.version() {
+        buffer.mark_saved();
+      }
+      self.editor.set_disk_state(buffer_id, disk_state);
+      return Ok(None);
+    }
+
+    if !buffer.is_dirty() {
+      let changes = buffer.reload(&disk, disk_format)?;
+      self.editor.set_disk_state(buffer_id, disk_state);
+      self.editor.update_syntax_after_changes(buffer_id, &changes)?;
+      return Ok(Some(Reconciliation::Reloaded { buffer_id, changes }));
+    }
+
+    let conflict = DiskConflict {
+      buffer_id,
+      path: path.to_path_buf(),
+      base,
+      disk,
+      buffer: current,
+      disk_format,
+    };
+    self.conflicts.insert(buffer_id, conflict.clone());
+    Ok(Some(Reconciliation::Conflict(conflict)))
+  }
+
+  /// Handle a file being created
+  ///
+  /// A file recreated under an open buffer's path is treated as a
+  /// modification. A new file with the same content as a deleted one is
+  /// taken as its rename.
+  pub fn file_created(&self, path: &Path) -> Result<Option<Reconciliation>> {
+    if self.editor.is_file_open(&path.to_path_buf()) {
+      return self.file_modified(path);
+    }
+    if self.orphans.is_empty() {
+      return Ok(None);
+    }
+
+    let bytes = match std::fs::read(path) {
+      Ok(bytes) => bytes,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e.into()),
+    };
+    let hash = content_hash(format::decode(&bytes).0);
+
+    let Some(buffer_id) = self
+      .orphans
+      .iter()
+      .find(|entry| *entry.value() == hash)
+      .map(|entry| *entry.key())
+    else {
+      return Ok(None);
+    };
+
+    self.orphans.remove(&buffer_id);
+    self.follow_rename(buffer_id, path)
+  }
+
+  /// Handle a file being deleted
+  pub fn file_deleted(&self, path: &Path) -> Option<Reconciliation> {
+    let buffer = self.editor.buffer_by_path(&path.to_path_buf())?;
+    let buffer_id = buffer.id();
+
+    self
+      .orphans
+      .insert(buffer_id, content_hash(buffer.saved_content()));
+    Some(Reconciliation::Deleted { buffer_id })
+  }
+
+  /// Handle a file being renamed
+  pub fn file_renamed(&self, from: &Path, to: &Path) -> Result<Option<Reconciliation>> {
+    let Some(buffer) = self.editor.buffer_by_path(&from.to_path_buf()) else {
+      return Ok(None);
+    };
+
+    let buffer_id = buffer.id();
+    self.orphans.remove(&buffer_id);
+    self.follow_rename(buffer_id, to)
+  }
+
+  /// Get the unresolved conflict for a buffer, if any
+  pub fn conflict(&self, buffer_id: BufferId) -> Option<DiskConflict> {
+    self.conflicts.get(&buffer_id).map(|c| c.clone())
+  }
+
+  /// Settle the conflict reported by `file_modified` for a buffer
+  ///
+  /// Returns the changes applied to the buffer, if any.
+  pub fn resolve(&self, buffer_id: BufferId, resolution: ConflictResolution) -> Result<Vec<Change>> {
+    let (_, conflict) = self
+      .conflicts
+      .remove(&buffer_id)
+      .ok_or_else(|| Error::Internal(format!("No disk conflict for buffer {}", buffer_id)))?;
+    let buffer = self
+      .editor
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+
+    // Whatever the choice, the buffer has now seen the disk version, so
+    // saving may overwrite it
+    if let Some(state) = DiskState::read(&conflict.path)? {
+      self.editor.set_disk_state(buffer_id, state);
+    }
+
+    let changes = match resolution {
+      ConflictResolution::KeepBuffer => {
+        buffer.set_saved_content(&conflict.disk);
+        Vec::new()
+      },
+      ConflictResolution::TakeDisk => buffer.reload(&conflict.disk, conflict.disk_format)?,
+      ConflictResolution::Merged(text) => {
+        buffer.set_saved_content(&conflict.disk);
+        buffer.set_text(&text)?
+      },
+    };
+
+    self.editor.update_syntax_after_changes(buffer_id, &changes)?;
+    Ok(changes)
+  }
+
+  /// Forget a buffer, e.g. after it was closed
+  pub fn forget(&self, buffer_id: BufferId) {
+    self.orphans.remove(&buffer_id);
+    self.conflicts.remove(&buffer_id);
+  }
+
+  fn follow_rename(&self, buffer_id: BufferId, to: &Path) -> Result<Option<Reconciliation>> {
+    let buffer = self
+      .editor
+      .buffer(buffer_id)
+      .ok_or(Error::BufferNotFound(buffer_id))?;
+    let Some(from) = buffer.file_path() else {
+      return Ok(None);
+    };
+
+    self.editor.set_buffer_path(buffer_id, to)?;
+    info!("Buffer followed rename {:?} -> {:?}", from, to);
+
+    Ok(Some(Reconciliation::Renamed {
+      buffer_id,
+      from,
+      to: to.to_path_buf(),
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn setup(content: &str) -> (tempfile::TempDir, PathBuf, Arc<Editor>, Reconciler) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("main.rs");
+    std::fs::write(&path, content).unwrap();
+
+    let editor = Arc::new(Editor::new());
+    editor.open_file(&path, content);
+    let reconciler = Reconciler::new(editor.clone());
+    (dir, path, editor, reconciler)
+  }
+
+  #[test]
+  fn test_clean_buffer_reloads() {
+    let (_dir, path, editor, reconciler) = setup("fn main() {}\n");
+    std::fs::write(&path, "fn main() { run(); }\n").unwrap();
+
+    let result = reconciler.file_modified(&path).unwrap();
+    assert!(matches!(result, Some(Reconciliation::Reloaded { .. })));
+
+    let buffer = editor.buffer_by_path(&path).unwrap();
+    assert_eq!(buffer.to_string(), "fn main() { run(); }\n");
+    assert!(!buffer.is_dirty());
+
+    // A second event for the same content is a no-op
+    assert!(reconciler.file_modified(&path).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_own_save_is_ignored() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("main.rs");
+    std::fs::write(&path, "a\r\nb\r\n").unwrap();
+    let editor = Arc::new(Editor::new());
+    let buffer = editor.open_file_bytes(&path, &std::fs::read(&path).unwrap());
+    let reconciler = Reconciler::new(editor.clone());
+
+    // Typed with a bare newline, which the save writes as CRLF
+    buffer.insert(0, "c\n").unwrap();
+    editor
+      .save_buffer(buffer.id(), ferrum_fs::PreSaveHooks::default(), false)
+      .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"c\r\na\r\nb\r\n");
+
+    let version = buffer.version();
+    assert!(reconciler.file_modified(&path).unwrap().is_none());
+    assert_eq!(buffer.version(), version);
+    assert_eq!(buffer.to_string(), "c\na\r\nb\r\n");
+  }
+
+  #[test]
+  fn test_dirty_buffer_conflicts() {
+    let (_dir, path, editor, reconciler) = setup("a\nb\nc\n");
+    let buffer = editor.buffer_by_path(&path).unwrap();
+    buffer.insert(0, "A").unwrap();
+    std::fs::write(&path, "a\nb\nC\n").unwrap();
+
+    let Some(Reconciliation::Conflict(conflict)) = reconciler.file_modified(&path).unwrap() else {
+      panic!("expected a conflict");
+    };
+    assert_eq!(conflict.base, "a\nb\nc\n");
+    assert_eq!(buffer.to_string(), "Aa\nb\nc\n");
+
+    let merge = conflict.merge();
+    assert!(merge.is_clean());
+    reconciler
+      .resolve(buffer.id(), ConflictResolution::Merged(merge.text))
+      .unwrap();
+    assert!(reconciler.conflict(buffer.id()).is_none());
+    assert_eq!(buffer.to_string(), "Aa\nb\nC\n");
+    assert_eq!(buffer.saved_content(), "a\nb\nC\n");
+  }
+
+  #[test]
+  fn test_delete_then_create_is_rename() {
+    let (dir, path, editor, reconciler) = setup("fn main() {}\n");
+    let buffer = editor.buffer_by_path(&path).unwrap();
+    let new_path = dir.path().join("lib.rs");
+    std::fs::rename(&path, &new_path).unwrap();
+
+    assert!(matches!(
+      reconciler.file_deleted(&path),
+      Some(Reconciliation::Deleted { .. })
+    ));
+    assert!(matches!(
+      reconciler.file_created(&new_path).unwrap(),
+      Some(Reconciliation::Renamed { .. })
+    ));
+    assert_eq!(buffer.file_path(), Some(new_path.clone()));
+    assert!(editor.is_file_open(&new_path));
+    assert!(!editor.is_file_open(&path));
+  }
+}
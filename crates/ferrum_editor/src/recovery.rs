@@ -0,0 +1,255 @@
+//! Crash recovery and hot exit
+//!
+//! Dirty buffers, untitled ones included, are periodically written to a
+//! recovery directory together with the tail of their undo history. If the
+//! app dies, or exits with hot exit enabled, the entries are still there on
+//! the next launch and the buffers can be recovered exactly as they were.
+//! Entries are removed as soon as their buffer is saved or closed.
+
+use crate::json_store::JsonStore;
+use dashmap::DashMap;
+use ferrum_buffer::format::encoding_for_label;
+use ferrum_buffer::{Buffer, FileFormat, History, LineEnding};
+use ferrum_core::constants::RECOVERY_HISTORY_ENTRIES;
+use ferrum_core::hash::content_hash;
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bumped whenever the entry format changes; older entries are discarded
+const FORMAT_VERSION: u32 = 2;
+
+/// A dirty buffer as persisted for recovery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryEntry {
+  /// ID of the buffer in the session that wrote the entry
+  pub buffer_id: BufferId,
+  /// File the buffer belongs to; `None` for untitled buffers
+  pub path: Option<PathBuf>,
+  pub content: String,
+  pub encoding: String,
+  pub bom: bool,
+  pub line_ending: LineEnding,
+  pub mixed_line_endings: bool,
+  /// Hash of the content last loaded from or saved to disk
+  pub saved_hash: Option<String>,
+  /// Most recent part of the undo history, ending at `content`
+  pub history: History,
+  /// When the entry was written, in milliseconds since the Unix epoch
+  pub timestamp: u64,
+  /// Whether the entry was written on a deliberate hot exit rather than
+  /// by the periodic swap
+  pub hot_exit: bool,
+}
+
+impl RecoveryEntry {
+  /// Check if the file on disk changed since the buffer last saw it
+  ///
+  /// A missing file counts as changed. Untitled buffers never change.
+  pub fn disk_changed(&self) -> bool {
+    let Some(path) = &self.path else {
+      return false;
+    };
+
+    match std::fs::read(path) {
+      Ok(bytes) => {
+        let (disk, _) = ferrum_buffer::format::decode(&bytes);
+        self.saved_hash.as_deref() != Some(content_hash(disk).as_str())
+      },
+      Err(_) => true,
+    }
+  }
+
+  /// Rebuild the file format the buffer was using
+  pub fn file_format(&self) -> FileFormat {
+    let encoding = encoding_for_label(&self.encoding).unwrap_or_else(|e| {
+      warn!("{}; recovering as UTF-8", e);
+      FileFormat::default().encoding
+    });
+
+    FileFormat {
+      encoding,
+      bom: self.bom,
+      line_ending: self.line_ending,
+      mixed_line_endings: self.mixed_line_endings,
+    }
+  }
+}
+
+/// Store of recovery entries for dirty buffers
+#[derive(Debug)]
+pub struct RecoveryStore {
+  entries: JsonStore<RecoveryEntry>,
+  /// Buffer version last written per buffer, to skip unchanged buffers
+  persisted: DashMap<BufferId, u64>,
+}
+
+impl RecoveryStore {
+  /// Create a store that keeps entries in `dir`
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self {
+      entries: JsonStore::new(dir, FORMAT_VERSION, "recovery entry"),
+      persisted: DashMap::new(),
+    }
+  }
+
+  /// Get the store directory
+  pub fn dir(&self) -> &Path {
+    self.entries.dir()
+  }
+
+  /// Persist a buffer if it is dirty, or drop its entry if it is clean
+  ///
+  /// A buffer unchanged since its last write is skipped unless `hot_exit`
+  /// is set, which always rewrites the entry and marks it as such. Large
+  /// files are never persisted. Returns true if an entry was written.
+  pub fn save(&self, buffer: &Buffer, hot_exit: bool) -> Result<bool> {
+    let id = buffer.id();
+    if !buffer.is_dirty() || buffer.is_large_file() {
+      if self.persisted.contains_key(&id) {
+        self.remove(id)?;
+      }
+      return Ok(false);
+    }
+
+    let snapshot = buffer.snapshot();
+    if !hot_exit && self.persisted.get(&id).map(|v| *v) == Some(snapshot.version()) {
+      return Ok(false);
+    }
+
+    // Trim the history, but let the recovered buffer keep its usual limit
+    let mut history = buffer.history();
+    let max_entries = history.max_entries();
+    history.set_max_entries(RECOVERY_HISTORY_ENTRIES);
+    history.set_max_entries(max_entries);
+    let format = buffer.format();
+    let path = snapshot.file_path().cloned();
+    let saved_hash = path.as_ref().map(|_| content_hash(buffer.saved_content()));
+
+    let entry = RecoveryEntry {
+      buffer_id: id,
+      path,
+      content: snapshot.to_string(),
+      encoding: format.encoding_name().to_string(),
+      bom: format.bom,
+      line_ending: format.line_ending,
+      mixed_line_endings: format.mixed_line_endings,
+      saved_hash,
+      history,
+      timestamp: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0),
+      hot_exit,
+    };
+    self.entries.save(&id.to_string(), None, &entry)?;
+
+    self.persisted.insert(id, snapshot.version());
+    Ok(true)
+  }
+
+  /// Read all entries left by previous sessions, oldest first
+  ///
+  /// Unreadable or outdated entries are deleted.
+  pub fn entries(&self) -> Result<Vec<RecoveryEntry>> {
+    let mut entries = self.entries.load_all()?;
+    entries.sort_by_key(|entry| entry.timestamp);
+    Ok(entries)
+  }
+
+  /// Delete the entry for a buffer
+  pub fn remove(&self, id: BufferId) -> Result<()> {
+    self.persisted.remove(&id);
+    self.entries.remove(&id.to_string())
+  }
+
+  /// Delete every entry this session wrote
+  ///
+  /// Entries left by earlier sessions, e.g. by a crash, stay to be
+  /// recovered or discarded by the user.
+  pub fn clear(&self) -> Result<()> {
+    let ids: Vec<BufferId> = self.persisted.iter().map(|entry| *entry.key()).collect();
+    for id in ids {
+      self.remove(id)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_only_dirty_buffers_are_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = RecoveryStore::new(dir.path());
+
+    let buffer = Buffer::with_content("draft");
+    assert!(!store.save(&buffer, false).unwrap());
+
+    buffer.insert(5, " notes").unwrap();
+    assert!(store.save(&buffer, false).unwrap());
+    // Unchanged since the last write
+    assert!(!store.save(&buffer, false).unwrap());
+
+    let entries = store.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].content, "draft notes");
+    assert_eq!(entries[0].path, None);
+
+    buffer.mark_saved();
+    store.save(&buffer, false).unwrap();
+    assert!(store.entries().unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_history_is_trimmed_without_keeping_the_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = RecoveryStore::new(dir.path());
+
+    let buffer = Buffer::new();
+    for i in 0..RECOVERY_HISTORY_ENTRIES + 10 {
+      buffer
+        .transact(|tx| {
+          tx.insert(i, "a");
+        })
+        .unwrap();
+    }
+    store.save(&buffer, false).unwrap();
+
+    let history = &store.entries().unwrap()[0].history;
+    assert_eq!(history.undo_count(), RECOVERY_HISTORY_ENTRIES);
+    assert_eq!(history.max_entries(), buffer.history().max_entries());
+  }
+
+  #[test]
+  fn test_clear_keeps_entries_of_earlier_sessions() {
+    let dir = tempfile::tempdir().unwrap();
+    let crashed = RecoveryStore::new(dir.path());
+    let left_behind = Buffer::with_content("draft");
+    left_behind.insert(5, " notes").unwrap();
+    crashed.save(&left_behind, false).unwrap();
+
+    let store = RecoveryStore::new(dir.path());
+    let buffer = Buffer::with_content("todo");
+    buffer.insert(4, " list").unwrap();
+    store.save(&buffer, false).unwrap();
+    store.clear().unwrap();
+
+    let entries = store.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].buffer_id, left_behind.id());
+  }
+
+  #[test]
+  fn test_unreadable_entries_are_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("garbage.json"), "{").unwrap();
+
+    let store = RecoveryStore::new(dir.path());
+    assert!(store.entries().unwrap().is_empty());
+    assert!(!dir.path().join("garbage.json").exists());
+  }
+}
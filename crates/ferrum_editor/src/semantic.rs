@@ -0,0 +1,128 @@
+//! Semantic highlighting
+//!
+//! Tree-sitter highlights are ready as soon as a buffer is parsed. Semantic
+//! tokens from a language server arrive later and know more, such as which
+//! variables are mutable, which calls are unsafe or which methods come from
+//! a trait, so they are layered over the tree-sitter highlights they cover.
+//! Until fresh tokens arrive, edits shift the old ones and drop those they
+//! touch, leaving tree-sitter as the fallback there.
+
+use ferrum_buffer::Change;
+use ferrum_buffer::syntax::Highlight;
+use ferrum_lsp::SemanticToken;
+
+/// The semantic tokens of a buffer
+#[derive(Debug, Clone)]
+pub(crate) struct SemanticLayer {
+  /// Buffer version the token ranges refer to
+  pub(crate) version: u64,
+  /// Tokens in document order
+  pub(crate) tokens: Vec<SemanticToken>,
+}
+
+impl SemanticLayer {
+  /// Move the tokens through changes made to the buffer
+  pub(crate) fn apply_changes(&mut self, changes: &[Change], version: u64) {
+    for change in changes {
+      self.shift(change.start_byte, change.old_end_byte, change.new_end_byte);
+    }
+    self.version = version;
+  }
+
+  /// Move the tokens after an edit and drop those it touches
+  pub(crate) fn shift(&mut self, start: usize, old_end: usize, new_end: usize) {
+    self.tokens.retain_mut(|token| {
+      if token.range.end <= start {
+        true
+      } else if token.range.start >= old_end {
+        token.range = token.range.start - old_end + new_end..token.range.end - old_end + new_end;
+        true
+      } else {
+        false
+      }
+    });
+  }
+}
+
+/// Layer semantic tokens over tree-sitter highlights
+///
+/// Highlights inside a token with a highlight kind are replaced by it;
+/// highlights enclosing tokens, and tokens without a highlight kind, are
+/// kept. `tokens` must be in document order and not overlap.
+pub fn merge_semantic_tokens(
+  mut highlights: Vec<Highlight>,
+  tokens: &[SemanticToken],
+) -> Vec<Highlight> {
+  let tokens: Vec<(&SemanticToken, _)> = tokens
+    .iter()
+    .filter_map(|token| Some((token, token.highlight_kind()?)))
+    .collect();
+  if tokens.is_empty() {
+    return highlights;
+  }
+
+  highlights.retain(|h| {
+    let i = tokens.partition_point(|(token, _)| token.range.start <= h.start);
+    i == 0 || tokens[i - 1].0.range.end < h.end
+  });
+  highlights.extend(tokens.into_iter().map(|(token, kind)| Highlight {
+    modifiers: token.modifiers.clone(),
+    ..Highlight::new(token.range.start, token.range.end, kind)
+  }));
+  highlights.sort_by_key(|h| (h.start, std::cmp::Reverse(h.end)));
+
+  highlights
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ferrum_buffer::syntax::HighlightKind;
+
+  fn token(range: std::ops::Range<usize>, token_type: &str, modifiers: &[&str]) -> SemanticToken {
+    SemanticToken {
+      range,
+      token_type: token_type.to_string(),
+      modifiers: modifiers.iter().map(|m| m.to_string()).collect(),
+    }
+  }
+
+  #[test]
+  fn test_tokens_replace_covered_highlights() {
+    // let mut count = 0;
+    let highlights = vec![
+      Highlight::new(0, 18, HighlightKind::Operator),
+      Highlight::new(0, 3, HighlightKind::Keyword),
+      Highlight::new(8, 13, HighlightKind::Variable),
+    ];
+    let tokens = [
+      token(4..7, "keyword", &[]),
+      token(8..13, "variable", &["declaration", "mutable"]),
+      token(16..17, "punctuation", &[]),
+    ];
+
+    let merged = merge_semantic_tokens(highlights, &tokens);
+    assert_eq!(merged.len(), 4);
+    assert_eq!(merged[0], Highlight::new(0, 18, HighlightKind::Operator));
+    assert_eq!(merged[2], Highlight::new(4, 7, HighlightKind::Keyword));
+    assert_eq!(merged[3].kind, HighlightKind::Variable);
+    assert_eq!(merged[3].modifiers, vec!["declaration", "mutable"]);
+  }
+
+  #[test]
+  fn test_edits_shift_and_drop_tokens() {
+    let mut layer = SemanticLayer {
+      version: 1,
+      tokens: vec![
+        token(0..3, "variable", &[]),
+        token(5..8, "function", &[]),
+        token(10..12, "variable", &[]),
+      ],
+    };
+
+    // Replace bytes 6..7 with three bytes
+    layer.shift(6, 7, 9);
+    let ranges: Vec<_> = layer.tokens.iter().map(|t| t.range.clone()).collect();
+    assert_eq!(ranges, vec![0..3, 12..14]);
+  }
+}
@@ -0,0 +1,146 @@
+//! Persistent undo history
+//!
+//! Undo history is stored per file under a directory in the app data dir,
+//! so it survives closing the buffer or restarting the app. Each entry
+//! records a hash of the content last saved to the file, with the history
+//! moved to that state and any unsaved edits left to redo; history is only
+//! restored when the file on disk still has exactly that content.
+
+use crate::json_store::JsonStore;
+use ferrum_buffer::{Buffer, History};
+use ferrum_core::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever the stored format changes; older entries are discarded
+const FORMAT_VERSION: u32 = 2;
+
+/// Per-file undo history store
+#[derive(Debug, Clone)]
+pub struct UndoStore {
+  entries: JsonStore<History>,
+}
+
+impl UndoStore {
+  /// Create a store that keeps entries in `dir`
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self {
+      entries: JsonStore::new(dir, FORMAT_VERSION, "undo history"),
+    }
+  }
+
+  /// Get the store directory
+  pub fn dir(&self) -> &Path {
+    self.entries.dir()
+  }
+
+  /// Persist a buffer's undo history
+  ///
+  /// Buffers without a file path or without any history are skipped, as
+  /// are large files, whose content is too costly to hash on every close.
+  pub fn save(&self, buffer: &Buffer) -> Result<()> {
+    let Some(path) = buffer.file_path() else {
+      return Ok(());
+    };
+    if buffer.is_large_file() {
+      return Ok(());
+    }
+
+    let history = match buffer.saved_history() {
+      Some(history) if !history.is_empty() => history,
+      _ => return self.remove(&path),
+    };
+    self.entries.save(
+      &path.to_string_lossy(),
+      Some(&buffer.saved_content()),
+      &history,
+    )
+  }
+
+  /// Restore a buffer's undo history if its content still matches
+  ///
+  /// Returns true if history was restored. A stale or unreadable entry is
+  /// deleted and the buffer keeps an empty history.
+  pub fn restore(&self, buffer: &Buffer) -> Result<bool> {
+    let Some(path) = buffer.file_path() else {
+      return Ok(false);
+    };
+    if buffer.is_large_file() {
+      return Ok(false);
+    }
+
+    match self
+      .entries
+      .load(&path.to_string_lossy(), Some(&buffer.to_string()))?
+    {
+      Some(history) => {
+        buffer.restore_history(history);
+        Ok(true)
+      },
+      None => Ok(false),
+    }
+  }
+
+  /// Delete the stored history for a file
+  pub fn remove(&self, path: &Path) -> Result<()> {
+    self.entries.remove(&path.to_string_lossy())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_restore_matching_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = UndoStore::new(dir.path());
+
+    let buffer = Buffer::from_file("/project/main.rs", "fn main() {}");
+    buffer.insert(11, " ").unwrap();
+    buffer.mark_saved();
+    store.save(&buffer).unwrap();
+
+    let reopened = Buffer::from_file("/project/main.rs", "fn main() { }");
+    assert!(store.restore(&reopened).unwrap());
+
+    reopened.undo();
+    assert_eq!(reopened.to_string(), "fn main() {}");
+  }
+
+  #[test]
+  fn test_unsaved_edits_are_kept_to_redo() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = UndoStore::new(dir.path());
+
+    let buffer = Buffer::from_file("/project/main.rs", "fn main() {}");
+    buffer.insert(11, " ").unwrap();
+    buffer.mark_saved();
+    buffer.insert(0, "pub ").unwrap();
+    store.save(&buffer).unwrap();
+
+    // Closed without saving, so the file has the saved content
+    let reopened = Buffer::from_file("/project/main.rs", "fn main() { }");
+    assert!(store.restore(&reopened).unwrap());
+    reopened.redo();
+    assert_eq!(reopened.to_string(), "pub fn main() { }");
+    reopened.undo();
+    reopened.undo();
+    assert_eq!(reopened.to_string(), "fn main() {}");
+  }
+
+  #[test]
+  fn test_discard_on_content_mismatch() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = UndoStore::new(dir.path());
+
+    let buffer = Buffer::from_file("/project/main.rs", "fn main() {}");
+    buffer.insert(11, " ").unwrap();
+    store.save(&buffer).unwrap();
+
+    // The file changed outside the editor since the history was saved
+    let reopened = Buffer::from_file("/project/main.rs", "fn other() {}");
+    assert!(!store.restore(&reopened).unwrap());
+    assert!(reopened.undo().is_none());
+    assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+  }
+}
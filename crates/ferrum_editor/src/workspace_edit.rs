@@ -0,0 +1,773 @@
+//! Applying LSP workspace edits
+//!
+//! Renames, code actions and servers' `workspace/applyEdit` requests hand
+//! over a `WorkspaceEdit`: text edits for any number of files, possibly
+//! mixed with file creations, renames and deletions that must happen in
+//! order. Text edits land in a file's buffer if it is open and are written
+//! to disk otherwise; either way each file's edits become one step, so a
+//! single undo takes a buffer back. Edits made against a version of a
+//! document the server no longer has are refused before anything changes,
+//! as are edits of files outside the project that aren't open. Operations
+//! that destroy data, such as deleting a directory with its contents, are
+//! for the caller to confirm with the user, see `destructive_operations`.
+
+use crate::editor::Editor;
+use ferrum_buffer::diff;
+use ferrum_buffer::{Buffer, Change};
+use ferrum_core::prelude::*;
+use ferrum_fs::DiskState;
+use ferrum_lsp::types::{range_to_chars, uri_to_path};
+use lsp_types::{
+  AnnotatedTextEdit, DocumentChangeOperation, DocumentChanges, OneOf, ResourceOp, TextDocumentEdit,
+  TextEdit, Uri, WorkspaceEdit,
+};
+use ropey::Rope;
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// What applying a workspace edit changed
+#[derive(Debug, Clone, Default)]
+pub struct AppliedEdit {
+  /// Open buffers that were edited, with their changes
+  pub changes: Vec<(BufferId, Vec<Change>)>,
+  /// Files created, or edited on disk because they weren't open
+  pub written: Vec<PathBuf>,
+  /// Buffers whose file was renamed, with their new path
+  pub moved: Vec<(BufferId, PathBuf)>,
+  /// Buffers closed because their file was deleted
+  pub closed: Vec<BufferId>,
+}
+
+/// A step of a workspace edit
+enum Operation<'a> {
+  /// Text edits of a document, with the version they were made against
+  Edit {
+    uri: &'a Uri,
+    version: Option<i32>,
+    edits: Vec<&'a TextEdit>,
+  },
+  /// A file creation, rename or deletion
+  Resource(&'a ResourceOp),
+}
+
+/// The text edits of one file, gathered until they are applied
+struct FileEdits {
+  path: PathBuf,
+  /// The file's open buffer, or one loaded from disk to edit it
+  buffer: Arc<Buffer>,
+  /// Disk state of a file that isn't open, checked when it is written
+  disk: Option<DiskState>,
+  /// The text after the edits so far
+  text: Rope,
+  /// The first batch of edits, in chars of the buffer's text
+  edits: Vec<(Range<usize>, String)>,
+  /// Number of batches gathered
+  batches: usize,
+}
+
+/// Applies LSP workspace edits to the editor's buffers and the files on
+/// disk
+pub struct WorkspaceEditApplier {
+  editor: Arc<Editor>,
+}
+
+impl WorkspaceEditApplier {
+  /// Create an applier for the editor's buffers
+  pub fn new(editor: Arc<Editor>) -> Self {
+    Self { editor }
+  }
+
+  /// Get the editor whose buffers are edited
+  pub fn editor(&self) -> &Arc<Editor> {
+    &self.editor
+  }
+
+  /// Apply a workspace edit
+  ///
+  /// Operations run in order, but text edits are held back until the next
+  /// file operation or the end, so that each file's edits land together.
+  /// If the edit fails part way, what was done before stays done, which is
+  /// the `abort` failure handling advertised to servers.
+  pub fn apply(&self, edit: &WorkspaceEdit) -> Result<AppliedEdit> {
+    let operations = operations(edit);
+    self.check_paths(&operations)?;
+    self.check_versions(&operations)?;
+
+    let mut applied = AppliedEdit::default();
+    let mut files = Vec::new();
+    for operation in operations {
+      match operation {
+        Operation::Edit { uri, edits, .. } => self.gather(&mut files, path_of(uri)?, &edits)?,
+        Operation::Resource(op) => {
+          self.flush(&mut files, &mut applied)?;
+          self.resource(op, &mut applied)?;
+        },
+      }
+    }
+    self.flush(&mut files, &mut applied)?;
+
+    Ok(applied)
+  }
+
+  /// Refuse the edit if it touches a file outside the project, other than
+  /// by editing the text of an open buffer
+  fn check_paths(&self, operations: &[Operation]) -> Result<()> {
+    let root = self.editor.project_root().and_then(|root| resolve(&root));
+    let check = |path: PathBuf| match (&root, resolve(&path)) {
+      (Some(root), Some(resolved)) if resolved.starts_with(root) => Ok(()),
+      _ => Err(Error::OutsideProject { path }),
+    };
+
+    for operation in operations {
+      match operation {
+        Operation::Edit { uri, .. } => {
+          let path = path_of(uri)?;
+          if self.editor.buffer_by_path(&path).is_none() {
+            check(path)?;
+          }
+        },
+        Operation::Resource(ResourceOp::Create(create)) => check(path_of(&create.uri)?)?,
+        Operation::Resource(ResourceOp::Rename(rename)) => {
+          check(path_of(&rename.old_uri)?)?;
+          check(path_of(&rename.new_uri)?)?;
+        },
+        Operation::Resource(ResourceOp::Delete(delete)) => check(path_of(&delete.uri)?)?,
+      }
+    }
+    Ok(())
+  }
+
+  /// Refuse the edit if a document it edits changed since the version its
+  /// edits were made against
+  fn check_versions(&self, operations: &[Operation]) -> Result<()> {
+    let sync = self.editor.document_sync();
+    for operation in operations {
+      let Operation::Edit {
+        uri,
+        version: Some(version),
+        ..
+      } = operation
+      else {
+        continue;
+      };
+
+      let path = path_of(uri)?;
+      let current = match (&sync, self.editor.buffer_by_path(&path)) {
+        (Some(sync), Some(buffer)) => sync.is_current(&path, *version, buffer.snapshot().rope()),
+        _ => false,
+      };
+      if !current {
+        return Err(Error::LspStaleEdit {
+          path,
+          version: *version,
+        });
+      }
+    }
+    Ok(())
+  }
+
+  /// Add a batch of text edits, all against the same text, to those of a
+  /// file
+  fn gather(&self, files: &mut Vec<FileEdits>, path: PathBuf, edits: &[&TextEdit]) -> Result<()> {
+    let index = match files.iter().position(|file| file.path == path) {
+      Some(index) => index,
+      None => {
+        files.push(self.load(path)?);
+        files.len() - 1
+      },
+    };
+    let file = &mut files[index];
+
+    let mut resolved: Vec<(Range<usize>, String)> = edits
+      .iter()
+      .map(|edit| {
+        let range = range_to_chars(&file.text, edit.range);
+        (range, edit.new_text.clone())
+      })
+      .collect();
+    // Stable, so inserts at one position keep their order
+    resolved.sort_by_key(|(range, _)| (range.start, range.end));
+    let mut prev_end = 0;
+    for (range, _) in &resolved {
+      if range.start > range.end || range.start < prev_end {
+        return Err(Error::InvalidRange {
+          start: range.start,
+          end: range.end,
+        });
+      }
+      prev_end = range.end;
+    }
+
+    for (range, text) in resolved.iter().rev() {
+      file.text.remove(range.clone());
+      file.text.insert(range.start, text);
+    }
+    if file.batches == 0 {
+      file.edits = resolved;
+    }
+    file.batches += 1;
+    Ok(())
+  }
+
+  /// Start gathering the edits of a file, from its open buffer or from disk
+  fn load(&self, path: PathBuf) -> Result<FileEdits> {
+    let (buffer, disk) = match self.editor.buffer_by_path(&path) {
+      Some(buffer) => (buffer, None),
+      None => {
+        let bytes = fs::read(&path).map_err(|e| match e.kind() {
+          IoErrorKind::NotFound => Error::FileNotFound { path: path.clone() },
+          _ => e.into(),
+        })?;
+        let disk = DiskState::from_bytes(&path, &bytes);
+        (Arc::new(Buffer::from_file_bytes(&path, &bytes)), Some(disk))
+      },
+    };
+    let text = buffer.snapshot().rope().clone();
+
+    Ok(FileEdits {
+      path,
+      buffer,
+      disk,
+      text,
+      edits: Vec::new(),
+      batches: 0,
+    })
+  }
+
+  /// Apply the gathered text edits, each file's as one transaction
+  fn flush(&self, files: &mut Vec<FileEdits>, applied: &mut AppliedEdit) -> Result<()> {
+    for file in files.drain(..) {
+      // Later batches were made against the text after the earlier ones, so
+      // several are turned into edits of the buffer's text by a diff
+      let edits = match file.batches {
+        1 => file.edits,
+        _ => diff::diff(&file.buffer.to_string(), &file.text.to_string())
+          .into_iter()
+          .map(|edit| (edit.start..edit.end, edit.text))
+          .collect(),
+      };
+      let changes = file.buffer.transact(|tx| {
+        for (range, text) in edits {
+          tx.replace(range.start, range.end, text);
+        }
+      })?;
+      if changes.is_empty() {
+        continue;
+      }
+
+      match file.disk {
+        Some(disk) => {
+          ferrum_fs::save::save(&file.path, &file.buffer.encode()?, Some(&disk))?;
+          applied.written.push(file.path);
+        },
+        None => {
+          let id = file.buffer.id();
+          self.editor.update_syntax_after_changes(id, &changes)?;
+          applied.changes.push((id, changes));
+        },
+      }
+    }
+    Ok(())
+  }
+
+  /// Create, rename or delete a file or directory
+  fn resource(&self, op: &ResourceOp, applied: &mut AppliedEdit) -> Result<()> {
+    match op {
+      ResourceOp::Create(create) => {
+        let path = path_of(&create.uri)?;
+        let options = create.options.as_ref();
+        let overwrite = options.and_then(|o| o.overwrite).unwrap_or(false);
+        let ignore_if_exists = options.and_then(|o| o.ignore_if_exists).unwrap_or(false);
+        if !vacate(&path, overwrite, ignore_if_exists)? {
+          return Ok(());
+        }
+
+        if let Some(parent) = path.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, "")?;
+        applied.written.push(path);
+      },
+      ResourceOp::Rename(rename) => {
+        let from = path_of(&rename.old_uri)?;
+        let to = path_of(&rename.new_uri)?;
+        let options = rename.options.as_ref();
+        let overwrite = options.and_then(|o| o.overwrite).unwrap_or(false);
+        let ignore_if_exists = options.and_then(|o| o.ignore_if_exists).unwrap_or(false);
+        if !from.exists() {
+          return Err(Error::FileNotFound { path: from });
+        }
+        if !vacate(&to, overwrite, ignore_if_exists)? {
+          return Ok(());
+        }
+
+        if let Some(parent) = to.parent() {
+          fs::create_dir_all(parent)?;
+        }
+        fs::rename(&from, &to)?;
+
+        // Buffers of the file, or of the files in a renamed directory,
+        // follow it
+        for (id, path) in self.buffers_under(&from) {
+          let rest = path.strip_prefix(&from).unwrap_or(Path::new(""));
+          let new_path = match rest.as_os_str().is_empty() {
+            true => to.clone(),
+            false => to.join(rest),
+          };
+          self.editor.set_buffer_path(id, &new_path)?;
+          applied.moved.push((id, new_path));
+        }
+      },
+      ResourceOp::Delete(delete) => {
+        let path = path_of(&delete.uri)?;
+        let options = delete.options.as_ref();
+        let recursive = options.and_then(|o| o.recursive).unwrap_or(false);
+        let ignore_if_not_exists = options
+          .and_then(|o| o.ignore_if_not_exists)
+          .unwrap_or(false);
+
+        // Closing the buffers would throw their changes away
+        let buffers = self.buffers_under(&path);
+        for (id, file) in &buffers {
+          if self
+            .editor
+            .buffer(*id)
+            .is_some_and(|buffer| buffer.is_dirty())
+          {
+            return Err(Error::UnsavedChanges { path: file.clone() });
+          }
+        }
+
+        match fs::symlink_metadata(&path) {
+          Err(e) if e.kind() == IoErrorKind::NotFound => {
+            return match ignore_if_not_exists {
+              true => Ok(()),
+              false => Err(Error::FileNotFound { path }),
+            };
+          },
+          Err(e) => return Err(e.into()),
+          Ok(metadata) if metadata.is_dir() && recursive => fs::remove_dir_all(&path)?,
+          Ok(metadata) if metadata.is_dir() => fs::remove_dir(&path)?,
+          Ok(_) => fs::remove_file(&path)?,
+        }
+
+        for (id, _) in buffers {
+          self.editor.close_buffer(id);
+          applied.closed.push(id);
+        }
+      },
+    }
+    Ok(())
+  }
+
+  /// Get the buffers of a file, or of the files in a directory
+  fn buffers_under(&self, path: &Path) -> Vec<(BufferId, PathBuf)> {
+    self
+      .editor
+      .buffer_ids()
+      .into_iter()
+      .filter_map(|id| Some((id, self.editor.buffer(id)?.file_path()?)))
+      .filter(|(_, file)| file.starts_with(path))
+      .collect()
+  }
+}
+
+/// Get the steps of a workspace edit in order
+///
+/// `document_changes` is preferred over `changes`, as the spec asks.
+fn operations(edit: &WorkspaceEdit) -> Vec<Operation<'_>> {
+  fn document_edit(edit: &TextDocumentEdit) -> Operation<'_> {
+    Operation::Edit {
+      uri: &edit.text_document.uri,
+      version: edit.text_document.version,
+      edits: edit
+        .edits
+        .iter()
+        .map(|edit| match edit {
+          OneOf::Left(edit) => edit,
+          OneOf::Right(AnnotatedTextEdit { text_edit, .. }) => text_edit,
+        })
+        .collect(),
+    }
+  }
+
+  match (&edit.document_changes, &edit.changes) {
+    (Some(DocumentChanges::Edits(edits)), _) => edits.iter().map(document_edit).collect(),
+    (Some(DocumentChanges::Operations(operations)), _) => operations
+      .iter()
+      .map(|operation| match operation {
+        DocumentChangeOperation::Edit(edit) => document_edit(edit),
+        DocumentChangeOperation::Op(op) => Operation::Resource(op),
+      })
+      .collect(),
+    (None, Some(changes)) => {
+      let mut operations: Vec<_> = changes
+        .iter()
+        .map(|(uri, edits)| Operation::Edit {
+          uri,
+          version: None,
+          edits: edits.iter().collect(),
+        })
+        .collect();
+      operations.sort_by_key(|operation| match operation {
+        Operation::Edit { uri, .. } => uri.as_str(),
+        Operation::Resource(_) => "",
+      });
+      operations
+    },
+    (None, None) => Vec::new(),
+  }
+}
+
+/// Get the file operations of a workspace edit that destroy data: deleting
+/// a directory with its contents, or creating or renaming over a file
+pub fn destructive_operations(edit: &WorkspaceEdit) -> Vec<&ResourceOp> {
+  operations(edit)
+    .into_iter()
+    .filter_map(|operation| match operation {
+      Operation::Resource(op) => Some(op),
+      Operation::Edit { .. } => None,
+    })
+    .filter(|op| {
+      match op {
+        ResourceOp::Create(create) => create.options.as_ref().and_then(|o| o.overwrite),
+        ResourceOp::Rename(rename) => rename.options.as_ref().and_then(|o| o.overwrite),
+        ResourceOp::Delete(delete) => delete.options.as_ref().and_then(|o| o.recursive),
+      }
+      .unwrap_or(false)
+    })
+    .collect()
+}
+
+/// Get the path of a file URI
+fn path_of(uri: &Uri) -> Result<PathBuf> {
+  uri_to_path(uri).ok_or_else(|| Error::Internal(format!("Not a file URI: {}", uri.as_str())))
+}
+
+/// Resolve symlinks and `..` in a path that may not exist yet, by
+/// resolving the part of it that does
+///
+/// `None` if nothing of the path exists, or if `..` follows a part that
+/// doesn't.
+fn resolve(path: &Path) -> Option<PathBuf> {
+  let mut existing = path;
+  let mut rest = Vec::new();
+  while fs::symlink_metadata(existing).is_err() {
+    rest.push(existing.file_name()?);
+    existing = existing.parent()?;
+  }
+
+  let mut resolved = existing.canonicalize().ok()?;
+  resolved.extend(rest.iter().rev());
+  Some(resolved)
+}
+
+/// Check a file can be created or renamed over, returning false if the
+/// operation should be skipped
+///
+/// `overwrite` wins over `ignore_if_exists`, as the spec asks.
+fn vacate(path: &Path, overwrite: bool, ignore_if_exists: bool) -> Result<bool> {
+  if !path.exists() || overwrite {
+    return Ok(true);
+  }
+  match ignore_if_exists {
+    true => Ok(false),
+    false => Err(Error::FileExists {
+      path: path.to_path_buf(),
+    }),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ferrum_lsp::types::path_to_uri;
+  use lsp_types::{
+    CreateFile, DeleteFile, OptionalVersionedTextDocumentIdentifier, Position, Range as LspRange,
+    RenameFile,
+  };
+  use std::collections::HashMap;
+
+  fn text_edit(line: u32, start: u32, end: u32, text: &str) -> TextEdit {
+    TextEdit::new(
+      LspRange::new(Position::new(line, start), Position::new(line, end)),
+      text.to_string(),
+    )
+  }
+
+  fn document_edit(path: &Path, version: Option<i32>, edits: Vec<TextEdit>) -> TextDocumentEdit {
+    TextDocumentEdit {
+      text_document: OptionalVersionedTextDocumentIdentifier {
+        uri: path_to_uri(path).unwrap(),
+        version,
+      },
+      edits: edits.into_iter().map(OneOf::Left).collect(),
+    }
+  }
+
+  fn setup() -> (tempfile::TempDir, Arc<Editor>, WorkspaceEditApplier) {
+    let dir = tempfile::tempdir().unwrap();
+    let editor = Arc::new(Editor::new());
+    editor.set_project_root(dir.path().to_path_buf()).unwrap();
+    let applier = WorkspaceEditApplier::new(editor.clone());
+    (dir, editor, applier)
+  }
+
+  #[test]
+  fn test_edits_land_as_one_step_per_file() {
+    let (dir, editor, applier) = setup();
+    let open = dir.path().join("open.rs");
+    let closed = dir.path().join("closed.rs");
+    fs::write(&open, "fn old() {}\nold();\n").unwrap();
+    fs::write(&closed, "use crate::old;\n").unwrap();
+    let buffer = editor.open_file(&open, "fn old() {}\nold();\n");
+
+    let edit = WorkspaceEdit::new(HashMap::from([
+      (
+        path_to_uri(&open).unwrap(),
+        vec![
+          text_edit(1, 0, 3, "new"),
+          text_edit(0, 3, 6, "new"),
+          // Past the end of the line, so at its end
+          text_edit(0, 11, 99, " // renamed"),
+        ],
+      ),
+      (
+        path_to_uri(&closed).unwrap(),
+        vec![text_edit(0, 11, 14, "new")],
+      ),
+    ]));
+
+    let applied = applier.apply(&edit).unwrap();
+    assert_eq!(buffer.to_string(), "fn new() {} // renamed\nnew();\n");
+    assert_eq!(applied.changes.len(), 1);
+    assert_eq!(applied.changes[0].0, buffer.id());
+    assert_eq!(applied.written, vec![closed.clone()]);
+    assert_eq!(fs::read_to_string(&closed).unwrap(), "use crate::new;\n");
+    // The open file is left for the user to save
+    assert_eq!(fs::read_to_string(&open).unwrap(), "fn old() {}\nold();\n");
+
+    buffer.undo();
+    assert_eq!(buffer.to_string(), "fn old() {}\nold();\n");
+
+    // Overlapping edits are refused
+    let edit = WorkspaceEdit::new(HashMap::from([(
+      path_to_uri(&open).unwrap(),
+      vec![text_edit(0, 0, 5, "a"), text_edit(0, 3, 8, "b")],
+    )]));
+    assert!(matches!(
+      applier.apply(&edit),
+      Err(Error::InvalidRange { .. })
+    ));
+  }
+
+  #[test]
+  fn test_file_operations_run_in_order() {
+    let (dir, editor, applier) = setup();
+    let lib = dir.path().join("lib.rs");
+    let module = dir.path().join("module.rs");
+    let renamed = dir.path().join("src/renamed.rs");
+    let created = dir.path().join("src/created.rs");
+    let deleted = dir.path().join("deleted.rs");
+    fs::write(&lib, "mod module;\n").unwrap();
+    fs::write(&module, "fn f() {}\n").unwrap();
+    fs::write(&deleted, "").unwrap();
+    let lib_buffer = editor.open_file(&lib, "mod module;\n");
+    let module_buffer = editor.open_file(&module, "fn f() {}\n");
+    let deleted_buffer = editor.open_file(&deleted, "");
+
+    let uri = |path: &Path| path_to_uri(path).unwrap();
+    let edit = WorkspaceEdit {
+      document_changes: Some(DocumentChanges::Operations(vec![
+        DocumentChangeOperation::Edit(document_edit(
+          &lib,
+          None,
+          vec![text_edit(0, 4, 10, "renamed")],
+        )),
+        DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+          old_uri: uri(&module),
+          new_uri: uri(&renamed),
+          options: None,
+          annotation_id: None,
+        })),
+        // Edits after the rename are against the new path
+        DocumentChangeOperation::Edit(document_edit(&renamed, None, vec![text_edit(0, 3, 4, "g")])),
+        DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+          uri: uri(&created),
+          options: None,
+          annotation_id: None,
+        })),
+        DocumentChangeOperation::Edit(document_edit(
+          &created,
+          None,
+          vec![text_edit(0, 0, 0, "fn h() {}\n")],
+        )),
+        DocumentChangeOperation::Edit(document_edit(
+          &lib,
+          None,
+          vec![text_edit(1, 0, 0, "mod created;\n")],
+        )),
+        DocumentChangeOperation::Op(ResourceOp::Delete(DeleteFile {
+          uri: uri(&deleted),
+          options: None,
+        })),
+      ])),
+      ..Default::default()
+    };
+
+    let applied = applier.apply(&edit).unwrap();
+    assert_eq!(lib_buffer.to_string(), "mod renamed;\nmod created;\n");
+    assert_eq!(module_buffer.file_path(), Some(renamed.clone()));
+    assert_eq!(module_buffer.to_string(), "fn g() {}\n");
+    assert_eq!(applied.moved, vec![(module_buffer.id(), renamed.clone())]);
+    assert!(!module.exists());
+    assert_eq!(fs::read_to_string(&created).unwrap(), "fn h() {}\n");
+    assert!(!deleted.exists());
+    assert_eq!(applied.closed, vec![deleted_buffer.id()]);
+    assert!(editor.buffer(deleted_buffer.id()).is_none());
+
+    // Creating over an existing file fails unless told what to do
+    let create = |options| WorkspaceEdit {
+      document_changes: Some(DocumentChanges::Operations(vec![
+        DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+          uri: uri(&created),
+          options,
+          annotation_id: None,
+        })),
+      ])),
+      ..Default::default()
+    };
+    assert!(matches!(
+      applier.apply(&create(None)),
+      Err(Error::FileExists { .. })
+    ));
+    applier
+      .apply(&create(Some(lsp_types::CreateFileOptions {
+        overwrite: None,
+        ignore_if_exists: Some(true),
+      })))
+      .unwrap();
+    assert_eq!(fs::read_to_string(&created).unwrap(), "fn h() {}\n");
+  }
+
+  #[test]
+  fn test_stale_versions_are_refused() {
+    let (dir, editor, applier) = setup();
+    let path = dir.path().join("main.rs");
+    fs::write(&path, "let x = 1;\n").unwrap();
+    let buffer = editor.open_file(&path, "let x = 1;\n");
+
+    // Not open in a server, so no version of it can be current
+    let edit = WorkspaceEdit {
+      document_changes: Some(DocumentChanges::Edits(vec![document_edit(
+        &path,
+        Some(3),
+        vec![text_edit(0, 4, 5, "y")],
+      )])),
+      ..Default::default()
+    };
+    assert!(matches!(
+      applier.apply(&edit),
+      Err(Error::LspStaleEdit { version: 3, .. })
+    ));
+    assert_eq!(buffer.to_string(), "let x = 1;\n");
+  }
+  #[test]
+  fn test_files_outside_the_project_are_refused() {
+    let (dir, editor, applier) = setup();
+    let outside = tempfile::tempdir().unwrap();
+    let open = outside.path().join("open.rs");
+    let closed = outside.path().join("closed.rs");
+    fs::write(&closed, "fn f() {}\n").unwrap();
+    let buffer = editor.open_file(&open, "fn f() {}\n");
+
+    // The text of an open buffer can be edited wherever its file is
+    let edit = WorkspaceEdit::new(HashMap::from([(
+      path_to_uri(&open).unwrap(),
+      vec![text_edit(0, 3, 4, "g")],
+    )]));
+    applier.apply(&edit).unwrap();
+    assert_eq!(buffer.to_string(), "fn g() {}\n");
+
+    let uri = |path: &Path| path_to_uri(path).unwrap();
+    let escaping = dir
+      .path()
+      .join("../")
+      .join(outside.path().file_name().unwrap());
+    for op in [
+      ResourceOp::Delete(DeleteFile {
+        uri: uri(outside.path()),
+        options: None,
+      }),
+      ResourceOp::Delete(DeleteFile {
+        uri: uri(&escaping.join("closed.rs")),
+        options: None,
+      }),
+      ResourceOp::Create(CreateFile {
+        uri: uri(&escaping.join("new.rs")),
+        options: None,
+        annotation_id: None,
+      }),
+    ] {
+      let edit = WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(vec![
+          DocumentChangeOperation::Op(op),
+        ])),
+        ..Default::default()
+      };
+      assert!(matches!(
+        applier.apply(&edit),
+        Err(Error::OutsideProject { .. })
+      ));
+    }
+    let edit = WorkspaceEdit::new(HashMap::from([(
+      uri(&closed),
+      vec![text_edit(0, 3, 4, "g")],
+    )]));
+    assert!(matches!(
+      applier.apply(&edit),
+      Err(Error::OutsideProject { .. })
+    ));
+    assert_eq!(fs::read_to_string(&closed).unwrap(), "fn f() {}\n");
+    assert!(!outside.path().join("new.rs").exists());
+  }
+
+  #[test]
+  fn test_dirty_buffers_are_not_deleted() {
+    let (dir, editor, applier) = setup();
+    let path = dir.path().join("src/main.rs");
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::write(&path, "fn main() {}\n").unwrap();
+    let buffer = editor.open_file(&path, "fn main() {}\n");
+    editor.edit(buffer.id(), 0, 0, "// draft\n").unwrap();
+
+    let delete = WorkspaceEdit {
+      document_changes: Some(DocumentChanges::Operations(vec![
+        DocumentChangeOperation::Op(ResourceOp::Delete(DeleteFile {
+          uri: path_to_uri(&dir.path().join("src")).unwrap(),
+          options: Some(lsp_types::DeleteFileOptions {
+            recursive: Some(true),
+            ignore_if_not_exists: None,
+            annotation_id: None,
+          }),
+        })),
+      ])),
+      ..Default::default()
+    };
+    assert_eq!(destructive_operations(&delete).len(), 1);
+    assert!(matches!(
+      applier.apply(&delete),
+      Err(Error::UnsavedChanges { .. })
+    ));
+    assert!(path.exists());
+    assert!(editor.buffer(buffer.id()).is_some());
+
+    buffer.mark_saved();
+    let applied = applier.apply(&delete).unwrap();
+    assert_eq!(applied.closed, vec![buffer.id()]);
+    assert!(!path.exists());
+  }
+}
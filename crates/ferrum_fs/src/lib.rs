@@ -3,9 +3,11 @@
 //! File system operations with watching, caching, and async support.
 
 pub mod file_info;
+pub mod save;
 pub mod walker;
 pub mod watcher;
 
 pub use file_info::FileInfo;
+pub use save::{DiskState, PreSaveHooks};
 pub use walker::FileWalker;
 pub use watcher::FileWatcher;
@@ -0,0 +1,372 @@
+//! Atomic file saving
+//!
+//! Files are never truncated in place. The new content goes to a temp file
+//! next to the target, is flushed to disk, takes over the target's mode and
+//! ownership, and is then renamed over it, so a crash leaves either the old
+//! file or the new one. Symlinks are followed and the file they point at is
+//! replaced, leaving the link itself intact.
+
+use ferrum_core::hash::content_hash;
+use ferrum_core::prelude::*;
+use ropey::Rope;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Maximum number of symlinks followed when resolving the save target
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// What a file looked like on disk when it was loaded or last saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskState {
+  pub modified: Option<SystemTime>,
+  pub len: u64,
+  /// Hash of the file bytes
+  pub hash: String,
+}
+
+impl DiskState {
+  /// Record the state of a file whose content was just read as `bytes`
+  pub fn from_bytes(path: impl AsRef<Path>, bytes: &[u8]) -> Self {
+    Self {
+      modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+      len: bytes.len() as u64,
+      hash: content_hash(bytes),
+    }
+  }
+
+  /// Read the current state of a file, or `None` if it does not exist
+  pub fn read(path: impl AsRef<Path>) -> Result<Option<Self>> {
+    match fs::read(path.as_ref()) {
+      Ok(bytes) => Ok(Some(Self::from_bytes(path, &bytes))),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// Check if the file still has this state
+  ///
+  /// An unchanged modification time and size is trusted; otherwise the
+  /// content is hashed, so touching a file does not count as a change. A
+  /// deleted file counts as unchanged, since saving simply recreates it.
+  pub fn is_current(&self, path: impl AsRef<Path>) -> Result<bool> {
+    let path = path.as_ref();
+    let metadata = match fs::metadata(path) {
+      Ok(metadata) => metadata,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+      Err(e) => return Err(e.into()),
+    };
+
+    if metadata.modified().ok() == self.modified && metadata.len() == self.len {
+      return Ok(true);
+    }
+    Ok(content_hash(fs::read(path)?) == self.hash)
+  }
+}
+
+/// Text transforms applied before a file is saved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PreSaveHooks {
+  /// Remove spaces and tabs at the end of each line
+  pub trim_trailing_whitespace: bool,
+  /// Make sure non-empty text ends with a line break
+  pub insert_final_newline: bool,
+}
+
+/// A replacement produced by a pre-save hook, in chars of the original text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookEdit {
+  pub start: usize,
+  pub end: usize,
+  pub text: String,
+}
+
+impl PreSaveHooks {
+  /// Check if any hook is enabled
+  pub fn is_empty(&self) -> bool {
+    !self.trim_trailing_whitespace && !self.insert_final_newline
+  }
+
+  /// Compute the edits the hooks make to `text`, sorted by position
+  ///
+  /// Lines break where the buffer breaks them, at `\n`, `\r\n` or a lone
+  /// `\r`. `line_ending` is inserted when a final line break is missing.
+  pub fn edits(&self, text: &Rope, line_ending: &str) -> Vec<HookEdit> {
+    let mut edits = Vec::new();
+
+    if self.trim_trailing_whitespace {
+      let mut offset = 0;
+      for line in text.lines() {
+        let breaks = line
+          .chars_at(line.len_chars())
+          .reversed()
+          .take_while(|c| matches!(c, '\n' | '\r'))
+          .count();
+        let body_chars = line.len_chars() - breaks;
+        let blanks = line
+          .chars_at(body_chars)
+          .reversed()
+          .take_while(|c| matches!(c, ' ' | '\t'))
+          .count();
+        if blanks > 0 {
+          edits.push(HookEdit {
+            start: offset + body_chars - blanks,
+            end: offset + body_chars,
+            text: String::new(),
+          });
+        }
+        offset += line.len_chars();
+      }
+    }
+
+    let last = text.len_chars().checked_sub(1).map(|idx| text.char(idx));
+    if self.insert_final_newline && last.is_some_and(|c| !matches!(c, '\n' | '\r')) {
+      let len = text.len_chars();
+      edits.push(HookEdit {
+        start: len,
+        end: len,
+        text: line_ending.to_string(),
+      });
+    }
+
+    edits
+  }
+}
+
+/// Atomically write `bytes` to `path`
+///
+/// With `expected` set, the save is refused with `FileChangedOnDisk` if the
+/// file no longer matches it. Returns the state of the saved file, to pass
+/// as `expected` next time.
+pub fn save(path: impl AsRef<Path>, bytes: &[u8], expected: Option<&DiskState>) -> Result<DiskState> {
+  let target = resolve_symlinks(path.as_ref())?;
+
+  if let Some(expected) = expected {
+    if !expected.is_current(&target)? {
+      return Err(Error::FileChangedOnDisk { path: target });
+    }
+  }
+
+  let existing = match fs::metadata(&target) {
+    Ok(metadata) => Some(metadata),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+    Err(e) => return Err(e.into()),
+  };
+
+  let tmp_path = temp_path(&target);
+  let result = write_temp(&tmp_path, bytes, existing.as_ref())
+    .and_then(|()| fs::rename(&tmp_path, &target).map_err(Error::from));
+  if let Err(e) = result {
+    let _ = fs::remove_file(&tmp_path);
+    return Err(e);
+  }
+
+  sync_parent_dir(&target);
+  Ok(DiskState::from_bytes(&target, bytes))
+}
+
+/// Atomically write `bytes` to `path` without blocking the async runtime
+pub async fn save_async(
+  path: impl Into<PathBuf>,
+  bytes: Vec<u8>,
+  expected: Option<DiskState>,
+) -> Result<DiskState> {
+  let path = path.into();
+  tokio::task::spawn_blocking(move || save(&path, &bytes, expected.as_ref()))
+    .await
+    .map_err(|e| Error::Internal(e.to_string()))?
+}
+
+/// Follow symlinks to the file that should be replaced
+///
+/// Unlike `canonicalize`, this works for a link whose target does not exist
+/// yet, which saving then creates.
+fn resolve_symlinks(path: &Path) -> Result<PathBuf> {
+  let mut path = path.to_path_buf();
+  for _ in 0..MAX_SYMLINK_HOPS {
+    match fs::symlink_metadata(&path) {
+      Ok(metadata) if metadata.file_type().is_symlink() => {
+        let link = fs::read_link(&path)?;
+        path = match path.parent() {
+          Some(parent) if link.is_relative() => parent.join(link),
+          _ => link,
+        };
+      },
+      Ok(_) => return Ok(path),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(path),
+      Err(e) => return Err(e.into()),
+    }
+  }
+
+  Err(Error::DirectoryError(format!(
+    "Too many levels of symbolic links: {}",
+    path.display()
+  )))
+}
+
+/// A unique temp file name in the target's directory, so the final rename
+/// never crosses file systems
+fn temp_path(target: &Path) -> PathBuf {
+  static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+  let name = target
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_default();
+  let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+  target.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), unique))
+}
+
+fn write_temp(tmp_path: &Path, bytes: &[u8], existing: Option<&fs::Metadata>) -> Result<()> {
+  let mut file = OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(tmp_path)?;
+  file.write_all(bytes)?;
+
+  if let Some(metadata) = existing {
+    file.set_permissions(metadata.permissions())?;
+    copy_ownership(&file, metadata);
+  }
+
+  file.sync_all()?;
+  Ok(())
+}
+
+#[cfg(unix)]
+fn copy_ownership(file: &File, metadata: &fs::Metadata) {
+  use std::os::unix::fs::MetadataExt;
+
+  // Only root can give a file away; anyone else keeps their own ownership
+  if let Err(e) = std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid())) {
+    debug!("Could not preserve file ownership: {}", e);
+  }
+}
+
+#[cfg(not(unix))]
+fn copy_ownership(_file: &File, _metadata: &fs::Metadata) {}
+
+/// Flush the rename itself to disk
+#[cfg(unix)]
+fn sync_parent_dir(target: &Path) {
+  if let Some(parent) = target.parent() {
+    if let Err(e) = File::open(parent).and_then(|dir| dir.sync_all()) {
+      debug!("Could not sync directory {:?}: {}", parent, e);
+    }
+  }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_target: &Path) {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_save_replaces_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.txt");
+    fs::write(&path, "old").unwrap();
+
+    let state = save(&path, b"new", None).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    assert_eq!(state.hash, content_hash(b"new"));
+
+    // No temp files are left behind
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn test_save_refuses_changed_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.txt");
+    fs::write(&path, "loaded").unwrap();
+    let state = DiskState::read(&path).unwrap().unwrap();
+
+    fs::write(&path, "changed elsewhere").unwrap();
+    let result = save(&path, b"mine", Some(&state));
+    assert!(matches!(result, Err(Error::FileChangedOnDisk { .. })));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "changed elsewhere");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_save_follows_symlink_and_keeps_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target.sh");
+    let link = dir.path().join("link.sh");
+    fs::write(&target, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o755)).unwrap();
+    std::os::unix::fs::symlink("target.sh", &link).unwrap();
+
+    save(&link, b"#!/bin/sh\necho hi\n", None).unwrap();
+
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "#!/bin/sh\necho hi\n");
+    let mode = fs::metadata(&target).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+  }
+
+  #[test]
+  fn test_pre_save_hooks() {
+    let hooks = PreSaveHooks {
+      trim_trailing_whitespace: true,
+      insert_final_newline: true,
+    };
+
+    let edits = hooks.edits(&Rope::from_str("a  \r\nb\t\nc "), "\n");
+    assert_eq!(
+      edits,
+      vec![
+        HookEdit {
+          start: 1,
+          end: 3,
+          text: String::new(),
+        },
+        HookEdit {
+          start: 6,
+          end: 7,
+          text: String::new(),
+        },
+        HookEdit {
+          start: 9,
+          end: 10,
+          text: String::new(),
+        },
+        HookEdit {
+          start: 10,
+          end: 10,
+          text: "\n".to_string(),
+        },
+      ]
+    );
+    assert!(
+      PreSaveHooks::default()
+        .edits(&Rope::from_str("a  "), "\n")
+        .is_empty()
+    );
+
+    // Blanks before a lone `\r` are trimmed and the `\r` is kept
+    let edits = hooks.edits(&Rope::from_str("a \rb\t\r\n"), "\r");
+    assert_eq!(
+      edits,
+      vec![
+        HookEdit {
+          start: 1,
+          end: 2,
+          text: String::new(),
+        },
+        HookEdit {
+          start: 4,
+          end: 5,
+          text: String::new(),
+        },
+      ]
+    );
+  }
+}
@@ -1,8 +1,9 @@
 //! Directory walking with ignore support
 
 use ferrum_core::prelude::*;
-use ignore::WalkBuilder;
-use std::path::PathBuf;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Match, WalkBuilder};
+use std::path::{Path, PathBuf};
 
 use crate::file_info::FileInfo;
 
@@ -70,6 +71,52 @@ impl FileWalker {
     Ok(files)
   }
 
+  /// Check if a walk would skip a path below the root, e.g. one created
+  /// after the walk, as hidden or ignored
+  pub fn is_ignored(&self, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(&self.root) else {
+      return false;
+    };
+    let hidden = relative.components().any(|c| {
+      c.as_os_str()
+        .to_str()
+        .is_some_and(|name| name.starts_with('.'))
+    });
+    if hidden && !self.include_hidden {
+      return true;
+    }
+
+    // As in a walk, git's ignore files only apply inside a repository, and
+    // deeper ignore files take precedence
+    let repo = self.root.ancestors().find(|dir| dir.join(".git").exists());
+    let top = repo.unwrap_or(&self.root);
+    let mut matchers = Vec::new();
+    for dir in path
+      .ancestors()
+      .skip(1)
+      .take_while(|dir| dir.starts_with(top))
+    {
+      matchers.extend(ignore_file(dir, dir.join(".ignore")));
+      if repo.is_some() {
+        matchers.extend(ignore_file(dir, dir.join(".gitignore")));
+      }
+    }
+    if let Some(repo) = repo {
+      matchers.extend(ignore_file(repo, repo.join(".git/info/exclude")));
+      matchers.push(Gitignore::global().0);
+    }
+
+    let is_dir = path.is_dir();
+    for matcher in matchers {
+      match matcher.matched_path_or_any_parents(path, is_dir) {
+        Match::Ignore(_) => return true,
+        Match::Whitelist(_) => return false,
+        Match::None => {},
+      }
+    }
+    false
+  }
+
   /// Walk the directory asynchronously with a callback
   pub async fn walk_async<F>(&self, mut callback: F) -> Result<()>
   where
@@ -83,6 +130,18 @@ impl FileWalker {
   }
 }
 
+/// Load the rules of an ignore file, relative to `dir`
+fn ignore_file(dir: &Path, file: PathBuf) -> Option<Gitignore> {
+  if !file.is_file() {
+    return None;
+  }
+  let mut builder = GitignoreBuilder::new(dir);
+  if let Some(e) = builder.add(&file) {
+    warn!("Failed to read {:?}: {}", file, e);
+  }
+  builder.build().ok()
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -101,4 +160,37 @@ mod tests {
 
     assert!(files.len() >= 2);
   }
+
+  #[test]
+  fn test_is_ignored_matches_walk() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join(".git")).unwrap();
+    fs::create_dir_all(root.join("src/gen")).unwrap();
+    fs::write(root.join(".gitignore"), "*.log\ngen/\n").unwrap();
+    fs::write(root.join("src/.gitignore"), "!keep.log\n").unwrap();
+    for file in ["main.ts", "debug.log", "keep.log", "gen/out.ts", ".env"] {
+      fs::write(root.join("src").join(file), "").unwrap();
+    }
+
+    let walker = FileWalker::new(root);
+    let walked: Vec<PathBuf> = walker
+      .walk()
+      .unwrap()
+      .into_iter()
+      .map(|info| info.path)
+      .collect();
+    let expected = [
+      ("main.ts", false),
+      ("debug.log", true),
+      ("keep.log", false),
+      ("gen/out.ts", true),
+      (".env", true),
+    ];
+    for (file, ignored) in expected {
+      let path = root.join("src").join(file);
+      assert_eq!(walker.is_ignored(&path), ignored, "{}", file);
+      assert_eq!(walked.contains(&path), !ignored, "{}", file);
+    }
+  }
 }
@@ -2,34 +2,78 @@
 //!
 //! Handles JSON-RPC communication with language servers.
 
+use crate::diagnostics::DiagnosticStore;
+use crate::handlers::{INVALID_PARAMS, METHOD_NOT_FOUND, ResponseError, ServerHandlers};
+use crate::progress::ProgressTracker;
+use crate::semantic_tokens::{self, SemanticToken};
+
+use ferrum_buffer::{FoldKind, FoldRange};
+use ferrum_core::constants::{
+  LSP_INITIALIZE_TIMEOUT_MS, LSP_REQUEST_TIMEOUT_MS, LSP_SHUTDOWN_TIMEOUT_MS,
+};
+use ferrum_core::event::{SemanticTokensRefreshed, global_event_bus};
 use ferrum_core::prelude::*;
 use lsp_types::{
-  ClientCapabilities, CompletionItem, CompletionParams, CompletionResponse,
+  ApplyWorkspaceEditResponse, ClientCapabilities, CodeAction, CodeActionCapabilityResolveSupport,
+  CodeActionClientCapabilities, CodeActionContext, CodeActionKind, CodeActionKindLiteralSupport,
+  CodeActionLiteralSupport, CodeActionOrCommand, CodeActionParams, CodeActionProviderCapability,
+  CodeActionTriggerKind, Command as LspCommand, CompletionItem, CompletionParams,
+  CompletionResponse, ConfigurationItem, ConfigurationParams, DidChangeConfigurationParams,
   DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
-  DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse,
-  Hover, HoverParams, InitializeParams, InitializeResult, InitializedParams, Location,
-  ReferenceParams, RenameParams, ServerCapabilities, TextDocumentClientCapabilities,
-  TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
-  TextDocumentPositionParams, TextDocumentSyncClientCapabilities, Uri,
-  VersionedTextDocumentIdentifier, WorkspaceEdit,
-  notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Initialized},
+  DocumentSymbolParams, DocumentSymbolResponse, DynamicRegistrationClientCapabilities,
+  ExecuteCommandParams, FailureHandlingKind, FoldingRange, FoldingRangeKind, FoldingRangeParams,
+  FoldingRangeProviderCapability, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverParams,
+  InitializeParams, InitializeResult, InitializedParams, Location, LogMessageParams,
+  ProgressParams, PublishDiagnosticsClientCapabilities, PublishDiagnosticsParams, ReferenceParams,
+  RenameClientCapabilities, RenameParams, ResourceOperationKind, SemanticTokenModifier,
+  SemanticTokenType, SemanticTokensClientCapabilities, SemanticTokensClientCapabilitiesRequests,
+  SemanticTokensDeltaParams, SemanticTokensFullDeltaResult, SemanticTokensFullOptions,
+  SemanticTokensLegend, SemanticTokensParams, SemanticTokensResult,
+  SemanticTokensServerCapabilities, SemanticTokensWorkspaceClientCapabilities, ServerCapabilities,
+  ShowMessageParams, ShowMessageRequestClientCapabilities, ShowMessageRequestParams,
+  TextDocumentClientCapabilities, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+  TextDocumentItem, TextDocumentPositionParams, TextDocumentSyncCapability,
+  TextDocumentSyncClientCapabilities, TextDocumentSyncKind, TokenFormat, Uri,
+  VersionedTextDocumentIdentifier, WindowClientCapabilities, WorkDoneProgressCreateParams,
+  WorkspaceClientCapabilities, WorkspaceEdit, WorkspaceEditClientCapabilities, WorkspaceFolder,
+  notification::{
+    Cancel, DidChangeConfiguration, DidChangeTextDocument, DidCloseTextDocument,
+    DidOpenTextDocument, Exit, Initialized, LogMessage, Notification, Progress, PublishDiagnostics,
+    ShowMessage,
+  },
   request::{
-    Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Initialize, References,
-    Rename,
+    ApplyWorkspaceEdit, CodeActionRequest, CodeActionResolveRequest, CodeLensRefresh, Completion,
+    DocumentSymbolRequest, ExecuteCommand, FoldingRangeRequest, GotoDefinition, HoverRequest,
+    Initialize, InlayHintRefreshRequest, InlineValueRefreshRequest, References, RegisterCapability,
+    Rename, Request, SemanticTokensFullDeltaRequest, SemanticTokensFullRequest,
+    SemanticTokensRefresh, ShowMessageRequest, Shutdown, UnregisterCapability,
+    WorkDoneProgressCreate, WorkspaceConfiguration, WorkspaceDiagnosticRefresh,
+    WorkspaceFoldersRequest,
   },
 };
 use parking_lot::{Mutex, RwLock};
+use ropey::Rope;
 use serde::{Serialize, de::DeserializeOwned};
 use serde_json::{Value, json};
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::sync::oneshot;
-use tracing::{debug, error, info};
+use tokio::sync::{oneshot, watch};
+use tracing::{debug, error, info, warn};
+
+/// Writer for messages to a server, shared with the thread replying to its
+/// requests
+type Writer = Arc<Mutex<Option<Box<dyn Write + Send>>>>;
+
+/// Senders of the responses to requests waiting on them, by request ID
+type PendingRequests = Arc<Mutex<HashMap<i64, oneshot::Sender<Result<Value>>>>>;
+
+/// Last semantic tokens per document, with their result ID
+type SemanticTokenCache = Arc<Mutex<HashMap<Uri, (String, Vec<lsp_types::SemanticToken>)>>>;
 
 /// LSP client state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -49,36 +93,103 @@ pub enum LspClientState {
 /// LSP client for a single language server
 pub struct LspClient {
   language: String,
-  state: RwLock<LspClientState>,
+  state: Arc<RwLock<LspClientState>>,
   next_request_id: AtomicI64,
   capabilities: RwLock<Option<ServerCapabilities>>,
 
   // Process handles
   process: Mutex<Option<Child>>,
-  stdin: Mutex<Option<BufWriter<ChildStdin>>>,
+  stdin: Writer,
+
+  // Command and arguments the server was last started with
+  launch: RwLock<Option<(String, Vec<String>)>>,
+
+  // Counts the times the server process exited
+  exits: Arc<watch::Sender<u64>>,
+
+  // Counts the times the server was started, so the thread reading from an
+  // earlier process can tell it was replaced
+  generation: Arc<AtomicU64>,
+
+  // Timeouts of requests, by method, overriding the defaults
+  request_timeouts: RwLock<HashMap<String, Duration>>,
 
   // Pending request handlers
-  pending_requests: Arc<Mutex<HashMap<i64, oneshot::Sender<Result<Value>>>>>,
+  pending_requests: PendingRequests,
 
   // Root path for the workspace
   root_path: RwLock<Option<PathBuf>>,
+
+  // Last semantic tokens per document, with their result ID, for delta requests
+  semantic_tokens: SemanticTokenCache,
+
+  // Store the server's diagnostics are published to
+  diagnostics: Arc<DiagnosticStore>,
+
+  // Handlers for requests and notifications from the server
+  handlers: Arc<ServerHandlers>,
+
+  // Work done progress reported by the server
+  progress: Arc<ProgressTracker>,
+
+  // Settings served to `workspace/configuration` requests
+  settings: Arc<RwLock<Value>>,
 }
 
 impl LspClient {
   /// Create a new LSP client
   pub fn new(language: impl Into<String>) -> Self {
+    Self::with_diagnostics(language, Arc::new(DiagnosticStore::new()))
+  }
+
+  /// Create a new LSP client publishing diagnostics to a shared store
+  pub fn with_diagnostics(language: impl Into<String>, diagnostics: Arc<DiagnosticStore>) -> Self {
+    let language = language.into();
     Self {
-      language: language.into(),
-      state: RwLock::new(LspClientState::Stopped),
+      progress: Arc::new(ProgressTracker::new(language.clone())),
+      language,
+      state: Arc::new(RwLock::new(LspClientState::Stopped)),
       next_request_id: AtomicI64::new(1),
       capabilities: RwLock::new(None),
       process: Mutex::new(None),
-      stdin: Mutex::new(None),
+      stdin: Arc::new(Mutex::new(None)),
+      launch: RwLock::new(None),
+      exits: Arc::new(watch::channel(0).0),
+      generation: Arc::new(AtomicU64::new(0)),
+      request_timeouts: RwLock::new(HashMap::new()),
       pending_requests: Arc::new(Mutex::new(HashMap::new())),
       root_path: RwLock::new(None),
+      semantic_tokens: Arc::new(Mutex::new(HashMap::new())),
+      diagnostics,
+      handlers: Arc::new(ServerHandlers::new()),
+      settings: Arc::new(RwLock::new(Value::Null)),
     }
   }
 
+  /// Create a client that writes its messages to `writer`, as if talking
+  /// to a running server initialized with `capabilities`
+  ///
+  /// Nothing is read back, so requests go unanswered. For tests that check
+  /// what is sent to a server.
+  #[cfg(any(test, feature = "test-support"))]
+  pub fn connected(
+    language: impl Into<String>,
+    writer: Box<dyn Write + Send>,
+    capabilities: ServerCapabilities,
+  ) -> Self {
+    let client = Self::new(language);
+    *client.stdin.lock() = Some(writer);
+    *client.capabilities.write() = Some(capabilities);
+    *client.state.write() = LspClientState::Running;
+    client
+  }
+
+  /// Use shared handlers for the server's requests and notifications
+  pub fn with_handlers(mut self, handlers: Arc<ServerHandlers>) -> Self {
+    self.handlers = handlers;
+    self
+  }
+
   /// Get the language
   pub fn language(&self) -> &str {
     &self.language
@@ -94,6 +205,57 @@ impl LspClient {
     *self.state.read() == LspClientState::Running
   }
 
+  /// Get the store the server's diagnostics are published to
+  pub fn diagnostics(&self) -> &Arc<DiagnosticStore> {
+    &self.diagnostics
+  }
+
+  /// Get the handlers for the server's requests and notifications
+  pub fn handlers(&self) -> &Arc<ServerHandlers> {
+    &self.handlers
+  }
+
+  /// Get the work done progress reported by the server
+  pub fn progress(&self) -> &Arc<ProgressTracker> {
+    &self.progress
+  }
+
+  /// Set the settings served to the server's `workspace/configuration`
+  /// requests, and tell a running server they changed
+  pub fn set_settings(&self, settings: Value) -> Result<()> {
+    *self.settings.write() = settings.clone();
+    if !self.is_initialized() {
+      return Ok(());
+    }
+    self.send_notification::<DidChangeConfiguration>(DidChangeConfigurationParams { settings })
+  }
+
+  /// Watch the number of times the server process exited, whether it
+  /// crashed or was stopped
+  pub fn exits(&self) -> watch::Receiver<u64> {
+    self.exits.subscribe()
+  }
+
+  /// Set how long to wait for the responses to requests of a method
+  pub fn set_request_timeout(&self, method: &str, timeout: Duration) {
+    self
+      .request_timeouts
+      .write()
+      .insert(method.to_string(), timeout);
+  }
+
+  /// Get how long to wait for the response to a request
+  fn request_timeout(&self, method: &str) -> Duration {
+    if let Some(timeout) = self.request_timeouts.read().get(method) {
+      return *timeout;
+    }
+    Duration::from_millis(match method {
+      Initialize::METHOD => LSP_INITIALIZE_TIMEOUT_MS,
+      Shutdown::METHOD => LSP_SHUTDOWN_TIMEOUT_MS,
+      _ => LSP_REQUEST_TIMEOUT_MS,
+    })
+  }
+
   /// Get server capabilities
   pub fn capabilities(&self) -> Option<ServerCapabilities> {
     self.capabilities.read().clone()
@@ -104,6 +266,13 @@ impl LspClient {
     // Update state
     *self.state.write() = LspClientState::Starting;
     *self.root_path.write() = Some(root_path.clone());
+    *self.launch.write() = Some((
+      command.to_string(),
+      args.iter().map(|arg| arg.to_string()).collect(),
+    ));
+    self.semantic_tokens.lock().clear();
+    self.generation.fetch_add(1, Ordering::SeqCst);
+    self.kill();
 
     // Spawn the server process
     let mut process = Command::new(command)
@@ -112,74 +281,112 @@ impl LspClient {
       .stdout(Stdio::piped())
       .stderr(Stdio::piped())
       .spawn()
-      .map_err(|e| Error::Internal(format!("Failed to start LSP server: {}", e)))?;
+      .map_err(|e| {
+        self.fail_start();
+        Error::Internal(format!("Failed to start LSP server: {}", e))
+      })?;
 
     let stdin = process.stdin.take().unwrap();
     let stdout = process.stdout.take().unwrap();
 
     // Store handles
-    *self.stdin.lock() = Some(BufWriter::new(stdin));
+    *self.stdin.lock() = Some(Box::new(BufWriter::new(stdin)));
     *self.process.lock() = Some(process);
 
     // Start reading responses in background
-    let pending = self.pending_requests.clone();
-    std::thread::spawn(move || {
-      let reader = BufReader::new(stdout);
-      Self::read_loop(reader, pending);
-    });
+    let dispatcher = self.dispatcher(root_path.clone());
+    std::thread::spawn(move || dispatcher.run(BufReader::new(stdout)));
 
     // Send initialize request
-    let init_result = self.initialize(&root_path).await?;
+    let init_result = match self.initialize(&root_path).await {
+      Ok(result) => result,
+      Err(e) => {
+        self.fail_start();
+        self.kill();
+        return Err(Error::LspInitFailed {
+          reason: e.to_string(),
+        });
+      },
+    };
     *self.capabilities.write() = Some(init_result.capabilities);
 
     // Send initialized notification
     self.send_notification::<Initialized>(InitializedParams {})?;
 
-    *self.state.write() = LspClientState::Running;
+    {
+      // Stopped while starting
+      let mut state = self.state.write();
+      if *state != LspClientState::Starting {
+        return Err(Error::LspNotRunning {
+          language: self.language.clone(),
+        });
+      }
+      *state = LspClientState::Running;
+    }
     info!("LSP server started for {}", self.language);
 
     Ok(())
   }
 
+  /// Start the server again with the command and root path it was last
+  /// started with
+  pub async fn restart(&self) -> Result<()> {
+    let not_started = || Error::LspNotRunning {
+      language: self.language.clone(),
+    };
+    let (command, args) = self.launch.read().clone().ok_or_else(not_started)?;
+    let root_path = self.root_path.read().clone().ok_or_else(not_started)?;
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    self.start(&command, &args, root_path).await
+  }
+
   /// Stop the language server
+  ///
+  /// A server that doesn't answer the shutdown request is killed anyway.
   pub async fn stop(&self) -> Result<()> {
-    if *self.state.read() != LspClientState::Running {
-      return Ok(());
+    if self.state() == LspClientState::Running {
+      *self.state.write() = LspClientState::ShuttingDown;
+
+      match self
+        .request_raw::<Value>(Shutdown::METHOD, Value::Null)
+        .await
+      {
+        Ok(_) => self.send_notification_raw(Exit::METHOD, Value::Null)?,
+        Err(e) => warn!("LSP server for {} did not shut down: {}", self.language, e),
+      }
     }
 
-    *self.state.write() = LspClientState::ShuttingDown;
+    // Marked stopped first, so the process exiting isn't taken for a crash
+    *self.state.write() = LspClientState::Stopped;
+    self.kill();
+    self.diagnostics.clear_server(&self.language);
+    self.progress.clear();
+    info!("LSP server stopped for {}", self.language);
 
-    // Send shutdown request
-    let _: Value = self.request_raw("shutdown", Value::Null).await?;
+    Ok(())
+  }
 
-    // Send exit notification
-    self.send_notification_raw("exit", Value::Null)?;
+  /// Mark a start as failed, unless the client was stopped meanwhile
+  fn fail_start(&self) {
+    let mut state = self.state.write();
+    if *state == LspClientState::Starting {
+      *state = LspClientState::Failed;
+    }
+  }
 
-    // Kill process
+  /// Kill the server process, if any, and reap it
+  fn kill(&self) {
     if let Some(mut process) = self.process.lock().take() {
       let _ = process.kill();
+      let _ = process.wait();
     }
-
-    *self.state.write() = LspClientState::Stopped;
-    info!("LSP server stopped for {}", self.language);
-
-    Ok(())
   }
 
   /// Initialize the server
-  async fn initialize(&self, root_path: &PathBuf) -> Result<InitializeResult> {
-    let root_uri: Uri = format!("file://{}", root_path.to_string_lossy())
-      .parse()
-      .unwrap();
-
+  async fn initialize(&self, root_path: &Path) -> Result<InitializeResult> {
     // Use workspace_folders instead of deprecated root_path/root_uri
-    let workspace_folder = lsp_types::WorkspaceFolder {
-      uri: root_uri,
-      name: root_path
-        .file_name()
-        .map(|n| n.to_string_lossy().to_string())
-        .unwrap_or_else(|| "workspace".to_string()),
-    };
+    let workspace_folder = workspace_folder(root_path);
 
     #[allow(deprecated)]
     let params = InitializeParams {
@@ -205,6 +412,36 @@ impl LspClient {
   /// Get client capabilities
   fn client_capabilities(&self) -> ClientCapabilities {
     ClientCapabilities {
+      workspace: Some(WorkspaceClientCapabilities {
+        apply_edit: Some(self.handlers.handles_request(ApplyWorkspaceEdit::METHOD)),
+        workspace_edit: Some(WorkspaceEditClientCapabilities {
+          document_changes: Some(true),
+          resource_operations: Some(vec![
+            ResourceOperationKind::Create,
+            ResourceOperationKind::Rename,
+            ResourceOperationKind::Delete,
+          ]),
+          failure_handling: Some(FailureHandlingKind::Abort),
+          normalizes_line_endings: Some(false),
+          change_annotation_support: None,
+        }),
+        execute_command: Some(DynamicRegistrationClientCapabilities {
+          dynamic_registration: Some(false),
+        }),
+        workspace_folders: Some(true),
+        configuration: Some(true),
+        semantic_tokens: Some(SemanticTokensWorkspaceClientCapabilities {
+          refresh_support: Some(true),
+        }),
+        ..Default::default()
+      }),
+      window: Some(WindowClientCapabilities {
+        work_done_progress: Some(true),
+        show_message: Some(ShowMessageRequestClientCapabilities {
+          message_action_item: None,
+        }),
+        show_document: None,
+      }),
       text_document: Some(TextDocumentClientCapabilities {
         synchronization: Some(TextDocumentSyncClientCapabilities {
           dynamic_registration: Some(false),
@@ -247,6 +484,98 @@ impl LspClient {
           hierarchical_document_symbol_support: Some(true),
           ..Default::default()
         }),
+        code_action: Some(CodeActionClientCapabilities {
+          dynamic_registration: Some(false),
+          code_action_literal_support: Some(CodeActionLiteralSupport {
+            code_action_kind: CodeActionKindLiteralSupport {
+              value_set: [
+                CodeActionKind::EMPTY,
+                CodeActionKind::QUICKFIX,
+                CodeActionKind::REFACTOR,
+                CodeActionKind::REFACTOR_EXTRACT,
+                CodeActionKind::REFACTOR_INLINE,
+                CodeActionKind::REFACTOR_REWRITE,
+                CodeActionKind::SOURCE,
+                CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                CodeActionKind::SOURCE_FIX_ALL,
+              ]
+              .iter()
+              .map(|kind| kind.as_str().to_string())
+              .collect(),
+            },
+          }),
+          is_preferred_support: Some(true),
+          disabled_support: Some(true),
+          data_support: Some(true),
+          resolve_support: Some(CodeActionCapabilityResolveSupport {
+            properties: vec!["edit".to_string()],
+          }),
+          honors_change_annotations: Some(false),
+        }),
+        rename: Some(RenameClientCapabilities {
+          dynamic_registration: Some(false),
+          prepare_support: Some(false),
+          ..Default::default()
+        }),
+        publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
+          related_information: Some(false),
+          version_support: Some(true),
+          ..Default::default()
+        }),
+        folding_range: Some(lsp_types::FoldingRangeClientCapabilities {
+          dynamic_registration: Some(false),
+          line_folding_only: Some(true),
+          ..Default::default()
+        }),
+        semantic_tokens: Some(SemanticTokensClientCapabilities {
+          dynamic_registration: Some(false),
+          requests: SemanticTokensClientCapabilitiesRequests {
+            range: Some(false),
+            full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+          },
+          token_types: vec![
+            SemanticTokenType::NAMESPACE,
+            SemanticTokenType::TYPE,
+            SemanticTokenType::CLASS,
+            SemanticTokenType::ENUM,
+            SemanticTokenType::INTERFACE,
+            SemanticTokenType::STRUCT,
+            SemanticTokenType::TYPE_PARAMETER,
+            SemanticTokenType::PARAMETER,
+            SemanticTokenType::VARIABLE,
+            SemanticTokenType::PROPERTY,
+            SemanticTokenType::ENUM_MEMBER,
+            SemanticTokenType::EVENT,
+            SemanticTokenType::FUNCTION,
+            SemanticTokenType::METHOD,
+            SemanticTokenType::MACRO,
+            SemanticTokenType::KEYWORD,
+            SemanticTokenType::MODIFIER,
+            SemanticTokenType::COMMENT,
+            SemanticTokenType::STRING,
+            SemanticTokenType::NUMBER,
+            SemanticTokenType::REGEXP,
+            SemanticTokenType::OPERATOR,
+            SemanticTokenType::DECORATOR,
+          ],
+          token_modifiers: vec![
+            SemanticTokenModifier::DECLARATION,
+            SemanticTokenModifier::DEFINITION,
+            SemanticTokenModifier::READONLY,
+            SemanticTokenModifier::STATIC,
+            SemanticTokenModifier::DEPRECATED,
+            SemanticTokenModifier::ABSTRACT,
+            SemanticTokenModifier::ASYNC,
+            SemanticTokenModifier::MODIFICATION,
+            SemanticTokenModifier::DOCUMENTATION,
+            SemanticTokenModifier::DEFAULT_LIBRARY,
+          ],
+          formats: vec![TokenFormat::RELATIVE],
+          overlapping_token_support: Some(false),
+          multiline_token_support: Some(false),
+          server_cancel_support: Some(false),
+          augments_syntax_tokens: Some(true),
+        }),
         ..Default::default()
       }),
       ..Default::default()
@@ -284,6 +613,7 @@ impl LspClient {
 
   /// Notify server that a document was closed
   pub fn did_close(&self, uri: Uri) -> Result<()> {
+    self.semantic_tokens.lock().remove(&uri);
     let params = DidCloseTextDocumentParams {
       text_document: TextDocumentIdentifier { uri },
     };
@@ -417,6 +747,230 @@ impl LspClient {
     Ok(response.unwrap_or_else(|| WorkspaceEdit::default()))
   }
 
+  /// Get the code actions for a range, e.g. the quick fixes of the
+  /// diagnostics in it
+  ///
+  /// `diagnostics` are those overlapping the range, which servers use to
+  /// offer fixes; `only` limits the actions to some kinds.
+  pub async fn code_actions(
+    &self,
+    uri: Uri,
+    range: lsp_types::Range,
+    diagnostics: Vec<lsp_types::Diagnostic>,
+    only: Option<Vec<CodeActionKind>>,
+  ) -> Result<Vec<CodeActionOrCommand>> {
+    let params = CodeActionParams {
+      text_document: TextDocumentIdentifier { uri },
+      range,
+      context: CodeActionContext {
+        diagnostics,
+        only,
+        trigger_kind: Some(CodeActionTriggerKind::INVOKED),
+      },
+      work_done_progress_params: Default::default(),
+      partial_result_params: Default::default(),
+    };
+
+    let response: Option<Vec<CodeActionOrCommand>> =
+      self.request::<CodeActionRequest>(params).await?;
+    Ok(response.unwrap_or_default())
+  }
+
+  /// Fill in the edit of a code action the server left to be resolved
+  ///
+  /// Actions that have an edit, or whose server can't resolve them, are
+  /// returned as they are.
+  pub async fn resolve_code_action(&self, action: CodeAction) -> Result<CodeAction> {
+    if action.edit.is_some() || !self.supports_code_action_resolve() {
+      return Ok(action);
+    }
+    self.request::<CodeActionResolveRequest>(action).await
+  }
+
+  /// Run a command on the server, e.g. that of a code action
+  ///
+  /// Servers usually carry out commands by sending `workspace/applyEdit`
+  /// requests before they reply.
+  pub async fn execute_command(&self, command: LspCommand) -> Result<Option<Value>> {
+    let params = ExecuteCommandParams {
+      command: command.command,
+      arguments: command.arguments.unwrap_or_default(),
+      work_done_progress_params: Default::default(),
+    };
+
+    self.request::<ExecuteCommand>(params).await
+  }
+
+  /// Check if the server resolves code actions lazily
+  fn supports_code_action_resolve(&self) -> bool {
+    let capabilities = self.capabilities.read();
+    match capabilities
+      .as_ref()
+      .and_then(|c| c.code_action_provider.as_ref())
+    {
+      Some(CodeActionProviderCapability::Options(options)) => {
+        options.resolve_provider.unwrap_or(false)
+      },
+      _ => false,
+    }
+  }
+
+  /// Check if the server provides folding ranges
+  pub fn supports_folding_ranges(&self) -> bool {
+    let capabilities = self.capabilities.read();
+    match capabilities
+      .as_ref()
+      .and_then(|c| c.folding_range_provider.as_ref())
+    {
+      Some(FoldingRangeProviderCapability::Simple(supported)) => *supported,
+      Some(_) => true,
+      None => false,
+    }
+  }
+
+  /// Get how the server wants document changes sent
+  ///
+  /// Servers that don't say get none, as the spec defaults to.
+  pub fn text_document_sync_kind(&self) -> TextDocumentSyncKind {
+    let capabilities = self.capabilities.read();
+    match capabilities
+      .as_ref()
+      .and_then(|c| c.text_document_sync.as_ref())
+    {
+      Some(TextDocumentSyncCapability::Kind(kind)) => *kind,
+      Some(TextDocumentSyncCapability::Options(options)) => {
+        options.change.unwrap_or(TextDocumentSyncKind::NONE)
+      },
+      None => TextDocumentSyncKind::NONE,
+    }
+  }
+
+  /// Get the folding ranges of a document
+  pub async fn folding_ranges(&self, uri: Uri) -> Result<Vec<FoldRange>> {
+    let params = FoldingRangeParams {
+      text_document: TextDocumentIdentifier { uri },
+      work_done_progress_params: Default::default(),
+      partial_result_params: Default::default(),
+    };
+
+    let response: Option<Vec<FoldingRange>> = self.request::<FoldingRangeRequest>(params).await?;
+    Ok(
+      response
+        .unwrap_or_default()
+        .into_iter()
+        .map(|range| {
+          let kind = range.kind.map(|kind| match kind {
+            FoldingRangeKind::Comment => FoldKind::Comment,
+            FoldingRangeKind::Imports => FoldKind::Imports,
+            FoldingRangeKind::Region => FoldKind::Region,
+          });
+          FoldRange::new(range.start_line as usize, range.end_line as usize, kind)
+        })
+        .collect(),
+    )
+  }
+
+  /// Get the server's semantic token legend, if it provides semantic tokens
+  pub fn semantic_tokens_legend(&self) -> Option<SemanticTokensLegend> {
+    self.semantic_tokens_options().map(|options| options.legend)
+  }
+
+  /// Get the semantic tokens of a document, decoded against `rope`
+  ///
+  /// After the first request, only the changes since the previous
+  /// response are requested if the server supports deltas. `rope` should
+  /// hold the text the server last received.
+  pub async fn semantic_tokens(&self, uri: Uri, rope: &Rope) -> Result<Vec<SemanticToken>> {
+    let options = self
+      .semantic_tokens_options()
+      .ok_or_else(|| Error::Internal("LSP server has no semantic tokens".to_string()))?;
+    let supports_delta = matches!(
+      options.full,
+      Some(SemanticTokensFullOptions::Delta { delta: Some(true) })
+    );
+
+    let previous = supports_delta
+      .then(|| self.semantic_tokens.lock().remove(&uri))
+      .flatten();
+    let (result_id, tokens) = match previous {
+      Some((result_id, tokens)) => {
+        self
+          .semantic_tokens_delta(uri.clone(), result_id, tokens)
+          .await?
+      },
+      None => self.semantic_tokens_full(uri.clone()).await?,
+    };
+
+    let decoded = semantic_tokens::decode(rope, &options.legend, &tokens);
+    if let Some(result_id) = result_id {
+      self.semantic_tokens.lock().insert(uri, (result_id, tokens));
+    }
+    Ok(decoded)
+  }
+
+  /// Request all semantic tokens of a document
+  async fn semantic_tokens_full(
+    &self,
+    uri: Uri,
+  ) -> Result<(Option<String>, Vec<lsp_types::SemanticToken>)> {
+    let params = SemanticTokensParams {
+      text_document: TextDocumentIdentifier { uri },
+      work_done_progress_params: Default::default(),
+      partial_result_params: Default::default(),
+    };
+
+    let response: Option<SemanticTokensResult> =
+      self.request::<SemanticTokensFullRequest>(params).await?;
+    Ok(match response {
+      Some(SemanticTokensResult::Tokens(tokens)) => (tokens.result_id, tokens.data),
+      Some(SemanticTokensResult::Partial(partial)) => (None, partial.data),
+      None => (None, Vec::new()),
+    })
+  }
+
+  /// Request the changes to a document's semantic tokens since a previous
+  /// response, and apply them to its tokens
+  async fn semantic_tokens_delta(
+    &self,
+    uri: Uri,
+    previous_result_id: String,
+    mut tokens: Vec<lsp_types::SemanticToken>,
+  ) -> Result<(Option<String>, Vec<lsp_types::SemanticToken>)> {
+    let params = SemanticTokensDeltaParams {
+      text_document: TextDocumentIdentifier { uri },
+      previous_result_id,
+      work_done_progress_params: Default::default(),
+      partial_result_params: Default::default(),
+    };
+
+    let response: Option<SemanticTokensFullDeltaResult> = self
+      .request::<SemanticTokensFullDeltaRequest>(params)
+      .await?;
+    Ok(match response {
+      Some(SemanticTokensFullDeltaResult::Tokens(tokens)) => (tokens.result_id, tokens.data),
+      Some(SemanticTokensFullDeltaResult::TokensDelta(delta)) => {
+        semantic_tokens::apply_edits(&mut tokens, delta.edits);
+        (delta.result_id, tokens)
+      },
+      Some(SemanticTokensFullDeltaResult::PartialTokensDelta { edits }) => {
+        semantic_tokens::apply_edits(&mut tokens, edits);
+        (None, tokens)
+      },
+      None => (None, Vec::new()),
+    })
+  }
+
+  /// Get the server's semantic token options
+  fn semantic_tokens_options(&self) -> Option<lsp_types::SemanticTokensOptions> {
+    let capabilities = self.capabilities.read();
+    match capabilities.as_ref()?.semantic_tokens_provider.as_ref()? {
+      SemanticTokensServerCapabilities::SemanticTokensOptions(options) => Some(options.clone()),
+      SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options) => {
+        Some(options.semantic_tokens_options.clone())
+      },
+    }
+  }
+
   // ========== JSON-RPC Communication ==========
 
   /// Send a request and wait for response (typed)
@@ -432,12 +986,19 @@ impl LspClient {
   }
 
   /// Send a raw request with method string
+  ///
+  /// If the caller stops waiting, by timing out or dropping the future, the
+  /// server is told to cancel the request.
   async fn request_raw<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
     let id = self.next_request_id.fetch_add(1, Ordering::SeqCst);
 
     // Create response channel
     let (tx, rx) = oneshot::channel();
     self.pending_requests.lock().insert(id, tx);
+    let mut guard = CancelOnDrop {
+      client: self,
+      id: Some(id),
+    };
 
     // Build message
     let message = json!({
@@ -451,10 +1012,17 @@ impl LspClient {
     self.send_message(&message)?;
 
     // Wait for response with timeout
-    let result = tokio::time::timeout(Duration::from_secs(120), rx)
-      .await
-      .map_err(|_| Error::Internal("LSP request timeout".to_string()))?
-      .map_err(|_| Error::Internal("LSP response channel closed".to_string()))??;
+    let response = tokio::time::timeout(self.request_timeout(method), rx).await;
+    if let Ok(Ok(_)) = response {
+      guard.id = None;
+    }
+    let result = response
+      .map_err(|_| Error::LspTimeout {
+        method: method.to_string(),
+      })?
+      .map_err(|_| Error::LspServerExited {
+        language: self.language.clone(),
+      })??;
 
     serde_json::from_value(result)
       .map_err(|e| Error::Internal(format!("Failed to parse LSP response: {}", e)))
@@ -482,36 +1050,93 @@ impl LspClient {
 
   /// Send a JSON-RPC message
   fn send_message(&self, message: &Value) -> Result<()> {
-    let content = serde_json::to_string(message).unwrap();
-    let header = format!("Content-Length: {}\r\n\r\n", content.len());
-
-    let mut stdin = self.stdin.lock();
-    if let Some(ref mut writer) = *stdin {
-      writer
-        .write_all(header.as_bytes())
-        .map_err(|e| Error::Internal(format!("Failed to write to LSP: {}", e)))?;
-      writer
-        .write_all(content.as_bytes())
-        .map_err(|e| Error::Internal(format!("Failed to write to LSP: {}", e)))?;
-      writer
-        .flush()
-        .map_err(|e| Error::Internal(format!("Failed to flush LSP: {}", e)))?;
-      Ok(())
-    } else {
-      Err(Error::Internal("LSP server not running".to_string()))
+    write_message(&self.stdin, message)
+  }
+
+  /// Create the dispatcher for messages read from the server
+  fn dispatcher(&self, root_path: PathBuf) -> Dispatcher {
+    Dispatcher {
+      language: self.language.clone(),
+      root_path,
+      state: self.state.clone(),
+      exits: self.exits.clone(),
+      generation: self.generation.load(Ordering::SeqCst),
+      current_generation: self.generation.clone(),
+      writer: self.stdin.clone(),
+      pending: self.pending_requests.clone(),
+      handlers: self.handlers.clone(),
+      diagnostics: self.diagnostics.clone(),
+      progress: self.progress.clone(),
+      settings: self.settings.clone(),
+      semantic_tokens: self.semantic_tokens.clone(),
+    }
+  }
+}
+
+/// Cancels a request whose caller stopped waiting for the response
+struct CancelOnDrop<'a> {
+  client: &'a LspClient,
+  /// ID of the request, until its response arrives
+  id: Option<i64>,
+}
+
+impl Drop for CancelOnDrop<'_> {
+  fn drop(&mut self) {
+    if let Some(id) = self.id {
+      self.client.pending_requests.lock().remove(&id);
+      let _ = self
+        .client
+        .send_notification_raw(Cancel::METHOD, json!({ "id": id }));
+    }
+  }
+}
+
+impl Drop for LspClient {
+  fn drop(&mut self) {
+    // Kill the process if still running
+    if let Some(mut process) = self.process.lock().take() {
+      let _ = process.kill();
     }
   }
+}
+
+/// Routes the messages read from a server: responses to the requests
+/// waiting on them, and the server's own requests and notifications to
+/// their handlers. Every server request gets a reply, with an error if
+/// nothing handles it.
+struct Dispatcher {
+  language: String,
+  root_path: PathBuf,
+  state: Arc<RwLock<LspClientState>>,
+  exits: Arc<watch::Sender<u64>>,
+  /// Start of the server this dispatcher reads from
+  generation: u64,
+  current_generation: Arc<AtomicU64>,
+  writer: Writer,
+  pending: PendingRequests,
+  handlers: Arc<ServerHandlers>,
+  diagnostics: Arc<DiagnosticStore>,
+  progress: Arc<ProgressTracker>,
+  settings: Arc<RwLock<Value>>,
+  semantic_tokens: SemanticTokenCache,
+}
+
+impl Dispatcher {
+  /// Read and dispatch messages until the server's output ends, then
+  /// clean up after the server
+  fn run(&self, reader: impl BufRead) {
+    self.read(reader);
+    self.exited();
+  }
 
-  /// Background thread to read responses
-  fn read_loop(
-    mut reader: BufReader<ChildStdout>,
-    pending: Arc<Mutex<HashMap<i64, oneshot::Sender<Result<Value>>>>>,
-  ) {
+  /// Read and dispatch messages until the server's output ends
+  fn read(&self, mut reader: impl BufRead) {
     loop {
       // Read Content-Length header
       let mut header = String::new();
-      if reader.read_line(&mut header).is_err() {
-        break;
+      match reader.read_line(&mut header) {
+        Ok(0) | Err(_) => break,
+        Ok(_) => {},
       }
 
       if !header.starts_with("Content-Length:") {
@@ -539,39 +1164,420 @@ impl LspClient {
       }
 
       // Parse JSON
-      let message: Value = match serde_json::from_slice(&content) {
-        Ok(v) => v,
-        Err(e) => {
-          error!("Failed to parse LSP message: {}", e);
-          continue;
+      match serde_json::from_slice(&content) {
+        Ok(message) => self.dispatch(message),
+        Err(e) => error!("Failed to parse LSP message: {}", e),
+      }
+    }
+  }
+
+  /// Clean up after the server process exited
+  ///
+  /// Requests waiting on it fail, and unless it was being stopped it is
+  /// marked as failed, for the manager to restart it.
+  fn exited(&self) {
+    // The client already moved on to a newer process
+    if self.current_generation.load(Ordering::SeqCst) != self.generation {
+      return;
+    }
+
+    {
+      let mut state = self.state.write();
+      if matches!(*state, LspClientState::Starting | LspClientState::Running) {
+        warn!("LSP server for {} exited unexpectedly", self.language);
+        *state = LspClientState::Failed;
+      }
+    }
+    *self.writer.lock() = None;
+
+    let pending: Vec<_> = self.pending.lock().drain().collect();
+    for (_, tx) in pending {
+      let _ = tx.send(Err(Error::LspServerExited {
+        language: self.language.clone(),
+      }));
+    }
+    self.diagnostics.clear_server(&self.language);
+    self.progress.clear();
+    self.exits.send_modify(|exits| *exits += 1);
+  }
+
+  /// Route a message by its shape: requests carry an ID and a method,
+  /// notifications only a method and responses only an ID
+  fn dispatch(&self, mut message: Value) {
+    let params = message
+      .get_mut("params")
+      .map(Value::take)
+      .unwrap_or(Value::Null);
+    let method = message.get("method").and_then(Value::as_str);
+
+    match (message.get("id"), method) {
+      (Some(id), Some(method)) => self.handle_request(id, method, params),
+      (None, Some(method)) => self.handle_notification(method, params),
+      (Some(id), None) => self.handle_response(id, &message),
+      (None, None) => debug!("Ignoring LSP message without ID or method"),
+    }
+  }
+
+  /// Hand a response to the request waiting on it
+  fn handle_response(&self, id: &Value, message: &Value) {
+    let Some(id) = id.as_i64() else {
+      return;
+    };
+    let result = if let Some(error) = message.get("error") {
+      Err(Error::Internal(format!("LSP error: {}", error)))
+    } else {
+      Ok(message.get("result").cloned().unwrap_or(Value::Null))
+    };
+
+    if let Some(tx) = self.pending.lock().remove(&id) {
+      let _ = tx.send(result);
+    }
+  }
+
+  /// Answer a request from the server
+  fn handle_request(&self, id: &Value, method: &str, params: Value) {
+    let result = match self.handlers.request(method, params.clone()) {
+      Some(result) => result,
+      None => self.default_request(method, params),
+    };
+
+    let message = match result {
+      Ok(result) => json!({
+          "jsonrpc": "2.0",
+          "id": id,
+          "result": result,
+      }),
+      Err(error) => {
+        debug!(
+          "{} LSP request {} failed: {}",
+          self.language, method, error.message
+        );
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": error,
+        })
+      },
+    };
+
+    if let Err(e) = write_message(&self.writer, &message) {
+      error!(
+        "Failed to answer {} LSP request {}: {}",
+        self.language, method, e
+      );
+    }
+  }
+
+  /// Answer a request no handler took over
+  fn default_request(
+    &self,
+    method: &str,
+    params: Value,
+  ) -> std::result::Result<Value, ResponseError> {
+    match method {
+      WorkspaceConfiguration::METHOD => {
+        let params: ConfigurationParams = parse_params(params)?;
+        Ok(Value::Array(self.configuration(&params.items)))
+      },
+      WorkspaceFoldersRequest::METHOD => Ok(json!([workspace_folder(&self.root_path)])),
+      WorkDoneProgressCreate::METHOD => {
+        let params: WorkDoneProgressCreateParams = parse_params(params)?;
+        self.progress.create(&params.token);
+        Ok(Value::Null)
+      },
+      ApplyWorkspaceEdit::METHOD => Ok(json!(ApplyWorkspaceEditResponse {
+        applied: false,
+        failure_reason: Some("Workspace edits are not supported".to_string()),
+        failed_change: None,
+      })),
+      ShowMessageRequest::METHOD => {
+        // Without a handler no action is picked
+        let params: ShowMessageRequestParams = parse_params(params)?;
+        info!("{} LSP: {}", self.language, params.message);
+        Ok(Value::Null)
+      },
+      SemanticTokensRefresh::METHOD => {
+        // Cached tokens can no longer be used as the base of a delta, and
+        // the frontend has to ask for the tokens again
+        self.semantic_tokens.lock().clear();
+        global_event_bus().publish(&SemanticTokensRefreshed {
+          language: self.language.clone(),
+        });
+        Ok(Value::Null)
+      },
+      // Capabilities are all declared up front, and code lenses, inlay
+      // hints, inline values and workspace diagnostics are never requested,
+      // so there is nothing for those refreshes to invalidate
+      RegisterCapability::METHOD
+      | UnregisterCapability::METHOD
+      | CodeLensRefresh::METHOD
+      | InlayHintRefreshRequest::METHOD
+      | InlineValueRefreshRequest::METHOD
+      | WorkspaceDiagnosticRefresh::METHOD => Ok(Value::Null),
+      _ => Err(ResponseError::new(
+        METHOD_NOT_FOUND,
+        format!("Unhandled method: {}", method),
+      )),
+    }
+  }
+
+  /// Handle a notification from the server
+  fn handle_notification(&self, method: &str, params: Value) {
+    match method {
+      PublishDiagnostics::METHOD => {
+        match serde_json::from_value::<PublishDiagnosticsParams>(params.clone()) {
+          Ok(params) => self.diagnostics.publish(&self.language, params),
+          Err(e) => error!("Invalid diagnostics from {} LSP: {}", self.language, e),
+        }
+      },
+      Progress::METHOD => match serde_json::from_value::<ProgressParams>(params.clone()) {
+        Ok(params) => {
+          self.progress.update(params);
         },
-      };
-
-      // Handle response
-      if let Some(id) = message.get("id").and_then(|v| v.as_i64()) {
-        let result = if let Some(error) = message.get("error") {
-          Err(Error::Internal(format!("LSP error: {}", error)))
-        } else {
-          Ok(message.get("result").cloned().unwrap_or(Value::Null))
-        };
-
-        if let Some(tx) = pending.lock().remove(&id) {
-          let _ = tx.send(result);
+        Err(e) => debug!("Unsupported progress from {} LSP: {}", self.language, e),
+      },
+      LogMessage::METHOD => {
+        if let Ok(params) = serde_json::from_value::<LogMessageParams>(params.clone()) {
+          debug!("{} LSP: {}", self.language, params.message);
         }
-      } else if let Some(method) = message.get("method").and_then(|v| v.as_str()) {
-        // Handle notifications/requests from server
-        debug!("LSP notification: {}", method);
-        // TODO: Handle diagnostics, progress, etc.
-      }
+      },
+      ShowMessage::METHOD => {
+        if let Ok(params) = serde_json::from_value::<ShowMessageParams>(params.clone()) {
+          info!("{} LSP: {}", self.language, params.message);
+        }
+      },
+      _ => debug!("LSP notification: {}", method),
     }
+    self.handlers.notify(method, &params);
+  }
+
+  /// Look up the settings of each requested section, as a dotted path
+  fn configuration(&self, items: &[ConfigurationItem]) -> Vec<Value> {
+    let settings = self.settings.read();
+    items
+      .iter()
+      .map(|item| match &item.section {
+        Some(section) => section
+          .split('.')
+          .try_fold(&*settings, |value, key| value.get(key))
+          .cloned()
+          .unwrap_or(Value::Null),
+        None => settings.clone(),
+      })
+      .collect()
   }
 }
 
-impl Drop for LspClient {
-  fn drop(&mut self) {
-    // Kill the process if still running
-    if let Some(mut process) = self.process.lock().take() {
-      let _ = process.kill();
+/// Parse the params of a server request
+fn parse_params<T: DeserializeOwned>(params: Value) -> std::result::Result<T, ResponseError> {
+  serde_json::from_value(params).map_err(|e| ResponseError::new(INVALID_PARAMS, e.to_string()))
+}
+
+/// Write a JSON-RPC message to a server
+fn write_message(writer: &Mutex<Option<Box<dyn Write + Send>>>, message: &Value) -> Result<()> {
+  let content = serde_json::to_string(message).unwrap();
+  let header = format!("Content-Length: {}\r\n\r\n", content.len());
+
+  let mut stdin = writer.lock();
+  if let Some(ref mut writer) = *stdin {
+    writer
+      .write_all(header.as_bytes())
+      .map_err(|e| Error::Internal(format!("Failed to write to LSP: {}", e)))?;
+    writer
+      .write_all(content.as_bytes())
+      .map_err(|e| Error::Internal(format!("Failed to write to LSP: {}", e)))?;
+    writer
+      .flush()
+      .map_err(|e| Error::Internal(format!("Failed to flush LSP: {}", e)))?;
+    Ok(())
+  } else {
+    Err(Error::Internal("LSP server not running".to_string()))
+  }
+}
+
+/// The workspace folder of a root path
+fn workspace_folder(root_path: &Path) -> WorkspaceFolder {
+  WorkspaceFolder {
+    uri: format!("file://{}", root_path.to_string_lossy())
+      .parse()
+      .unwrap(),
+    name: root_path
+      .file_name()
+      .map(|n| n.to_string_lossy().to_string())
+      .unwrap_or_else(|| "workspace".to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{Cursor, Read};
+
+  /// Writer appending to a buffer the test can read back
+  struct Sink(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.0.lock().extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn frame(messages: &[Value]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for message in messages {
+      let content = message.to_string();
+      bytes.extend_from_slice(format!("Content-Length: {}\r\n\r\n", content.len()).as_bytes());
+      bytes.extend_from_slice(content.as_bytes());
+    }
+    bytes
+  }
+
+  fn unframe(bytes: &[u8]) -> Vec<Value> {
+    let mut reader = Cursor::new(bytes);
+    let mut messages = Vec::new();
+    loop {
+      let mut header = String::new();
+      if reader.read_line(&mut header).unwrap() == 0 {
+        return messages;
+      }
+      let length: usize = header["Content-Length:".len()..].trim().parse().unwrap();
+      reader.read_line(&mut String::new()).unwrap();
+      let mut content = vec![0u8; length];
+      reader.read_exact(&mut content).unwrap();
+      messages.push(serde_json::from_slice(&content).unwrap());
+    }
+  }
+
+  #[test]
+  fn test_dispatch_server_messages() {
+    let client = LspClient::new("dispatch");
+    let output = Arc::new(Mutex::new(Vec::new()));
+    *client.stdin.lock() = Some(Box::new(Sink(output.clone())));
+    client
+      .set_settings(json!({ "check": { "command": "clippy" } }))
+      .unwrap();
+
+    let (tx, mut rx) = oneshot::channel();
+    client.pending_requests.lock().insert(7, tx);
+
+    let input = frame(&[
+      json!({ "jsonrpc": "2.0", "id": 7, "result": { "ok": true } }),
+      json!({
+        "jsonrpc": "2.0",
+        "id": "config",
+        "method": "workspace/configuration",
+        "params": { "items": [{ "section": "check.command" }, { "section": "missing" }] },
+      }),
+      json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "window/workDoneProgress/create",
+        "params": { "token": "index" },
+      }),
+      json!({
+        "jsonrpc": "2.0",
+        "method": "$/progress",
+        "params": { "token": "index", "value": { "kind": "begin", "title": "Indexing" } },
+      }),
+      json!({ "jsonrpc": "2.0", "id": 2, "method": "custom/unknown", "params": null }),
+    ]);
+    client
+      .dispatcher(PathBuf::from("/project"))
+      .read(Cursor::new(input));
+
+    assert_eq!(rx.try_recv().unwrap().unwrap(), json!({ "ok": true }));
+
+    let replies = unframe(&output.lock());
+    assert_eq!(replies.len(), 3);
+    assert_eq!(replies[0]["id"], "config");
+    assert_eq!(replies[0]["result"], json!(["clippy", null]));
+    assert_eq!(replies[1]["id"], 1);
+    assert_eq!(replies[1]["result"], Value::Null);
+    assert_eq!(replies[2]["id"], 2);
+    assert_eq!(replies[2]["error"]["code"], METHOD_NOT_FOUND);
+
+    let active = client.progress().active();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].title, "Indexing");
+    assert_eq!(active[0].percentage, None);
+  }
+
+  #[test]
+  fn test_semantic_tokens_refresh_clears_cache() {
+    let refreshed = Arc::new(AtomicU64::new(0));
+    let counter = refreshed.clone();
+    global_event_bus().subscribe::<SemanticTokensRefreshed, _>(move |event| {
+      if event.language == "refresh" {
+        counter.fetch_add(1, Ordering::SeqCst);
+      }
+    });
+
+    let client = LspClient::new("refresh");
+    let output = Arc::new(Mutex::new(Vec::new()));
+    *client.stdin.lock() = Some(Box::new(Sink(output.clone())));
+    let uri: Uri = "file:///project/main.rs".parse().unwrap();
+    client
+      .semantic_tokens
+      .lock()
+      .insert(uri, ("1".to_string(), Vec::new()));
+
+    let input = frame(&[json!({
+      "jsonrpc": "2.0",
+      "id": 3,
+      "method": "workspace/semanticTokens/refresh",
+      "params": null,
+    })]);
+    client
+      .dispatcher(PathBuf::from("/project"))
+      .read(Cursor::new(input));
+
+    let replies = unframe(&output.lock());
+    assert_eq!(replies[0]["id"], 3);
+    assert_eq!(replies[0]["result"], Value::Null);
+    assert!(client.semantic_tokens.lock().is_empty());
+    assert_eq!(refreshed.load(Ordering::SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn test_cancel_and_exit_end_requests() {
+    let client = Arc::new(LspClient::new("exit"));
+    let output = Arc::new(Mutex::new(Vec::new()));
+    *client.stdin.lock() = Some(Box::new(Sink(output.clone())));
+    *client.state.write() = LspClientState::Running;
+    let exits = client.exits();
+
+    // A request whose caller gives up is cancelled
+    let abandoned = client.request_raw::<Value>("custom/slow", Value::Null);
+    assert!(futures::FutureExt::now_or_never(abandoned).is_none());
+    let sent = unframe(&output.lock());
+    assert_eq!(sent[1]["method"], "$/cancelRequest");
+    assert_eq!(sent[1]["params"]["id"], sent[0]["id"]);
+    assert!(client.pending_requests.lock().is_empty());
+
+    // Requests still waiting when the server exits fail
+    let waiting = tokio::spawn({
+      let client = client.clone();
+      async move {
+        client
+          .request_raw::<Value>("custom/pending", Value::Null)
+          .await
+      }
+    });
+    while client.pending_requests.lock().is_empty() {
+      tokio::task::yield_now().await;
     }
+    client
+      .dispatcher(PathBuf::from("/project"))
+      .run(Cursor::new(Vec::new()));
+
+    let result = waiting.await.unwrap();
+    assert!(matches!(result, Err(Error::LspServerExited { .. })));
+    assert_eq!(client.state(), LspClientState::Failed);
+    assert!(exits.has_changed().unwrap());
   }
 }
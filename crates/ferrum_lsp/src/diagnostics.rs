@@ -0,0 +1,674 @@
+//! Diagnostics published by language servers
+//!
+//! Servers push diagnostics with `textDocument/publishDiagnostics`, each
+//! time replacing all earlier ones of a file. The store keeps the latest of
+//! every file, keyed by path. Each range is held as a pair of anchors, so
+//! while the file is open in a buffer, edits move the diagnostics with the
+//! text they point at until the server publishes fresh ones. Ranges are
+//! placed against the text the server diagnosed: the version it was last
+//! sent, moved through the edits made since. Diagnostics of a file that
+//! isn't open only have their LSP range until the file is opened, so the
+//! file is never read while handling a server's message.
+
+use crate::types::{char_to_position, range_to_chars, uri_to_path};
+use ferrum_buffer::{Anchor, Change};
+use ferrum_core::constants::LSP_DIAGNOSED_VERSIONS;
+use ferrum_core::event::{self, DiagnosticSeverity, DiagnosticsReceived};
+use lsp_types::{NumberOrString, PublishDiagnosticsParams};
+use parking_lot::RwLock;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// A diagnostic of a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDiagnostic {
+  /// Byte range in the file; `0..0` until the file is opened
+  pub range: Range<usize>,
+  /// The same range in LSP coordinates
+  pub lsp_range: lsp_types::Range,
+  pub severity: DiagnosticSeverity,
+  pub message: String,
+  pub source: Option<String>,
+  pub code: Option<String>,
+}
+
+impl From<&FileDiagnostic> for event::Diagnostic {
+  fn from(diagnostic: &FileDiagnostic) -> Self {
+    Self {
+      range: diagnostic.range.clone(),
+      severity: diagnostic.severity,
+      message: diagnostic.message.clone(),
+      source: diagnostic.source.clone(),
+      code: diagnostic.code.clone(),
+    }
+  }
+}
+
+/// Number of diagnostics of each severity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeverityCounts {
+  pub errors: usize,
+  pub warnings: usize,
+  pub infos: usize,
+  pub hints: usize,
+}
+
+impl SeverityCounts {
+  fn add(&mut self, severity: DiagnosticSeverity) {
+    match severity {
+      DiagnosticSeverity::Error => self.errors += 1,
+      DiagnosticSeverity::Warning => self.warnings += 1,
+      DiagnosticSeverity::Info => self.infos += 1,
+      DiagnosticSeverity::Hint => self.hints += 1,
+    }
+  }
+
+  fn extend(&mut self, other: SeverityCounts) {
+    self.errors += other.errors;
+    self.warnings += other.warnings;
+    self.infos += other.infos;
+    self.hints += other.hints;
+  }
+
+  /// Get the total number of diagnostics
+  pub fn total(&self) -> usize {
+    self.errors + self.warnings + self.infos + self.hints
+  }
+}
+
+/// The diagnostics of one file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathDiagnostics {
+  pub path: PathBuf,
+  /// Sorted by position
+  pub diagnostics: Vec<FileDiagnostic>,
+  pub counts: SeverityCounts,
+}
+
+/// A stored diagnostic and the anchors of its range
+struct Entry {
+  diagnostic: FileDiagnostic,
+  /// The diagnostic as published, handed back to the server in code action
+  /// requests
+  published: lsp_types::Diagnostic,
+  start: Anchor,
+  end: Anchor,
+}
+
+impl Entry {
+  /// Create an entry with only its LSP range, not yet placed in a text
+  fn new(diagnostic: lsp_types::Diagnostic) -> Self {
+    let severity = match diagnostic.severity {
+      Some(lsp_types::DiagnosticSeverity::WARNING) => DiagnosticSeverity::Warning,
+      Some(lsp_types::DiagnosticSeverity::INFORMATION) => DiagnosticSeverity::Info,
+      Some(lsp_types::DiagnosticSeverity::HINT) => DiagnosticSeverity::Hint,
+      // Errors, and diagnostics without a severity as most clients do
+      _ => DiagnosticSeverity::Error,
+    };
+    let code = diagnostic.code.clone().map(|code| match code {
+      NumberOrString::Number(number) => number.to_string(),
+      NumberOrString::String(string) => string,
+    });
+
+    Self {
+      diagnostic: FileDiagnostic {
+        range: 0..0,
+        lsp_range: diagnostic.range,
+        severity,
+        message: diagnostic.message.clone(),
+        source: diagnostic.source.clone(),
+        code,
+      },
+      published: diagnostic,
+      start: Anchor::right(0),
+      end: Anchor::left(0),
+    }
+  }
+
+  /// Create an entry placed in the text the server diagnosed
+  fn placed(diagnostic: lsp_types::Diagnostic, text: &Rope) -> Self {
+    let mut entry = Self::new(diagnostic);
+    entry.place(text);
+    entry
+  }
+
+  /// Anchor the LSP range in a text
+  fn place(&mut self, text: &Rope) {
+    let chars = range_to_chars(text, self.diagnostic.lsp_range);
+    let start = text.char_to_byte(chars.start);
+    let end = text.char_to_byte(chars.end).max(start);
+    // Text typed at either end is outside the range
+    self.start = Anchor::right(start);
+    self.end = Anchor::left(end);
+    self.diagnostic.range = start..end;
+  }
+
+  /// Move the range through an edit of the text, given in bytes
+  fn apply_edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+    self
+      .start
+      .apply_edit(start, old_end - start, new_end - start);
+    self.end.apply_edit(start, old_end - start, new_end - start);
+  }
+
+  /// Update the ranges from the anchors, in the text after the edits
+  fn update(&mut self, text: &Rope) {
+    let start = self.start.offset().min(text.len_bytes());
+    let end = self.end.offset().clamp(start, text.len_bytes());
+    self.diagnostic.range = start..end;
+    let position = |byte| char_to_position(text, text.byte_to_char(byte));
+    if let (Some(start), Some(end)) = (position(start), position(end)) {
+      self.diagnostic.lsp_range = lsp_types::Range::new(start, end);
+    }
+  }
+}
+
+/// Text of an open file a server was sent
+struct SentText {
+  /// Document version
+  version: i32,
+  /// Buffer version the text is at
+  buffer_version: u64,
+  text: Rope,
+}
+
+/// An edit of an open file's buffer, in bytes
+struct BufferEdit {
+  /// Buffer version after the edit
+  buffer_version: u64,
+  start: usize,
+  old_end: usize,
+  new_end: usize,
+}
+
+#[derive(Default)]
+struct FileEntry {
+  /// Text of the file's buffer, while it is open
+  text: Option<Rope>,
+  /// Texts the server may still publish diagnostics for, oldest first
+  sent: VecDeque<SentText>,
+  /// Edits made since the oldest text in `sent`
+  edits: Vec<BufferEdit>,
+  /// Language of the server that published the diagnostics
+  server: Option<String>,
+  /// Sorted by position
+  diagnostics: Vec<Entry>,
+}
+
+impl FileEntry {
+  fn diagnostics(&self) -> Vec<FileDiagnostic> {
+    self
+      .diagnostics
+      .iter()
+      .map(|e| e.diagnostic.clone())
+      .collect()
+  }
+
+  fn counts(&self) -> SeverityCounts {
+    let mut counts = SeverityCounts::default();
+    for entry in &self.diagnostics {
+      counts.add(entry.diagnostic.severity);
+    }
+    counts
+  }
+
+  /// Replace the diagnostics with those published for a document version
+  fn publish(&mut self, diagnostics: Vec<lsp_types::Diagnostic>, version: Option<i32>) {
+    let Some(text) = self.text.clone() else {
+      self.diagnostics = diagnostics.into_iter().map(Entry::new).collect();
+      return;
+    };
+
+    // Without a version, the server diagnosed the last text it was sent
+    let sent = match version {
+      Some(version) => self.sent.iter().position(|s| s.version == version),
+      None => self.sent.len().checked_sub(1),
+    };
+    let Some(index) = sent else {
+      self.diagnostics = diagnostics
+        .into_iter()
+        .map(|diagnostic| Entry::placed(diagnostic, &text))
+        .collect();
+      return;
+    };
+
+    // Servers don't go back to diagnosing older versions
+    self.sent.drain(..index);
+    let sent = &self.sent[0];
+    self
+      .edits
+      .retain(|edit| edit.buffer_version > sent.buffer_version);
+    self.diagnostics = diagnostics
+      .into_iter()
+      .map(|diagnostic| {
+        let mut entry = Entry::placed(diagnostic, &sent.text);
+        for edit in &self.edits {
+          entry.apply_edit(edit.start, edit.old_end, edit.new_end);
+        }
+        entry.update(&text);
+        entry
+      })
+      .collect();
+  }
+
+  /// Forget the texts sent to the server
+  fn clear_sent(&mut self) {
+    self.sent.clear();
+    self.edits.clear();
+  }
+
+  fn sort(&mut self) {
+    // Diagnostics not placed yet only differ in their LSP ranges
+    self.diagnostics.sort_by_key(|e| {
+      let diagnostic = &e.diagnostic;
+      (
+        diagnostic.range.start,
+        diagnostic.range.end,
+        diagnostic.lsp_range.start,
+        diagnostic.lsp_range.end,
+      )
+    });
+  }
+}
+
+/// Diagnostics of all files, from all language servers
+#[derive(Default)]
+pub struct DiagnosticStore {
+  files: RwLock<HashMap<PathBuf, FileEntry>>,
+}
+
+impl DiagnosticStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Replace the diagnostics of a file with those a server published
+  ///
+  /// Publishes `DiagnosticsReceived` on the global event bus.
+  pub fn publish(&self, server: &str, params: PublishDiagnosticsParams) {
+    let Some(path) = uri_to_path(&params.uri) else {
+      debug!("Diagnostics for a non-file URI: {}", params.uri.as_str());
+      return;
+    };
+
+    let diagnostics = {
+      let mut files = self.files.write();
+      let file = files.entry(path.clone()).or_default();
+      file.server = Some(server.to_string());
+      file.publish(params.diagnostics, params.version);
+      file.sort();
+      let diagnostics = file.diagnostics();
+      if file.diagnostics.is_empty() && file.text.is_none() {
+        files.remove(&path);
+      }
+      diagnostics
+    };
+    Self::notify(path, &diagnostics);
+  }
+
+  /// Drop the diagnostics a server published, e.g. when it stops
+  pub fn clear_server(&self, server: &str) {
+    let mut cleared = Vec::new();
+    self.files.write().retain(|path, file| {
+      if file.server.as_deref() != Some(server) {
+        return true;
+      }
+      file.server = None;
+      if !file.diagnostics.is_empty() {
+        file.diagnostics.clear();
+        cleared.push(path.clone());
+      }
+      file.text.is_some()
+    });
+    for path in cleared {
+      Self::notify(path, &[]);
+    }
+  }
+
+  fn notify(path: PathBuf, diagnostics: &[FileDiagnostic]) {
+    event::global_event_bus().publish(&DiagnosticsReceived {
+      path,
+      diagnostics: diagnostics.iter().map(Into::into).collect(),
+    });
+  }
+
+  /// Track the buffer of a file that was opened
+  ///
+  /// Diagnostics already published for the file are placed against the
+  /// buffer's text, and published again with their byte ranges.
+  pub fn open(&self, path: &Path, text: &Rope) {
+    let diagnostics = {
+      let mut files = self.files.write();
+      let file = files.entry(path.to_path_buf()).or_default();
+      for entry in &mut file.diagnostics {
+        entry.place(text);
+      }
+      file.sort();
+      file.clear_sent();
+      file.text = Some(text.clone());
+      file.diagnostics()
+    };
+    if !diagnostics.is_empty() {
+      Self::notify(path.to_path_buf(), &diagnostics);
+    }
+  }
+
+  /// Record the text of an open file a server was sent as `version`
+  ///
+  /// Diagnostics the server publishes for that version are placed against
+  /// this text and moved through the edits made since. Without the buffer
+  /// version the text is at, the edits can't be told apart, so diagnostics
+  /// are placed against the buffer's text as it is.
+  pub fn sent(&self, path: &Path, version: i32, buffer_version: Option<u64>, text: &Rope) {
+    let mut files = self.files.write();
+    let Some(file) = files.get_mut(path).filter(|f| f.text.is_some()) else {
+      return;
+    };
+    // Versions only go up while a document stays open in a server
+    let reopened = file.sent.back().is_some_and(|s| s.version >= version);
+    let Some(buffer_version) = buffer_version.filter(|_| !reopened) else {
+      file.clear_sent();
+      return;
+    };
+
+    file.sent.push_back(SentText {
+      version,
+      buffer_version,
+      text: text.clone(),
+    });
+    if file.sent.len() > LSP_DIAGNOSED_VERSIONS {
+      file.sent.pop_front();
+      let oldest = file.sent[0].buffer_version;
+      file.edits.retain(|edit| edit.buffer_version > oldest);
+    }
+  }
+
+  /// Move the diagnostics of an open file through changes to its buffer
+  ///
+  /// `text` is the buffer's text after the changes, at `buffer_version`.
+  pub fn apply_changes(&self, path: &Path, text: &Rope, buffer_version: u64, changes: &[Change]) {
+    let edits: Vec<_> = changes
+      .iter()
+      .map(|change| BufferEdit {
+        buffer_version,
+        start: change.start_byte,
+        old_end: change.old_end_byte,
+        new_end: change.new_end_byte,
+      })
+      .collect();
+    self.update(path, text, edits);
+  }
+
+  /// Move the diagnostics of an open file through an edit of its buffer
+  ///
+  /// `text` is the buffer's text after the edit, at `buffer_version`.
+  pub fn apply_edit(
+    &self,
+    path: &Path,
+    text: &Rope,
+    buffer_version: u64,
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+  ) {
+    let edit = BufferEdit {
+      buffer_version,
+      start,
+      old_end,
+      new_end,
+    };
+    self.update(path, text, vec![edit]);
+  }
+
+  fn update(&self, path: &Path, text: &Rope, edits: Vec<BufferEdit>) {
+    let mut files = self.files.write();
+    let Some(file) = files.get_mut(path).filter(|f| f.text.is_some()) else {
+      return;
+    };
+    for entry in &mut file.diagnostics {
+      for edit in &edits {
+        entry.apply_edit(edit.start, edit.old_end, edit.new_end);
+      }
+      entry.update(text);
+    }
+    if !file.sent.is_empty() {
+      file.edits.extend(edits);
+    }
+    file.text = Some(text.clone());
+  }
+
+  /// Stop tracking the buffer of a file that was closed
+  ///
+  /// Its diagnostics keep their last positions.
+  pub fn close(&self, path: &Path) {
+    let mut files = self.files.write();
+    if let Some(file) = files.get_mut(path) {
+      file.text = None;
+      file.clear_sent();
+      if file.diagnostics.is_empty() {
+        files.remove(path);
+      }
+    }
+  }
+
+  /// Get the diagnostics of a file, sorted by position
+  pub fn file(&self, path: &Path) -> Vec<FileDiagnostic> {
+    self
+      .files
+      .read()
+      .get(path)
+      .map(FileEntry::diagnostics)
+      .unwrap_or_default()
+  }
+
+  /// Get the diagnostics of a file overlapping or touching an LSP range,
+  /// as published but with their current ranges
+  ///
+  /// These are the context of a code action request for the range.
+  pub fn overlapping(&self, path: &Path, range: lsp_types::Range) -> Vec<lsp_types::Diagnostic> {
+    let files = self.files.read();
+    let Some(file) = files.get(path) else {
+      return Vec::new();
+    };
+    file
+      .diagnostics
+      .iter()
+      .filter(|e| e.diagnostic.lsp_range.start <= range.end)
+      .filter(|e| range.start <= e.diagnostic.lsp_range.end)
+      .map(|e| lsp_types::Diagnostic {
+        range: e.diagnostic.lsp_range,
+        ..e.published.clone()
+      })
+      .collect()
+  }
+
+  /// Count the diagnostics of a file by severity
+  pub fn counts(&self, path: &Path) -> SeverityCounts {
+    self
+      .files
+      .read()
+      .get(path)
+      .map(FileEntry::counts)
+      .unwrap_or_default()
+  }
+
+  /// Get the diagnostics of every file that has any, sorted by path
+  pub fn project(&self) -> Vec<PathDiagnostics> {
+    let files = self.files.read();
+    let mut project: Vec<PathDiagnostics> = files
+      .iter()
+      .filter(|(_, file)| !file.diagnostics.is_empty())
+      .map(|(path, file)| PathDiagnostics {
+        path: path.clone(),
+        diagnostics: file.diagnostics(),
+        counts: file.counts(),
+      })
+      .collect();
+    project.sort_by(|a, b| a.path.cmp(&b.path));
+    project
+  }
+
+  /// Count the diagnostics of all files by severity
+  pub fn total_counts(&self) -> SeverityCounts {
+    let mut counts = SeverityCounts::default();
+    for file in self.files.read().values() {
+      counts.extend(file.counts());
+    }
+    counts
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lsp_types::{Diagnostic, Position, Uri};
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  fn diagnostic(
+    line: u32,
+    start: u32,
+    end: u32,
+    severity: lsp_types::DiagnosticSeverity,
+  ) -> Diagnostic {
+    Diagnostic {
+      range: lsp_types::Range::new(Position::new(line, start), Position::new(line, end)),
+      severity: Some(severity),
+      message: format!("at {}:{}", line, start),
+      ..Default::default()
+    }
+  }
+
+  fn params(path: &str, diagnostics: Vec<Diagnostic>) -> PublishDiagnosticsParams {
+    let uri: Uri = format!("file://{}", path).parse().unwrap();
+    PublishDiagnosticsParams::new(uri, diagnostics, None)
+  }
+
+  #[test]
+  fn test_diagnostics_move_with_edits() {
+    let store = DiagnosticStore::new();
+    let path = Path::new("/diagnostics/edits.rs");
+    let text = Rope::from_str("let x = 1;\nlet y = z;\n");
+    store.open(path, &text);
+    store.publish(
+      "rust",
+      params(
+        "/diagnostics/edits.rs",
+        vec![
+          diagnostic(1, 8, 9, lsp_types::DiagnosticSeverity::ERROR),
+          diagnostic(0, 4, 5, lsp_types::DiagnosticSeverity::WARNING),
+        ],
+      ),
+    );
+
+    let diagnostics = store.file(path);
+    assert_eq!(diagnostics[0].range, 4..5);
+    assert_eq!(diagnostics[1].range, 19..20);
+
+    // Insert a line at the top
+    let edited = Rope::from_str("// hi\nlet x = 1;\nlet y = z;\n");
+    store.apply_edit(path, &edited, 1, 0, 0, 6);
+    let diagnostics = store.file(path);
+    assert_eq!(diagnostics[1].range, 25..26);
+    assert_eq!(diagnostics[1].lsp_range.start, Position::new(2, 8));
+
+    // Code action context gets the diagnostics at the cursor, where they are now
+    let cursor = Position::new(2, 9);
+    let context = store.overlapping(path, lsp_types::Range::new(cursor, cursor));
+    assert_eq!(context.len(), 1);
+    assert_eq!(context[0].message, "at 1:8");
+    assert_eq!(context[0].range.start, Position::new(2, 8));
+
+    let counts = store.counts(path);
+    assert_eq!((counts.errors, counts.warnings, counts.total()), (1, 1, 2));
+    assert_eq!(store.total_counts(), counts);
+
+    // Closing keeps the diagnostics, an empty publish clears them
+    store.close(path);
+    assert_eq!(store.project().len(), 1);
+    store.publish("rust", params("/diagnostics/edits.rs", Vec::new()));
+    assert!(store.project().is_empty());
+  }
+
+  #[test]
+  fn test_diagnostics_are_placed_against_the_diagnosed_version() {
+    let store = DiagnosticStore::new();
+    let path = Path::new("/diagnostics/versions.rs");
+    let text = Rope::from_str("let x = 1;\nlet y = z;\n");
+    store.open(path, &text);
+    store.sent(path, 1, Some(0), &text);
+
+    // A line is typed at the top before the server publishes for version 1
+    let edited = Rope::from_str("// hi\nlet x = 1;\nlet y = z;\n");
+    store.apply_edit(path, &edited, 1, 0, 0, 6);
+    let mut published = params(
+      "/diagnostics/versions.rs",
+      vec![diagnostic(1, 8, 9, lsp_types::DiagnosticSeverity::ERROR)],
+    );
+    published.version = Some(1);
+    store.publish("rust", published.clone());
+    let diagnostics = store.file(path);
+    assert_eq!(diagnostics[0].range, 25..26);
+    assert_eq!(diagnostics[0].lsp_range.start, Position::new(2, 8));
+
+    // Unversioned diagnostics are for the last text sent
+    published.version = None;
+    store.publish("rust", published.clone());
+    assert_eq!(store.file(path)[0].range, 25..26);
+
+    // Once version 2 is sent, version 1 is no longer known and falls back
+    // to the text as it is
+    store.sent(path, 2, Some(1), &edited);
+    let mut current = params(
+      "/diagnostics/versions.rs",
+      vec![diagnostic(2, 8, 9, lsp_types::DiagnosticSeverity::ERROR)],
+    );
+    current.version = Some(2);
+    store.publish("rust", current);
+    assert_eq!(store.file(path)[0].range, 25..26);
+    published.version = Some(1);
+    store.publish("rust", published);
+    assert_eq!(store.file(path)[0].range, 14..15);
+  }
+
+  #[test]
+  fn test_publish_and_clear_server() {
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = received.clone();
+    event::global_event_bus().subscribe::<DiagnosticsReceived, _>(move |event| {
+      if event.path == Path::new("/diagnostics/closed.py") {
+        counter.store(event.diagnostics.len(), Ordering::SeqCst);
+      }
+    });
+
+    // The file isn't open, so only the LSP range is known until it is
+    let store = DiagnosticStore::new();
+    let path = Path::new("/diagnostics/closed.py");
+    store.publish(
+      "python",
+      params(
+        "/diagnostics/closed.py",
+        vec![diagnostic(1, 2, 3, lsp_types::DiagnosticSeverity::HINT)],
+      ),
+    );
+    assert_eq!(received.load(Ordering::SeqCst), 1);
+    let project = store.project();
+    assert_eq!(project[0].counts.hints, 1);
+    assert_eq!(project[0].diagnostics[0].range, 0..0);
+    assert_eq!(project[0].diagnostics[0].lsp_range.start.line, 1);
+
+    // Opening the file places them in its text, counting UTF-16 columns
+    store.open(path, &Rope::from_str("x = 1\né = 'ab'\n"));
+    assert_eq!(store.file(path)[0].range, 9..10);
+
+    store.clear_server("rust");
+    assert_eq!(store.project().len(), 1);
+    store.clear_server("python");
+    assert!(store.project().is_empty());
+    assert_eq!(received.load(Ordering::SeqCst), 0);
+  }
+}
@@ -0,0 +1,169 @@
+//! Handlers for messages sent by language servers
+//!
+//! Besides answering the client's requests, servers send their own:
+//! requests the client must reply to, such as `workspace/configuration` or
+//! `workspace/applyEdit`, and notifications. The client answers the common
+//! ones itself; handlers registered here take over a request method, or are
+//! run in addition for a notification. Handlers run on the thread reading
+//! the server's output, so they should return quickly.
+
+use ferrum_core::prelude::*;
+use lsp_types::notification::Notification;
+use lsp_types::request::Request;
+use parking_lot::RwLock;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// JSON-RPC error code for a method the client doesn't handle
+pub(crate) const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code for parameters that don't match the method
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+/// LSP error code for a request that was valid but failed
+pub(crate) const REQUEST_FAILED: i64 = -32803;
+
+/// Error replied to a server request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ResponseError {
+  pub code: i64,
+  pub message: String,
+}
+
+impl ResponseError {
+  pub(crate) fn new(code: i64, message: impl Into<String>) -> Self {
+    Self {
+      code,
+      message: message.into(),
+    }
+  }
+}
+
+type RequestHandler = Arc<dyn Fn(Value) -> std::result::Result<Value, ResponseError> + Send + Sync>;
+type NotificationHandler = Arc<dyn Fn(Value) + Send + Sync>;
+
+/// Handlers for requests and notifications from language servers
+#[derive(Default)]
+pub struct ServerHandlers {
+  requests: RwLock<HashMap<String, RequestHandler>>,
+  notifications: RwLock<HashMap<String, Vec<NotificationHandler>>>,
+}
+
+impl ServerHandlers {
+  /// Create an empty set of handlers
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Handle a request method, replacing any earlier handler of it
+  pub fn on_request<R, F>(&self, handler: F)
+  where
+    R: Request,
+    R::Params: DeserializeOwned,
+    R::Result: Serialize,
+    F: Fn(R::Params) -> Result<R::Result> + Send + Sync + 'static,
+  {
+    let handler: RequestHandler = Arc::new(move |params| {
+      let params = serde_json::from_value(params)
+        .map_err(|e| ResponseError::new(INVALID_PARAMS, e.to_string()))?;
+      let result =
+        handler(params).map_err(|e| ResponseError::new(REQUEST_FAILED, e.to_string()))?;
+      serde_json::to_value(result).map_err(|e| ResponseError::new(REQUEST_FAILED, e.to_string()))
+    });
+    self.requests.write().insert(R::METHOD.to_string(), handler);
+  }
+
+  /// Handle a notification method
+  pub fn on_notification<N, F>(&self, handler: F)
+  where
+    N: Notification,
+    N::Params: DeserializeOwned,
+    F: Fn(N::Params) + Send + Sync + 'static,
+  {
+    let handler: NotificationHandler =
+      Arc::new(move |params| match serde_json::from_value(params) {
+        Ok(params) => handler(params),
+        Err(e) => tracing::error!("Invalid {} params: {}", N::METHOD, e),
+      });
+    self
+      .notifications
+      .write()
+      .entry(N::METHOD.to_string())
+      .or_default()
+      .push(handler);
+  }
+
+  /// Check if a request method has a handler
+  pub fn handles_request(&self, method: &str) -> bool {
+    self.requests.read().contains_key(method)
+  }
+
+  /// Run the handler of a request, if it has one
+  pub(crate) fn request(
+    &self,
+    method: &str,
+    params: Value,
+  ) -> Option<std::result::Result<Value, ResponseError>> {
+    let handler = self.requests.read().get(method).cloned()?;
+    Some(handler(params))
+  }
+
+  /// Run the handlers of a notification
+  pub(crate) fn notify(&self, method: &str, params: &Value) {
+    let handlers = self.notifications.read().get(method).cloned();
+    for handler in handlers.into_iter().flatten() {
+      handler(params.clone());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lsp_types::notification::LogMessage;
+  use lsp_types::request::ApplyWorkspaceEdit;
+  use lsp_types::{ApplyWorkspaceEditResponse, MessageType};
+  use serde_json::json;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test]
+  fn test_typed_handlers() {
+    let handlers = ServerHandlers::new();
+    assert!(
+      handlers
+        .request(ApplyWorkspaceEdit::METHOD, json!({}))
+        .is_none()
+    );
+
+    handlers.on_request::<ApplyWorkspaceEdit, _>(|params| {
+      Ok(ApplyWorkspaceEditResponse {
+        applied: params.label.is_some(),
+        failure_reason: None,
+        failed_change: None,
+      })
+    });
+    assert!(handlers.handles_request(ApplyWorkspaceEdit::METHOD));
+
+    let reply = handlers.request(
+      ApplyWorkspaceEdit::METHOD,
+      json!({ "label": "Rename", "edit": {} }),
+    );
+    assert_eq!(reply, Some(Ok(json!({ "applied": true }))));
+
+    let reply = handlers.request(ApplyWorkspaceEdit::METHOD, json!({ "label": 1 }));
+    assert_eq!(reply.unwrap().unwrap_err().code, INVALID_PARAMS);
+
+    let logged = Arc::new(AtomicUsize::new(0));
+    let counter = logged.clone();
+    handlers.on_notification::<LogMessage, _>(move |params| {
+      assert_eq!(params.typ, MessageType::INFO);
+      counter.fetch_add(1, Ordering::SeqCst);
+    });
+    handlers.notify(
+      LogMessage::METHOD,
+      &json!({ "type": 3, "message": "ready" }),
+    );
+    handlers.notify(LogMessage::METHOD, &json!({ "message": "no type" }));
+    assert_eq!(logged.load(Ordering::SeqCst), 1);
+  }
+}
@@ -3,8 +3,18 @@
 //! Language Server Protocol client for code intelligence.
 
 pub mod client;
+pub mod diagnostics;
+pub mod handlers;
 pub mod manager;
+pub mod progress;
+pub mod semantic_tokens;
+pub mod sync;
 pub mod types;
 
 pub use client::{LspClient, LspClientState};
+pub use diagnostics::{DiagnosticStore, FileDiagnostic, PathDiagnostics, SeverityCounts};
+pub use handlers::ServerHandlers;
 pub use manager::{LspManager, LspServerConfig};
+pub use progress::ProgressTracker;
+pub use semantic_tokens::SemanticToken;
+pub use sync::{ClosedDocument, DocumentSync};
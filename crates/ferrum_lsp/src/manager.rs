@@ -3,12 +3,22 @@
 //! Manages multiple LSP servers for different languages.
 
 use dashmap::DashMap;
+use ferrum_core::constants::{
+  LSP_CRASH_WINDOW_MS, LSP_MAX_CRASHES, LSP_RESTART_BACKOFF_MAX_MS, LSP_RESTART_BACKOFF_MS,
+};
+use ferrum_core::event::LspProgress;
 use ferrum_core::prelude::*;
+use serde_json::Value;
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::{info, warn};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
 
 use crate::client::{LspClient, LspClientState};
+use crate::diagnostics::DiagnosticStore;
+use crate::handlers::ServerHandlers;
+use crate::sync::DocumentSync;
 
 /// LSP server configuration for a language
 #[derive(Debug, Clone)]
@@ -21,6 +31,8 @@ pub struct LspServerConfig {
   pub args: Vec<String>,
   /// File extensions this server handles
   pub extensions: Vec<String>,
+  /// Settings served to the server's `workspace/configuration` requests
+  pub settings: Value,
 }
 
 impl LspServerConfig {
@@ -31,8 +43,15 @@ impl LspServerConfig {
       command: command.to_string(),
       args: args.into_iter().map(String::from).collect(),
       extensions: extensions.into_iter().map(String::from).collect(),
+      settings: Value::Null,
     }
   }
+
+  /// Set the settings served to the server
+  pub fn with_settings(mut self, settings: Value) -> Self {
+    self.settings = settings;
+    self
+  }
 }
 
 /// Get default LSP server configurations for common languages
@@ -113,6 +132,9 @@ pub struct LspManager {
   clients: DashMap<String, Arc<LspClient>>,
   configs: DashMap<String, LspServerConfig>,
   extension_map: DashMap<String, String>,
+  diagnostics: Arc<DiagnosticStore>,
+  handlers: Arc<ServerHandlers>,
+  sync: Arc<DocumentSync>,
 }
 
 impl LspManager {
@@ -122,6 +144,9 @@ impl LspManager {
       clients: DashMap::new(),
       configs: DashMap::new(),
       extension_map: DashMap::new(),
+      diagnostics: Arc::new(DiagnosticStore::new()),
+      handlers: Arc::new(ServerHandlers::new()),
+      sync: Arc::new(DocumentSync::new()),
     };
 
     // Load default configs
@@ -145,6 +170,34 @@ impl LspManager {
     self.configs.get(language).map(|v| v.clone())
   }
 
+  /// Get the diagnostics published by all servers
+  pub fn diagnostics(&self) -> Arc<DiagnosticStore> {
+    self.diagnostics.clone()
+  }
+
+  /// Get the store keeping open documents in sync with all servers
+  pub fn sync(&self) -> Arc<DocumentSync> {
+    self.sync.clone()
+  }
+
+  /// Get the handlers for requests and notifications from all servers
+  ///
+  /// Handlers registered after a server started still apply to it, but
+  /// capabilities depending on them are only declared to servers started
+  /// later.
+  pub fn handlers(&self) -> Arc<ServerHandlers> {
+    self.handlers.clone()
+  }
+
+  /// Get the work in flight of all running servers
+  pub fn progress(&self) -> Vec<LspProgress> {
+    self
+      .clients
+      .iter()
+      .flat_map(|e| e.value().progress().active())
+      .collect()
+  }
+
   /// Get an existing client for a language (if running)
   pub fn get_client(&self, language: &str) -> Option<Arc<LspClient>> {
     self.clients.get(language).map(|v| v.clone())
@@ -152,17 +205,24 @@ impl LspManager {
 
   /// Start an LSP server for a language
   pub async fn start(&self, language: &str, root_path: PathBuf) -> Result<Arc<LspClient>> {
-    // Check if already running
-    if let Some(client) = self.clients.get(language) {
-      if client.state() == LspClientState::Running {
-        return Ok(client.clone());
+    // Check if already running, or being restarted
+    let existing = self.get_client(language);
+    let mut documents = Vec::new();
+    if let Some(client) = existing {
+      match client.state() {
+        LspClientState::Running | LspClientState::Starting => return Ok(client),
+        // Replaced, so a server that crashed too often is started afresh,
+        // with the documents that were open in it
+        _ => {
+          client.stop().await?;
+          documents = self.sync.close_server(language);
+        },
       }
     }
 
     // Get config
     let config = self
-      .configs
-      .get(language)
+      .config_for(language)
       .ok_or_else(|| Error::LspNotRunning {
         language: format!("No LSP config for language: {}", language),
       })?;
@@ -170,13 +230,35 @@ impl LspManager {
     info!("Starting LSP server for {}: {}", language, config.command);
 
     // Create and start client
-    let client = Arc::new(LspClient::new(language));
+    let client = Arc::new(
+      LspClient::with_diagnostics(language, self.diagnostics.clone())
+        .with_handlers(self.handlers.clone()),
+    );
+    client.set_settings(config.settings.clone())?;
     let args: Vec<&str> = config.args.iter().map(String::as_str).collect();
 
     client.start(&config.command, &args, root_path).await?;
 
     // Store client
     self.clients.insert(language.to_string(), client.clone());
+    tokio::spawn(supervise(client.clone(), self.sync.clone()));
+
+    for document in documents {
+      let opened = self.sync.open(
+        client.clone(),
+        &document.path,
+        &document.language_id,
+        document.version + 1,
+        document.text,
+        document.buffer_version,
+      );
+      if let Err(e) = opened {
+        warn!(
+          "Failed to reopen {:?} in its language server: {}",
+          document.path, e
+        );
+      }
+    }
 
     Ok(client)
   }
@@ -184,6 +266,7 @@ impl LspManager {
   /// Stop an LSP server
   pub async fn stop(&self, language: &str) -> Result<()> {
     if let Some((_, client)) = self.clients.remove(language) {
+      self.sync.close_server(language);
       client.stop().await?;
     }
     Ok(())
@@ -226,8 +309,112 @@ impl LspManager {
   }
 }
 
+/// Restart a client's server whenever it crashes, until it is stopped or
+/// crashes too often
+async fn supervise(client: Arc<LspClient>, sync: Arc<DocumentSync>) {
+  let mut exits = client.exits();
+  let mut crashes = CrashTracker::default();
+  loop {
+    if exits.changed().await.is_err() {
+      return;
+    }
+
+    while client.state() == LspClientState::Failed {
+      let Some(delay) = crashes.record(Instant::now()) else {
+        error!(
+          "LSP server for {} keeps crashing, not restarting it",
+          client.language()
+        );
+        return;
+      };
+      warn!(
+        "LSP server for {} crashed, restarting in {:?}",
+        client.language(),
+        delay
+      );
+      tokio::time::sleep(delay).await;
+      if client.state() != LspClientState::Failed {
+        break;
+      }
+
+      match client.restart().await {
+        Ok(()) => {
+          exits.borrow_and_update();
+          sync.reopen_server(client.language());
+          info!("LSP server for {} restarted", client.language());
+        },
+        Err(e) => warn!(
+          "Failed to restart LSP server for {}: {}",
+          client.language(),
+          e
+        ),
+      }
+    }
+
+    if client.state() == LspClientState::Stopped {
+      return;
+    }
+  }
+}
+
+/// Recent crashes of a server, spacing out its restarts and catching crash
+/// loops
+#[derive(Debug, Default)]
+struct CrashTracker {
+  crashes: VecDeque<Instant>,
+}
+
+impl CrashTracker {
+  /// Record a crash, returning how long to wait before restarting the
+  /// server, or `None` if it crashed too often to restart
+  fn record(&mut self, now: Instant) -> Option<Duration> {
+    let window = Duration::from_millis(LSP_CRASH_WINDOW_MS);
+    while let Some(&crash) = self.crashes.front() {
+      if now.duration_since(crash) <= window {
+        break;
+      }
+      self.crashes.pop_front();
+    }
+    self.crashes.push_back(now);
+    if self.crashes.len() > LSP_MAX_CRASHES {
+      return None;
+    }
+
+    let backoff = LSP_RESTART_BACKOFF_MS.saturating_mul(1 << (self.crashes.len() - 1));
+    Some(Duration::from_millis(
+      backoff.min(LSP_RESTART_BACKOFF_MAX_MS),
+    ))
+  }
+}
+
 impl Default for LspManager {
   fn default() -> Self {
     Self::new()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_restarts_back_off_until_crash_loop() {
+    let mut crashes = CrashTracker::default();
+    let start = Instant::now();
+
+    let delays: Vec<_> = (0..LSP_MAX_CRASHES as u64)
+      .map(|i| crashes.record(start + Duration::from_secs(i)).unwrap())
+      .collect();
+    assert_eq!(delays[0], Duration::from_millis(LSP_RESTART_BACKOFF_MS));
+    assert_eq!(delays[1], Duration::from_millis(LSP_RESTART_BACKOFF_MS * 2));
+    assert!(delays.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(crashes.record(start + Duration::from_secs(10)), None);
+
+    // Crashes long ago no longer count
+    let later = start + Duration::from_millis(LSP_CRASH_WINDOW_MS) + Duration::from_secs(60);
+    assert_eq!(
+      crashes.record(later),
+      Some(Duration::from_millis(LSP_RESTART_BACKOFF_MS))
+    );
+  }
+}
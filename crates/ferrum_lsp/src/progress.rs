@@ -0,0 +1,216 @@
+//! Work done progress reported by language servers
+//!
+//! Servers ask for a token with `window/workDoneProgress/create`, then
+//! report work under it with `$/progress` notifications: one `begin`, any
+//! number of `report`s and one `end`. Each piece of work in flight is
+//! tracked as a [`TaskProgress`], and every change is published as an
+//! [`LspProgress`] event so the UI can show "indexing…" indicators.
+
+use ferrum_core::event::{LspProgress, global_event_bus};
+use ferrum_core::task::TaskProgress;
+use lsp_types::{
+  NumberOrString, ProgressParams, ProgressParamsValue, ProgressToken, WorkDoneProgress,
+};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A piece of work a server reports progress on
+struct Work {
+  title: String,
+  /// Whether the server reported a percentage
+  percentage: bool,
+  progress: Arc<TaskProgress>,
+}
+
+impl Work {
+  fn new(title: String) -> Self {
+    Self {
+      title,
+      percentage: false,
+      progress: Arc::new(TaskProgress::new()),
+    }
+  }
+}
+
+/// Tracks the work in flight of one language server
+pub struct ProgressTracker {
+  language: String,
+  work: Mutex<HashMap<String, Work>>,
+}
+
+impl ProgressTracker {
+  /// Create a tracker for a language's server
+  pub fn new(language: impl Into<String>) -> Self {
+    Self {
+      language: language.into(),
+      work: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Create a token for the server to report progress under
+  pub fn create(&self, token: &ProgressToken) {
+    self
+      .work
+      .lock()
+      .insert(token_key(token), Work::new(String::new()));
+  }
+
+  /// Apply a `$/progress` notification, and publish the change
+  pub fn update(&self, params: ProgressParams) -> LspProgress {
+    let token = token_key(&params.token);
+    let ProgressParamsValue::WorkDone(value) = params.value;
+
+    let mut work = self.work.lock();
+    let update = match value {
+      WorkDoneProgress::Begin(begin) => {
+        let mut entry = Work::new(begin.title);
+        entry.progress.set_message(begin.message);
+        if let Some(percentage) = begin.percentage {
+          entry.percentage = true;
+          entry.progress.set_current(percentage as u64);
+        }
+        let update = self.snapshot(&token, &entry, false);
+        work.insert(token, entry);
+        update
+      },
+      WorkDoneProgress::Report(report) => {
+        let entry = work
+          .entry(token.clone())
+          .or_insert_with(|| Work::new(String::new()));
+        if report.message.is_some() {
+          entry.progress.set_message(report.message);
+        }
+        if let Some(percentage) = report.percentage {
+          entry.percentage = true;
+          entry.progress.set_current(percentage as u64);
+        }
+        self.snapshot(&token, entry, false)
+      },
+      WorkDoneProgress::End(end) => {
+        let entry = work
+          .remove(&token)
+          .unwrap_or_else(|| Work::new(String::new()));
+        entry.progress.set_message(end.message);
+        if entry.percentage {
+          entry.progress.set_current(100);
+        }
+        self.snapshot(&token, &entry, true)
+      },
+    };
+    drop(work);
+
+    global_event_bus().publish(&update);
+    update
+  }
+
+  /// Get the progress of a piece of work in flight
+  pub fn task(&self, token: &str) -> Option<Arc<TaskProgress>> {
+    self
+      .work
+      .lock()
+      .get(token)
+      .map(|work| work.progress.clone())
+  }
+
+  /// Get all work in flight that has begun
+  pub fn active(&self) -> Vec<LspProgress> {
+    let mut active: Vec<LspProgress> = self
+      .work
+      .lock()
+      .iter()
+      .filter(|(_, work)| !work.title.is_empty())
+      .map(|(token, work)| self.snapshot(token, work, false))
+      .collect();
+    active.sort_by(|a, b| a.token.cmp(&b.token));
+    active
+  }
+
+  /// Forget all work, as when the server stops
+  pub fn clear(&self) {
+    let tokens: Vec<String> = self.work.lock().drain().map(|(token, _)| token).collect();
+    for token in tokens {
+      global_event_bus().publish(&LspProgress {
+        language: self.language.clone(),
+        token,
+        title: String::new(),
+        message: None,
+        percentage: None,
+        done: true,
+      });
+    }
+  }
+
+  fn snapshot(&self, token: &str, work: &Work, done: bool) -> LspProgress {
+    LspProgress {
+      language: self.language.clone(),
+      token: token.to_string(),
+      title: work.title.clone(),
+      message: work.progress.message(),
+      percentage: work.percentage.then(|| work.progress.percentage()),
+      done,
+    }
+  }
+}
+
+/// Key a progress token is tracked under
+fn token_key(token: &ProgressToken) -> String {
+  match token {
+    NumberOrString::Number(n) => n.to_string(),
+    NumberOrString::String(s) => s.clone(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lsp_types::{WorkDoneProgressBegin, WorkDoneProgressEnd, WorkDoneProgressReport};
+
+  fn params(token: &str, value: WorkDoneProgress) -> ProgressParams {
+    ProgressParams {
+      token: NumberOrString::String(token.to_string()),
+      value: ProgressParamsValue::WorkDone(value),
+    }
+  }
+
+  #[test]
+  fn test_progress_lifecycle() {
+    let tracker = ProgressTracker::new("rust");
+    tracker.create(&NumberOrString::String("index".to_string()));
+    assert!(tracker.active().is_empty());
+
+    let begin = tracker.update(params(
+      "index",
+      WorkDoneProgress::Begin(WorkDoneProgressBegin {
+        title: "Indexing".to_string(),
+        percentage: Some(0),
+        ..Default::default()
+      }),
+    ));
+    assert_eq!(begin.title, "Indexing");
+    assert_eq!(begin.percentage, Some(0));
+    assert!(!begin.done);
+
+    let report = tracker.update(params(
+      "index",
+      WorkDoneProgress::Report(WorkDoneProgressReport {
+        message: Some("12/48 crates".to_string()),
+        percentage: Some(25),
+        ..Default::default()
+      }),
+    ));
+    assert_eq!(report.message.as_deref(), Some("12/48 crates"));
+    assert_eq!(tracker.task("index").unwrap().percentage(), 25);
+    assert_eq!(tracker.active(), vec![report]);
+
+    let end = tracker.update(params(
+      "index",
+      WorkDoneProgress::End(WorkDoneProgressEnd { message: None }),
+    ));
+    assert!(end.done);
+    assert_eq!(end.title, "Indexing");
+    assert_eq!(end.percentage, Some(100));
+    assert!(tracker.task("index").is_none());
+    assert!(tracker.active().is_empty());
+  }
+}
@@ -0,0 +1,210 @@
+//! Semantic tokens
+//!
+//! Language servers send semantic tokens as a flat, relatively encoded
+//! array: each token's line is a delta from the previous token, its start
+//! column a delta on the same line, columns count UTF-16 code units, and
+//! its type and modifiers index into the server's legend. These helpers
+//! apply delta responses to that array and decode it into byte ranges, as
+//! the syntax highlights use.
+
+use ferrum_buffer::coords;
+use ferrum_buffer::position::Point;
+use ferrum_buffer::syntax::HighlightKind;
+use lsp_types::{SemanticTokensEdit, SemanticTokensLegend};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Numbers in the encoded array per token
+const TOKEN_LEN: usize = 5;
+
+/// A decoded semantic token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SemanticToken {
+  /// Byte range in the document
+  pub range: Range<usize>,
+  /// Token type from the legend, e.g. `variable` or `interface`
+  pub token_type: String,
+  /// Token modifiers from the legend, e.g. `mutable` or `unsafe`
+  pub modifiers: Vec<String>,
+}
+
+impl SemanticToken {
+  /// Get the highlight for this token's type
+  ///
+  /// `None` for types tree-sitter already highlights as well as the server,
+  /// such as punctuation, or that have no highlight.
+  pub fn highlight_kind(&self) -> Option<HighlightKind> {
+    let has = |modifier: &str| self.modifiers.iter().any(|m| m == modifier);
+
+    Some(match self.token_type.as_str() {
+      "namespace" | "crateRoot" => HighlightKind::Namespace,
+      "type" | "class" | "enum" | "interface" | "struct" | "typeParameter" | "typeAlias"
+      | "union" | "trait" => HighlightKind::Type,
+      "builtinType" => HighlightKind::TypeBuiltin,
+      "parameter" => HighlightKind::VariableParameter,
+      "variable" if has("constant") || (has("static") && has("readonly")) => {
+        HighlightKind::Constant
+      },
+      "variable" => HighlightKind::Variable,
+      "selfKeyword" | "selfTypeKeyword" => HighlightKind::VariableBuiltin,
+      "property" | "event" => HighlightKind::Property,
+      "enumMember" | "constParameter" => HighlightKind::Constant,
+      "function" if has("defaultLibrary") => HighlightKind::FunctionBuiltin,
+      "function" => HighlightKind::Function,
+      "method" => HighlightKind::FunctionMethod,
+      "macro" | "derive" => HighlightKind::FunctionMacro,
+      "keyword" | "modifier" => HighlightKind::Keyword,
+      "comment" if has("documentation") => HighlightKind::CommentDoc,
+      "comment" => HighlightKind::Comment,
+      "string" => HighlightKind::String,
+      "regexp" | "escapeSequence" | "formatSpecifier" => HighlightKind::StringSpecial,
+      "number" => HighlightKind::Number,
+      "boolean" => HighlightKind::ConstantBuiltin,
+      "operator" => HighlightKind::Operator,
+      "decorator" | "attribute" | "attributeBracket" => HighlightKind::Attribute,
+      "label" | "lifetime" => HighlightKind::Label,
+      _ => return None,
+    })
+  }
+}
+
+/// Apply the edits of a delta response to the previous tokens
+///
+/// Edit offsets count numbers in the encoded array, and all refer to the
+/// previous array.
+pub fn apply_edits(tokens: &mut Vec<lsp_types::SemanticToken>, mut edits: Vec<SemanticTokensEdit>) {
+  edits.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+  for edit in edits {
+    let start = (edit.start as usize / TOKEN_LEN).min(tokens.len());
+    let end = (start + edit.delete_count as usize / TOKEN_LEN).min(tokens.len());
+    tokens.splice(start..end, edit.data.unwrap_or_default());
+  }
+}
+
+/// Decode tokens into byte ranges, using the legend for type and modifier
+/// names
+///
+/// Tokens outside the text, e.g. from a response to an older version, are
+/// dropped.
+pub fn decode(
+  rope: &Rope,
+  legend: &SemanticTokensLegend,
+  tokens: &[lsp_types::SemanticToken],
+) -> Vec<SemanticToken> {
+  let mut decoded = Vec::with_capacity(tokens.len());
+  let (mut line, mut column) = (0usize, 0usize);
+
+  for token in tokens {
+    if token.delta_line > 0 {
+      line += token.delta_line as usize;
+      column = 0;
+    }
+    column += token.delta_start as usize;
+
+    let Some(token_type) = legend.token_types.get(token.token_type as usize) else {
+      continue;
+    };
+    let start = coords::utf16_point_to_char(rope, Point::new(line, column));
+    let end = coords::utf16_point_to_char(rope, Point::new(line, column + token.length as usize));
+
+    let modifiers = legend
+      .token_modifiers
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| *i < 32 && token.token_modifiers_bitset & (1 << i) != 0)
+      .map(|(_, modifier)| modifier.as_str().to_string())
+      .collect();
+
+    decoded.push(SemanticToken {
+      range: rope.char_to_byte(start)..rope.char_to_byte(end),
+      token_type: token_type.as_str().to_string(),
+      modifiers,
+    });
+  }
+
+  decoded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lsp_types::{SemanticTokenModifier, SemanticTokenType};
+
+  fn token(
+    delta_line: u32,
+    delta_start: u32,
+    length: u32,
+    token_type: u32,
+    modifiers: u32,
+  ) -> lsp_types::SemanticToken {
+    lsp_types::SemanticToken {
+      delta_line,
+      delta_start,
+      length,
+      token_type,
+      token_modifiers_bitset: modifiers,
+    }
+  }
+
+  fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+      token_types: vec![SemanticTokenType::VARIABLE, SemanticTokenType::FUNCTION],
+      token_modifiers: vec![
+        SemanticTokenModifier::DECLARATION,
+        SemanticTokenModifier::new("mutable"),
+      ],
+    }
+  }
+
+  #[test]
+  fn test_decode_relative_utf16_positions() {
+    // The emoji takes two UTF-16 code units and four bytes
+    let rope = Rope::from_str("let 😀 = x;\nlet mut y = f();\n");
+    let tokens = [
+      token(0, 9, 1, 0, 0),
+      token(1, 8, 1, 0, 0b11),
+      token(0, 4, 1, 1, 0),
+    ];
+
+    let decoded = decode(&rope, &legend(), &tokens);
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(decoded[0].range, 11..12);
+    assert_eq!(decoded[1].range, 22..23);
+    assert_eq!(decoded[1].modifiers, vec!["declaration", "mutable"]);
+    assert_eq!(decoded[1].highlight_kind(), Some(HighlightKind::Variable));
+    assert_eq!(decoded[2].range, 26..27);
+    assert_eq!(decoded[2].token_type, "function");
+  }
+
+  #[test]
+  fn test_apply_edits() {
+    let mut tokens = vec![
+      token(0, 0, 1, 0, 0),
+      token(0, 2, 1, 0, 0),
+      token(1, 0, 1, 0, 0),
+    ];
+    let edits = vec![
+      SemanticTokensEdit {
+        start: 10,
+        delete_count: 5,
+        data: None,
+      },
+      SemanticTokensEdit {
+        start: 5,
+        delete_count: 0,
+        data: Some(vec![token(0, 1, 1, 1, 0)]),
+      },
+    ];
+
+    apply_edits(&mut tokens, edits);
+    assert_eq!(
+      tokens,
+      vec![
+        token(0, 0, 1, 0, 0),
+        token(0, 1, 1, 1, 0),
+        token(0, 2, 1, 0, 0)
+      ]
+    );
+  }
+}
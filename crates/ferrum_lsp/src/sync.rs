@@ -0,0 +1,518 @@
+//! Document synchronization with language servers
+//!
+//! Documents opened in a server are kept in sync as their buffers are
+//! edited. Edits are merged into the regions changed since the server last
+//! received the text, and a burst of them is sent as one
+//! `textDocument/didChange`, with a range in UTF-16 for each region, or the
+//! full text if the server asked for that. If an edit doesn't follow on
+//! from the text the store last saw, e.g. because a buffer was reloaded
+//! without going through the editor, the next change sends the full text.
+
+use crate::client::LspClient;
+use crate::types::{chars_to_range, path_to_uri};
+use ferrum_buffer::{BufferSnapshot, Change};
+use ferrum_core::constants::LSP_CHANGE_DEBOUNCE_MS;
+use ferrum_core::prelude::*;
+use ferrum_core::task::Debouncer;
+use lsp_types::{TextDocumentContentChangeEvent, TextDocumentSyncKind, Uri};
+use parking_lot::Mutex;
+use ropey::Rope;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::warn;
+
+/// A region changed since the server last received the text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+  /// Start byte offset, the same in both texts
+  start: usize,
+  /// Length in bytes of the text the server has
+  old_len: usize,
+  /// Length in bytes of the text now
+  new_len: usize,
+}
+
+/// Regions changed since the server last received the text, sorted and
+/// apart from each other
+#[derive(Debug, Default)]
+struct PendingEdits {
+  regions: Vec<Region>,
+}
+
+impl PendingEdits {
+  /// Record an edit, in byte offsets of the text as it was just before
+  fn record(&mut self, start: usize, old_end: usize, new_end: usize) {
+    // Merge every region the edit touches
+    let first = self
+      .regions
+      .partition_point(|r| r.start + r.new_len < start);
+    let mut last = first;
+    let (mut merged_start, mut merged_end) = (start, old_end);
+    let (mut old_len, mut covered) = (0, 0);
+    while let Some(region) = self.regions.get(last).filter(|r| r.start <= old_end) {
+      merged_start = merged_start.min(region.start);
+      merged_end = merged_end.max(region.start + region.new_len);
+      old_len += region.old_len;
+      covered += region.new_len;
+      last += 1;
+    }
+
+    // Text of the merged span outside any region is unchanged
+    let span = merged_end - merged_start;
+    let region = Region {
+      start: merged_start,
+      old_len: old_len + span - covered,
+      new_len: span - (old_end - start) + (new_end - start),
+    };
+    self.regions.splice(first..last, [region]);
+
+    for region in &mut self.regions[first + 1..] {
+      region.start = region.start + new_end - old_end;
+    }
+  }
+
+  /// Build the change events turning `server_text` into `text`
+  ///
+  /// Regions are sent last to first, so the ranges of those before stay
+  /// valid in the text the server has.
+  fn content_changes(
+    &self,
+    server_text: &Rope,
+    text: &Rope,
+  ) -> Vec<TextDocumentContentChangeEvent> {
+    let mut shift = 0isize;
+    let mut changes: Vec<TextDocumentContentChangeEvent> = self
+      .regions
+      .iter()
+      .filter_map(|region| {
+        let server_start = (region.start as isize - shift) as usize;
+        shift += region.new_len as isize - region.old_len as isize;
+
+        let start = server_text.try_byte_to_char(server_start).ok()?;
+        let end = server_text
+          .try_byte_to_char(server_start + region.old_len)
+          .ok()?;
+        let inserted = text.get_byte_slice(region.start..region.start + region.new_len)?;
+        Some(TextDocumentContentChangeEvent {
+          range: Some(chars_to_range(server_text, start..end)?),
+          range_length: None,
+          text: inserted.to_string(),
+        })
+      })
+      .collect();
+    changes.reverse();
+    changes
+  }
+}
+
+/// A document open in a language server
+struct Document {
+  client: Arc<LspClient>,
+  path: PathBuf,
+  uri: Uri,
+  language_id: String,
+  /// Version of the last text sent
+  version: i32,
+  /// Buffer version the text is at, once known
+  buffer_version: Option<u64>,
+  /// The text the server has
+  server_text: Rope,
+  /// The text now
+  text: Rope,
+  pending: PendingEdits,
+  /// Whether the next change must send the full text
+  resync: bool,
+}
+
+impl Document {
+  /// Move the text to a new buffer version, checking it follows on from
+  /// the one before
+  fn advance(&mut self, rope: &Rope, buffer_version: u64, len_delta: isize) {
+    let follows = match self.buffer_version {
+      Some(version) => version + 1 == buffer_version,
+      None => true,
+    };
+    if !follows || self.text.len_bytes() as isize + len_delta != rope.len_bytes() as isize {
+      self.resync = true;
+    }
+    self.buffer_version = Some(buffer_version);
+    self.text = rope.clone();
+  }
+
+  /// Send the changes since the last flush
+  fn flush(&mut self) -> Result<()> {
+    if !self.resync && self.pending.regions.is_empty() {
+      return Ok(());
+    }
+
+    let kind = self.client.text_document_sync_kind();
+    let changes = if kind == TextDocumentSyncKind::NONE {
+      Vec::new()
+    } else if kind == TextDocumentSyncKind::INCREMENTAL && !self.resync {
+      self.pending.content_changes(&self.server_text, &self.text)
+    } else {
+      vec![TextDocumentContentChangeEvent {
+        range: None,
+        range_length: None,
+        text: self.text.to_string(),
+      }]
+    };
+
+    self.pending.regions.clear();
+    self.resync = false;
+    self.server_text = self.text.clone();
+    if changes.is_empty() {
+      return Ok(());
+    }
+
+    self.version += 1;
+    self
+      .client
+      .did_change(self.uri.clone(), self.version, changes)?;
+    self.record_sent();
+    Ok(())
+  }
+
+  /// Tell the diagnostic store which text the server has, so diagnostics
+  /// published for it can be placed
+  fn record_sent(&self) {
+    self.client.diagnostics().sent(
+      &self.path,
+      self.version,
+      self.buffer_version,
+      &self.server_text,
+    );
+  }
+}
+
+/// A document that stopped being synced, as it was then
+#[derive(Debug, Clone)]
+pub struct ClosedDocument {
+  pub path: PathBuf,
+  pub language_id: String,
+  /// Version of the last text sent
+  pub version: i32,
+  pub text: Rope,
+  /// Buffer version the text is at, if known
+  pub buffer_version: Option<u64>,
+}
+
+/// Keeps documents open in language servers in sync with their buffers
+#[derive(Default)]
+pub struct DocumentSync {
+  documents: Arc<Mutex<HashMap<PathBuf, Document>>>,
+  debouncer: OnceLock<Debouncer<()>>,
+}
+
+impl DocumentSync {
+  /// Create an empty store
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Open a document in a server and start syncing its changes
+  ///
+  /// `buffer_version` is the version of the buffer `text` was taken from,
+  /// if any. Called from within a Tokio runtime, changes are debounced;
+  /// otherwise each edit is sent right away.
+  pub fn open(
+    &self,
+    client: Arc<LspClient>,
+    path: &Path,
+    language_id: &str,
+    version: i32,
+    text: Rope,
+    buffer_version: Option<u64>,
+  ) -> Result<()> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+      let documents = self.documents.clone();
+      self.debouncer.get_or_init(|| {
+        Debouncer::new(Duration::from_millis(LSP_CHANGE_DEBOUNCE_MS), move |()| {
+          flush_all(&documents)
+        })
+      });
+    }
+
+    let uri = path_to_uri(path)
+      .ok_or_else(|| Error::Internal(format!("Invalid document path: {}", path.display())))?;
+    client.did_open(uri.clone(), language_id, version, &text.to_string())?;
+    let document = Document {
+      client,
+      path: path.to_path_buf(),
+      uri,
+      language_id: language_id.to_string(),
+      version,
+      buffer_version,
+      server_text: text.clone(),
+      text,
+      pending: PendingEdits::default(),
+      resync: false,
+    };
+    document.record_sent();
+    self.documents.lock().insert(path.to_path_buf(), document);
+    Ok(())
+  }
+
+  /// Open the file of a buffer snapshot in a server; see
+  /// [`open`](Self::open)
+  pub fn open_snapshot(
+    &self,
+    client: Arc<LspClient>,
+    snapshot: &BufferSnapshot,
+    language_id: &str,
+    version: i32,
+  ) -> Result<()> {
+    let path = snapshot
+      .file_path()
+      .ok_or_else(|| Error::Internal(format!("Buffer {} has no file path", snapshot.id())))?;
+    self.open(
+      client,
+      path,
+      language_id,
+      version,
+      snapshot.rope().clone(),
+      Some(snapshot.version()),
+    )
+  }
+
+  /// Stop syncing a document and close it in its server
+  pub fn close(&self, path: &Path) -> Result<()> {
+    match self.documents.lock().remove(path) {
+      Some(document) => document.client.did_close(document.uri),
+      None => Ok(()),
+    }
+  }
+
+  /// Check if a document is being synced
+  pub fn is_open(&self, path: &Path) -> bool {
+    self.documents.lock().contains_key(path)
+  }
+
+  /// Check if a server has `version` of a document and that it is the
+  /// text in `rope`, i.e. edits made against that version still apply
+  ///
+  /// False while later edits are waiting to be sent.
+  pub fn is_current(&self, path: &Path, version: i32, rope: &Rope) -> bool {
+    self
+      .documents
+      .lock()
+      .get(path)
+      .is_some_and(|document| document.version == version && document.server_text == *rope)
+  }
+
+  /// Record an edit of a document, in byte offsets; `rope` is the text
+  /// after it
+  pub fn apply_edit(
+    &self,
+    path: &Path,
+    rope: &Rope,
+    buffer_version: u64,
+    start: usize,
+    old_end: usize,
+    new_end: usize,
+  ) {
+    self.update(path, |document| {
+      document.advance(rope, buffer_version, new_end as isize - old_end as isize);
+      document.pending.record(start, old_end, new_end);
+    });
+  }
+
+  /// Record a transaction's changes of a document; `rope` is the text after
+  /// them
+  pub fn apply_changes(&self, path: &Path, rope: &Rope, buffer_version: u64, changes: &[Change]) {
+    self.update(path, |document| {
+      let len_delta = changes
+        .iter()
+        .map(|c| c.new_end_byte as isize - c.old_end_byte as isize)
+        .sum();
+      document.advance(rope, buffer_version, len_delta);
+      for change in changes {
+        document
+          .pending
+          .record(change.start_byte, change.old_end_byte, change.new_end_byte);
+      }
+    });
+  }
+
+  /// Replace the whole text of a document, sending it right away
+  pub fn replace(&self, path: &Path, text: Rope) -> Result<()> {
+    self.set_text(path, text, None)
+  }
+
+  /// Replace the whole text of a buffer's document with a snapshot of the
+  /// buffer, sending it right away
+  ///
+  /// Unlike [`replace`](Self::replace), the buffer's later edits still
+  /// follow on from the text.
+  pub fn replace_snapshot(&self, snapshot: &BufferSnapshot) -> Result<()> {
+    let path = snapshot
+      .file_path()
+      .ok_or_else(|| Error::Internal(format!("Buffer {} has no file path", snapshot.id())))?;
+    self.set_text(path, snapshot.rope().clone(), Some(snapshot.version()))
+  }
+
+  fn set_text(&self, path: &Path, text: Rope, buffer_version: Option<u64>) -> Result<()> {
+    let mut documents = self.documents.lock();
+    let document = documents.get_mut(path).ok_or_else(|| {
+      Error::Internal(format!(
+        "{} is not open in a language server",
+        path.display()
+      ))
+    })?;
+    document.text = text;
+    document.buffer_version = buffer_version;
+    document.resync = true;
+    document.flush()
+  }
+
+  /// Send the pending changes of a document right away
+  pub fn flush(&self, path: &Path) -> Result<()> {
+    match self.documents.lock().get_mut(path) {
+      Some(document) => document.flush(),
+      None => Ok(()),
+    }
+  }
+
+  /// Open the documents of a server in it again, e.g. after it restarted
+  pub fn reopen_server(&self, language: &str) {
+    for (path, document) in self.documents.lock().iter_mut() {
+      if document.client.language() != language {
+        continue;
+      }
+      document.version += 1;
+      document.pending.regions.clear();
+      document.resync = false;
+      document.server_text = document.text.clone();
+
+      let text = document.text.to_string();
+      let opened = document.client.did_open(
+        document.uri.clone(),
+        &document.language_id,
+        document.version,
+        &text,
+      );
+      match opened {
+        Ok(()) => document.record_sent(),
+        Err(e) => warn!("Failed to reopen {:?} in its language server: {}", path, e),
+      }
+    }
+  }
+
+  /// Stop syncing the documents of a server, e.g. after it stopped,
+  /// returning them so they can be opened in another
+  pub fn close_server(&self, language: &str) -> Vec<ClosedDocument> {
+    let mut documents = self.documents.lock();
+    let paths: Vec<PathBuf> = documents
+      .iter()
+      .filter(|(_, document)| document.client.language() == language)
+      .map(|(path, _)| path.clone())
+      .collect();
+    paths
+      .into_iter()
+      .filter_map(|path| documents.remove(&path))
+      .map(|document| ClosedDocument {
+        path: document.path,
+        language_id: document.language_id,
+        version: document.version,
+        text: document.text,
+        buffer_version: document.buffer_version,
+      })
+      .collect()
+  }
+
+  fn update(&self, path: &Path, f: impl FnOnce(&mut Document)) {
+    {
+      let mut documents = self.documents.lock();
+      let Some(document) = documents.get_mut(path) else {
+        return;
+      };
+      f(document);
+    }
+
+    match self.debouncer.get() {
+      Some(debouncer) => debouncer.call(()),
+      None => flush_all(&self.documents),
+    }
+  }
+}
+
+/// Send the pending changes of every document
+fn flush_all(documents: &Mutex<HashMap<PathBuf, Document>>) {
+  for (path, document) in documents.lock().iter_mut() {
+    if let Err(e) = document.flush() {
+      warn!("Failed to sync {:?} with its language server: {}", path, e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lsp_types::{Position, Range};
+
+  fn edit(rope: &mut Rope, pending: &mut PendingEdits, start: usize, end: usize, text: &str) {
+    let start_char = rope.byte_to_char(start);
+    let end_char = rope.byte_to_char(end);
+    rope.remove(start_char..end_char);
+    rope.insert(start_char, text);
+    pending.record(start, end, start + text.len());
+  }
+
+  fn apply(rope: &mut Rope, changes: &[TextDocumentContentChangeEvent]) {
+    for change in changes {
+      let range = change.range.unwrap();
+      let start = crate::types::position_to_char(rope, range.start);
+      let end = crate::types::position_to_char(rope, range.end);
+      rope.remove(start..end);
+      rope.insert(start, &change.text);
+    }
+  }
+
+  #[test]
+  fn test_edits_merge_into_regions() {
+    let server_text = Rope::from_str("fn main() {\n  let a = 1;\n  let b = 2;\n}\n");
+    let mut text = server_text.clone();
+    let mut pending = PendingEdits::default();
+
+    // Typing a word a character at a time makes one region
+    edit(&mut text, &mut pending, 18, 19, "c");
+    edit(&mut text, &mut pending, 19, 19, "d");
+    edit(&mut text, &mut pending, 20, 20, "e");
+    // An edit elsewhere makes another, and one bridging them merges both
+    edit(&mut text, &mut pending, 33, 34, "ñ");
+    assert_eq!(pending.regions.len(), 2);
+    edit(&mut text, &mut pending, 20, 35, "");
+    assert_eq!(pending.regions.len(), 1);
+    edit(&mut text, &mut pending, 0, 2, "pub fn");
+
+    let changes = pending.content_changes(&server_text, &text);
+    assert_eq!(changes.len(), 2);
+    assert_eq!(
+      changes[1].range,
+      Some(Range::new(Position::new(0, 0), Position::new(0, 2)))
+    );
+
+    let mut synced = server_text.clone();
+    apply(&mut synced, &changes);
+    assert_eq!(synced, text);
+  }
+
+  #[test]
+  fn test_ranges_count_utf16_units() {
+    let server_text = Rope::from_str("let s = \"😀\";\n");
+    let mut text = server_text.clone();
+    let mut pending = PendingEdits::default();
+    edit(&mut text, &mut pending, 13, 14, ", t");
+
+    let changes = pending.content_changes(&server_text, &text);
+    assert_eq!(
+      changes[0].range,
+      Some(Range::new(Position::new(0, 11), Position::new(0, 12)))
+    );
+    let mut synced = server_text.clone();
+    apply(&mut synced, &changes);
+    assert_eq!(synced, text);
+  }
+}
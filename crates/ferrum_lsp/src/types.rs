@@ -1,7 +1,140 @@
-//! LSP type conversions (placeholder)
+//! LSP type conversions
+//!
+//! LSP positions count columns in UTF-16 code units, while buffers use char
+//! offsets. Every position that crosses the boundary goes through these
+//! helpers.
+
+use ferrum_buffer::coords;
+use ferrum_buffer::position::Point;
+use ropey::Rope;
+use std::path::{Path, PathBuf};
 
 // Re-export commonly used LSP types
 pub use lsp_types::{
   CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, DocumentSymbol, Hover,
-  Location, Position, Range, SymbolKind, TextEdit,
+  Location, Position, PublishDiagnosticsParams, Range, SymbolKind, TextEdit, Uri,
 };
+
+/// Convert an LSP position to a char offset
+///
+/// A character past the end of the line is taken as the line's end, as
+/// the spec asks, and a line past the end of the text as the text's end.
+pub fn position_to_char(rope: &Rope, position: Position) -> usize {
+  let point = Point::new(position.line as usize, position.character as usize);
+  coords::utf16_point_to_char(rope, point)
+}
+
+/// Convert a char offset to an LSP position
+pub fn char_to_position(rope: &Rope, char_idx: usize) -> Option<Position> {
+  let point = coords::char_to_utf16_point(rope, char_idx)?;
+  Some(Position::new(point.line as u32, point.column as u32))
+}
+
+/// Convert an LSP range to a char range, clamped to the text
+pub fn range_to_chars(rope: &Rope, range: Range) -> std::ops::Range<usize> {
+  position_to_char(rope, range.start)..position_to_char(rope, range.end)
+}
+
+/// Convert a char range to an LSP range
+pub fn chars_to_range(rope: &Rope, range: std::ops::Range<usize>) -> Option<Range> {
+  Some(Range::new(
+    char_to_position(rope, range.start)?,
+    char_to_position(rope, range.end)?,
+  ))
+}
+
+/// Get the `file://` URI of an absolute path, percent-encoding what URIs
+/// can't hold
+pub fn path_to_uri(path: &Path) -> Option<Uri> {
+  let path = path.to_str()?;
+  let mut uri = String::from("file://");
+  for byte in path.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+        uri.push(byte as char)
+      },
+      _ => uri.push_str(&format!("%{:02X}", byte)),
+    }
+  }
+  uri.parse().ok()
+}
+
+/// Get the path of a `file://` URI, decoding percent escapes
+pub fn uri_to_path(uri: &Uri) -> Option<PathBuf> {
+  let path = uri.as_str().strip_prefix("file://")?;
+  let bytes = path.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = (bytes[i] == b'%')
+      .then(|| path.get(i + 1..i + 3))
+      .flatten()
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match escaped {
+      Some(byte) => {
+        decoded.push(byte);
+        i += 3;
+      },
+      None => {
+        decoded.push(bytes[i]);
+        i += 1;
+      },
+    }
+  }
+  String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_position_round_trip_with_surrogates() {
+    // The emoji takes two UTF-16 code units
+    let rope = Rope::from_str("let 😀 = 1;\nfoo");
+
+    assert_eq!(char_to_position(&rope, 6), Some(Position::new(0, 7)));
+    assert_eq!(position_to_char(&rope, Position::new(0, 7)), 6);
+    assert_eq!(position_to_char(&rope, Position::new(1, 3)), 14);
+
+    let range = chars_to_range(&rope, 4..5).unwrap();
+    assert_eq!(range, Range::new(Position::new(0, 4), Position::new(0, 6)));
+    assert_eq!(range_to_chars(&rope, range), 4..5);
+  }
+
+  #[test]
+  fn test_only_lsp_line_breaks_start_lines() {
+    let rope = Rope::from_str("a\u{2028}b\u{85}c\rd");
+
+    assert_eq!(char_to_position(&rope, 4), Some(Position::new(0, 4)));
+    assert_eq!(char_to_position(&rope, 6), Some(Position::new(1, 0)));
+    assert_eq!(position_to_char(&rope, Position::new(1, 1)), 7);
+  }
+
+  #[test]
+  fn test_positions_are_clamped() {
+    let rope = Rope::from_str("ab\r\ncd");
+
+    // Past the end of the line, or between its `\r` and `\n`
+    assert_eq!(position_to_char(&rope, Position::new(0, 9)), 2);
+    assert_eq!(position_to_char(&rope, Position::new(0, 3)), 2);
+    // A range over the whole text, ending on the line after the last
+    let range = Range::new(Position::new(0, 0), Position::new(2, 0));
+    assert_eq!(range_to_chars(&rope, range), 0..6);
+  }
+
+  #[test]
+  fn test_uri_to_path() {
+    let uri: Uri = "file:///home/me/my%20project/main.rs".parse().unwrap();
+    assert_eq!(
+      uri_to_path(&uri),
+      Some(PathBuf::from("/home/me/my project/main.rs"))
+    );
+    assert_eq!(
+      path_to_uri(Path::new("/home/me/my project/main.rs")).as_ref(),
+      Some(&uri)
+    );
+    let uri: Uri = "untitled:Untitled-1".parse().unwrap();
+    assert_eq!(uri_to_path(&uri), None);
+  }
+}
@@ -1,8 +1,9 @@
 //! Plugin manifest parsing and validation
 
+use ferrum_buffer::GrammarDefinition;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Plugin manifest (plugin.json)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -147,8 +148,52 @@ pub struct LanguageContribution {
   /// Path to language configuration
   pub configuration: Option<String>,
 
-  /// Path to TextMate grammar
+  /// Path to the tree-sitter grammar, a shared library or `.wasm` module
+  /// exporting `tree_sitter_<id>`
   pub grammar: Option<String>,
+
+  /// Path to a directory of tree-sitter query files (`highlights.scm`,
+  /// `locals.scm`, `folds.scm`, ...)
+  pub queries: Option<String>,
+}
+
+impl LanguageContribution {
+  /// Get the grammar to load, with paths resolved against the plugin
+  /// directory, or `None` if the language has no grammar
+  ///
+  /// Fails if a path does not exist or leads outside the plugin directory.
+  pub fn grammar_definition(&self, plugin_dir: &Path) -> Result<Option<GrammarDefinition>, String> {
+    let Some(grammar) = &self.grammar else {
+      return Ok(None);
+    };
+    Ok(Some(GrammarDefinition {
+      name: self.id.clone(),
+      grammar: resolve_in(plugin_dir, grammar)?,
+      queries: self
+        .queries
+        .as_ref()
+        .map(|q| resolve_in(plugin_dir, q))
+        .transpose()?,
+      extensions: self.extensions.clone(),
+      file_names: self.filenames.clone(),
+    }))
+  }
+}
+
+/// Resolve a path from a manifest against the plugin directory, following
+/// `..` and symlinks, and reject it if it ends up outside the directory
+fn resolve_in(plugin_dir: &Path, path: &str) -> Result<PathBuf, String> {
+  let dir = plugin_dir
+    .canonicalize()
+    .map_err(|e| format!("Cannot resolve plugin directory {:?}: {}", plugin_dir, e))?;
+  let resolved = dir
+    .join(path)
+    .canonicalize()
+    .map_err(|e| format!("Cannot resolve {:?}: {}", path, e))?;
+  if !resolved.starts_with(&dir) {
+    return Err(format!("{:?} is outside the plugin directory", path));
+  }
+  Ok(resolved)
 }
 
 /// Panel contribution
@@ -323,4 +368,74 @@ mod tests {
     assert_eq!(manifest.permissions.len(), 2);
     assert_eq!(manifest.contributes.commands.len(), 1);
   }
+
+  #[test]
+  fn test_language_grammar_definition() {
+    let json = r#"{
+      "name": "lua-support",
+      "version": "1.0.0",
+      "main": "dist/index.js",
+      "contributes": {
+        "languages": [
+          {
+            "id": "lua",
+            "extensions": ["lua"],
+            "grammar": "grammars/lua.wasm",
+            "queries": "queries/lua"
+          },
+          { "id": "plain", "extensions": ["txt"] }
+        ]
+      }
+    }"#;
+
+    let dir = tempfile::tempdir().unwrap();
+    let plugin_dir = dir.path().join("lua-support");
+    std::fs::create_dir_all(plugin_dir.join("grammars")).unwrap();
+    std::fs::create_dir_all(plugin_dir.join("queries/lua")).unwrap();
+    std::fs::write(plugin_dir.join("grammars/lua.wasm"), "").unwrap();
+    let plugin_dir = plugin_dir.canonicalize().unwrap();
+
+    let manifest = PluginManifest::from_json(json).unwrap();
+    let languages = &manifest.contributes.languages;
+    let definition = languages[0]
+      .grammar_definition(&plugin_dir)
+      .unwrap()
+      .unwrap();
+    assert_eq!(definition.name, "lua");
+    assert_eq!(definition.grammar, plugin_dir.join("grammars/lua.wasm"));
+    assert_eq!(
+      definition.queries.as_deref(),
+      Some(plugin_dir.join("queries/lua").as_path())
+    );
+    assert!(
+      languages[1]
+        .grammar_definition(&plugin_dir)
+        .unwrap()
+        .is_none()
+    );
+  }
+
+  #[test]
+  fn test_grammar_outside_plugin_dir_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let plugin_dir = dir.path().join("plugin");
+    std::fs::create_dir(&plugin_dir).unwrap();
+    let outside = dir.path().join("evil.so");
+    std::fs::write(&outside, "").unwrap();
+
+    for grammar in [
+      outside.to_string_lossy().into_owned(),
+      "../evil.so".to_string(),
+    ] {
+      let language = LanguageContribution {
+        id: "evil".to_string(),
+        extensions: Vec::new(),
+        filenames: Vec::new(),
+        configuration: None,
+        grammar: Some(grammar),
+        queries: None,
+      };
+      assert!(language.grammar_definition(&plugin_dir).is_err());
+    }
+  }
 }
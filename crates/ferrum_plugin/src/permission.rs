@@ -60,6 +60,9 @@ pub enum Permission {
 
   /// Access environment variables
   EnvAccess,
+
+  /// Load native code, such as tree-sitter grammar libraries
+  NativeCode,
 }
 
 impl Permission {
@@ -82,6 +85,7 @@ impl Permission {
       "terminal.access" => Some(Permission::TerminalAccess),
       "git.access" => Some(Permission::GitAccess),
       "env.access" => Some(Permission::EnvAccess),
+      "native.code" => Some(Permission::NativeCode),
       _ => None,
     }
   }
@@ -105,6 +109,7 @@ impl Permission {
       Permission::TerminalAccess => "terminal.access",
       Permission::GitAccess => "git.access",
       Permission::EnvAccess => "env.access",
+      Permission::NativeCode => "native.code",
     }
   }
 
@@ -127,6 +132,7 @@ impl Permission {
       Permission::TerminalAccess => "Access the integrated terminal",
       Permission::GitAccess => "Perform git operations",
       Permission::EnvAccess => "Access environment variables",
+      Permission::NativeCode => "Load native libraries, such as language grammars",
     }
   }
 
@@ -138,6 +144,7 @@ impl Permission {
         | Permission::ProcessSpawn
         | Permission::NetworkRequest
         | Permission::TerminalAccess
+        | Permission::NativeCode
     )
   }
 }
@@ -235,10 +242,14 @@ impl PermissionManager {
   }
 
   /// Parse permission strings and grant them
+  ///
+  /// Permissions the user denied stay denied.
   pub fn grant_from_strings(&self, plugin_id: PluginId, permission_strings: &[String]) {
     for perm_str in permission_strings {
       if let Some(perm) = Permission::from_string(perm_str) {
-        self.grant(plugin_id, perm);
+        if !self.is_denied(plugin_id, perm) {
+          self.grant(plugin_id, perm);
+        }
       }
     }
   }
@@ -268,6 +279,20 @@ mod tests {
     assert!(!manager.has_permission(plugin_id, Permission::EditorRead));
   }
 
+  #[test]
+  fn test_denied_permissions_are_not_granted_from_manifest() {
+    let manager = PermissionManager::new();
+    let plugin_id = PluginId::new_v4();
+
+    manager.deny(plugin_id, Permission::NativeCode);
+    manager.grant_from_strings(
+      plugin_id,
+      &["native.code".to_string(), "editor.read".to_string()],
+    );
+    assert!(!manager.has_permission(plugin_id, Permission::NativeCode));
+    assert!(manager.has_permission(plugin_id, Permission::EditorRead));
+  }
+
   #[test]
   fn test_permission_from_string() {
     assert_eq!(
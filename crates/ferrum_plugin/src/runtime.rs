@@ -4,8 +4,9 @@
 //! will be added in a future phase.
 
 use crate::api::PluginApi;
-use crate::permission::{PermissionManager, PluginId};
-use crate::registry::{PluginRegistry, PluginState};
+use crate::permission::{Permission, PermissionManager, PluginId};
+use crate::registry::{PluginInfo, PluginRegistry, PluginState};
+use ferrum_buffer::global_grammar_registry;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -133,6 +134,8 @@ impl PluginRuntime {
       .permissions
       .grant_from_strings(plugin_id, &info.manifest.permissions);
 
+    self.load_grammars(plugin_id, &info);
+
     // Initialize runtime state
     {
       let mut states = self.states.write().await;
@@ -160,6 +163,54 @@ impl PluginRuntime {
     Ok(())
   }
 
+  /// Load the grammars of a plugin's languages into the global grammar
+  /// registry
+  ///
+  /// Grammar libraries run native code, so only WASM grammars load unless
+  /// the plugin holds the `native.code` permission. A grammar that fails to
+  /// load is logged and skipped.
+  fn load_grammars(&self, plugin_id: PluginId, info: &PluginInfo) {
+    let name = &info.manifest.name;
+    for language in &info.manifest.contributes.languages {
+      let definition = match language.grammar_definition(&info.path) {
+        Ok(Some(definition)) => definition,
+        Ok(None) => continue,
+        Err(e) => {
+          tracing::warn!(
+            "Plugin {} has an invalid grammar for {}: {}",
+            name,
+            language.id,
+            e
+          );
+          continue;
+        },
+      };
+
+      let native = definition.grammar.extension().and_then(|e| e.to_str()) != Some("wasm");
+      if native
+        && !self
+          .permissions
+          .has_permission(plugin_id, Permission::NativeCode)
+      {
+        tracing::warn!(
+          "Plugin {} needs the native.code permission to load the grammar for {}",
+          name,
+          language.id
+        );
+        continue;
+      }
+
+      if let Err(e) = global_grammar_registry().register(&definition) {
+        tracing::warn!(
+          "Plugin {} failed to load grammar for {}: {}",
+          name,
+          language.id,
+          e
+        );
+      }
+    }
+  }
+
   /// Deactivate a plugin
   pub async fn deactivate(&self, plugin_id: PluginId) -> Result<(), String> {
     let info = self.registry.get(plugin_id).ok_or("Plugin not found")?;
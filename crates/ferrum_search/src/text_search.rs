@@ -1,7 +1,13 @@
 //! Text search using ripgrep-style searching
+//!
+//! Files open in buffers can be searched through snapshots of the buffers,
+//! so unsaved edits are found without blocking further typing.
 
+use ferrum_buffer::BufferSnapshot;
 use ferrum_core::prelude::*;
 use ignore::WalkBuilder;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -28,12 +34,26 @@ pub struct SearchOptions {
 /// Text searcher
 pub struct TextSearcher {
   root: PathBuf,
+  /// Snapshots of open buffers by file path
+  buffers: HashMap<PathBuf, BufferSnapshot>,
 }
 
 impl TextSearcher {
   /// Create a new text searcher
   pub fn new(root: impl Into<PathBuf>) -> Self {
-    Self { root: root.into() }
+    Self {
+      root: root.into(),
+      buffers: HashMap::new(),
+    }
+  }
+
+  /// Search the files of buffers in these snapshots instead of on disk
+  pub fn with_buffers(mut self, snapshots: impl IntoIterator<Item = BufferSnapshot>) -> Self {
+    self.buffers = snapshots
+      .into_iter()
+      .filter_map(|snapshot| Some((snapshot.file_path()?.clone(), snapshot)))
+      .collect();
+    self
   }
 
   /// Search for text in files
@@ -69,18 +89,22 @@ impl TextSearcher {
         }
       }
 
-      if let Ok(content) = fs::read_to_string(path) {
-        self.search_in_content(path, &content, query, options, &mut results, max);
+      if let Some(snapshot) = self.buffers.get(path) {
+        let lines = snapshot.rope().lines().map(Cow::from);
+        self.search_in_lines(path, lines, query, options, &mut results, max);
+      } else if let Ok(content) = fs::read_to_string(path) {
+        let lines = content.lines().map(Cow::from);
+        self.search_in_lines(path, lines, query, options, &mut results, max);
       }
     }
 
     Ok(results)
   }
 
-  fn search_in_content(
+  fn search_in_lines<'a>(
     &self,
     path: &Path,
-    content: &str,
+    lines: impl Iterator<Item = Cow<'a, str>>,
     query: &str,
     options: &SearchOptions,
     results: &mut Vec<TextMatch>,
@@ -92,10 +116,12 @@ impl TextSearcher {
       query.to_lowercase()
     };
 
-    for (line_idx, line) in content.lines().enumerate() {
+    for (line_idx, line) in lines.enumerate() {
       if results.len() >= max {
         break;
       }
+      let line = line.strip_suffix('\n').unwrap_or(&line);
+      let line = line.strip_suffix('\r').unwrap_or(line);
 
       let search_line = if options.case_sensitive {
         line.to_string()
@@ -169,4 +195,19 @@ mod tests {
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].line_number, 2);
   }
+
+  #[test]
+  fn test_open_buffers_are_searched_as_edited() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("main.rs");
+    fs::write(&path, "fn main() {\n    println!(\"Hello\");\n}\n").unwrap();
+    let buffer = ferrum_buffer::Buffer::from_file(&path, "// Hello\r\nfn main() {}\r\n");
+
+    let searcher = TextSearcher::new(dir.path()).with_buffers([buffer.snapshot()]);
+    let results = searcher.search("Hello", &SearchOptions::default()).unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].line_number, 1);
+    assert_eq!(results[0].line_content, "// Hello");
+  }
 }
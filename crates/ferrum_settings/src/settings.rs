@@ -21,6 +21,12 @@ pub struct EditorSettings {
   pub auto_save: bool,
   pub auto_save_delay_ms: u64,
   pub format_on_save: bool,
+  /// Remove trailing spaces and tabs from every line on save
+  pub trim_trailing_whitespace: bool,
+  /// End files with a line break on save
+  pub insert_final_newline: bool,
+  /// Keep unsaved buffers on exit and restore them on the next launch
+  pub hot_exit: bool,
 }
 
 impl Default for EditorSettings {
@@ -39,6 +45,9 @@ impl Default for EditorSettings {
       auto_save: false,
       auto_save_delay_ms: 1000,
       format_on_save: true,
+      trim_trailing_whitespace: false,
+      insert_final_newline: false,
+      hot_exit: true,
     }
   }
 }
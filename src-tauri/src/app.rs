@@ -1,7 +1,16 @@
 //! Tauri application setup
 
-use tauri::Manager;
-use tracing::info;
+use ferrum_buffer::global_grammar_registry;
+use ferrum_core::constants::AUTOSAVE_INTERVAL_MS;
+use ferrum_core::event::{
+  DiagnosticsReceived, LspProgress, SemanticTokensRefreshed, global_event_bus,
+};
+use ferrum_editor::{FoldStore, RecoveryStore, UndoStore};
+use lsp_types::ApplyWorkspaceEditResponse;
+use lsp_types::request::ApplyWorkspaceEdit;
+use std::time::Duration;
+use tauri::{Emitter, Manager};
+use tracing::{info, warn};
 
 use crate::commands;
 use crate::commands::terminal::TerminalManager;
@@ -20,8 +29,72 @@ pub fn build() -> tauri::Builder<tauri::Wry> {
         // State
         .setup(|app| {
             let state = AppState::new();
+            match app.path().app_data_dir() {
+                Ok(dir) => {
+                    state.editor.set_undo_store(UndoStore::new(dir.join("undo")));
+                    state.editor.set_fold_store(FoldStore::new(dir.join("folds")));
+                    state.editor.set_recovery_store(RecoveryStore::new(dir.join("recovery")));
+                    if let Err(e) = global_grammar_registry().load_dir(&dir.join("grammars")) {
+                        warn!("Failed to load grammars: {}", e);
+                    }
+                },
+                Err(e) => warn!("Undo history and recovery will not persist: {}", e),
+            }
+
+            // Periodically swap dirty buffers out for crash recovery
+            let editor = state.editor.clone();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_millis(AUTOSAVE_INTERVAL_MS));
+                loop {
+                    interval.tick().await;
+                    let editor = editor.clone();
+                    let _ = tokio::task::spawn_blocking(move || editor.persist_recovery(false)).await;
+                }
+            });
+
+            // Apply the edits language servers ask for, e.g. while running a
+            // code action's command, and tell the frontend what changed
+            let handle = app.handle().clone();
+            state.lsp.handlers().on_request::<ApplyWorkspaceEdit, _>(move |params| {
+                let applied = tauri::async_runtime::block_on(
+                    commands::lsp::apply_workspace_edit(&handle, &params.edit),
+                );
+                Ok(match applied {
+                    Ok(applied) => {
+                        let _ = handle.emit("lsp:workspace-edit", &applied);
+                        ApplyWorkspaceEditResponse {
+                            applied: true,
+                            failure_reason: None,
+                            failed_change: None,
+                        }
+                    },
+                    Err(reason) => ApplyWorkspaceEditResponse {
+                        applied: false,
+                        failure_reason: Some(reason),
+                        failed_change: None,
+                    },
+                })
+            });
             app.manage(state);
 
+            // Forward diagnostics and progress pushed by language servers to the frontend
+            let handle = app.handle().clone();
+            global_event_bus().subscribe::<DiagnosticsReceived, _>(move |event| {
+                let _ = handle.emit("lsp:diagnostics", event);
+            });
+            let handle = app.handle().clone();
+            global_event_bus().subscribe::<LspProgress, _>(move |event| {
+                let _ = handle.emit("lsp:progress", event);
+            });
+
+            // Drop semantic tokens a language server refreshes, so the
+            // frontend requests them again
+            let handle = app.handle().clone();
+            global_event_bus().subscribe::<SemanticTokensRefreshed, _>(move |event| {
+                let buffer_ids = commands::lsp::clear_semantic_tokens(&handle, &event.language);
+                let _ = handle.emit("lsp:semantic-tokens-refresh", buffer_ids);
+            });
+
             // Terminal manager
             let terminal_manager = TerminalManager::new();
             app.manage(terminal_manager);
@@ -29,6 +102,21 @@ pub fn build() -> tauri::Builder<tauri::Wry> {
             info!("Application setup complete");
             Ok(())
         })
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::Destroyed = event {
+                let state = window.state::<AppState>();
+                state.editor.save_all_undo_history();
+                state.editor.save_all_fold_states();
+
+                // With hot exit, dirty buffers come back on the next launch;
+                // otherwise closing means their changes were let go
+                if state.settings.read().editor.hot_exit {
+                    state.editor.persist_recovery(true);
+                } else if let Err(e) = state.editor.clear_recovery() {
+                    warn!("Failed to clear recovery entries: {}", e);
+                }
+            }
+        })
         // Commands
         .invoke_handler(tauri::generate_handler![
             // File operations
@@ -40,14 +128,35 @@ pub fn build() -> tauri::Builder<tauri::Wry> {
             commands::buffer::buffer_insert,
             commands::buffer::buffer_delete,
             commands::buffer::buffer_replace,
+            commands::buffer::buffer_apply_edits,
+            commands::buffer::buffer_newline,
+            commands::buffer::buffer_reindent,
+            commands::buffer::buffer_paste,
+            commands::buffer::buffer_swap_siblings,
             commands::buffer::buffer_content,
             commands::buffer::buffer_undo,
             commands::buffer::buffer_redo,
+            commands::buffer::buffer_undo_tree,
+            commands::buffer::buffer_undo_goto,
+            commands::buffer::buffer_undo_goto_time,
             commands::buffer::buffer_get_line,
             commands::buffer::buffer_metrics,
             commands::buffer::buffer_save,
             commands::buffer::buffer_save_as,
             commands::buffer::open_file_buffer,
+            commands::buffer::open_large_file_buffer,
+            commands::buffer::buffer_viewport,
+            commands::buffer::close_buffer,
+            commands::buffer::buffer_disk_conflict,
+            commands::buffer::buffer_resolve_conflict,
+            // Recovery
+            commands::recovery::recovery_list,
+            commands::recovery::recovery_restore,
+            commands::recovery::recovery_discard,
+            commands::buffer::buffer_format,
+            commands::buffer::buffer_set_line_ending,
+            commands::buffer::buffer_set_encoding,
+            commands::buffer::buffer_set_bom,
             // Project operations
             commands::project::open_project,
             commands::project::get_file_tree,
@@ -55,6 +164,12 @@ pub fn build() -> tauri::Builder<tauri::Wry> {
             commands::project::watch_directory,
             commands::project::unwatch_directory,
             commands::project::get_recent_projects,
+            commands::project::build_dependency_graph,
+            commands::project::dependency_graph_edges,
+            commands::project::dependency_graph_imports,
+            commands::project::dependency_graph_dependents,
+            commands::project::dependency_graph_cycles,
+            commands::project::dependency_graph_path,
             // Search operations
             commands::search::search_files,
             commands::search::search_text,
@@ -64,6 +179,16 @@ pub fn build() -> tauri::Builder<tauri::Wry> {
             commands::syntax::get_supported_languages,
             commands::syntax::expand_selection,
             commands::syntax::shrink_selection,
+            commands::syntax::select_text_object,
+            commands::syntax::goto_text_object,
+            commands::syntax::select_parent_node,
+            commands::syntax::select_sibling_node,
+            commands::syntax::jump_to_matching_bracket,
+            commands::syntax::get_bracket_pairs,
+            commands::syntax::symbol_references,
+            commands::syntax::inspect_syntax_tree,
+            commands::syntax::syntax_tree_sexp,
+            commands::syntax::run_syntax_query,
             commands::syntax::analyze_dependencies,
             commands::syntax::get_scopes,
             // Settings
@@ -79,11 +204,19 @@ pub fn build() -> tauri::Builder<tauri::Wry> {
             commands::lsp::lsp_references,
             commands::lsp::lsp_document_symbols,
             commands::lsp::lsp_diagnostics,
+            commands::lsp::lsp_project_diagnostics,
+            commands::lsp::lsp_diagnostic_counts,
+            commands::lsp::lsp_progress,
             commands::lsp::lsp_did_open,
             commands::lsp::lsp_did_change,
             commands::lsp::lsp_did_save,
             commands::lsp::lsp_did_close,
             commands::lsp::lsp_rename,
+            commands::lsp::lsp_code_actions,
+            commands::lsp::lsp_apply_code_action,
+            commands::lsp::lsp_confirm_workspace_edit,
+            commands::lsp::lsp_semantic_tokens,
+            commands::lsp::lsp_folding_ranges,
             // Terminal
             commands::terminal::terminal_create,
             commands::terminal::terminal_write,
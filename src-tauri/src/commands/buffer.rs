@@ -1,9 +1,17 @@
 //! Buffer operation commands
 
+use crate::commands::syntax::{ByteRange, SelectionRange};
 use crate::state::AppState;
+use ferrum_buffer::{Change, FileFormat, IndentStyle, LineEnding, UndoTree, Viewport};
+use ferrum_core::constants;
 use ferrum_core::prelude::*;
+use ferrum_core::task::TaskProgress;
+use ferrum_editor::{ConflictResolution, Reconciler, Reconciliation};
+use ferrum_fs::watcher::FsEvent;
+use ferrum_fs::PreSaveHooks;
 use serde::{Deserialize, Serialize};
-use tauri::State;
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, State};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BufferInfo {
@@ -46,20 +54,12 @@ pub fn buffer_insert(
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
 
-  let buffer = state
+  state
     .editor
-    .buffer(id)
-    .ok_or_else(|| "Buffer not found".to_string())?;
-
-  buffer.insert(position, &text).map_err(|e| e.to_string())?;
+    .edit(id, position, position, &text)
+    .map_err(|e| e.to_string())?;
 
-  Ok(BufferInfo {
-    id: buffer.id().to_string(),
-    content: buffer.to_string(),
-    version: buffer.version(),
-    is_dirty: buffer.is_dirty(),
-    language: buffer.language_id(),
-  })
+  buffer_info(&state, id)
 }
 
 /// Delete text from a buffer
@@ -74,20 +74,12 @@ pub fn buffer_delete(
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
 
-  let buffer = state
+  state
     .editor
-    .buffer(id)
-    .ok_or_else(|| "Buffer not found".to_string())?;
-
-  buffer.delete(start, end).map_err(|e| e.to_string())?;
+    .edit(id, start, end, "")
+    .map_err(|e| e.to_string())?;
 
-  Ok(BufferInfo {
-    id: buffer.id().to_string(),
-    content: buffer.to_string(),
-    version: buffer.version(),
-    is_dirty: buffer.is_dirty(),
-    language: buffer.language_id(),
-  })
+  buffer_info(&state, id)
 }
 
 /// Get buffer content
@@ -118,25 +110,120 @@ pub fn buffer_undo(state: State<'_, AppState>, buffer_id: String) -> Result<Buff
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
 
+  state.editor.undo(id).map_err(|e| e.to_string())?;
+
+  buffer_info(&state, id)
+}
+
+/// Redo the last undone edit
+#[tauri::command]
+pub fn buffer_redo(state: State<'_, AppState>, buffer_id: String) -> Result<BufferInfo, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state.editor.redo(id).map_err(|e| e.to_string())?;
+
+  buffer_info(&state, id)
+}
+
+/// Get the undo tree of a buffer
+#[tauri::command]
+pub fn buffer_undo_tree(state: State<'_, AppState>, buffer_id: String) -> Result<UndoTree, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
   let buffer = state
     .editor
     .buffer(id)
     .ok_or_else(|| "Buffer not found".to_string())?;
 
-  buffer.undo();
+  Ok(buffer.undo_tree())
+}
+
+/// Move to an undo state by sequence number, across branches
+#[tauri::command]
+pub fn buffer_undo_goto(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  seq: usize,
+) -> Result<BufferInfo, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
 
-  Ok(BufferInfo {
-    id: buffer.id().to_string(),
-    content: buffer.to_string(),
-    version: buffer.version(),
-    is_dirty: buffer.is_dirty(),
-    language: buffer.language_id(),
-  })
+  let moved = state
+    .editor
+    .undo_goto(id, seq)
+    .map_err(|e| e.to_string())?;
+  if moved.is_none() {
+    return Err(format!("Undo state not found: {}", seq));
+  }
+
+  buffer_info(&state, id)
 }
 
-/// Redo the last undone edit
+/// Move to the state the buffer was in at a point in time
+///
+/// `timestamp` is in milliseconds since the Unix epoch.
 #[tauri::command]
-pub fn buffer_redo(state: State<'_, AppState>, buffer_id: String) -> Result<BufferInfo, String> {
+pub fn buffer_undo_goto_time(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  timestamp: u64,
+) -> Result<BufferInfo, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .undo_goto_time(id, timestamp)
+    .map_err(|e| e.to_string())?;
+
+  buffer_info(&state, id)
+}
+
+/// Replace text in a range
+#[tauri::command]
+pub fn buffer_replace(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  start: usize,
+  end: usize,
+  text: String,
+) -> Result<BufferInfo, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .edit(id, start, end, &text)
+    .map_err(|e| e.to_string())?;
+
+  buffer_info(&state, id)
+}
+
+/// A range edit in buffer char offsets
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RangeEdit {
+  pub start: usize,
+  pub end: usize,
+  pub text: String,
+}
+
+/// Apply several range edits as a single undoable step
+///
+/// All ranges refer to the buffer content before any of the edits, as with
+/// multi-cursor typing or an LSP workspace edit.
+#[tauri::command]
+pub fn buffer_apply_edits(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  edits: Vec<RangeEdit>,
+) -> Result<BufferInfo, String> {
   let id: BufferId = buffer_id
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
@@ -146,7 +233,18 @@ pub fn buffer_redo(state: State<'_, AppState>, buffer_id: String) -> Result<Buff
     .buffer(id)
     .ok_or_else(|| "Buffer not found".to_string())?;
 
-  buffer.redo();
+  let changes = buffer
+    .transact(|tx| {
+      for edit in edits {
+        tx.replace(edit.start, edit.end, edit.text);
+      }
+    })
+    .map_err(|e| e.to_string())?;
+
+  state
+    .editor
+    .update_syntax_after_changes(id, &changes)
+    .map_err(|e| e.to_string())?;
 
   Ok(BufferInfo {
     id: buffer.id().to_string(),
@@ -157,28 +255,127 @@ pub fn buffer_redo(state: State<'_, AppState>, buffer_id: String) -> Result<Buff
   })
 }
 
-/// Replace text in a range
+/// Buffer info after an edit, with the cursor's char offset
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CursorEdit {
+  #[serde(flatten)]
+  pub buffer: BufferInfo,
+  pub cursor: usize,
+}
+
+/// Break the line at a char offset, auto-indenting the new line
 #[tauri::command]
-pub fn buffer_replace(
+pub fn buffer_newline(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  position: usize,
+) -> Result<CursorEdit, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let (_, cursor) = state
+    .editor
+    .insert_newline(id, position, indent_style(&state))
+    .map_err(|e| e.to_string())?;
+
+  Ok(CursorEdit {
+    buffer: buffer_info(&state, id)?,
+    cursor,
+  })
+}
+
+/// Reindent the lines in `start_line..=end_line`, e.g. of a selection
+#[tauri::command]
+pub fn buffer_reindent(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  start_line: usize,
+  end_line: usize,
+) -> Result<BufferInfo, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .reindent_lines(id, start_line..end_line + 1, indent_style(&state))
+    .map_err(|e| e.to_string())?;
+
+  buffer_info(&state, id)
+}
+
+/// Paste text over the chars in `start..end`, reindenting its lines to fit
+#[tauri::command]
+pub fn buffer_paste(
   state: State<'_, AppState>,
   buffer_id: String,
   start: usize,
   end: usize,
   text: String,
-) -> Result<BufferInfo, String> {
+) -> Result<CursorEdit, String> {
   let id: BufferId = buffer_id
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
 
+  let (_, cursor) = state
+    .editor
+    .paste(id, start, end, &text, indent_style(&state))
+    .map_err(|e| e.to_string())?;
+
+  Ok(CursorEdit {
+    buffer: buffer_info(&state, id)?,
+    cursor,
+  })
+}
+
+/// Buffer info after an edit, with the selections it moved
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SelectionEdit {
+  #[serde(flatten)]
+  pub buffer: BufferInfo,
+  pub selections: Vec<SelectionRange>,
+}
+
+/// Swap the syntax node at each selection with its next or previous
+/// sibling, e.g. to move a parameter
+#[tauri::command]
+pub fn buffer_swap_siblings(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  selections: Vec<ByteRange>,
+  forward: bool,
+) -> Result<SelectionEdit, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+  let selections: Vec<_> = selections.iter().map(ByteRange::to_range).collect();
+
+  let (_, selections) = state
+    .editor
+    .swap_sibling_nodes(id, &selections, forward)
+    .map_err(|e| e.to_string())?;
+
+  Ok(SelectionEdit {
+    buffer: buffer_info(&state, id)?,
+    selections: selections.into_iter().map(SelectionRange::from).collect(),
+  })
+}
+
+fn indent_style(state: &AppState) -> IndentStyle {
+  let settings = state.settings.read();
+  IndentStyle {
+    tab_size: settings.editor.tab_size,
+    insert_spaces: settings.editor.insert_spaces,
+  }
+}
+
+fn buffer_info(state: &AppState, id: BufferId) -> Result<BufferInfo, String> {
   let buffer = state
     .editor
     .buffer(id)
     .ok_or_else(|| "Buffer not found".to_string())?;
 
-  buffer
-    .replace(start, end, &text)
-    .map_err(|e| e.to_string())?;
-
   Ok(BufferInfo {
     id: buffer.id().to_string(),
     content: buffer.to_string(),
@@ -244,43 +441,174 @@ pub struct BufferMetrics {
 pub async fn buffer_save(
   state: State<'_, AppState>,
   buffer_id: String,
+  force: Option<bool>,
+) -> Result<BufferInfo, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let hooks = pre_save_hooks(&state);
+  let editor = state.editor.clone();
+  tokio::task::spawn_blocking(move || editor.save_buffer(id, hooks, force.unwrap_or(false)))
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to save file: {}", e))?;
+
+  saved_buffer_info(&state, id)
+}
+
+/// Save buffer to a new file path
+#[tauri::command]
+pub async fn buffer_save_as(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  path: String,
 ) -> Result<BufferInfo, String> {
   let id: BufferId = buffer_id
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
 
+  let hooks = pre_save_hooks(&state);
+  let editor = state.editor.clone();
+  tokio::task::spawn_blocking(move || editor.save_buffer_as(id, path, hooks))
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("Failed to save file: {}", e))?;
+
+  saved_buffer_info(&state, id)
+}
+
+fn pre_save_hooks(state: &AppState) -> PreSaveHooks {
+  let settings = state.settings.read();
+  PreSaveHooks {
+    trim_trailing_whitespace: settings.editor.trim_trailing_whitespace,
+    insert_final_newline: settings.editor.insert_final_newline,
+  }
+}
+
+/// Buffer info after a save, including any edits made by pre-save hooks
+fn saved_buffer_info(state: &AppState, id: BufferId) -> Result<BufferInfo, String> {
   let buffer = state
     .editor
     .buffer(id)
     .ok_or_else(|| "Buffer not found".to_string())?;
 
-  let path = buffer
-    .file_path()
-    .ok_or_else(|| "Buffer has no file path".to_string())?;
+  Ok(BufferInfo {
+    id: buffer.id().to_string(),
+    content: buffer.to_string(),
+    version: buffer.version(),
+    is_dirty: buffer.is_dirty(),
+    language: buffer.language_id(),
+  })
+}
 
-  let content = buffer.to_string();
-  tokio::fs::write(&path, &content)
+/// Open a file and create a buffer for it
+#[tauri::command]
+pub async fn open_file_buffer(
+  state: State<'_, AppState>,
+  path: String,
+) -> Result<BufferInfo, String> {
+  // Large files must go through `open_large_file_buffer`, which never sends
+  // the whole content to the frontend
+  let size = tokio::fs::metadata(&path)
     .await
-    .map_err(|e| format!("Failed to save file: {}", e))?;
+    .map_err(|e| format!("Failed to read file: {}", e))?
+    .len();
+  if size >= constants::LARGE_FILE_THRESHOLD {
+    return Err(
+      Error::FileTooLarge {
+        path: path.into(),
+        size,
+        max: constants::LARGE_FILE_THRESHOLD,
+      }
+      .to_string(),
+    );
+  }
+
+  let bytes = tokio::fs::read(&path)
+    .await
+    .map_err(|e| format!("Failed to read file: {}", e))?;
 
-  buffer.mark_saved();
+  let buffer = state.editor.open_file_bytes(&path, &bytes);
 
   Ok(BufferInfo {
     id: buffer.id().to_string(),
-    content,
+    content: buffer.to_string(),
     version: buffer.version(),
     is_dirty: buffer.is_dirty(),
     language: buffer.language_id(),
   })
 }
 
-/// Save buffer to a new file path
+/// Summary of a buffer opened in large-file mode
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LargeFileInfo {
+  pub id: String,
+  pub version: u64,
+  pub total_lines: usize,
+  pub len_bytes: usize,
+}
+
+/// Load progress of a large file
+#[derive(Debug, Clone, Serialize)]
+struct LoadProgress {
+  path: String,
+  percentage: u8,
+}
+
+/// Open a file in large-file mode
+///
+/// The file is streamed into the buffer on a blocking thread while
+/// `buffer:load_progress` events report how far loading has got. The
+/// content is then read through `buffer_viewport`.
 #[tauri::command]
-pub async fn buffer_save_as(
+pub async fn open_large_file_buffer(
+  app: AppHandle,
   state: State<'_, AppState>,
-  buffer_id: String,
   path: String,
-) -> Result<BufferInfo, String> {
+) -> Result<LargeFileInfo, String> {
+  let progress = Arc::new(TaskProgress::new());
+  let editor = state.editor.clone();
+
+  let load = tokio::task::spawn_blocking({
+    let path = path.clone();
+    let progress = progress.clone();
+    move || editor.open_large_file(path, &progress)
+  });
+  tokio::pin!(load);
+
+  let mut ticker = tokio::time::interval(std::time::Duration::from_millis(100));
+  let buffer = loop {
+    tokio::select! {
+      result = &mut load => break result.map_err(|e| e.to_string())?,
+      _ = ticker.tick() => {
+        let payload = LoadProgress {
+          path: path.clone(),
+          percentage: progress.percentage(),
+        };
+        let _ = app.emit("buffer:load_progress", payload);
+      },
+    }
+  }
+  .map_err(|e| e.to_string())?;
+
+  let snapshot = buffer.snapshot();
+  Ok(LargeFileInfo {
+    id: buffer.id().to_string(),
+    version: snapshot.version(),
+    total_lines: snapshot.len_lines(),
+    len_bytes: snapshot.len_bytes(),
+  })
+}
+
+/// Read a window of lines from a buffer
+#[tauri::command]
+pub fn buffer_viewport(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  first_line: usize,
+  line_count: usize,
+) -> Result<Viewport, String> {
   let id: BufferId = buffer_id
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
@@ -290,34 +618,256 @@ pub async fn buffer_save_as(
     .buffer(id)
     .ok_or_else(|| "Buffer not found".to_string())?;
 
-  let content = buffer.to_string();
-  tokio::fs::write(&path, &content)
-    .await
-    .map_err(|e| format!("Failed to save file: {}", e))?;
+  Ok(buffer.viewport(first_line, line_count))
+}
+
+/// Close a buffer, persisting its undo history
+#[tauri::command]
+pub fn close_buffer(state: State<'_, AppState>, buffer_id: String) -> Result<(), String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .close_buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
+  state.reconciler.forget(id);
+
+  Ok(())
+}
+
+/// A change made to a buffer by reloading it, in chars
+#[derive(Debug, Clone, Serialize)]
+pub struct ReloadChange {
+  pub start: usize,
+  pub old_len: usize,
+  pub new_len: usize,
+}
+
+/// How an open buffer was reconciled with a change on disk
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiskChangePayload {
+  /// The buffer was clean and has been reloaded; `changes` lets the
+  /// frontend move cursors
+  Reloaded {
+    buffer: BufferInfo,
+    changes: Vec<ReloadChange>,
+  },
+  /// The buffer is dirty and the disk changed too
+  Conflict { buffer_id: String, path: String },
+  /// The file was deleted
+  Deleted { buffer_id: String },
+  /// The file was renamed and the buffer follows it
+  Renamed {
+    buffer_id: String,
+    from: String,
+    to: String,
+    language: Option<String>,
+  },
+}
+
+/// Reconcile open buffers with a file system event, emitting
+/// `buffer:disk_change` for each affected buffer
+pub(crate) fn reconcile_buffers(app: &AppHandle, reconciler: &Reconciler, event: &FsEvent) {
+  let result = match event {
+    FsEvent::Created(path) => reconciler.file_created(path),
+    FsEvent::Modified(path) => reconciler.file_modified(path),
+    FsEvent::Deleted(path) => Ok(reconciler.file_deleted(path)),
+    FsEvent::Renamed { from, to } => reconciler.file_renamed(from, to),
+  };
+
+  let reconciliation = match result {
+    Ok(Some(reconciliation)) => reconciliation,
+    Ok(None) => return,
+    Err(e) => {
+      warn!("Failed to reconcile {:?}: {}", event, e);
+      return;
+    },
+  };
+
+  let payload = match reconciliation {
+    Reconciliation::Reloaded { buffer_id, changes } => {
+      let Some(buffer) = reconciler.editor().buffer(buffer_id) else {
+        return;
+      };
+      DiskChangePayload::Reloaded {
+        buffer: BufferInfo {
+          id: buffer_id.to_string(),
+          content: buffer.to_string(),
+          version: buffer.version(),
+          is_dirty: buffer.is_dirty(),
+          language: buffer.language_id(),
+        },
+        changes: changes.iter().map(reload_change).collect(),
+      }
+    },
+    Reconciliation::Conflict(conflict) => DiskChangePayload::Conflict {
+      buffer_id: conflict.buffer_id.to_string(),
+      path: conflict.path.to_string_lossy().to_string(),
+    },
+    Reconciliation::Deleted { buffer_id } => DiskChangePayload::Deleted {
+      buffer_id: buffer_id.to_string(),
+    },
+    Reconciliation::Renamed {
+      buffer_id,
+      from,
+      to,
+    } => DiskChangePayload::Renamed {
+      buffer_id: buffer_id.to_string(),
+      from: from.to_string_lossy().to_string(),
+      to: to.to_string_lossy().to_string(),
+      language: reconciler
+        .editor()
+        .buffer(buffer_id)
+        .and_then(|b| b.language_id()),
+    },
+  };
+
+  let _ = app.emit("buffer:disk_change", payload);
+}
+
+fn reload_change(change: &Change) -> ReloadChange {
+  ReloadChange {
+    start: change.start,
+    old_len: change.old_len,
+    new_len: change.new_len,
+  }
+}
+
+/// The three versions of a buffer in conflict with its file
+#[derive(Debug, Serialize)]
+pub struct DiskConflictInfo {
+  pub base: String,
+  pub disk: String,
+  pub buffer: String,
+  /// Line-by-line merge, with conflict markers where both sides changed
+  pub merged: String,
+  pub conflicts: usize,
+}
+
+/// Get the pending disk conflict for a buffer
+#[tauri::command]
+pub fn buffer_disk_conflict(
+  state: State<'_, AppState>,
+  buffer_id: String,
+) -> Result<Option<DiskConflictInfo>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  Ok(state.reconciler.conflict(id).map(|conflict| {
+    let merge = conflict.merge();
+    DiskConflictInfo {
+      base: conflict.base,
+      disk: conflict.disk,
+      buffer: conflict.buffer,
+      merged: merge.text,
+      conflicts: merge.conflicts,
+    }
+  }))
+}
+
+/// Resolve a disk conflict
+///
+/// `resolution` is `keep_buffer`, `take_disk` or `merged`; `merged` takes
+/// the final text in `text`.
+#[tauri::command]
+pub fn buffer_resolve_conflict(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  resolution: String,
+  text: Option<String>,
+) -> Result<BufferInfo, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let resolution = match (resolution.as_str(), text) {
+    ("keep_buffer", _) => ConflictResolution::KeepBuffer,
+    ("take_disk", _) => ConflictResolution::TakeDisk,
+    ("merged", Some(text)) => ConflictResolution::Merged(text),
+    ("merged", None) => return Err("Merged resolution needs text".to_string()),
+    (other, _) => return Err(format!("Unknown resolution: {}", other)),
+  };
 
-  buffer.set_file_path(&path);
-  buffer.mark_saved();
+  state
+    .reconciler
+    .resolve(id, resolution)
+    .map_err(|e| e.to_string())?;
+
+  let buffer = state
+    .editor
+    .buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
 
   Ok(BufferInfo {
     id: buffer.id().to_string(),
-    content,
+    content: buffer.to_string(),
     version: buffer.version(),
     is_dirty: buffer.is_dirty(),
     language: buffer.language_id(),
   })
 }
 
-/// Open a file and create a buffer for it
+/// On-disk format of a buffer
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BufferFormat {
+  pub encoding: String,
+  pub bom: bool,
+  pub line_ending: LineEnding,
+  pub mixed_line_endings: bool,
+}
+
+impl From<FileFormat> for BufferFormat {
+  fn from(format: FileFormat) -> Self {
+    Self {
+      encoding: format.encoding_name().to_string(),
+      bom: format.bom,
+      line_ending: format.line_ending,
+      mixed_line_endings: format.mixed_line_endings,
+    }
+  }
+}
+
+/// Get the encoding, BOM and line ending of a buffer
 #[tauri::command]
-pub async fn open_file_buffer(
+pub fn buffer_format(
   state: State<'_, AppState>,
-  path: String,
+  buffer_id: String,
+) -> Result<BufferFormat, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let buffer = state
+    .editor
+    .buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
+
+  Ok(buffer.format().into())
+}
+
+/// Convert all line breaks in a buffer to one line ending
+#[tauri::command]
+pub fn buffer_set_line_ending(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  line_ending: LineEnding,
 ) -> Result<BufferInfo, String> {
-  let content = tokio::fs::read_to_string(&path)
-    .await
-    .map_err(|e| format!("Failed to read file: {}", e))?;
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let buffer = state
+    .editor
+    .buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
 
-  let buffer = state.editor.open_file(&path, &content);
+  buffer
+    .set_line_ending(line_ending)
+    .map_err(|e| e.to_string())?;
 
   Ok(BufferInfo {
     id: buffer.id().to_string(),
@@ -327,3 +877,45 @@ pub async fn open_file_buffer(
     language: buffer.language_id(),
   })
 }
+
+/// Set the encoding a buffer is saved with
+#[tauri::command]
+pub fn buffer_set_encoding(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  encoding: String,
+) -> Result<BufferFormat, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let buffer = state
+    .editor
+    .buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
+
+  buffer.set_encoding(&encoding).map_err(|e| e.to_string())?;
+
+  Ok(buffer.format().into())
+}
+
+/// Set whether a buffer is saved with a byte order mark
+#[tauri::command]
+pub fn buffer_set_bom(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  bom: bool,
+) -> Result<BufferFormat, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let buffer = state
+    .editor
+    .buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
+
+  buffer.set_bom(bom);
+
+  Ok(buffer.format().into())
+}
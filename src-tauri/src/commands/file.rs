@@ -42,7 +42,7 @@ pub async fn read_file(path: String) -> Result<FileContent, String> {
 /// Write content to a file
 #[tauri::command]
 pub async fn write_file(path: String, content: String) -> Result<(), String> {
-  tokio::fs::write(&path, &content)
+  ferrum_fs::save::save_async(path, content.into_bytes(), None)
     .await
     .map_err(|e| format!("Failed to write file: {}", e))?;
   Ok(())
@@ -1,9 +1,18 @@
 //! LSP-related Tauri commands
 
 use crate::state::AppState;
+use ferrum_core::BufferId;
+use ferrum_core::constants::EDIT_CONFIRMATION_TIMEOUT_MS;
+use ferrum_core::event::LspProgress;
+use ferrum_editor::{AppliedEdit, destructive_operations};
+use ferrum_lsp::{FileDiagnostic, SeverityCounts};
+use ropey::Rope;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use tauri::State;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::oneshot;
 use tracing::{error, info};
 
 // ========== Helper Functions ==========
@@ -116,6 +125,15 @@ impl From<lsp_types::Range> for Range {
   }
 }
 
+impl From<Range> for lsp_types::Range {
+  fn from(r: Range) -> Self {
+    Self {
+      start: r.start.into(),
+      end: r.end.into(),
+    }
+  }
+}
+
 /// Location (file + range)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Location {
@@ -196,7 +214,6 @@ impl From<lsp_types::Hover> for HoverResult {
 /// Diagnostic severity
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[repr(u8)]
-#[allow(dead_code)]
 pub enum DiagnosticSeverity {
   Error = 1,
   Warning = 2,
@@ -214,6 +231,32 @@ pub struct Diagnostic {
   pub message: String,
 }
 
+impl From<FileDiagnostic> for Diagnostic {
+  fn from(d: FileDiagnostic) -> Self {
+    let severity = match d.severity {
+      ferrum_core::event::DiagnosticSeverity::Error => DiagnosticSeverity::Error,
+      ferrum_core::event::DiagnosticSeverity::Warning => DiagnosticSeverity::Warning,
+      ferrum_core::event::DiagnosticSeverity::Info => DiagnosticSeverity::Information,
+      ferrum_core::event::DiagnosticSeverity::Hint => DiagnosticSeverity::Hint,
+    };
+    Self {
+      range: d.lsp_range.into(),
+      severity: severity as u8,
+      code: d.code,
+      source: d.source,
+      message: d.message,
+    }
+  }
+}
+
+/// Diagnostics of one file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDiagnostics {
+  pub path: String,
+  pub diagnostics: Vec<Diagnostic>,
+  pub counts: SeverityCounts,
+}
+
 /// Symbol information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SymbolInfo {
@@ -236,6 +279,85 @@ impl From<lsp_types::DocumentSymbol> for SymbolInfo {
   }
 }
 
+/// Code action offered for a range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeActionItem {
+  pub title: String,
+  pub kind: Option<String>,
+  pub is_preferred: bool,
+  /// Why the action can't be applied now, if it can't
+  pub disabled: Option<String>,
+  /// The action as the server sent it, to pass to `lsp_apply_code_action`
+  pub action: serde_json::Value,
+}
+
+impl From<lsp_types::CodeActionOrCommand> for CodeActionItem {
+  fn from(action: lsp_types::CodeActionOrCommand) -> Self {
+    let value = serde_json::to_value(&action).unwrap_or_default();
+    match action {
+      lsp_types::CodeActionOrCommand::Command(command) => Self {
+        title: command.title,
+        kind: None,
+        is_preferred: false,
+        disabled: None,
+        action: value,
+      },
+      lsp_types::CodeActionOrCommand::CodeAction(action) => Self {
+        title: action.title,
+        kind: action.kind.map(|kind| kind.as_str().to_string()),
+        is_preferred: action.is_preferred.unwrap_or(false),
+        disabled: action.disabled.map(|disabled| disabled.reason),
+        action: value,
+      },
+    }
+  }
+}
+
+/// Buffer whose file a workspace edit renamed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovedBuffer {
+  pub buffer_id: String,
+  pub path: String,
+}
+
+/// What a workspace edit changed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AppliedWorkspaceEdit {
+  /// Buffers whose text was edited
+  pub edited: Vec<String>,
+  /// Files created, or edited on disk because they weren't open
+  pub written: Vec<String>,
+  pub moved: Vec<MovedBuffer>,
+  /// Buffers closed because their file was deleted
+  pub closed: Vec<String>,
+}
+
+impl From<AppliedEdit> for AppliedWorkspaceEdit {
+  fn from(applied: AppliedEdit) -> Self {
+    Self {
+      edited: applied
+        .changes
+        .iter()
+        .map(|(id, _)| id.to_string())
+        .collect(),
+      written: applied
+        .written
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect(),
+      moved: applied
+        .moved
+        .iter()
+        .map(|(id, path)| MovedBuffer {
+          buffer_id: id.to_string(),
+          path: path.to_string_lossy().to_string(),
+        })
+        .collect(),
+      closed: applied.closed.iter().map(ToString::to_string).collect(),
+    }
+  }
+}
+
 /// LSP server status
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LspStatus {
@@ -249,6 +371,133 @@ fn file_uri(path: &str) -> lsp_types::Uri {
   format!("file://{}", path).parse().unwrap()
 }
 
+/// Send the pending edits of a file, so a request about it sees the text
+/// it was made against
+fn flush_changes(state: &AppState, file_path: &str) {
+  if let Err(e) = state.lsp.sync().flush(Path::new(file_path)) {
+    error!(
+      "Failed to sync {} with its language server: {}",
+      file_path, e
+    );
+  }
+}
+
+/// A workspace edit that deletes or overwrites files, emitted as
+/// `lsp:confirm-workspace-edit` for the user to confirm with
+/// `lsp_confirm_workspace_edit`
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkspaceEditConfirmation {
+  pub id: u64,
+  /// The operations that destroy data
+  pub operations: Vec<lsp_types::ResourceOp>,
+}
+
+/// Ask the user to confirm the operations of a workspace edit that delete
+/// or overwrite files, failing unless they do so in time
+async fn confirm_workspace_edit(
+  app: &AppHandle,
+  edit: &lsp_types::WorkspaceEdit,
+) -> Result<(), String> {
+  static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+  let operations: Vec<_> = destructive_operations(edit).into_iter().cloned().collect();
+  if operations.is_empty() {
+    return Ok(());
+  }
+
+  let state = app.state::<AppState>();
+  let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+  let (tx, rx) = oneshot::channel();
+  state.edit_confirmations.insert(id, tx);
+  let emitted = app.emit(
+    "lsp:confirm-workspace-edit",
+    &WorkspaceEditConfirmation { id, operations },
+  );
+  let confirmed = match emitted {
+    Ok(()) => {
+      let timeout = Duration::from_millis(EDIT_CONFIRMATION_TIMEOUT_MS);
+      matches!(tokio::time::timeout(timeout, rx).await, Ok(Ok(true)))
+    },
+    Err(e) => {
+      error!("Failed to ask for workspace edit confirmation: {}", e);
+      false
+    },
+  };
+  state.edit_confirmations.remove(&id);
+
+  match confirmed {
+    true => Ok(()),
+    false => Err("The edit deletes or overwrites files and was not confirmed".to_string()),
+  }
+}
+
+/// Answer a `lsp:confirm-workspace-edit` event
+#[tauri::command]
+pub fn lsp_confirm_workspace_edit(state: State<'_, AppState>, id: u64, confirmed: bool) {
+  if let Some((_, tx)) = state.edit_confirmations.remove(&id) {
+    let _ = tx.send(confirmed);
+  }
+}
+
+/// Apply a workspace edit to buffers and files, opening renamed files in
+/// their language server again
+///
+/// Operations that delete or overwrite files are confirmed with the user
+/// first.
+pub(crate) async fn apply_workspace_edit(
+  app: &AppHandle,
+  edit: &lsp_types::WorkspaceEdit,
+) -> Result<AppliedWorkspaceEdit, String> {
+  confirm_workspace_edit(app, edit).await?;
+
+  let state = app.state::<AppState>();
+  let applied = state.edit_applier.apply(edit).map_err(|e| {
+    error!("Failed to apply workspace edit: {}", e);
+    e.to_string()
+  })?;
+
+  for (id, path) in &applied.moved {
+    let Some(buffer) = state.editor.buffer(*id) else {
+      continue;
+    };
+    let Some(language) = detect_language(&path.to_string_lossy()) else {
+      continue;
+    };
+    let Some(client) = state.lsp.get_client(&language) else {
+      continue;
+    };
+    let opened = state
+      .lsp
+      .sync()
+      .open_snapshot(client, &buffer.snapshot(), &language, 0);
+    if let Err(e) = opened {
+      error!("Failed to open {:?} in its language server: {}", path, e);
+    }
+  }
+
+  Ok(applied.into())
+}
+
+/// Drop the semantic tokens of the buffers `language`'s server serves, e.g.
+/// when the server asks for a refresh, returning their IDs
+pub(crate) fn clear_semantic_tokens(app: &AppHandle, language: &str) -> Vec<String> {
+  let state = app.state::<AppState>();
+  let mut cleared = Vec::new();
+  for id in state.editor.buffer_ids() {
+    let served = state
+      .editor
+      .buffer(id)
+      .and_then(|buffer| buffer.file_path())
+      .and_then(|path| detect_language(&path.to_string_lossy()))
+      .is_some_and(|detected| detected == language);
+    if served {
+      state.editor.clear_semantic_tokens(id);
+      cleared.push(id.to_string());
+    }
+  }
+  cleared
+}
+
 /// Helper to detect language from file path
 fn detect_language(path: &str) -> Option<String> {
   let ext = std::path::Path::new(path)
@@ -332,6 +581,7 @@ pub async fn lsp_completion(
     .get_client(&language)
     .ok_or_else(|| format!("LSP not running for {}", language))?;
 
+  flush_changes(&state, &file_path);
   let uri = file_uri(&file_path);
   let position = lsp_types::Position { line, character };
 
@@ -359,6 +609,7 @@ pub async fn lsp_hover(
     .get_client(&language)
     .ok_or_else(|| format!("LSP not running for {}", language))?;
 
+  flush_changes(&state, &file_path);
   let uri = file_uri(&file_path);
   let position = lsp_types::Position { line, character };
 
@@ -386,6 +637,7 @@ pub async fn lsp_goto_definition(
     .get_client(&language)
     .ok_or_else(|| format!("LSP not running for {}", language))?;
 
+  flush_changes(&state, &file_path);
   let uri = file_uri(&file_path);
   let position = lsp_types::Position { line, character };
 
@@ -414,6 +666,7 @@ pub async fn lsp_references(
     .get_client(&language)
     .ok_or_else(|| format!("LSP not running for {}", language))?;
 
+  flush_changes(&state, &file_path);
   let uri = file_uri(&file_path);
   let position = lsp_types::Position { line, character };
 
@@ -439,6 +692,7 @@ pub async fn lsp_document_symbols(
     .get_client(&language)
     .ok_or_else(|| format!("LSP not running for {}", language))?;
 
+  flush_changes(&state, &file_path);
   let uri = file_uri(&file_path);
 
   match client.document_symbols(uri).await {
@@ -469,10 +723,49 @@ pub async fn lsp_document_symbols(
 
 /// Get diagnostics for a file
 #[tauri::command]
-pub fn lsp_diagnostics(_state: State<'_, AppState>, _file_path: String) -> Vec<Diagnostic> {
-  // TODO: Return cached diagnostics from LSP client
-  // Diagnostics are pushed from server, need to implement caching
-  Vec::new()
+pub fn lsp_diagnostics(state: State<'_, AppState>, file_path: String) -> Vec<Diagnostic> {
+  state
+    .lsp
+    .diagnostics()
+    .file(Path::new(&file_path))
+    .into_iter()
+    .map(Into::into)
+    .collect()
+}
+
+/// Get diagnostics for every file that has any
+#[tauri::command]
+pub fn lsp_project_diagnostics(state: State<'_, AppState>) -> Vec<FileDiagnostics> {
+  state
+    .lsp
+    .diagnostics()
+    .project()
+    .into_iter()
+    .map(|file| FileDiagnostics {
+      path: file.path.to_string_lossy().to_string(),
+      diagnostics: file.diagnostics.into_iter().map(Into::into).collect(),
+      counts: file.counts,
+    })
+    .collect()
+}
+
+/// Count diagnostics by severity, for a file or the whole project
+#[tauri::command]
+pub fn lsp_diagnostic_counts(
+  state: State<'_, AppState>,
+  file_path: Option<String>,
+) -> SeverityCounts {
+  let store = state.lsp.diagnostics();
+  match file_path {
+    Some(path) => store.counts(Path::new(&path)),
+    None => store.total_counts(),
+  }
+}
+
+/// Get the work language servers report progress on, such as indexing
+#[tauri::command]
+pub fn lsp_progress(state: State<'_, AppState>) -> Vec<LspProgress> {
+  state.lsp.progress()
 }
 
 /// Notify LSP that a file was opened
@@ -498,13 +791,28 @@ pub async fn lsp_did_open(
       .map_err(|e| e.to_string())?
   };
 
-  let uri = file_uri(&file_path);
-  client
-    .did_open(uri, &language_id, version, &content)
-    .map_err(|e| e.to_string())
+  // Sync from the file's buffer if it has one, so its edits reach the server
+  let path = PathBuf::from(&file_path);
+  let sync = state.lsp.sync();
+  let opened = match state.editor.buffer_by_path(&path) {
+    Some(buffer) => sync.open_snapshot(client, &buffer.snapshot(), &language_id, version),
+    None => sync.open(
+      client,
+      &path,
+      &language_id,
+      version,
+      Rope::from_str(&content),
+      None,
+    ),
+  };
+  opened.map_err(|e| e.to_string())
 }
 
 /// Notify LSP that a file was changed
+///
+/// Edits to the buffer of a file opened with `lsp_did_open` are sent as
+/// they happen; this replaces its whole text, with the buffer's if it has
+/// one.
 #[tauri::command]
 pub async fn lsp_did_change(
   state: State<'_, AppState>,
@@ -512,6 +820,16 @@ pub async fn lsp_did_change(
   version: i32,
   content: String,
 ) -> Result<(), String> {
+  let sync = state.lsp.sync();
+  let path = Path::new(&file_path);
+  if sync.is_open(path) {
+    let replaced = match state.editor.buffer_by_path(path) {
+      Some(buffer) => sync.replace_snapshot(&buffer.snapshot()),
+      None => sync.replace(path, Rope::from_str(&content)),
+    };
+    return replaced.map_err(|e| e.to_string());
+  }
+
   let language = detect_language(&file_path).ok_or("Unknown language")?;
 
   let client = state
@@ -543,6 +861,12 @@ pub async fn lsp_did_close(
   state: State<'_, AppState>,
   file_path: String,
 ) -> Result<(), String> {
+  let sync = state.lsp.sync();
+  let path = Path::new(&file_path);
+  if sync.is_open(path) {
+    return sync.close(path).map_err(|e| e.to_string());
+  }
+
   let language = detect_language(&file_path).ok_or("Unknown language")?;
 
   if let Some(client) = state.lsp.get_client(&language) {
@@ -553,22 +877,16 @@ pub async fn lsp_did_close(
   }
 }
 
-/// Text edit for rename operations
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TextEdit {
-  pub range: Range,
-  pub new_text: String,
-}
-
-/// Rename a symbol
+/// Rename a symbol, applying the server's edits across the project
 #[tauri::command]
 pub async fn lsp_rename(
+  app: AppHandle,
   state: State<'_, AppState>,
   file_path: String,
   line: u32,
   character: u32,
   new_name: String,
-) -> Result<Vec<TextEdit>, String> {
+) -> Result<AppliedWorkspaceEdit, String> {
   let language = detect_language(&file_path).ok_or("Unknown language")?;
 
   let client = state
@@ -576,29 +894,205 @@ pub async fn lsp_rename(
     .get_client(&language)
     .ok_or_else(|| format!("LSP not running for {}", language))?;
 
+  flush_changes(&state, &file_path);
   let uri = file_uri(&file_path);
   let position = lsp_types::Position { line, character };
 
   match client.rename(uri, position, &new_name).await {
-    Ok(workspace_edit) => {
-      let mut edits = Vec::new();
-
-      if let Some(changes) = workspace_edit.changes {
-        for (_uri, file_edits) in changes {
-          for edit in file_edits {
-            edits.push(TextEdit {
-              range: edit.range.into(),
-              new_text: edit.new_text,
-            });
-          }
-        }
-      }
-
-      Ok(edits)
-    },
+    Ok(workspace_edit) => apply_workspace_edit(&app, &workspace_edit).await,
     Err(e) => {
       error!("LSP rename failed: {}", e);
       Err(e.to_string())
     },
   }
 }
+
+/// Get the code actions for a range, with the diagnostics in it as their
+/// context
+///
+/// `only` limits the actions to kinds such as `quickfix` or `refactor`.
+#[tauri::command]
+pub async fn lsp_code_actions(
+  state: State<'_, AppState>,
+  file_path: String,
+  range: Range,
+  only: Option<Vec<String>>,
+) -> Result<Vec<CodeActionItem>, String> {
+  let language = detect_language(&file_path).ok_or("Unknown language")?;
+
+  let client = state
+    .lsp
+    .get_client(&language)
+    .ok_or_else(|| format!("LSP not running for {}", language))?;
+
+  flush_changes(&state, &file_path);
+  let range = lsp_types::Range::from(range);
+  let diagnostics = state
+    .lsp
+    .diagnostics()
+    .overlapping(Path::new(&file_path), range);
+  let only = only.map(|kinds| {
+    kinds
+      .into_iter()
+      .map(lsp_types::CodeActionKind::from)
+      .collect()
+  });
+
+  match client
+    .code_actions(file_uri(&file_path), range, diagnostics, only)
+    .await
+  {
+    Ok(actions) => Ok(actions.into_iter().map(Into::into).collect()),
+    Err(e) => {
+      error!("LSP code actions failed: {}", e);
+      Err(e.to_string())
+    },
+  }
+}
+
+/// Apply a code action from `lsp_code_actions`
+///
+/// Its edit is resolved if the server left it out, then applied, then its
+/// command is run. Edits the server makes while running the command are
+/// emitted as `lsp:workspace-edit` events.
+#[tauri::command]
+pub async fn lsp_apply_code_action(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_path: String,
+  action: serde_json::Value,
+) -> Result<AppliedWorkspaceEdit, String> {
+  let language = detect_language(&file_path).ok_or("Unknown language")?;
+
+  let client = state
+    .lsp
+    .get_client(&language)
+    .ok_or_else(|| format!("LSP not running for {}", language))?;
+
+  let action: lsp_types::CodeActionOrCommand =
+    serde_json::from_value(action).map_err(|e| format!("Invalid code action: {}", e))?;
+  let (edit, command) = match action {
+    lsp_types::CodeActionOrCommand::Command(command) => (None, Some(command)),
+    lsp_types::CodeActionOrCommand::CodeAction(action) => {
+      let action = client.resolve_code_action(action).await.map_err(|e| {
+        error!("LSP code action resolve failed: {}", e);
+        e.to_string()
+      })?;
+      (action.edit, action.command)
+    },
+  };
+
+  let applied = match edit {
+    Some(edit) => apply_workspace_edit(&app, &edit).await?,
+    None => AppliedWorkspaceEdit::default(),
+  };
+  if let Some(command) = command {
+    client.execute_command(command).await.map_err(|e| {
+      error!("LSP command failed: {}", e);
+      e.to_string()
+    })?;
+  }
+
+  Ok(applied)
+}
+
+/// Request folding ranges for a buffer and merge them with those of its
+/// folds query
+///
+/// Returns whether the ranges were applied. They are dropped if the buffer
+/// changed while the server was working, or if the server has none.
+#[tauri::command]
+pub async fn lsp_folding_ranges(
+  state: State<'_, AppState>,
+  buffer_id: String,
+) -> Result<bool, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let buffer = state
+    .editor
+    .buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
+  let file_path = buffer
+    .file_path()
+    .ok_or("Buffer has no file")?
+    .to_string_lossy()
+    .to_string();
+  let language = detect_language(&file_path).ok_or("Unknown language")?;
+
+  let client = state
+    .lsp
+    .get_client(&language)
+    .ok_or_else(|| format!("LSP not running for {}", language))?;
+  if !client.supports_folding_ranges() {
+    return Ok(false);
+  }
+
+  flush_changes(&state, &file_path);
+  let version = buffer.version();
+  let ranges = match client.folding_ranges(file_uri(&file_path)).await {
+    Ok(ranges) => ranges,
+    Err(e) => {
+      error!("LSP folding ranges failed: {}", e);
+      return Err(e.to_string());
+    },
+  };
+
+  state
+    .editor
+    .set_lsp_folds(id, version, ranges)
+    .map_err(|e| e.to_string())
+}
+
+/// Request semantic tokens for a buffer and layer them over its syntax
+/// highlights
+///
+/// Returns whether the tokens were applied. They are dropped if the buffer
+/// changed while the server was working, or if the server has none.
+#[tauri::command]
+pub async fn lsp_semantic_tokens(
+  state: State<'_, AppState>,
+  buffer_id: String,
+) -> Result<bool, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let buffer = state
+    .editor
+    .buffer(id)
+    .ok_or_else(|| "Buffer not found".to_string())?;
+  let file_path = buffer
+    .file_path()
+    .ok_or("Buffer has no file")?
+    .to_string_lossy()
+    .to_string();
+  let language = detect_language(&file_path).ok_or("Unknown language")?;
+
+  let client = state
+    .lsp
+    .get_client(&language)
+    .ok_or_else(|| format!("LSP not running for {}", language))?;
+  if client.semantic_tokens_legend().is_none() {
+    return Ok(false);
+  }
+
+  flush_changes(&state, &file_path);
+  let snapshot = buffer.snapshot();
+  let tokens = match client
+    .semantic_tokens(file_uri(&file_path), snapshot.rope())
+    .await
+  {
+    Ok(tokens) => tokens,
+    Err(e) => {
+      error!("LSP semantic tokens failed: {}", e);
+      return Err(e.to_string());
+    },
+  };
+
+  state
+    .editor
+    .set_semantic_tokens(id, snapshot.version(), tokens)
+    .map_err(|e| e.to_string())
+}
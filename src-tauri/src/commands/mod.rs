@@ -6,6 +6,7 @@ pub mod file;
 pub mod git;
 pub mod lsp;
 pub mod project;
+pub mod recovery;
 pub mod search;
 pub mod settings;
 pub mod syntax;
@@ -1,12 +1,15 @@
 //! Project operation commands
 
+use crate::commands::buffer::reconcile_buffers;
 use crate::state::AppState;
 use ferrum_core::prelude::*;
 use ferrum_fs::walker::FileWalker;
 use ferrum_fs::watcher::FsEvent;
+use ferrum_editor::{DependencyEdge, DependencyGraph, FileImport};
 use ferrum_fs::FileWatcher;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tauri::{AppHandle, Emitter, State};
 
 /// Project info response
@@ -198,6 +201,8 @@ pub async fn watch_directory(
     // Spawn event handler task
     let app_clone = app.clone();
     let watcher_state_clone = state.watcher.clone();
+    let reconciler = state.reconciler.clone();
+    let dependency_graph = state.dependency_graph.clone();
 
     tokio::spawn(async move {
       loop {
@@ -211,6 +216,21 @@ pub async fn watch_directory(
         };
 
         if let Some(event) = event {
+          // Reconciling reads the file, so keep it off the async runtime
+          let (app, reconciler, fs_event) = (app_clone.clone(), reconciler.clone(), event.clone());
+          let _ =
+            tokio::task::spawn_blocking(move || reconcile_buffers(&app, &reconciler, &fs_event))
+              .await;
+          let graph = dependency_graph.read().clone();
+          if let Some(graph) = graph {
+            let event = event.clone();
+            let changed = tokio::task::spawn_blocking(move || graph.handle_event(&event))
+              .await
+              .unwrap_or(false);
+            if changed {
+              let _ = app_clone.emit("deps:changed", ());
+            }
+          }
           let payload: FsEventPayload = event.into();
           let _ = app_clone.emit("fs:change", payload);
         }
@@ -248,3 +268,81 @@ pub fn get_recent_projects(_state: State<'_, AppState>) -> Vec<ProjectInfo> {
   // TODO: Load from settings/persistence
   Vec::new()
 }
+
+/// Dependency graph summary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DependencyGraphInfo {
+  pub root: String,
+  pub file_count: usize,
+}
+
+fn dependency_graph(state: &AppState) -> Result<Arc<DependencyGraph>, String> {
+  state
+    .dependency_graph
+    .read()
+    .clone()
+    .ok_or_else(|| "Dependency graph not built".to_string())
+}
+
+/// Build the dependency graph of a project, replacing any previous one
+///
+/// The graph is kept up to date from the events of watched directories.
+#[tauri::command]
+pub async fn build_dependency_graph(
+  state: State<'_, AppState>,
+  root: String,
+) -> Result<DependencyGraphInfo, String> {
+  let graph = Arc::new(DependencyGraph::new(&root));
+  let built = graph.clone();
+  tokio::task::spawn_blocking(move || built.build())
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+
+  let info = DependencyGraphInfo {
+    root: graph.root().to_string_lossy().to_string(),
+    file_count: graph.file_count(),
+  };
+  *state.dependency_graph.write() = Some(graph);
+  Ok(info)
+}
+
+/// Get all edges of the dependency graph
+#[tauri::command]
+pub fn dependency_graph_edges(state: State<'_, AppState>) -> Result<Vec<DependencyEdge>, String> {
+  Ok(dependency_graph(&state)?.edges())
+}
+
+/// Get the imports of a file and the files they resolve to
+#[tauri::command]
+pub fn dependency_graph_imports(
+  state: State<'_, AppState>,
+  path: String,
+) -> Result<Vec<FileImport>, String> {
+  Ok(dependency_graph(&state)?.imports(Path::new(&path)))
+}
+
+/// Get the imports of other files resolving to a file
+#[tauri::command]
+pub fn dependency_graph_dependents(
+  state: State<'_, AppState>,
+  path: String,
+) -> Result<Vec<DependencyEdge>, String> {
+  Ok(dependency_graph(&state)?.dependents(Path::new(&path)))
+}
+
+/// Get the groups of files importing each other in a cycle
+#[tauri::command]
+pub fn dependency_graph_cycles(state: State<'_, AppState>) -> Result<Vec<Vec<PathBuf>>, String> {
+  Ok(dependency_graph(&state)?.cycles())
+}
+
+/// Get a shortest chain of imports from one file to another
+#[tauri::command]
+pub fn dependency_graph_path(
+  state: State<'_, AppState>,
+  from: String,
+  to: String,
+) -> Result<Option<Vec<PathBuf>>, String> {
+  Ok(dependency_graph(&state)?.shortest_path(Path::new(&from), Path::new(&to)))
+}
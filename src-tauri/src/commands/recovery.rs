@@ -0,0 +1,98 @@
+//! Crash recovery and hot exit commands
+
+use crate::commands::buffer::BufferInfo;
+use crate::state::AppState;
+use ferrum_core::prelude::*;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+/// A buffer that can be recovered from a previous session
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryInfo {
+  /// Full ID of the buffer that wrote the entry, to pass back to
+  /// `recovery_restore` and `recovery_discard`
+  pub id: String,
+  /// File path; `None` for untitled buffers
+  pub path: Option<String>,
+  /// First line of the content, to tell untitled buffers apart
+  pub preview: String,
+  pub timestamp: u64,
+  /// Whether the buffer was kept on purpose by hot exit rather than left
+  /// behind by a crash
+  pub hot_exit: bool,
+  /// Whether the file changed on disk since the buffer last saw it
+  pub disk_changed: bool,
+}
+
+/// List buffers left by previous sessions
+#[tauri::command]
+pub fn recovery_list(state: State<'_, AppState>) -> Result<Vec<RecoveryInfo>, String> {
+  let entries = state
+    .editor
+    .recovery_entries()
+    .map_err(|e| e.to_string())?;
+
+  Ok(
+    entries
+      .iter()
+      .map(|entry| RecoveryInfo {
+        id: entry.buffer_id.as_uuid().to_string(),
+        path: entry
+          .path
+          .as_ref()
+          .map(|p| p.to_string_lossy().to_string()),
+        preview: entry.content.lines().next().unwrap_or_default().to_string(),
+        timestamp: entry.timestamp,
+        hot_exit: entry.hot_exit,
+        disk_changed: entry.disk_changed(),
+      })
+      .collect(),
+  )
+}
+
+/// Recover buffers by the IDs from `recovery_list`
+#[tauri::command]
+pub fn recovery_restore(
+  state: State<'_, AppState>,
+  ids: Vec<String>,
+) -> Result<Vec<BufferInfo>, String> {
+  let entries = state
+    .editor
+    .recovery_entries()
+    .map_err(|e| e.to_string())?;
+
+  let mut buffers = Vec::new();
+  for entry in entries
+    .iter()
+    .filter(|entry| ids.contains(&entry.buffer_id.as_uuid().to_string()))
+  {
+    let buffer = state
+      .editor
+      .recover_buffer(entry)
+      .map_err(|e| e.to_string())?;
+
+    buffers.push(BufferInfo {
+      id: buffer.id().to_string(),
+      content: buffer.to_string(),
+      version: buffer.version(),
+      is_dirty: buffer.is_dirty(),
+      language: buffer.language_id(),
+    });
+  }
+
+  Ok(buffers)
+}
+
+/// Discard recoverable buffers by the IDs from `recovery_list`
+#[tauri::command]
+pub fn recovery_discard(state: State<'_, AppState>, ids: Vec<String>) -> Result<(), String> {
+  for id in ids {
+    let id: BufferId = id.parse().map_err(|_| "Invalid buffer ID".to_string())?;
+    state
+      .editor
+      .discard_recovery(id)
+      .map_err(|e| e.to_string())?;
+  }
+
+  Ok(())
+}
@@ -48,14 +48,21 @@ pub fn search_files(
     .collect()
 }
 
-/// Search for text in files
+/// Search for text in files, with the unsaved edits of open buffers
 #[tauri::command]
 pub fn search_text(
+  state: State<'_, AppState>,
   root: String,
   query: String,
   options: Option<TextSearchOptions>,
 ) -> Result<Vec<TextSearchResult>, String> {
-  let searcher = TextSearcher::new(&root);
+  let snapshots = state
+    .editor
+    .buffer_ids()
+    .into_iter()
+    .filter_map(|id| state.editor.buffer(id))
+    .map(|buffer| buffer.snapshot());
+  let searcher = TextSearcher::new(&root).with_buffers(snapshots);
   let opts = options
     .map(|o| SearchOptions {
       case_sensitive: o.case_sensitive.unwrap_or(false),
@@ -1,8 +1,11 @@
 //! Syntax highlighting commands
 
 use crate::state::AppState;
+use ferrum_buffer::global_grammar_registry;
 use ferrum_buffer::syntax::{Highlight, LanguageId, SyntaxError};
+use ferrum_buffer::{Brackets, QueryResult, SyntaxNode, TextObjectKind, TextObjectScope};
 use ferrum_core::prelude::*;
+use ferrum_editor::editor::TextRange;
 use serde::{Deserialize, Serialize};
 use tauri::State;
 
@@ -13,6 +16,8 @@ pub struct HighlightSpan {
   pub end: usize,
   pub kind: String,
   pub css_class: String,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub modifiers: Vec<String>,
 }
 
 /// Parse result for frontend
@@ -37,11 +42,24 @@ pub struct SyntaxErrorResponse {
 
 impl From<Highlight> for HighlightSpan {
   fn from(h: Highlight) -> Self {
+    // Semantic token modifiers get a class each, e.g. `hl-mod-default-library`
+    let mut css_class = h.kind.css_class().to_string();
+    for modifier in &h.modifiers {
+      css_class.push_str(" hl-mod-");
+      for c in modifier.chars() {
+        if c.is_ascii_uppercase() {
+          css_class.push('-');
+        }
+        css_class.push(c.to_ascii_lowercase());
+      }
+    }
+
     Self {
       start: h.start,
       end: h.end,
       kind: format!("{:?}", h.kind),
-      css_class: h.kind.css_class().to_string(),
+      css_class,
+      modifiers: h.modifiers,
     }
   }
 }
@@ -72,30 +90,16 @@ pub fn get_highlights(
     .parse()
     .map_err(|_| "Invalid buffer ID".to_string())?;
 
-  let buffer = state
+  // Highlight one version of the content, even if edits land meanwhile
+  let snapshot = state
     .editor
     .buffer(id)
-    .ok_or_else(|| "Buffer not found".to_string())?;
-
-  // Get language from buffer
-  let language_str = buffer
-    .language_id()
-    .unwrap_or_else(|| "unknown".to_string());
-  let language = match language_str.as_str() {
-    "rust" => LanguageId::Rust,
-    "typescript" => LanguageId::TypeScript,
-    "javascript" => LanguageId::JavaScript,
-    "python" => LanguageId::Python,
-    "go" => LanguageId::Go,
-    "json" => LanguageId::Json,
-    "toml" => LanguageId::Toml,
-    "html" => LanguageId::Html,
-    "css" => LanguageId::Css,
-    "markdown" => LanguageId::Markdown,
-    _ => LanguageId::Unknown,
-  };
-
-  if language == LanguageId::Unknown {
+    .ok_or_else(|| "Buffer not found".to_string())?
+    .snapshot();
+
+  let language = LanguageId::from_name(snapshot.language_id().unwrap_or("unknown"));
+
+  if language.tree_sitter_language().is_none() {
     return Ok(ParseResultResponse {
       highlights: Vec::new(),
       errors: Vec::new(),
@@ -106,7 +110,7 @@ pub fn get_highlights(
   // Get or create syntax manager for this buffer
   let result = state
     .editor
-    .get_syntax_highlights(id, start_byte, end_byte)
+    .snapshot_highlights(&snapshot, start_byte, end_byte)
     .map_err(|e| e.to_string())?;
 
   Ok(ParseResultResponse {
@@ -124,17 +128,14 @@ pub fn detect_language(path: String) -> String {
   Path::new(&path)
     .extension()
     .and_then(|e| e.to_str())
-    .map(|ext| {
-      let lang = LanguageId::from_extension(ext);
-      format!("{:?}", lang).to_lowercase()
-    })
+    .map(|ext| LanguageId::from_extension(ext).name().into_owned())
     .unwrap_or_else(|| "unknown".to_string())
 }
 
 /// Get supported languages
 #[tauri::command]
 pub fn get_supported_languages() -> Vec<String> {
-  vec![
+  let mut languages = vec![
     "rust".to_string(),
     "typescript".to_string(),
     "typescriptreact".to_string(),
@@ -147,7 +148,16 @@ pub fn get_supported_languages() -> Vec<String> {
     "html".to_string(),
     "css".to_string(),
     "markdown".to_string(),
-  ]
+  ];
+
+  // Grammars loaded at runtime
+  languages.extend(
+    global_grammar_registry()
+      .languages()
+      .into_iter()
+      .map(|(name, _)| name),
+  );
+  languages
 }
 
 /// Selection range for smart expansion
@@ -161,6 +171,153 @@ pub struct SelectionRange {
   pub end_byte: usize,
 }
 
+impl From<TextRange> for SelectionRange {
+  fn from(r: TextRange) -> Self {
+    Self {
+      start_line: r.0 as u32,
+      start_character: r.1 as u32,
+      end_line: r.2 as u32,
+      end_character: r.3 as u32,
+      start_byte: r.4,
+      end_byte: r.5,
+    }
+  }
+}
+
+/// Byte range of a selection, as sent for structural selection commands
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ByteRange {
+  pub start_byte: usize,
+  pub end_byte: usize,
+}
+
+impl ByteRange {
+  pub fn to_range(&self) -> std::ops::Range<usize> {
+    self.start_byte..self.end_byte
+  }
+}
+
+/// Select the text object enclosing each selection, e.g. "around function"
+#[tauri::command]
+pub fn select_text_object(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  selections: Vec<ByteRange>,
+  kind: TextObjectKind,
+  scope: TextObjectScope,
+) -> Result<Vec<SelectionRange>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+  let selections: Vec<_> = selections.iter().map(ByteRange::to_range).collect();
+
+  let ranges = state
+    .editor
+    .select_text_object(id, &selections, kind, scope)
+    .map_err(|e| e.to_string())?;
+  Ok(ranges.into_iter().map(SelectionRange::from).collect())
+}
+
+/// Move each selection to the next or previous text object, e.g. "next
+/// argument"
+#[tauri::command]
+pub fn goto_text_object(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  selections: Vec<ByteRange>,
+  kind: TextObjectKind,
+  scope: TextObjectScope,
+  forward: bool,
+) -> Result<Vec<SelectionRange>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+  let selections: Vec<_> = selections.iter().map(ByteRange::to_range).collect();
+
+  let ranges = state
+    .editor
+    .goto_text_object(id, &selections, kind, scope, forward)
+    .map_err(|e| e.to_string())?;
+  Ok(ranges.into_iter().map(SelectionRange::from).collect())
+}
+
+/// Select the syntax node enclosing each selection
+#[tauri::command]
+pub fn select_parent_node(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  selections: Vec<ByteRange>,
+) -> Result<Vec<SelectionRange>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+  let selections: Vec<_> = selections.iter().map(ByteRange::to_range).collect();
+
+  let ranges = state
+    .editor
+    .select_parent_node(id, &selections)
+    .map_err(|e| e.to_string())?;
+  Ok(ranges.into_iter().map(SelectionRange::from).collect())
+}
+
+/// Select the next or previous sibling of the syntax node at each selection
+#[tauri::command]
+pub fn select_sibling_node(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  selections: Vec<ByteRange>,
+  forward: bool,
+) -> Result<Vec<SelectionRange>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+  let selections: Vec<_> = selections.iter().map(ByteRange::to_range).collect();
+
+  let ranges = state
+    .editor
+    .select_sibling_node(id, &selections, forward)
+    .map_err(|e| e.to_string())?;
+  Ok(ranges.into_iter().map(SelectionRange::from).collect())
+}
+
+/// Move each selection's cursor to the matching bracket
+#[tauri::command]
+pub fn jump_to_matching_bracket(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  selections: Vec<ByteRange>,
+) -> Result<Vec<SelectionRange>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+  let selections: Vec<_> = selections.iter().map(ByteRange::to_range).collect();
+
+  let ranges = state
+    .editor
+    .jump_to_matching_bracket(id, &selections)
+    .map_err(|e| e.to_string())?;
+  Ok(ranges.into_iter().map(SelectionRange::from).collect())
+}
+
+/// Get the bracket pairs and unmatched brackets in a byte range, e.g. the
+/// viewport
+#[tauri::command]
+pub fn get_bracket_pairs(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  start_byte: usize,
+  end_byte: usize,
+) -> Result<Brackets, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .bracket_pairs(id, start_byte..end_byte)
+    .map_err(|e| e.to_string())
+}
+
 /// Expand selection to the next larger syntax node
 #[tauri::command]
 pub fn expand_selection(
@@ -215,6 +372,93 @@ pub fn shrink_selection(
   })
 }
 
+/// Get the definition and references of the local symbol at a byte offset
+#[tauri::command]
+pub fn symbol_references(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  byte: usize,
+) -> Result<Vec<SelectionRange>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  let ranges = state
+    .editor
+    .symbol_references(id, byte)
+    .map_err(|e| e.to_string())?;
+
+  Ok(
+    ranges
+      .into_iter()
+      .map(|r| SelectionRange {
+        start_line: r.0 as u32,
+        start_character: r.1 as u32,
+        end_line: r.2 as u32,
+        end_character: r.3 as u32,
+        start_byte: r.4,
+        end_byte: r.5,
+      })
+      .collect(),
+  )
+}
+
+/// Get the syntax tree of a buffer, or the subtree of the node covering a
+/// byte range, for debugging grammars and queries
+#[tauri::command]
+pub fn inspect_syntax_tree(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  range: Option<ByteRange>,
+  named_only: bool,
+) -> Result<Option<SyntaxNode>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .syntax_tree(id, range.as_ref().map(ByteRange::to_range), named_only)
+    .map_err(|e| e.to_string())
+}
+
+/// Get the syntax tree of a buffer, or the subtree of the node covering a
+/// byte range, as an S-expression
+#[tauri::command]
+pub fn syntax_tree_sexp(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  range: Option<ByteRange>,
+) -> Result<Option<String>, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .syntax_tree_sexp(id, range.as_ref().map(ByteRange::to_range))
+    .map_err(|e| e.to_string())
+}
+
+/// Run a tree-sitter query against a buffer and get its captures, and
+/// whether they were cut short
+#[tauri::command]
+pub fn run_syntax_query(
+  state: State<'_, AppState>,
+  buffer_id: String,
+  query: String,
+  range: Option<ByteRange>,
+) -> Result<QueryResult, String> {
+  let id: BufferId = buffer_id
+    .parse()
+    .map_err(|_| "Invalid buffer ID".to_string())?;
+
+  state
+    .editor
+    .run_syntax_query(id, &query, range.as_ref().map(ByteRange::to_range))
+    .map_err(|e| e.to_string())
+}
+
 /// Scope information for sticky headers
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ScopeInfo {
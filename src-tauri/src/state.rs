@@ -1,6 +1,7 @@
 //! Application state management
 
-use ferrum_editor::Editor;
+use dashmap::DashMap;
+use ferrum_editor::{DependencyGraph, Editor, Reconciler, WorkspaceEditApplier};
 use ferrum_fs::FileWatcher;
 use ferrum_lsp::LspManager;
 use ferrum_search::FileSearcher;
@@ -8,7 +9,7 @@ use ferrum_settings::Settings;
 use parking_lot::RwLock;
 use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, oneshot};
 
 /// File watcher state
 pub struct WatcherState {
@@ -28,22 +29,37 @@ impl WatcherState {
 /// Global application state
 pub struct AppState {
   pub editor: Arc<Editor>,
-  #[allow(dead_code)]
+  pub reconciler: Arc<Reconciler>,
+  /// Applies the workspace edits of renames, code actions and servers
+  pub edit_applier: Arc<WorkspaceEditApplier>,
+  /// Workspace edits that delete or overwrite files, waiting for the user
+  /// to confirm them, by confirmation ID
+  pub edit_confirmations: Arc<DashMap<u64, oneshot::Sender<bool>>>,
   pub lsp: Arc<LspManager>,
   pub file_searcher: Arc<RwLock<FileSearcher>>,
   pub settings: Arc<RwLock<Settings>>,
   pub watcher: Arc<Mutex<WatcherState>>,
+  /// Dependency graph of the open project, once built
+  pub dependency_graph: Arc<RwLock<Option<Arc<DependencyGraph>>>>,
 }
 
 impl AppState {
   /// Create new application state
   pub fn new() -> Self {
+    let editor = Arc::new(Editor::new());
+    let lsp = Arc::new(LspManager::new());
+    editor.set_diagnostic_store(lsp.diagnostics());
+    editor.set_document_sync(lsp.sync());
     Self {
-      editor: Arc::new(Editor::new()),
-      lsp: Arc::new(LspManager::new()),
+      reconciler: Arc::new(Reconciler::new(editor.clone())),
+      edit_applier: Arc::new(WorkspaceEditApplier::new(editor.clone())),
+      edit_confirmations: Arc::new(DashMap::new()),
+      editor,
+      lsp,
       file_searcher: Arc::new(RwLock::new(FileSearcher::new())),
       settings: Arc::new(RwLock::new(Settings::default())),
       watcher: Arc::new(Mutex::new(WatcherState::new())),
+      dependency_graph: Arc::new(RwLock::new(None)),
     }
   }
 }
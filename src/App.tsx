@@ -6,7 +6,8 @@
  * and bottom panel.
  */
 
-import { createEffect, createSignal, onMount, Show } from "solid-js";
+import { listen } from "@tauri-apps/api/event";
+import { createEffect, createSignal, onCleanup, onMount, Show } from "solid-js";
 import { ActivityBar } from "./components/layout/ActivityBar";
 import { EditorArea } from "./components/layout/EditorArea";
 import { Panel } from "./components/layout/Panel";
@@ -81,6 +82,24 @@ function App() {
     return () => window.removeEventListener("keydown", handleKeyDown);
   });
 
+  // Ask before language server edits delete or overwrite files
+  onMount(() => {
+    if (!isTauriEnvironment()) return;
+    const unlisten = listen<ipc.WorkspaceEditConfirmation>(
+      "lsp:confirm-workspace-edit",
+      (event) => {
+        const { id, operations } = event.payload;
+        const files = operations.map((op) =>
+          op.kind === "rename" ? `${op.oldUri} -> ${op.newUri}` : op.uri
+        );
+        const message = "The language server wants to delete or overwrite:";
+        const confirmed = confirm(`${message}\n${files.join("\n")}`);
+        void ipc.lspConfirmWorkspaceEdit(id, confirmed);
+      }
+    );
+    onCleanup(() => void unlisten.then((stop) => stop()));
+  });
+
   // Update scopes when active tab changes
   createEffect(async () => {
     const activeTab = editorStore.getActiveTab();
@@ -10,6 +10,7 @@
  * - Undo/Redo with history
  */
 
+import { listen } from "@tauri-apps/api/event";
 import {
   batch,
   createEffect,
@@ -82,6 +83,7 @@ export function Editor(props: EditorProps) {
       if (isTauriEnvironment()) {
         const result = await ipc.getHighlights(props.bufferId);
         setState("highlights", result.highlights);
+        loadSemanticTokens(props.bufferId);
       } else {
         // Use client-side highlighting in browser mode
         const code = state.lines.join("\n");
@@ -93,6 +95,18 @@ export function Editor(props: EditorProps) {
     }
   }
 
+  // Layer language server semantic tokens over the tree-sitter highlights
+  async function loadSemanticTokens(bufferId: string) {
+    try {
+      if (await ipc.lspSemanticTokens(bufferId)) {
+        const result = await ipc.getHighlights(bufferId);
+        setState("highlights", result.highlights);
+      }
+    } catch {
+      // No language server for this buffer; tree-sitter highlights stay
+    }
+  }
+
   // Debounced highlight loading
   let highlightTimeout: ReturnType<typeof setTimeout>;
   function scheduleHighlightUpdate() {
@@ -690,6 +704,17 @@ export function Editor(props: EditorProps) {
     loadHighlights();
   });
 
+  // Request semantic tokens again when the language server refreshes them
+  onMount(() => {
+    if (!isTauriEnvironment()) return;
+    const unlisten = listen<string[]>("lsp:semantic-tokens-refresh", (event) => {
+      if (props.bufferId && event.payload.includes(props.bufferId)) {
+        loadHighlights();
+      }
+    });
+    onCleanup(() => void unlisten.then((stop) => stop()));
+  });
+
   onCleanup(() => {
     clearTimeout(highlightTimeout);
   });
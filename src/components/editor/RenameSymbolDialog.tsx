@@ -155,14 +155,12 @@ export async function renameSymbol(
   if (!isTauriEnvironment()) return false;
 
   try {
-    const edits = await ipc.lspRename(filePath, line, character, newName);
-
-    if (edits && edits.length > 0) {
-      console.log("Rename successful, edits:", edits);
-      return true;
-    }
-
-    return false;
+    const applied = await ipc.lspRename(filePath, line, character, newName);
+    return (
+      applied.edited.length > 0 ||
+      applied.written.length > 0 ||
+      applied.moved.length > 0
+    );
   } catch (e) {
     console.error("Rename failed:", e);
     return false;
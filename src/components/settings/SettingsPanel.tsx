@@ -121,6 +121,27 @@ const SETTINGS_SCHEMA: SettingItem[] = [
     category: "Editor",
     type: "boolean",
   },
+  {
+    key: "editor.trim_trailing_whitespace",
+    label: "Trim Trailing Whitespace",
+    description: "Remove trailing spaces and tabs from each line when saving",
+    category: "Editor",
+    type: "boolean",
+  },
+  {
+    key: "editor.insert_final_newline",
+    label: "Insert Final Newline",
+    description: "End the file with a line break when saving",
+    category: "Editor",
+    type: "boolean",
+  },
+  {
+    key: "editor.hot_exit",
+    label: "Hot Exit",
+    description: "Keep unsaved changes on exit and restore them on the next launch",
+    category: "Editor",
+    type: "boolean",
+  },
 
   // Terminal settings
   {
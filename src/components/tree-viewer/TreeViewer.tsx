@@ -6,7 +6,12 @@
  */
 
 import { createEffect, createSignal, For, type JSX, Show } from "solid-js";
-import { getDepthRegions, getFoldState, toggleFold } from "../../ipc/commands";
+import {
+  getDepthRegions,
+  getFoldState,
+  lspFoldingRanges,
+  toggleFold,
+} from "../../ipc/commands";
 import type { DepthRegionInfo, FoldState } from "../../ipc/types";
 
 interface TreeViewerProps {
@@ -56,6 +61,17 @@ export function TreeViewer(props: TreeViewerProps) {
     }
   };
 
+  // Merge language server folding ranges with the tree-sitter ones
+  const loadLspFolds = async (bufferId: string) => {
+    try {
+      if (await lspFoldingRanges(bufferId)) {
+        await loadFoldState();
+      }
+    } catch {
+      // No language server for this buffer; tree-sitter folds stay
+    }
+  };
+
   // Toggle fold at a specific line
   const handleToggleFold = async (line: number) => {
     try {
@@ -73,6 +89,7 @@ export function TreeViewer(props: TreeViewerProps) {
     if (props.bufferId) {
       loadDepthData();
       loadFoldState();
+      loadLspFolds(props.bufferId);
     }
   });
 
@@ -7,6 +7,7 @@ import { isTauriEnvironment } from "./tauri-check";
 import type {
   BufferInfo,
   BufferMetrics,
+  CursorEdit,
   DepthRegionInfo,
   DirectoryEntry,
   FileContent,
@@ -115,6 +116,36 @@ export async function bufferReplace(
   });
 }
 
+export async function bufferNewline(bufferId: string, position: number): Promise<CursorEdit> {
+  return await invoke<CursorEdit>("buffer_newline", { buffer_id: bufferId, position });
+}
+
+export async function bufferReindent(
+  bufferId: string,
+  startLine: number,
+  endLine: number
+): Promise<BufferInfo> {
+  return await invoke<BufferInfo>("buffer_reindent", {
+    buffer_id: bufferId,
+    start_line: startLine,
+    end_line: endLine,
+  });
+}
+
+export async function bufferPaste(
+  bufferId: string,
+  start: number,
+  end: number,
+  text: string
+): Promise<CursorEdit> {
+  return await invoke<CursorEdit>("buffer_paste", {
+    buffer_id: bufferId,
+    start,
+    end,
+    text,
+  });
+}
+
 export async function bufferGetLine(bufferId: string, line: number): Promise<string | null> {
   return await invoke<string | null>("buffer_get_line", {
     buffer_id: bufferId,
@@ -128,9 +159,10 @@ export async function bufferMetrics(bufferId: string): Promise<BufferMetrics> {
   });
 }
 
-export async function bufferSave(bufferId: string): Promise<BufferInfo> {
+export async function bufferSave(bufferId: string, force?: boolean): Promise<BufferInfo> {
   return await invoke<BufferInfo>("buffer_save", {
     buffer_id: bufferId,
+    force,
   });
 }
 
@@ -173,6 +205,54 @@ export async function getRecentProjects(): Promise<ProjectInfo[]> {
   return await invoke<ProjectInfo[]>("get_recent_projects");
 }
 
+// Project dependency graph
+export interface DependencyGraphInfo {
+  root: string;
+  file_count: number;
+}
+
+/** An import of a file and the project files it resolves to */
+export interface FileImport {
+  kind: "import" | "module";
+  source: string;
+  line: number;
+  /** Empty for imports from outside the project, e.g. packages */
+  targets: string[];
+}
+
+export interface DependencyEdge {
+  from: string;
+  to: string;
+  source: string;
+  line: number;
+}
+
+/** Build the project's dependency graph; updates emit `deps:changed` */
+export async function buildDependencyGraph(root: string): Promise<DependencyGraphInfo> {
+  return await invoke<DependencyGraphInfo>("build_dependency_graph", { root });
+}
+
+export async function getDependencyEdges(): Promise<DependencyEdge[]> {
+  return await invoke<DependencyEdge[]>("dependency_graph_edges");
+}
+
+export async function getFileImports(path: string): Promise<FileImport[]> {
+  return await invoke<FileImport[]>("dependency_graph_imports", { path });
+}
+
+export async function getFileDependents(path: string): Promise<DependencyEdge[]> {
+  return await invoke<DependencyEdge[]>("dependency_graph_dependents", { path });
+}
+
+export async function getDependencyCycles(): Promise<string[][]> {
+  return await invoke<string[][]>("dependency_graph_cycles");
+}
+
+/** Shortest chain of imports from one file to another, if any */
+export async function getDependencyPath(from: string, to: string): Promise<string[] | null> {
+  return await invoke<string[] | null>("dependency_graph_path", { from, to });
+}
+
 // Syntax highlighting
 export async function getHighlights(
   bufferId: string,
@@ -230,6 +310,205 @@ export async function shrinkSelection(
   });
 }
 
+export type TextObjectKind = "function" | "class" | "parameter" | "comment";
+export type TextObjectScope = "inside" | "around";
+export type ByteRange = Pick<SelectionRange, "start_byte" | "end_byte">;
+
+/** Select the text object enclosing each selection, e.g. "around function" */
+export async function selectTextObject(
+  bufferId: string,
+  selections: ByteRange[],
+  kind: TextObjectKind,
+  scope: TextObjectScope
+): Promise<SelectionRange[]> {
+  return await invoke<SelectionRange[]>("select_text_object", {
+    buffer_id: bufferId,
+    selections,
+    kind,
+    scope,
+  });
+}
+
+/** Move each selection to the next or previous text object */
+export async function gotoTextObject(
+  bufferId: string,
+  selections: ByteRange[],
+  kind: TextObjectKind,
+  scope: TextObjectScope,
+  forward: boolean
+): Promise<SelectionRange[]> {
+  return await invoke<SelectionRange[]>("goto_text_object", {
+    buffer_id: bufferId,
+    selections,
+    kind,
+    scope,
+    forward,
+  });
+}
+
+export async function selectParentNode(
+  bufferId: string,
+  selections: ByteRange[]
+): Promise<SelectionRange[]> {
+  return await invoke<SelectionRange[]>("select_parent_node", {
+    buffer_id: bufferId,
+    selections,
+  });
+}
+
+export async function selectSiblingNode(
+  bufferId: string,
+  selections: ByteRange[],
+  forward: boolean
+): Promise<SelectionRange[]> {
+  return await invoke<SelectionRange[]>("select_sibling_node", {
+    buffer_id: bufferId,
+    selections,
+    forward,
+  });
+}
+
+/** Move each cursor to the matching bracket, or out to the enclosing pair */
+export async function jumpToMatchingBracket(
+  bufferId: string,
+  selections: ByteRange[]
+): Promise<SelectionRange[]> {
+  return await invoke<SelectionRange[]>("jump_to_matching_bracket", {
+    buffer_id: bufferId,
+    selections,
+  });
+}
+
+export interface TextSpan {
+  start: number;
+  end: number;
+}
+
+export interface BracketPair {
+  open: TextSpan;
+  close: TextSpan;
+  /** Number of enclosing pairs, for bracket pair colorization */
+  depth: number;
+}
+
+export interface Brackets {
+  pairs: BracketPair[];
+  unmatched: TextSpan[];
+}
+
+/** Get the bracket pairs intersecting a byte range, e.g. the viewport */
+export async function getBracketPairs(
+  bufferId: string,
+  startByte: number,
+  endByte: number
+): Promise<Brackets> {
+  return await invoke<Brackets>("get_bracket_pairs", {
+    buffer_id: bufferId,
+    start_byte: startByte,
+    end_byte: endByte,
+  });
+}
+
+export interface SelectionEdit extends BufferInfo {
+  selections: SelectionRange[];
+}
+
+/** Swap the syntax node at each selection with its next or previous sibling */
+export async function bufferSwapSiblings(
+  bufferId: string,
+  selections: ByteRange[],
+  forward: boolean
+): Promise<SelectionEdit> {
+  return await invoke<SelectionEdit>("buffer_swap_siblings", {
+    buffer_id: bufferId,
+    selections,
+    forward,
+  });
+}
+
+export async function symbolReferences(
+  bufferId: string,
+  byte: number
+): Promise<SelectionRange[]> {
+  return await invoke<SelectionRange[]>("symbol_references", {
+    buffer_id: bufferId,
+    byte,
+  });
+}
+
+// Syntax tree inspection
+export interface SyntaxPoint {
+  line: number;
+  /** In bytes */
+  column: number;
+}
+
+export interface SyntaxNode {
+  kind: string;
+  named: boolean;
+  field: string | null;
+  start_byte: number;
+  end_byte: number;
+  start: SyntaxPoint;
+  end: SyntaxPoint;
+  is_error: boolean;
+  is_missing: boolean;
+  children: SyntaxNode[];
+}
+
+export interface QueryCapture {
+  name: string;
+  pattern: number;
+  kind: string;
+  start_byte: number;
+  end_byte: number;
+  start: SyntaxPoint;
+  end: SyntaxPoint;
+  text: string;
+}
+
+export interface QueryResult {
+  captures: QueryCapture[];
+  /** Set if the query hit the match limit or timed out */
+  truncated: boolean;
+}
+
+/** Get the syntax tree, or the subtree of the node covering a range */
+export async function inspectSyntaxTree(
+  bufferId: string,
+  range: ByteRange | null,
+  namedOnly: boolean
+): Promise<SyntaxNode | null> {
+  return await invoke<SyntaxNode | null>("inspect_syntax_tree", {
+    buffer_id: bufferId,
+    range,
+    named_only: namedOnly,
+  });
+}
+
+export async function syntaxTreeSexp(
+  bufferId: string,
+  range: ByteRange | null
+): Promise<string | null> {
+  return await invoke<string | null>("syntax_tree_sexp", {
+    buffer_id: bufferId,
+    range,
+  });
+}
+
+/** Run a tree-sitter query against a buffer; rejects if it does not compile */
+export async function runSyntaxQuery(
+  bufferId: string,
+  query: string,
+  range: ByteRange | null = null
+): Promise<QueryResult> {
+  return await invoke<QueryResult>("run_syntax_query", {
+    buffer_id: bufferId,
+    query,
+    range,
+  });
+}
+
 // LSP operations
 export interface LspStatus {
   language: string;
@@ -364,6 +643,46 @@ export async function lspDiagnostics(filePath: string): Promise<LspDiagnostic[]>
   });
 }
 
+export interface DiagnosticCounts {
+  errors: number;
+  warnings: number;
+  infos: number;
+  hints: number;
+}
+
+export interface LspFileDiagnostics {
+  path: string;
+  diagnostics: LspDiagnostic[];
+  counts: DiagnosticCounts;
+}
+
+/** Diagnostics of every file that has any; updates emit `lsp:diagnostics` */
+export async function lspProjectDiagnostics(): Promise<LspFileDiagnostics[]> {
+  return await invoke<LspFileDiagnostics[]>("lsp_project_diagnostics");
+}
+
+/** Count diagnostics by severity, for one file or, without a path, the project */
+export async function lspDiagnosticCounts(filePath?: string): Promise<DiagnosticCounts> {
+  return await invoke<DiagnosticCounts>("lsp_diagnostic_counts", {
+    file_path: filePath,
+  });
+}
+
+export interface LspProgress {
+  language: string;
+  token: string;
+  title: string;
+  message: string | null;
+  /** Percentage done, if the server knows it */
+  percentage: number | null;
+  done: boolean;
+}
+
+/** Work language servers report progress on; updates emit `lsp:progress` */
+export async function lspProgress(): Promise<LspProgress[]> {
+  return await invoke<LspProgress[]>("lsp_progress");
+}
+
 export async function lspDidOpen(
   filePath: string,
   languageId: string,
@@ -398,21 +717,47 @@ export async function lspDidClose(filePath: string): Promise<void> {
   return await invoke<void>("lsp_did_close", { file_path: filePath });
 }
 
-export interface LspTextEdit {
-  range: {
-    start: { line: number; character: number };
-    end: { line: number; character: number };
-  };
-  new_text: string;
+/**
+ * Returns whether the tokens were applied; reload highlights if so. Servers
+ * refreshing their tokens emit `lsp:semantic-tokens-refresh` with the IDs of
+ * the buffers to request again
+ */
+export async function lspSemanticTokens(bufferId: string): Promise<boolean> {
+  return await invoke<boolean>("lsp_semantic_tokens", { buffer_id: bufferId });
+}
+
+/** Returns whether the ranges were applied; reload the fold state if so */
+export async function lspFoldingRanges(bufferId: string): Promise<boolean> {
+  return await invoke<boolean>("lsp_folding_ranges", { buffer_id: bufferId });
 }
 
+export interface LspRange {
+  start: { line: number; character: number };
+  end: { line: number; character: number };
+}
+
+/**
+ * What a workspace edit changed; edits servers make while running a code
+ * action's command emit `lsp:workspace-edit`
+ */
+export interface AppliedWorkspaceEdit {
+  /** Buffers whose text was edited */
+  edited: string[];
+  /** Files created, or edited on disk because they weren't open */
+  written: string[];
+  moved: { buffer_id: string; path: string }[];
+  /** Buffers closed because their file was deleted */
+  closed: string[];
+}
+
+/** Rename a symbol, applying the edits across the project */
 export async function lspRename(
   filePath: string,
   line: number,
   character: number,
   newName: string
-): Promise<LspTextEdit[]> {
-  return await invoke<LspTextEdit[]>("lsp_rename", {
+): Promise<AppliedWorkspaceEdit> {
+  return await invoke<AppliedWorkspaceEdit>("lsp_rename", {
     file_path: filePath,
     line,
     character,
@@ -420,6 +765,62 @@ export async function lspRename(
   });
 }
 
+export interface LspCodeAction {
+  title: string;
+  kind: string | null;
+  is_preferred: boolean;
+  /** Why the action can't be applied now, if it can't */
+  disabled: string | null;
+  /** The action as the server sent it, passed back to apply it */
+  action: unknown;
+}
+
+/** Code actions for a range, e.g. quick fixes; `only` limits their kinds */
+export async function lspCodeActions(
+  filePath: string,
+  range: LspRange,
+  only?: string[]
+): Promise<LspCodeAction[]> {
+  return await invoke<LspCodeAction[]>("lsp_code_actions", {
+    file_path: filePath,
+    range,
+    only: only ?? null,
+  });
+}
+
+export async function lspApplyCodeAction(
+  filePath: string,
+  action: LspCodeAction
+): Promise<AppliedWorkspaceEdit> {
+  return await invoke<AppliedWorkspaceEdit>("lsp_apply_code_action", {
+    file_path: filePath,
+    action: action.action,
+  });
+}
+
+/** An LSP file operation; options are left out */
+export type ResourceOperation =
+  | { kind: "create"; uri: string }
+  | { kind: "rename"; oldUri: string; newUri: string }
+  | { kind: "delete"; uri: string };
+
+/**
+ * A workspace edit that deletes or overwrites files, emitted as
+ * `lsp:confirm-workspace-edit`; it is refused unless confirmed
+ */
+export interface WorkspaceEditConfirmation {
+  id: number;
+  /** The operations that destroy data */
+  operations: ResourceOperation[];
+}
+
+export async function lspConfirmWorkspaceEdit(
+  id: number,
+  confirmed: boolean
+): Promise<void> {
+  return await invoke<void>("lsp_confirm_workspace_edit", { id, confirmed });
+}
+
 // Scope info for sticky headers
 export interface ScopeInfo {
   scope_name: string;
@@ -607,7 +1008,7 @@ export async function getDepthRegions(bufferId: string): Promise<DepthRegionInfo
 
 export async function getFoldState(bufferId: string): Promise<FoldState> {
   if (!isTauriEnvironment()) {
-    return { folded_lines: [], fold_ranges: {} };
+    return { folded_lines: [], fold_ranges: {}, collapsed: [] };
   }
   return await invoke<FoldState>("get_fold_state", {
     buffer_id: bufferId,
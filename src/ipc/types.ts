@@ -42,6 +42,11 @@ export interface BufferInfo {
   language: string | null;
 }
 
+/** Buffer info after an edit that moves the cursor, as a char offset */
+export interface CursorEdit extends BufferInfo {
+  cursor: number;
+}
+
 // Search types
 export interface FileSearchResult {
   path: string;
@@ -79,6 +84,9 @@ export interface EditorSettings {
   auto_save: boolean;
   auto_save_delay_ms: number;
   format_on_save: boolean;
+  trim_trailing_whitespace: boolean;
+  insert_final_newline: boolean;
+  hot_exit: boolean;
 }
 
 export interface TerminalSettings {
@@ -135,6 +143,8 @@ export interface HighlightSpan {
   end: number;
   kind: string;
   css_class: string;
+  /** Semantic token modifiers from a language server, e.g. "mutable" */
+  modifiers?: string[];
 }
 
 export interface SyntaxError {
@@ -182,15 +192,21 @@ export interface DepthRegionInfo {
   node_type?: string;
 }
 
+export type FoldKind = "comment" | "imports" | "region";
+
 export interface FoldRegion {
   start_line: number;
   end_line: number;
   is_placeholder: boolean;
+  /** Null for code */
+  kind: FoldKind | null;
 }
 
 export interface FoldState {
   folded_lines: number[];
   fold_ranges: Record<number, FoldRegion>;
+  /** Start lines of the collapsed folds */
+  collapsed: number[];
 }
 
 // ScopeInfo is defined in commands.ts
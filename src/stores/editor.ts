@@ -15,6 +15,32 @@ function generateTabId(): string {
   return `tab-${Date.now()}-${Math.random().toString(36).substr(2, 9)}`;
 }
 
+// Find the span of `oldText` replaced to make `newText`, in code points
+// to match the buffer's char offsets
+function changedSpan(oldText: string, newText: string) {
+  const oldChars = Array.from(oldText);
+  const newChars = Array.from(newText);
+  let start = 0;
+  while (
+    start < oldChars.length &&
+    start < newChars.length &&
+    oldChars[start] === newChars[start]
+  ) {
+    start++;
+  }
+  let oldEnd = oldChars.length;
+  let newEnd = newChars.length;
+  while (
+    oldEnd > start &&
+    newEnd > start &&
+    oldChars[oldEnd - 1] === newChars[newEnd - 1]
+  ) {
+    oldEnd--;
+    newEnd--;
+  }
+  return { start, end: oldEnd, text: newChars.slice(start, newEnd).join("") };
+}
+
 function getLanguageFromPath(filePath: string): string {
   const ext = filePath.split(".").pop()?.toLowerCase() || "";
   const langMap: Record<string, string> = {
@@ -163,9 +189,10 @@ export const editorStore = {
         return true; // No change needed
       }
 
-      // For simplicity, replace entire content
-      // In production, you'd want incremental updates
-      await ipc.bufferReplace(tab.bufferId, 0, oldContent.length, content);
+      // Replace only the span that changed, so undo merges typing and
+      // language servers get a small edit
+      const { start, end, text } = changedSpan(oldContent, content);
+      await ipc.bufferReplace(tab.bufferId, start, end, text);
       return true;
     } catch (e) {
       console.error("Failed to sync buffer:", e);
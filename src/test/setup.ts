@@ -177,15 +177,28 @@ const mockTauriInvoke = vi.fn(async (cmd: string, args?: Record<string, unknown>
       return {
         folded_lines: [],
         fold_ranges: {},
+        collapsed: [],
       };
 
     case "toggle_fold":
       return true;
 
+    case "lsp_semantic_tokens":
+    case "lsp_folding_ranges":
+      return false;
+
     case "buffer_undo":
     case "buffer_redo":
+    case "buffer_reindent":
       return { content: args?.content || "" };
 
+    case "buffer_newline":
+    case "buffer_paste":
+      return { content: args?.content || "", cursor: 0 };
+
+    case "buffer_swap_siblings":
+      return { content: args?.content || "", selections: [] };
+
     case "expand_selection":
     case "shrink_selection":
       return {
@@ -195,11 +208,37 @@ const mockTauriInvoke = vi.fn(async (cmd: string, args?: Record<string, unknown>
         end_character: 10,
       };
 
+    case "symbol_references":
+    case "select_text_object":
+    case "goto_text_object":
+    case "select_parent_node":
+    case "select_sibling_node":
+    case "jump_to_matching_bracket":
     case "lsp_goto_definition":
     case "lsp_references":
       return [];
 
     case "lsp_hover":
+    case "inspect_syntax_tree":
+    case "syntax_tree_sexp":
+      return null;
+
+    case "run_syntax_query":
+      return { captures: [], truncated: false };
+
+    case "get_bracket_pairs":
+      return { pairs: [], unmatched: [] };
+
+    case "build_dependency_graph":
+      return { root: "/test", file_count: 0 };
+
+    case "dependency_graph_edges":
+    case "dependency_graph_imports":
+    case "dependency_graph_dependents":
+    case "dependency_graph_cycles":
+      return [];
+
+    case "dependency_graph_path":
       return null;
 
     case "analyze_dependencies":
@@ -212,8 +251,22 @@ const mockTauriInvoke = vi.fn(async (cmd: string, args?: Record<string, unknown>
       return [];
 
     case "lsp_document_symbols":
+    case "lsp_project_diagnostics":
+      return [];
+
+    case "lsp_diagnostic_counts":
+      return { errors: 0, warnings: 0, infos: 0, hints: 0 };
+
+    case "lsp_progress":
+      return [];
+
+    case "lsp_code_actions":
       return [];
 
+    case "lsp_rename":
+    case "lsp_apply_code_action":
+      return { edited: [], written: [], moved: [], closed: [] };
+
     case "get_git_blame":
       return [];
 